@@ -0,0 +1,53 @@
+//! Compares the tree walking interpreter with the bytecode VM. Run with
+//! `cargo bench`.
+
+use std::time::{Duration, Instant};
+use yora::interpreter::Interpreter;
+use yora::syntax_analysis::produce_ast;
+use yora::vm::Vm;
+
+const FIBONACCI: &str = "pr fibonacci(n: Int) -> Int:\n\
+                         \tif n < 2:\n\t\treturn n\n\
+                         \treturn fibonacci(n - 1) + fibonacci(n - 2)\n\
+                         var result = fibonacci(22)\n";
+
+const SIEVE: &str = "var is_prime: Bool[]\n\
+                     for i in 0..2000:\n\tpush(is_prime, true)\n\
+                     var count = 0\n\
+                     for i in 2..len(is_prime):\n\
+                     \tif is_prime[i]:\n\
+                     \t\tcount += 1\n\
+                     \t\tvar j = i * i\n\
+                     \t\twhile j < len(is_prime):\n\
+                     \t\t\tis_prime[j] = false\n\
+                     \t\t\tj += i\n";
+
+const RUNS: u32 = 5;
+
+fn main() {
+    for (name, source) in [("fibonacci", FIBONACCI), ("sieve", SIEVE)] {
+        let ast = produce_ast(source.to_string()).unwrap();
+
+        let interpreter = time(|| Interpreter::new().run(&ast).unwrap());
+        let vm = time(|| Vm::new().run(&ast).unwrap());
+        println!(
+            "{:<10} interpreter {:>10.2?}   vm {:>10.2?}   {:.1}x faster",
+            name,
+            interpreter,
+            vm,
+            interpreter.as_secs_f64() / vm.as_secs_f64()
+        );
+    }
+}
+
+/// The fastest of a few runs.
+fn time(mut run: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
@@ -0,0 +1,16 @@
+use std::io::{stdin, stdout};
+use std::process;
+
+use yora::lsp::serve;
+
+fn main() {
+    match serve(stdin().lock(), stdout().lock()) {
+        Ok(true) => {}
+        // exiting without a shutdown request is an error for the client
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::code_generation::ir_gen::ir::*;
+
+/// Preserved across calls, so they can hold any variable.
+pub const CALLEE_SAVED_REGISTERS: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
+/// Clobbered by calls, so they only hold variables that are not live across one.
+pub const CALLER_SAVED_REGISTERS: [&str; 2] = ["r10", "r11"];
+
+#[derive(Debug, PartialEq)]
+pub struct Allocation {
+    pub locations: HashMap<String, String>,
+    pub stack_size: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Interval {
+    pub var: String,
+    pub start: usize,
+    pub end: usize,
+    pub crosses_call: bool,
+}
+
+/// Assigns a register or an rbp relative stack slot to every variable of a
+/// procedure body. `args` are defined on entry, before the first instruction,
+/// and `labels` are data section labels, which are not variables.
+pub fn allocate_registers(
+    code: &[IrInstruction],
+    args: &[Value],
+    labels: &HashSet<String>,
+) -> Allocation {
+    let intervals = get_live_intervals(code, args, labels);
+
+    let mut locations = HashMap::new();
+    let mut stack_size = 0;
+    let mut active: Vec<(Interval, &str)> = Vec::new();
+    // caller saved registers first, as they don't have to be pushed in the prologue
+    let registers: Vec<&str> = CALLER_SAVED_REGISTERS
+        .into_iter()
+        .chain(CALLEE_SAVED_REGISTERS)
+        .collect();
+    let mut free = registers.clone();
+
+    let mut spill = |var: &str, locations: &mut HashMap<String, String>| {
+        stack_size += 8;
+        locations.insert(var.to_string(), format!("qword [rbp-{}]", stack_size));
+    };
+
+    for interval in intervals {
+        // registers of intervals that ended are free again
+        active.retain(|(active_interval, reg)| {
+            if active_interval.end < interval.start {
+                free.push(reg);
+                false
+            } else {
+                true
+            }
+        });
+        free.sort_by_key(|reg| registers.iter().position(|r| r == reg));
+
+        let allowed = |reg: &str| !interval.crosses_call || CALLEE_SAVED_REGISTERS.contains(&reg);
+
+        if let Some(pos) = free.iter().position(|reg| allowed(reg)) {
+            let reg = free.remove(pos);
+            locations.insert(interval.var.clone(), reg.to_string());
+            active.push((interval, reg));
+            continue;
+        }
+
+        // steal the register of the interval that ends last, if it outlives this one
+        let victim = active
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, reg))| allowed(reg))
+            .max_by_key(|(_, (active_interval, _))| active_interval.end)
+            .map(|(i, _)| i);
+
+        match victim {
+            Some(i) if active[i].0.end > interval.end => {
+                let (victim_interval, reg) = active.remove(i);
+                spill(&victim_interval.var, &mut locations);
+                locations.insert(interval.var.clone(), reg.to_string());
+                active.push((interval, reg));
+            }
+            _ => spill(&interval.var, &mut locations),
+        }
+    }
+
+    Allocation {
+        locations,
+        stack_size,
+    }
+}
+
+/// Computes, for every variable, the range of instructions in which it is
+/// defined or live, sorted by where the ranges start. Position 0 is the entry
+/// of the procedure and instruction `i` is at position `i + 1`.
+pub fn get_live_intervals(
+    code: &[IrInstruction],
+    args: &[Value],
+    labels: &HashSet<String>,
+) -> Vec<Interval> {
+    let (live_in, live_out) = get_liveness(code, labels);
+
+    let mut intervals: HashMap<String, Interval> = HashMap::new();
+    let mut extend = |var: &String, pos: usize| {
+        let interval = intervals.entry(var.clone()).or_insert(Interval {
+            var: var.clone(),
+            start: pos,
+            end: pos,
+            crosses_call: false,
+        });
+        interval.start = interval.start.min(pos);
+        interval.end = interval.end.max(pos);
+    };
+
+    for arg in args {
+        if let Value::Identifier { id } = arg {
+            extend(id, 0);
+        }
+    }
+    for (i, instruction) in code.iter().enumerate() {
+        for var in get_defs(instruction, labels)
+            .iter()
+            .chain(&live_in[i])
+            .chain(&live_out[i])
+        {
+            extend(var, i + 1);
+        }
+    }
+
+    for (i, instruction) in code.iter().enumerate() {
+        if let IrInstruction::Call { .. } = instruction {
+            let defs = get_defs(instruction, labels);
+            for var in &live_out[i] {
+                if !defs.contains(var) {
+                    intervals.get_mut(var).unwrap().crosses_call = true;
+                }
+            }
+        }
+    }
+
+    let mut intervals: Vec<Interval> = intervals.into_values().collect();
+    intervals.sort_by(|a, b| (a.start, &a.var).cmp(&(b.start, &b.var)));
+    intervals
+}
+
+/// Iterates the backwards liveness equations until they reach a fixed point.
+fn get_liveness(
+    code: &[IrInstruction],
+    labels: &HashSet<String>,
+) -> (Vec<HashSet<String>>, Vec<HashSet<String>>) {
+    let label_positions: HashMap<&String, usize> = code
+        .iter()
+        .enumerate()
+        .filter_map(|(i, instruction)| match instruction {
+            IrInstruction::Label(label) => Some((label, i)),
+            _ => None,
+        })
+        .collect();
+
+    let successors: Vec<Vec<usize>> = code
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| {
+            let mut successors = Vec::new();
+            match instruction {
+                IrInstruction::Goto { label } => successors.extend(label_positions.get(label)),
+                IrInstruction::IfGoto { label, .. } => {
+                    successors.extend(label_positions.get(label));
+                    successors.push(i + 1);
+                }
+                IrInstruction::Ret { .. } => {}
+                _ => successors.push(i + 1),
+            }
+            successors.retain(|successor| *successor < code.len());
+            successors
+        })
+        .collect();
+
+    let uses: Vec<HashSet<String>> = code
+        .iter()
+        .map(|instruction| get_uses(instruction, labels))
+        .collect();
+    let defs: Vec<HashSet<String>> = code
+        .iter()
+        .map(|instruction| get_defs(instruction, labels))
+        .collect();
+
+    let mut live_in = vec![HashSet::new(); code.len()];
+    let mut live_out: Vec<HashSet<String>> = vec![HashSet::new(); code.len()];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for i in (0..code.len()).rev() {
+            let out: HashSet<String> = successors[i]
+                .iter()
+                .flat_map(|successor| live_in[*successor].iter().cloned())
+                .collect();
+
+            let mut new_in: HashSet<String> = out.difference(&defs[i]).cloned().collect();
+            new_in.extend(uses[i].iter().cloned());
+
+            if new_in != live_in[i] || out != live_out[i] {
+                live_in[i] = new_in;
+                live_out[i] = out;
+                changed = true;
+            }
+        }
+    }
+
+    (live_in, live_out)
+}
+
+fn get_uses(instruction: &IrInstruction, labels: &HashSet<String>) -> HashSet<String> {
+    let mut values = match instruction {
+        IrInstruction::Ass { src, .. }
+        | IrInstruction::Not { src, .. }
+        | IrInstruction::Param { src, .. }
+        | IrInstruction::Ret { src, .. } => vec![src],
+        IrInstruction::Op { src1, src2, .. } | IrInstruction::IfGoto { src1, src2, .. } => {
+            vec![src1, src2]
+        }
+        _ => Vec::new(),
+    };
+
+    // storing into an array element reads the array and the index
+    if let IrInstruction::Ass {
+        dest: dest @ Value::MemPos { .. },
+        ..
+    } = instruction
+    {
+        values.push(dest);
+    }
+
+    let mut uses = HashSet::new();
+    for value in values {
+        add_vars(value, labels, &mut uses);
+    }
+    uses
+}
+
+fn get_defs(instruction: &IrInstruction, labels: &HashSet<String>) -> HashSet<String> {
+    let mut defs = HashSet::new();
+
+    match instruction {
+        IrInstruction::Ass {
+            dest: Value::Identifier { id },
+            ..
+        }
+        | IrInstruction::Not {
+            dest: Value::Identifier { id },
+            ..
+        }
+        | IrInstruction::Op {
+            dest: Value::Identifier { id },
+            ..
+        }
+        | IrInstruction::Call {
+            dest: Some(Value::Identifier { id }),
+            ..
+        } if !labels.contains(id) => {
+            defs.insert(id.clone());
+        }
+        _ => {}
+    }
+
+    defs
+}
+
+fn add_vars(value: &Value, labels: &HashSet<String>, vars: &mut HashSet<String>) {
+    match value {
+        Value::Identifier { id } => {
+            if !labels.contains(id) {
+                vars.insert(id.clone());
+            }
+        }
+        Value::MemPos { id, offset } => {
+            if !labels.contains(id) {
+                vars.insert(id.clone());
+            }
+            add_vars(offset, labels, vars);
+        }
+        Value::Constant { .. } => {}
+    }
+}
@@ -1,26 +1,34 @@
-use std::collections::HashMap;
+pub mod allocator;
 
+use std::collections::{HashMap, HashSet};
+
+use crate::code_generation::ir_gen::ir::*;
 use crate::core::PrimitiveType;
-use crate::ir_gen::ir::*;
-use crate::op::Op;
+use allocator::*;
+
+const PARAM_REGISTERS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
 
 struct AsmGenerator {
     asm_data: String,
     asm_text: String,
     symbol_table: HashMap<String, String>,
+    data_labels: HashSet<String>,
     current_stack: usize,
     num_params: usize,
     current_param_stack: usize,
+    current_label: String,
 }
 
 pub fn generate_asm(ir: Ir) -> String {
     let mut generator = AsmGenerator {
         asm_data: String::from("section .data\n"),
-        asm_text: String::from("section .text\nglobal _start\n_start:\n\tmov rbp, rsp\n"),
+        asm_text: String::new(),
         symbol_table: HashMap::new(),
+        data_labels: HashSet::new(),
         current_stack: 0,
         num_params: 0,
         current_param_stack: 0,
+        current_label: String::new(),
     };
 
     generator.generate_data(ir.data);
@@ -31,17 +39,119 @@ pub fn generate_asm(ir: Ir) -> String {
 impl AsmGenerator {
     fn generate_data(&mut self, data: Vec<Buffer>) {
         for buffer in data {
-            if buffer.contents.contains('\"') {
-                self.asm_data
-                    .push_str(&format!("{}:\tdb\t{}\n", buffer.label, buffer.contents));
-            } else {
+            self.data_labels.insert(buffer.label.clone());
+            self.asm_data
+                .push_str(&format!("{}:\tdq\t{}\n", buffer.label, buffer.size));
+            if buffer.size > 0 {
                 self.asm_data
-                    .push_str(&format!("{}:\tdb\t{}\n", buffer.label, buffer.contents));
+                    .push_str(&format!("\tdb\t{}\n", buffer.contents));
             }
         }
+        self.asm_data.push_str(RUNTIME_DATA);
     }
+
     fn generate_code(&mut self, ir: Vec<IrInstruction>) {
+        let mut main = Vec::new();
+        let mut procedures: Vec<Vec<IrInstruction>> = Vec::new();
+        let mut in_procedure = false;
+
         for instruction in ir {
+            match instruction {
+                IrInstruction::Prologue { .. } => {
+                    in_procedure = true;
+                    procedures.push(vec![instruction]);
+                }
+                IrInstruction::Epilogue { .. } => {
+                    in_procedure = false;
+                    procedures.last_mut().unwrap().push(instruction);
+                }
+                _ if in_procedure => procedures.last_mut().unwrap().push(instruction),
+                _ => main.push(instruction),
+            }
+        }
+
+        self.asm_text.push_str("section .text\nglobal _start\n");
+        self.generate_main(main);
+        for procedure in procedures {
+            self.generate_procedure(procedure);
+        }
+        self.asm_text.push_str(RUNTIME_TEXT);
+    }
+
+    fn generate_main(&mut self, code: Vec<IrInstruction>) {
+        self.start_frame("_start", &code, &[]);
+        let body = self.generate_body(code);
+
+        self.asm_text.push_str(&format!(
+            "_start:\n\
+            \tmov rbp, rsp\n\
+            \tsub rsp, {}\n",
+            self.get_frame_size()
+        ));
+        self.asm_text.push_str(&body);
+        self.asm_text.push_str(
+            "\n_start_end:\n\
+            \tmov rdi, 0\n\
+            \tcall exit\n",
+        );
+    }
+
+    fn generate_procedure(&mut self, mut code: Vec<IrInstruction>) {
+        let Some(IrInstruction::Prologue { label, args }) = code.first().cloned() else {
+            panic!("Procedures must start with a prologue");
+        };
+        code.remove(0);
+        code.pop();
+
+        self.start_frame(&label, &code, &args);
+
+        // arguments past the sixth were pushed by the caller, the last one on top
+        let mut body = String::new();
+        for (i, arg) in args.iter().enumerate() {
+            if i < PARAM_REGISTERS.len() {
+                body.push_str(&self.get_store(arg, PARAM_REGISTERS[i], &PrimitiveType::Int));
+            } else {
+                body.push_str(&format!(
+                    "\tmov rax, qword [rbp+{}]\n",
+                    16 + 8 * (args.len() - 1 - i)
+                ));
+                body.push_str(&self.get_store(arg, "rax", &PrimitiveType::Int));
+            }
+        }
+        body.push_str(&self.generate_body(code));
+
+        // the callee saved registers belong to the caller
+        let used_regs: Vec<&str> = CALLEE_SAVED_REGISTERS
+            .into_iter()
+            .filter(|reg| self.symbol_table.values().any(|loc| loc == reg))
+            .collect();
+
+        self.asm_text.push_str(&format!(
+            "\n{label}:\n\
+            \tpush rbp\n\
+            \tmov rbp, rsp\n\
+            \tsub rsp, {}\n",
+            self.get_frame_size()
+        ));
+        for reg in &used_regs {
+            self.asm_text.push_str(&format!("\tpush {}\n", reg));
+        }
+        self.asm_text.push_str(&body);
+        self.asm_text.push_str(&format!("{label}_end:\n"));
+        for reg in used_regs.iter().rev() {
+            self.asm_text.push_str(&format!("\tpop {}\n", reg));
+        }
+        self.asm_text.push_str(
+            "\tmov rsp, rbp\n\
+            \tpop rbp\n\
+            \tret\n",
+        );
+    }
+
+    fn generate_body(&mut self, code: Vec<IrInstruction>) -> String {
+        let mut body = String::new();
+
+        for instruction in code {
             if let IrInstruction::Param { .. } = instruction {
                 self.num_params += 1;
             } else {
@@ -49,38 +159,15 @@ impl AsmGenerator {
             }
 
             let string = match instruction {
-                IrInstruction::Ass {
-                    ref dest,
-                    ref src,
-                    r#type,
-                } => self.get_assign(dest, src, r#type),
-                IrInstruction::Not {
-                    ref dest,
-                    ref src,
-                    r#type,
-                } => self.get_not(dest, src, r#type),
+                IrInstruction::Ass { dest, src, r#type } => self.get_assign(&dest, &src, &r#type),
+                IrInstruction::Not { dest, src, .. } => self.get_not(&dest, &src),
                 IrInstruction::Op {
-                    ref dest,
-                    ref src1,
-                    ref op,
-                    ref src2,
-                    r#type,
-                } => {
-                    let id = self.get_var(dest);
-                    if !self.symbol_table.contains_key(&id) {
-                        self.insert_reg(id.clone(), r#type.get_size());
-                    }
-                    match op {
-                        Op::Add | Op::Sub | Op::And | Op::Or => {
-                            self.get_simple_op(dest, src1, src2, op, r#type)
-                        }
-                        Op::Mul => self.get_mul(dest, src1, src2, r#type),
-                        Op::Div | Op::Mod => self.get_div_or_mod(dest, src1, src2, op, r#type),
-                        Op::Eq | Op::Neq | Op::Lt | Op::Leq | Op::Gt | Op::Geq => {
-                            self.get_cmp(dest, src1, src2, op, r#type)
-                        }
-                    }
-                }
+                    dest,
+                    src1,
+                    op,
+                    src2,
+                    ..
+                } => self.get_op(&dest, &src1, &op, &src2),
                 IrInstruction::Label(label) => format!("{}:\n", label),
                 IrInstruction::Goto { label } => format!("\tjmp {}\n", label),
                 IrInstruction::IfGoto {
@@ -89,328 +176,195 @@ impl AsmGenerator {
                     cond,
                     label,
                     ..
-                } => self.get_if_goto(&src1, &src2, cond, &label),
-                IrInstruction::Param { src, r#type } => self.get_param(&src, r#type),
-                IrInstruction::Call { label } => format!("\tcall {}\n", label),
-                IrInstruction::Ret { src, r#type } => self.get_ret(&src, r#type),
+                } => self.get_if_goto(&src1, &src2, &cond, &label),
+                IrInstruction::Param { src, .. } => self.get_param(&src),
+                IrInstruction::Call { dest, label } => self.get_call(&dest, &label),
+                IrInstruction::Ret { src, .. } => self.get_ret(&src),
+                IrInstruction::Prologue { .. } | IrInstruction::Epilogue { .. } => {
+                    panic!("Nested procedures are not supported")
+                }
             };
-            self.asm_text.push_str(&string);
+            body.push_str(&string);
         }
 
-        self.asm_text.push_str(
-            "\n\tmov rdi, 0\n\
-            exit:\n\
-            \tmov rax, 60\n\
-            \tsyscall\n",
-        );
-
-        self.asm_text.push_str(
-            "\nprint:\n\
-            \tmov rax, 1\n\
-            \tsyscall\n\
-            \tret\n",
-        );
+        body
     }
 
-    fn get_assign(&mut self, dest: &Value, src: &Value, r#type: PrimitiveType) -> String {
-        let id = self.get_var(dest);
-        if !self.symbol_table.contains_key(&id) {
-            self.insert_reg(id.clone(), r#type.get_size());
-        }
-
-        let src_val = self.get_value(src);
-        let dest_val = self.get_value(dest);
-        let acc = Self::get_reg_with_size("rax".to_string(), r#type.get_size());
+    fn start_frame(&mut self, label: &str, code: &[IrInstruction], args: &[Value]) {
+        let allocation = allocate_registers(code, args, &self.data_labels);
 
-        if src_val.contains('[') && dest_val.contains('[') {
-            format!(
-                "\tpush rax\n\
-                \tmov {acc}, {src_val}\n\
-                \tmov {dest_val}, {acc}\n\
-                \tpop rax\n",
-            )
-        } else {
-            format!("\tmov {dest_val}, {src_val}\n")
-        }
+        self.current_label = label.to_string();
+        self.symbol_table = allocation.locations;
+        self.current_stack = allocation.stack_size;
     }
 
-    fn get_not(&mut self, dest: &Value, src: &Value, r#type: PrimitiveType) -> String {
-        let id = self.get_var(dest);
-        if !self.symbol_table.contains_key(&id) {
-            self.insert_reg(id.clone(), r#type.get_size());
-        }
-
-        format!(
-            "\tmov {2}, {1}\n\
-            \tmov {0}, {1}\n\
-            \tnot {0}\n\
-            \tand {0}, 1\n",
-            self.get_value(dest),
-            self.get_value(src),
-            Self::get_reg_with_size("rax".to_string(), r#type.get_size()),
-        )
+    fn get_assign(&mut self, dest: &Value, src: &Value, r#type: &PrimitiveType) -> String {
+        let mut string = self.get_load("rax", src, r#type);
+        string.push_str(&self.get_store(dest, "rax", r#type));
+        string
     }
 
-    fn get_simple_op(
-        &mut self,
-        dest: &Value,
-        src1: &Value,
-        src2: &Value,
-        op: &Op,
-        r#type: PrimitiveType,
-    ) -> String {
-        let op_str = match op {
-            Op::Add => "add",
-            Op::Sub => "sub",
-            Op::And => "and",
-            Op::Or => "or",
-            _ => panic!("Must be 'add', 'sub', 'and' or 'or' operation"),
-        };
+    fn get_not(&mut self, dest: &Value, src: &Value) -> String {
+        let mut string = self.get_load("rax", src, &PrimitiveType::Bool);
+        string.push_str("\txor rax, 1\n");
+        string.push_str(&self.get_store(dest, "rax", &PrimitiveType::Bool));
+        string
+    }
 
-        let dest_val = self.get_value(dest);
-        let src1_val = self.get_value(src1);
-        let src2_val = self.get_value(src2);
-        let acc = Self::get_reg_with_size("rax".to_string(), r#type.get_size());
+    fn get_op(&mut self, dest: &Value, src1: &Value, op: &Op, src2: &Value) -> String {
+        let mut string = self.get_load("rax", src1, &PrimitiveType::Int);
+        string.push_str(&self.get_load("rcx", src2, &PrimitiveType::Int));
+
+        string.push_str(&match op {
+            Op::Add => "\tadd rax, rcx\n".to_string(),
+            Op::Sub => "\tsub rax, rcx\n".to_string(),
+            Op::Mul => "\timul rax, rcx\n".to_string(),
+            Op::And => "\tand rax, rcx\n".to_string(),
+            Op::Or => "\tor rax, rcx\n".to_string(),
+            Op::Div => "\tcqo\n\tidiv rcx\n".to_string(),
+            Op::Mod => "\tcqo\n\tidiv rcx\n\tmov rax, rdx\n".to_string(),
+            Op::Eq | Op::Neq | Op::Lt | Op::Leq | Op::Gt | Op::Geq => format!(
+                "\tcmp rax, rcx\n\
+                \tset{} al\n\
+                \tmovzx rax, al\n",
+                get_relation_str(op)
+            ),
+        });
+
+        string.push_str(&self.get_store(dest, "rax", &PrimitiveType::Int));
+        string
+    }
 
-        if dest == src1 {
-            format!("\t{op_str} {dest_val}, {src2_val}\n")
-        } else {
-            format!(
-                "\tmov {acc}, {src1_val}\n\
-                \t{op_str} {acc}, {src2_val}\n\
-                \tmov {dest_val}, {acc}\n",
-            )
-        }
+    fn get_if_goto(&mut self, src1: &Value, src2: &Value, cond: &Op, label: &String) -> String {
+        let mut string = self.get_load("rax", src1, &PrimitiveType::Int);
+        string.push_str(&self.get_load("rcx", src2, &PrimitiveType::Int));
+        string.push_str(&format!(
+            "\tcmp rax, rcx\n\
+            \tj{} {}\n",
+            get_relation_str(cond),
+            label
+        ));
+        string
     }
 
-    fn get_mul(
-        &mut self,
-        dest: &Value,
-        src1: &Value,
-        src2: &Value,
-        r#type: PrimitiveType,
-    ) -> String {
-        let dest_val = self.get_value(dest);
-        let src1_val = self.get_value(src1);
-        let src2_val = self.get_value(src2);
-        let acc = Self::get_reg_with_size("rax".to_string(), r#type.get_size());
-
-        if dest == src1 {
-            format!(
-                "\tmov {acc}, {src2_val}\n\
-                \tmul {dest_val}\n\
-                \tmov {dest_val}, {acc}\n",
-            )
-        } else if dest == src2 {
-            format!(
-                "\tmov {acc}, {src1_val}\n\
-                \tmul {dest_val}\n\
-                \tmov {dest_val}, {acc}\n",
+    fn get_param(&mut self, src: &Value) -> String {
+        if self.num_params <= PARAM_REGISTERS.len() {
+            self.get_load(
+                PARAM_REGISTERS[self.num_params - 1],
+                src,
+                &PrimitiveType::Int,
             )
         } else {
-            format!(
-                "\tmov {acc}, {src1_val}\n\
-                \tmov {dest_val}, {acc}\n\
-                \tmov {acc}, {src2_val}\n\
-                \tmul {dest_val}\n\
-                \tmov {dest_val}, {acc}\n",
-            )
+            self.current_param_stack += 8;
+            let mut string = self.get_load("rax", src, &PrimitiveType::Int);
+            string.push_str("\tpush rax\n");
+            string
         }
     }
 
-    fn get_div_or_mod(
-        &mut self,
-        dest: &Value,
-        src1: &Value,
-        src2: &Value,
-        op: &Op,
-        r#type: PrimitiveType,
-    ) -> String {
-        let dest_val = self.get_value(dest);
-        let src1_val = self.get_value(src1);
-        let acc = Self::get_reg_with_size("rax".to_string(), r#type.get_size());
-
-        let result_reg = if *op == Op::Div {
-            acc.clone()
-        } else {
-            Self::get_reg_with_size("rdx".to_string(), r#type.get_size())
-        };
-
-        format!(
-            "\tmov rax, 0\n\
-                \tmov rdx, 0\n\
-                \tmov {acc}, {src1_val}\n\
-                \tdiv {0}\n\
-                \tmov {dest_val}, {result_reg}\n",
-            Self::get_reg_with_size(self.get_value(src2), 8),
-        )
-    }
+    fn get_call(&mut self, dest: &Option<Value>, label: &String) -> String {
+        let mut string = format!("\tcall {}\n", label);
 
-    fn get_cmp(
-        &mut self,
-        dest: &Value,
-        src1: &Value,
-        src2: &Value,
-        op: &Op,
-        r#type: PrimitiveType,
-    ) -> String {
-        let dest_val = self.get_value(dest);
-        let src1_val = self.get_value(src1);
-        let src2_val = self.get_value(src2);
-
-        let id = self.get_var(dest);
-        if !self.symbol_table.contains_key(&id) {
-            self.insert_reg(id.clone(), r#type.get_size());
+        if self.current_param_stack > 0 {
+            string.push_str(&format!("\tadd rsp, {}\n", self.current_param_stack));
+            self.current_param_stack = 0;
         }
 
-        self.asm_text
-            .push_str(&format!("\tcmp {}, {}\n", src1_val, src2_val,));
+        if let Some(dest) = dest {
+            string.push_str(&self.get_store(dest, "rax", &PrimitiveType::Int));
+        }
 
-        format!("\tset{} {}\n", get_relation_str(op), dest_val)
+        string
     }
 
-    fn get_if_goto(&mut self, src1: &Value, src2: &Value, cond: Op, label: &String) -> String {
-        format!(
-            "\tcmp {}, {}\n\
-            \tj{} {}\n",
-            self.get_value(src1),
-            self.get_value(src2),
-            get_relation_str(&cond),
-            label
-        )
+    fn get_ret(&mut self, src: &Value) -> String {
+        let mut string = self.get_load("rax", src, &PrimitiveType::Int);
+        string.push_str(&format!("\tjmp {}_end\n", self.current_label));
+        string
     }
 
-    fn get_param(&mut self, src: &Value, r#type: PrimitiveType) -> String {
-        let mut type_size = 8;
-
-        let reg = match src {
-            Value::Constant { value } => {
-                if value.parse::<i32>().is_ok() {
-                    value.clone()
-                } else {
-                    panic!("Invalid param")
-                }
-            }
+    /// Loads `value` into the 64-bit register `reg`.
+    fn get_load(&mut self, reg: &str, value: &Value, r#type: &PrimitiveType) -> String {
+        match value {
+            Value::Constant { value } => format!("\tmov {}, {}\n", reg, value),
             Value::Identifier { id } => {
-                if !self.symbol_table.contains_key(id) {
-                    self.get_value(src)
+                if self.symbol_table.contains_key(id) {
+                    format!("\tmov {}, {}\n", reg, self.symbol_table[id])
                 } else {
-                    type_size = r#type.get_size();
-                    self.get_value(src)
+                    // not a variable, so it is the label of a buffer in the data section
+                    format!("\tlea {}, [{}]\n", reg, id)
                 }
             }
-            Value::MemPos { .. } => {
-                type_size = r#type.get_size();
-                self.get_value(src)
-            }
-        };
-
-        if self.num_params < 7 {
-            format!(
-                "\tmov {}, {}\n",
-                match self.num_params {
-                    1 => Self::get_reg_with_size("rdi".to_string(), type_size),
-                    2 => Self::get_reg_with_size("rsi".to_string(), type_size),
-                    3 => Self::get_reg_with_size("rdx".to_string(), type_size),
-                    4 => Self::get_reg_with_size("rcx".to_string(), type_size),
-                    5 => Self::get_reg_with_size("r8".to_string(), type_size),
-                    6 => Self::get_reg_with_size("r9".to_string(), type_size),
-                    _ => unreachable!(),
-                },
-                reg
-            )
-        } else {
-            self.current_param_stack += type_size;
-            format!("\tpush {}\n", reg)
-        }
-    }
-
-    fn get_ret(&mut self, src: &Value, r#type: PrimitiveType) -> String {
-        let ret = format!(
-            "\tmov {}, {}\n\
-                \tret {}",
-            Self::get_reg_with_size("rax".to_string(), r#type.get_size()),
-            self.get_value(src),
-            self.current_param_stack
-        );
-        self.current_param_stack = 0;
-        ret
-    }
-
-    fn get_value(&mut self, value: &Value) -> String {
-        match value {
             Value::MemPos { id, offset } => {
-                let offset_val = self.get_value(offset); // register where it is
-
-                if offset_val.contains('[') {
-                    let acc = Self::get_reg_with_size("rax".to_string(), 4);
-                    self.asm_text
-                        .push_str(&format!("\tmov {}, {}\n", acc, offset_val));
-                    acc
+                let mut string = self.get_element_address(id, offset);
+                let size = r#type.get_size();
+                if size == 8 {
+                    string.push_str(&format!("\tmov {}, qword [rsi + rcx*8 + 8]\n", reg));
                 } else {
-                    format!("byte [{} + {}]", id, Self::get_reg_with_size(offset_val, 4))
+                    string.push_str(&format!(
+                        "\tmovzx {}, {} [rsi + rcx*{} + 8]\n",
+                        reg,
+                        get_word_for_size(size),
+                        size
+                    ));
                 }
+                string
             }
-            Value::Identifier { id } => {
-                if self.symbol_table.contains_key(id) {
-                    self.symbol_table[id].clone()
-                } else {
-                    value.to_string()
-                }
-            }
-            Value::Constant { value } => value.to_string(),
         }
     }
 
-    fn get_var(&self, value: &Value) -> String {
+    /// Stores the 64-bit register `reg` into `value`.
+    fn get_store(&mut self, value: &Value, reg: &str, r#type: &PrimitiveType) -> String {
         match value {
-            Value::MemPos { id, .. } => id.to_string(),
-            Value::Identifier { id } => id.to_string(),
-            Value::Constant { .. } => panic!("Invalid variable"),
+            Value::Identifier { id } => {
+                format!("\tmov {}, {}\n", self.symbol_table[id], reg)
+            }
+            Value::MemPos { id, offset } => {
+                let mut string = format!("\tpush {}\n", reg);
+                string.push_str(&self.get_element_address(id, offset));
+                string.push_str(&format!("\tpop {}\n", reg));
+
+                let size = r#type.get_size();
+                string.push_str(&format!(
+                    "\tmov {} [rsi + rcx*{} + 8], {}\n",
+                    get_word_for_size(size),
+                    size,
+                    Self::get_reg_with_size(reg, size)
+                ));
+                string
+            }
+            Value::Constant { .. } => panic!("Cannot store into a constant"),
         }
     }
 
-    fn insert_reg(&mut self, dest: String, size: usize) {
-        let num_regs = self.symbol_table.len();
-        let next_reg = self.get_next_reg(num_regs, size);
-
-        // number of work registers
-        if num_regs >= 7 {
-            self.current_stack += size;
-        }
-
-        self.symbol_table.insert(dest.to_string(), next_reg);
+    /// Leaves the array pointer in rsi and the index in rcx.
+    fn get_element_address(&mut self, id: &str, offset: &Value) -> String {
+        let mut string = self.get_load(
+            "rsi",
+            &Value::Identifier { id: id.to_string() },
+            &PrimitiveType::Int,
+        );
+        string.push_str(&self.get_load("rcx", offset, &PrimitiveType::Int));
+        string
     }
 
-    fn get_next_reg(&mut self, num_regs: usize, size: usize) -> String {
-        Self::get_reg_with_size(
-            if num_regs == 0 {
-                "rbx".to_string()
-            } else if num_regs < 7 {
-                format!("r{}", num_regs + 9)
-            } else {
-                format!("{} [rbp-{}]", get_word_for_size(size), self.current_stack)
-            },
-            size,
-        )
+    fn get_frame_size(&self) -> usize {
+        // keep the stack 16 byte aligned
+        self.current_stack.div_ceil(16) * 16
     }
 
-    fn get_reg_with_size(reg: String, size: usize) -> String {
-        if reg.parse::<i32>().is_ok() {
-            return reg;
-        } else if reg.contains("[") {
-            let split_reg: Vec<&str> = reg.split(' ').collect();
-
-            return reg.replace(split_reg[0], &get_word_for_size(size));
-        }
-
-        let mut reg_letter = &reg[reg.len() - 2..reg.len() - 1];
-        let reg_last = &reg[reg.len() - 1..reg.len()];
+    fn get_reg_with_size(reg: &str, size: usize) -> String {
+        let reg_letter = &reg[1..2];
 
-        if reg_letter == "i" || reg_last == "i" {
-            if reg_last == "l" {
-                reg_letter = &reg[reg.len() - 3..reg.len() - 2]
+        if reg.len() == 3 && reg.ends_with('x') {
+            match size {
+                8 => format!("r{}x", reg_letter),
+                4 => format!("e{}x", reg_letter),
+                2 => format!("{}x", reg_letter),
+                1 => format!("{}l", reg_letter),
+                _ => panic!("Invalid register size"),
             }
+        } else if reg.len() == 3 && reg.ends_with('i') {
             match size {
                 8 => format!("r{}i", reg_letter),
                 4 => format!("e{}i", reg_letter),
@@ -418,26 +372,10 @@ impl AsmGenerator {
                 1 => format!("{}il", reg_letter),
                 _ => panic!("Invalid register size"),
             }
-        } else if reg_last.parse::<i32>().is_err() && reg_letter.parse::<i32>().is_err() {
-            match size {
-                8 => format!("r{}x", reg_letter),
-                4 => format!("e{}x", reg_letter),
-                2 => format!("{}x", reg_letter),
-                1 => format!("{}l", reg_letter),
-                _ => panic!("Invalid register size"),
-            }
         } else {
-            let mut new_reg = reg.clone();
-
-            if let Some(ch) = reg.chars().last() {
-                if ch.is_alphabetic() {
-                    new_reg.pop();
-                }
-            }
-
             format!(
                 "{}{}",
-                new_reg,
+                reg,
                 match size {
                     8 => "",
                     4 => "d",
@@ -472,3 +410,373 @@ fn get_word_for_size(size: usize) -> String {
     }
     .to_string()
 }
+
+const RUNTIME_DATA: &str = "true_str:\tdq\t4\n\
+    \tdb\t\"true\"\n\
+    false_str:\tdq\t5\n\
+    \tdb\t\"false\"\n";
+
+// Builtin procedures. Arrays are pointers to a block whose first quadword is
+// the number of elements. Only rax, rcx, rdx, rsi, rdi and r8 to r11 are
+// clobbered, so values kept in the callee saved registers survive the calls.
+const RUNTIME_TEXT: &str = "
+exit:
+\tmov rax, 60
+\tsyscall
+
+print_string:
+\tmov rdx, [rdi]
+\tlea rsi, [rdi + 8]
+\tmov rdi, 1
+\tmov rax, 1
+\tsyscall
+\tret
+
+print_char:
+\tsub rsp, 8
+\tmov [rsp], dil
+\tmov rsi, rsp
+\tmov rdx, 1
+\tmov rdi, 1
+\tmov rax, 1
+\tsyscall
+\tadd rsp, 8
+\tret
+
+print_bool:
+\tlea rsi, [true_str]
+\tcmp rdi, 0
+\tjne print_bool_true
+\tlea rsi, [false_str]
+print_bool_true:
+\tmov rdi, rsi
+\tjmp print_string
+
+print_int:
+\tsub rsp, 32
+\tlea rsi, [rsp + 31]
+\tmov rax, rdi
+\tmov r8, 0
+\tmov rcx, 10
+\tcmp rax, 0
+\tjge print_int_digits
+\tneg rax
+\tmov r8, 1
+print_int_digits:
+\tmov rdx, 0
+\tdiv rcx
+\tadd dl, 48
+\tmov [rsi], dl
+\tdec rsi
+\tcmp rax, 0
+\tjne print_int_digits
+\tcmp r8, 0
+\tje print_int_write
+\tmov byte [rsi], 45
+\tdec rsi
+print_int_write:
+\tinc rsi
+\tlea rdx, [rsp + 32]
+\tsub rdx, rsi
+\tmov rdi, 1
+\tmov rax, 1
+\tsyscall
+\tadd rsp, 32
+\tret
+
+input:
+\tmov rdi, 4088
+\tmov rsi, 1
+\tcall array_new
+\tpush rax
+\tlea rsi, [rax + 8]
+input_loop:
+\tmov rdx, [rsp]
+\tadd rdx, 4096
+\tcmp rsi, rdx
+\tje input_end
+\tpush rsi
+\tmov rdx, 1
+\tmov rdi, 0
+\tmov rax, 0
+\tsyscall
+\tpop rsi
+\tcmp rax, 1
+\tjne input_end
+\tinc rsi
+\tcmp byte [rsi - 1], 10
+\tjne input_loop
+input_end:
+\tpop rax
+\tlea rdx, [rax + 8]
+\tsub rsi, rdx
+\tmov [rax], rsi
+\tret
+
+string_len:
+array_len:
+\tmov rax, [rdi]
+\tret
+
+string_to_int:
+\tmov rcx, [rdi]
+\tlea rsi, [rdi + 8]
+\tmov rax, 0
+\tmov r8, 0
+\tcmp rcx, 0
+\tje string_to_int_end
+\tcmp byte [rsi], 45
+\tjne string_to_int_loop
+\tmov r8, 1
+\tinc rsi
+\tdec rcx
+string_to_int_loop:
+\tcmp rcx, 0
+\tje string_to_int_end
+\tmovzx rdx, byte [rsi]
+\tsub rdx, 48
+\tcmp rdx, 9
+\tja string_to_int_end
+\timul rax, rax, 10
+\tadd rax, rdx
+\tinc rsi
+\tdec rcx
+\tjmp string_to_int_loop
+string_to_int_end:
+\tcmp r8, 0
+\tje string_to_int_ret
+\tneg rax
+string_to_int_ret:
+\tret
+
+array_capacity:
+\tlea rcx, [rdi - 1]
+\tmov rax, 1
+\tbsr rcx, rcx
+\tjz array_capacity_end
+\tinc rcx
+\tshl rax, cl
+array_capacity_end:
+\tret
+
+array_new:
+\tpush rdi
+\tpush rsi
+\tcall array_capacity
+\tpop rsi
+\timul rsi, rax
+\tadd rsi, 8
+\tmov rdi, 0
+\tmov rdx, 3
+\tmov r10, 34
+\tmov r8, -1
+\tmov r9, 0
+\tmov rax, 9
+\tsyscall
+\tpop rdi
+\tmov [rax], rdi
+\tret
+
+array_copy:
+\tpush rdi
+\tpush rsi
+\tmov rdi, [rdi]
+\tcall array_new
+\tpop rsi
+\tpop rdi
+\tmov rcx, [rdi]
+\timul rcx, rsi
+\tadd rcx, 8
+\tmov rsi, rdi
+\tmov rdi, rax
+\trep movsb
+\tret
+
+array_copy_nested:
+\tcmp rdx, 0
+\tje array_copy
+\tpush rsi
+\tpush rdx
+\tmov rsi, 8
+\tcall array_copy
+\tpop rdx
+\tpop rsi
+\tdec rdx
+\tpush rax
+\tpush rsi
+\tpush rdx
+\tpush 0
+array_copy_nested_loop:
+\tmov rcx, [rsp]
+\tmov rax, [rsp + 24]
+\tcmp rcx, [rax]
+\tje array_copy_nested_end
+\tmov rdi, [rax + rcx*8 + 8]
+\tmov rsi, [rsp + 16]
+\tmov rdx, [rsp + 8]
+\tcall array_copy_nested
+\tmov rcx, [rsp]
+\tmov rdi, [rsp + 24]
+\tmov [rdi + rcx*8 + 8], rax
+\tinc qword [rsp]
+\tjmp array_copy_nested_loop
+array_copy_nested_end:
+\tadd rsp, 24
+\tpop rax
+\tret
+
+array_resize:
+\tpush rdi
+\tpush rsi
+\tpush rdx
+\tmov rdi, [rdi]
+\tcall array_capacity
+\tpop rdx
+\tpop rsi
+\tpop rdi
+\tcmp rsi, rax
+\tja array_resize_new
+\tmov [rdi], rsi
+\tmov rax, rdi
+\tret
+array_resize_new:
+\tpush rdi
+\tpush rdx
+\tmov rdi, rsi
+\tmov rsi, rdx
+\tcall array_new
+\tpop rdx
+\tpop rsi
+\tmov rcx, [rsi]
+\timul rcx, rdx
+\tlea rdi, [rax + 8]
+\tadd rsi, 8
+\trep movsb
+\tret
+
+array_get:
+\timul rcx, rdx
+\tcmp rdx, 1
+\tje array_get_byte
+\tmov rax, [rdi + rcx + 8]
+\tret
+array_get_byte:
+\tmovzx rax, byte [rdi + rcx + 8]
+\tret
+
+array_set:
+\timul rcx, rdx
+\tcmp rdx, 1
+\tje array_set_byte
+\tmov [rax + rcx + 8], rsi
+\tret
+array_set_byte:
+\tmov [rax + rcx + 8], sil
+\tret
+
+array_push:
+\tpush rsi
+\tpush rdx
+\tmov rsi, [rdi]
+\tinc rsi
+\tcall array_resize
+\tpop rdx
+\tpop rsi
+\tmov rcx, [rax]
+\tdec rcx
+\tjmp array_set
+
+array_pop:
+\tmov rdx, rsi
+\tmov rcx, [rdi]
+\tdec rcx
+\tmov [rdi], rcx
+\tjmp array_get
+
+array_insert:
+\tpush rsi
+\tpush rdx
+\tpush rcx
+\tmov rdx, rcx
+\tmov rsi, [rdi]
+\tinc rsi
+\tcall array_resize
+\tpop rdx
+\tpop r8
+\tpop r9
+\tmov rsi, [rax]
+\tdec rsi
+\tmov rcx, rsi
+\tsub rcx, r9
+\timul rcx, rdx
+\timul rsi, rdx
+\tlea rsi, [rax + rsi + 7]
+\tlea rdi, [rsi + rdx]
+\tstd
+\trep movsb
+\tcld
+\tmov rcx, r9
+\tmov rsi, r8
+\tjmp array_set
+
+array_remove:
+\tpush rdi
+\tpush rsi
+\tpush rdx
+\tmov rcx, rsi
+\tcall array_get
+\tpop rdx
+\tpop rsi
+\tpop rdi
+\tmov rcx, [rdi]
+\tdec rcx
+\tmov [rdi], rcx
+\tsub rcx, rsi
+\timul rcx, rdx
+\timul rsi, rdx
+\tlea rdi, [rdi + rsi + 8]
+\tlea rsi, [rdi + rdx]
+\trep movsb
+\tret
+
+array_slice:
+\tpush rdi
+\tpush rsi
+\tpush rcx
+\tmov rdi, rdx
+\tsub rdi, rsi
+\tmov rsi, rcx
+\tcall array_new
+\tpop rdx
+\tpop rsi
+\tpop rdi
+\timul rsi, rdx
+\tlea rsi, [rdi + rsi + 8]
+\tmov rcx, [rax]
+\timul rcx, rdx
+\tlea rdi, [rax + 8]
+\trep movsb
+\tret
+
+array_concat:
+\tpush rdi
+\tpush rsi
+\tpush rdx
+\tmov rdi, [rdi]
+\tadd rdi, [rsi]
+\tmov rsi, rdx
+\tcall array_new
+\tpop rdx
+\tpop r8
+\tpop rsi
+\tlea rdi, [rax + 8]
+\tmov rcx, [rsi]
+\timul rcx, rdx
+\tadd rsi, 8
+\trep movsb
+\tmov rcx, [r8]
+\timul rcx, rdx
+\tlea rsi, [r8 + 8]
+\trep movsb
+\tret
+";
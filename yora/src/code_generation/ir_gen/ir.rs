@@ -1,7 +1,6 @@
-use crate::op::Op;
 use std::fmt;
 
-use super::PrimitiveType;
+use crate::core::PrimitiveType;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Ir {
@@ -37,6 +36,12 @@ impl Ir {
     }
 }
 
+impl Default for Ir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl fmt::Display for Ir {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
         let mut string = String::new();
@@ -87,11 +92,19 @@ pub enum IrInstruction {
     },
 
     // Funcion calls
+    Prologue {
+        label: String,
+        args: Vec<Value>,
+    },
+    Epilogue {
+        label: String,
+    },
     Param {
         src: Value,
         r#type: PrimitiveType,
     },
     Call {
+        dest: Option<Value>,
         label: String,
     },
     Ret {
@@ -112,7 +125,7 @@ impl fmt::Display for IrInstruction {
                 src2,
                 r#type,
             } => {
-                write!(f, "\t{type}: {dest} = {src1} {op} {src2}")
+                write!(f, "\t{type}:\t{dest} = {src1} {op} {src2}")
             }
             IrInstruction::Label(str) => write!(f, "{}:", str),
             IrInstruction::Goto { label, .. } => write!(f, "    goto {}", label),
@@ -123,8 +136,16 @@ impl fmt::Display for IrInstruction {
                 label,
                 ..
             } => write!(f, "    if {} {} {} goto {}", src1, cond, src2, label),
+            IrInstruction::Prologue { label, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "pr {}({}):", label, args.join(", "))
+            }
+            IrInstruction::Epilogue { label } => write!(f, "end pr {}", label),
             IrInstruction::Param { src, .. } => write!(f, "    param {}", src),
-            IrInstruction::Call { label } => write!(f, "    call {}", label),
+            IrInstruction::Call { dest, label } => match dest {
+                Some(dest) => write!(f, "    {} = call {}", dest, label),
+                None => write!(f, "    call {}", label),
+            },
             IrInstruction::Ret { src, .. } => write!(f, "    ret {}", src),
         }
     }
@@ -146,3 +167,81 @@ impl fmt::Display for Value {
         }
     }
 }
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    Eq,
+    Neq,
+    Lt,
+    Leq,
+    Gt,
+    Geq,
+}
+
+impl Op {
+    pub fn from_name(name: &str) -> Option<Op> {
+        Some(match name {
+            "+" => Op::Add,
+            "-" => Op::Sub,
+            "*" => Op::Mul,
+            "/" => Op::Div,
+            "%" => Op::Mod,
+            "and" => Op::And,
+            "or" => Op::Or,
+            "==" => Op::Eq,
+            "!=" => Op::Neq,
+            "<" => Op::Lt,
+            "<=" => Op::Leq,
+            ">" => Op::Gt,
+            ">=" => Op::Geq,
+            _ => return None,
+        })
+    }
+
+    pub fn is_relational(&self) -> bool {
+        matches!(self, Op::Eq | Op::Neq | Op::Lt | Op::Leq | Op::Gt | Op::Geq)
+    }
+
+    pub fn negate(&self) -> Op {
+        match self {
+            Op::Eq => Op::Neq,
+            Op::Neq => Op::Eq,
+            Op::Lt => Op::Geq,
+            Op::Leq => Op::Gt,
+            Op::Gt => Op::Leq,
+            Op::Geq => Op::Lt,
+            _ => panic!("Not a relational operator"),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Op::Add => "+",
+                Op::Sub => "-",
+                Op::Mul => "*",
+                Op::Div => "/",
+                Op::Mod => "%",
+                Op::And => "and",
+                Op::Or => "or",
+                Op::Eq => "==",
+                Op::Neq => "!=",
+                Op::Lt => "<",
+                Op::Leq => "<=",
+                Op::Gt => ">",
+                Op::Geq => ">=",
+            }
+        )
+    }
+}
@@ -1,22 +1,42 @@
+use std::collections::HashSet;
+use std::mem;
+
+use self::ir::*;
 use crate::core::*;
-use crate::ir::*;
-use crate::op::*;
-use crate::parser::expression::*;
-use crate::statement::*;
+use crate::syntax_analysis::parser::expression::*;
+use crate::syntax_analysis::parser::statement::*;
 
 pub mod ir;
 
-pub fn generate_ir(ast: Vec<Statement>) -> Ir {
+/// The IR of the program, or which of its features the compiler doesn't
+/// support yet.
+pub fn generate_ir(ast: &[Statement]) -> Result<Ir, String> {
     let mut generator = IrGenerator::new();
 
-    generator.gen_ir(ast);
+    generator.gen_ir(ast)?;
+
+    Ok(generator.ir)
+}
+
+type GenResult<T = ()> = Result<T, String>;
 
-    generator.ir
+fn unsupported<T>(features: &str) -> GenResult<T> {
+    Err(format!(
+        "{} are not supported by the compiler yet",
+        features
+    ))
 }
 
 pub struct IrGenerator {
     nums: Nums,
     ir: Ir,
+    // the names of the variables in scope and their names in the IR, by slot
+    variables: Vec<(String, String)>,
+    // whether a procedure is being generated
+    in_procedure: bool,
+    loops: Vec<u32>,
+    procedures: HashSet<String>,
+    procedures_code: Vec<IrInstruction>,
 }
 
 struct Nums {
@@ -24,6 +44,7 @@ struct Nums {
     ifs: u32,
     loops: u32,
     buf: u32,
+    vars: u32,
 }
 
 impl IrGenerator {
@@ -34,357 +55,859 @@ impl IrGenerator {
                 ifs: 0,
                 loops: 0,
                 buf: 0,
+                vars: 0,
             },
             ir: Ir::new(),
+            variables: Vec::new(),
+            in_procedure: false,
+            loops: Vec::new(),
+            procedures: HashSet::new(),
+            procedures_code: Vec::new(),
         }
     }
 
-    fn gen_ir(&mut self, ast: Vec<Statement>) {
+    fn gen_ir(&mut self, ast: &[Statement]) -> GenResult {
         for statement in ast {
-            self.get_statement(&statement);
+            self.get_statement(statement)?;
         }
+
+        // procedures are placed after the main code
+        let procedures_code = mem::take(&mut self.procedures_code);
+        self.ir.code.extend(procedures_code);
+        Ok(())
     }
 
-    fn get_statement(&mut self, statement: &Statement) {
+    fn get_statement(&mut self, statement: &Statement) -> GenResult {
         match &statement.kind {
             StatementKind::Procedure {
                 name,
                 args,
-                ret,
                 block,
-            } => todo!(),
-            StatementKind::Call { name, args } => self.get_call(name, args),
-            StatementKind::Return { value } => todo!(),
+                binding,
+                ..
+            } => self.get_procedure(name, args, block, binding.unwrap()),
+            StatementKind::Call { name, args, .. } => self.get_call(name, args, None),
+            StatementKind::Return { value } => self.get_return(value),
+            StatementKind::Struct { .. } => unsupported("Structs, enums and 'match'"),
+            StatementKind::Enum { .. } | StatementKind::Match { .. } => {
+                unsupported("Structs, enums and 'match'")
+            }
 
-            StatementKind::Declare { name, value, .. } => self.get_declare(name, value),
+            StatementKind::Declare {
+                name,
+                type_hint,
+                value,
+                binding,
+            } => self.get_declare(name, binding.unwrap(), type_hint, value),
             StatementKind::Assign { dest, src } => self.get_assign(dest, src),
 
-            StatementKind::If { cond, block } => self.get_if(cond, block, statement),
+            StatementKind::If { cond, block } => self.get_if(cond, block),
             StatementKind::IfElse {
                 cond,
                 true_block,
                 false_block,
-            } => self.get_if_else(cond, true_block, false_block, statement),
+            } => self.get_if_else(cond, true_block, false_block),
             StatementKind::Loop { block } => self.get_loop(block),
-            StatementKind::While { cond, block } => self.get_while(cond, block, statement),
-            StatementKind::Continue => self.get_continue(),
-            StatementKind::Break => self.get_break(),
+            StatementKind::While { cond, block } => self.get_while(cond, block),
+            StatementKind::For {
+                var,
+                binding,
+                iterable,
+                block,
+            } => self.get_for(var, binding.unwrap(), iterable, block),
+            StatementKind::Continue => {
+                self.get_continue();
+                Ok(())
+            }
+            StatementKind::Break => {
+                self.get_break();
+                Ok(())
+            }
         }
     }
 
-    fn get_array_lit(&mut self, contents: &Vec<Expression>) -> Value {
-        let mut string = String::new();
-        for expr in contents {
-            match &expr.r#type {
-                PrimitiveType::Int => string.push_str(&int),
-                _ => panic!("Array must be of int literals"),
-            }
-            string.push_str(", ")
+    fn get_block(&mut self, block: &[Statement]) -> GenResult {
+        for statement in block {
+            self.get_statement(statement)?;
         }
-        string.pop();
-        string.pop();
-        self.nums.buf += 1;
-        self.ir
-            .add_data(format!("buf_{}", self.nums.buf), string, contents.len());
-        Value::Identifier {
-            id: format!("buf_{}", self.nums.buf),
+        Ok(())
+    }
+
+    fn get_procedure(
+        &mut self,
+        name: &str,
+        args: &[Param],
+        block: &[Statement],
+        binding: Binding,
+    ) -> GenResult {
+        if args.iter().any(|arg| arg.is_ref) {
+            return unsupported("'ref' parameters");
+        }
+        if self.in_procedure {
+            return unsupported("Nested procedures");
         }
+        if args
+            .iter()
+            .any(|arg| matches!(arg.r#type, Some(PrimitiveType::Pr(..))))
+        {
+            return unsupported("Procedure values");
+        }
+
+        // declared before the body so that recursive calls are recognized
+        self.procedures.insert(name.to_string());
+
+        // procedures cannot see the variables of the enclosing code
+        let code = mem::take(&mut self.ir.code);
+        let variables = mem::take(&mut self.variables);
+        let loops = mem::take(&mut self.loops);
+        self.in_procedure = true;
+
+        // the procedure itself takes the first slot, which is only used to
+        // call it by name
+        self.declare_variable(name, 0);
+        let args = args
+            .iter()
+            .enumerate()
+            .map(|(slot, arg)| Value::Identifier {
+                id: self.declare_variable(&arg.name, slot + 1),
+            })
+            .collect();
+
+        self.ir.add_instruction(IrInstruction::Prologue {
+            label: name.to_string(),
+            args,
+        });
+        for statement in block {
+            self.get_statement(statement)?;
+        }
+        self.ir.add_instruction(IrInstruction::Epilogue {
+            label: name.to_string(),
+        });
+
+        let procedure_code = mem::replace(&mut self.ir.code, code);
+        self.procedures_code.extend(procedure_code);
+        self.variables = variables;
+        self.loops = loops;
+        self.in_procedure = false;
+        self.declare_variable(name, binding.slot);
+        Ok(())
     }
 
-    fn get_call(&mut self, name: &str, args: &Vec<Expression>) {
+    fn get_return(&mut self, value: &Expression) -> GenResult {
+        let src = self.get_expression(value)?;
+
+        self.ir.add_instruction(IrInstruction::Ret {
+            src,
+            r#type: get_type(value),
+        });
+        Ok(())
+    }
+
+    fn get_call(&mut self, name: &str, args: &[Expression], dest: Option<Value>) -> GenResult {
+        if matches!(name, "push" | "pop" | "insert" | "remove") {
+            return self.get_array_call(name, args, dest);
+        }
+        if matches!(name, "()" | "map" | "filter" | "fold") {
+            return unsupported("Procedure values");
+        }
+
+        // arguments are passed by value to procedures, builtins do not modify them
+        let is_procedure = self.procedures.contains(name);
         let mut arg_vals = Vec::new();
         for arg in args {
-            arg_vals.push(self.get_expression(arg));
+            arg_vals.push(if is_procedure {
+                self.get_owned_expression(arg)?
+            } else {
+                self.get_expression(arg)?
+            });
         }
 
-        match name {
-            "exit" => self.ir.add_instruction(IrInstruction::Param {
-                src: arg_vals[0].clone(),
-                r#type: PrimitiveType::Int,
-            }),
-            "print" => {
-                self.ir.add_instruction(IrInstruction::Param {
-                    src: Value::Constant {
-                        value: "1".to_string(),
-                    },
-                    r#type: PrimitiveType::Int,
-                });
-                self.ir.add_instruction(IrInstruction::Param {
-                    src: arg_vals[0].clone(),
-                    r#type: PrimitiveType::Arr(Box::new(PrimitiveType::Char)),
-                });
-
-                let mut string_size = 0; // get size of buffer from ir.data
-                for buffer in &self.ir.data {
-                    if let Value::Identifier { ref id } = arg_vals[0] {
-                        if *id == buffer.label {
-                            string_size = buffer.size;
-                            break;
-                        }
-                    }
-                }
-                self.ir.add_instruction(IrInstruction::Param {
-                    src: Value::Constant {
-                        value: format!("{}", string_size),
-                    },
-                    r#type: PrimitiveType::Int,
-                });
+        let label = match name {
+            "print" => match get_type(&args[0]) {
+                PrimitiveType::Int => "print_int",
+                PrimitiveType::Bool => "print_bool",
+                PrimitiveType::Char => "print_char",
+                PrimitiveType::Arr(..) => "print_string",
+                PrimitiveType::Record(..) => return unsupported("Structs, enums and 'match'"),
+                PrimitiveType::Enum(..) => return unsupported("Structs, enums and 'match'"),
+                PrimitiveType::Pr(..) => return unsupported("Procedure values"),
+                PrimitiveType::Var(_) => unreachable!(),
             }
-            _ => panic!("Undefined function"),
+            .to_string(),
+            "len" => "array_len".to_string(),
+            _ => name.to_string(),
         };
+
+        for (arg, arg_val) in args.iter().zip(arg_vals) {
+            self.ir.add_instruction(IrInstruction::Param {
+                src: arg_val,
+                r#type: get_type(arg),
+            });
+        }
+        self.ir.add_instruction(IrInstruction::Call { dest, label });
+        Ok(())
+    }
+
+    /// The builtins that change an array get it without a copy. `push` and
+    /// `insert` may move it to make room, so it is stored back afterwards.
+    fn get_array_call(
+        &mut self,
+        name: &str,
+        args: &[Expression],
+        dest: Option<Value>,
+    ) -> GenResult {
+        let PrimitiveType::Arr(elem_type) = get_type(&args[0]) else {
+            unreachable!()
+        };
+
+        let mut arg_vals = vec![(self.get_expression(&args[0])?, get_type(&args[0]))];
+        for arg in &args[1..] {
+            arg_vals.push((self.get_owned_expression(arg)?, get_type(arg)));
+        }
+        arg_vals.push((
+            Value::Constant {
+                value: elem_type.get_size().to_string(),
+            },
+            PrimitiveType::Int,
+        ));
+
+        if name == "push" || name == "insert" {
+            let array = self.new_tmp();
+            self.get_runtime_call(&format!("array_{}", name), arg_vals, Some(array.clone()));
+            let dest = self.get_place(&args[0])?;
+            self.ir.add_instruction(IrInstruction::Ass {
+                dest,
+                src: array,
+                r#type: get_type(&args[0]),
+            });
+        } else {
+            self.get_runtime_call(&format!("array_{}", name), arg_vals, dest);
+        }
+        Ok(())
+    }
+
+    /// Calls a procedure of the runtime with arguments that are already
+    /// evaluated.
+    fn get_runtime_call(
+        &mut self,
+        label: &str,
+        args: Vec<(Value, PrimitiveType)>,
+        dest: Option<Value>,
+    ) {
+        for (src, r#type) in args {
+            self.ir
+                .add_instruction(IrInstruction::Param { src, r#type });
+        }
         self.ir.add_instruction(IrInstruction::Call {
-            label: name.to_string(),
+            dest,
+            label: label.to_string(),
         });
     }
 
-    fn get_declare(&mut self, name: &String, value: &Option<Expression>) {
-        let src_val = self.get_expression(&value.unwrap());
+    fn get_declare(
+        &mut self,
+        name: &str,
+        binding: Binding,
+        type_hint: &Option<PrimitiveType>,
+        value: &Option<Expression>,
+    ) -> GenResult {
+        let (src_val, r#type) = if let Some(value) = value {
+            (self.get_owned_expression(value)?, get_type(value))
+        } else {
+            (
+                Value::Constant {
+                    value: "0".to_string(),
+                },
+                type_hint
+                    .clone()
+                    .expect("Uninitialized variable without type"),
+            )
+        };
+
         let name_val = Value::Identifier {
-            id: name.to_string(),
+            id: self.declare_variable(name, binding.slot),
         };
 
         self.ir.add_instruction(IrInstruction::Ass {
             dest: name_val,
             src: src_val,
-            r#type: value.unwrap().r#type.unwrap(),
+            r#type,
         });
+        Ok(())
     }
 
-    fn get_assign(&mut self, dest: &Expression, src: &Expression) {
-        let src_val = self.get_expression(src);
-        let dest_val = self.get_expression(dest);
+    fn get_assign(&mut self, dest: &Expression, src: &Expression) -> GenResult {
+        let src_val = self.get_owned_expression(src)?;
+        let dest_val = self.get_place(dest)?;
 
         self.ir.add_instruction(IrInstruction::Ass {
             dest: dest_val,
             src: src_val,
-            r#type: dest_val.r#type.unwrap(),
+            r#type: get_type(src),
         });
+        Ok(())
     }
 
-    fn get_operation(
-        &mut self,
-        src1: &Expression,
-        op: &Op,
-        src2: &Expression,
-        r#type: PrimitiveType,
-    ) {
-        self.nums.tmp += 1;
-
-        let dest = Value::Identifier {
-            id: format!("t{}", self.nums.tmp),
-        };
-
-        let arg1 = self.get_value(src1);
-        let arg2 = self.get_value(src2);
-
-        self.ir.add_instruction(IrInstruction::Op {
-            dest: dest.clone(),
-            src1: arg1.clone(),
-            op: op.clone(),
-            src2: arg2.clone(),
-            r#type,
-        });
+    /// Where a variable or an element of an array is stored.
+    fn get_place(&mut self, place: &Expression) -> GenResult<Value> {
+        match &place.kind {
+            ExpressionKind::Id(_) => Ok(Value::Identifier {
+                id: self.get_variable(place.binding.unwrap())?,
+            }),
+            ExpressionKind::Call(name, args) if name == "[]" => self.get_idx(&args[0], &args[1]),
+            ExpressionKind::Field(..) => unsupported("Structs, enums and 'match'"),
+            _ => panic!("Invalid assignment"),
+        }
     }
 
-    fn get_if(&mut self, cond: &Expression, seq: &Expression, expr: &Expression) {
-        // todo: remove current_ifs
+    fn get_if(&mut self, cond: &Expression, block: &[Statement]) -> GenResult {
         self.nums.ifs += 1;
         let current_ifs = self.nums.ifs;
 
-        self.get_condition(cond, &expr.kind, current_ifs);
-
-        let seq_value = self.get_value(seq);
+        self.get_condition(cond, format!("end_if_{}", current_ifs))?;
+        self.get_block(block)?;
 
         self.ir
             .add_instruction(IrInstruction::Label(format!("end_if_{}", current_ifs)));
+        Ok(())
     }
 
     fn get_if_else(
         &mut self,
         cond: &Expression,
-        if_seq: &Expression,
-        else_seq: &Expression,
-        expr: &Expression,
-    ) {
+        true_block: &[Statement],
+        false_block: &[Statement],
+    ) -> GenResult {
         self.nums.ifs += 1;
         let current_ifs = self.nums.ifs;
 
-        self.get_condition(cond, &expr.kind, current_ifs);
+        self.get_condition(cond, format!("else_{}", current_ifs))?;
 
-        let if_seq_value = self.get_value(if_seq);
+        self.get_block(true_block)?;
         self.ir.add_instruction(IrInstruction::Goto {
             label: format!("end_if_{}", current_ifs),
         });
 
         self.ir
             .add_instruction(IrInstruction::Label(format!("else_{}", current_ifs)));
-        self.get_value(else_seq);
+        self.get_block(false_block)?;
         self.ir
             .add_instruction(IrInstruction::Label(format!("end_if_{}", current_ifs)));
+        Ok(())
+    }
 
-        if_seq_value
-    }
-
-    fn get_condition(&mut self, cond: &Expression, kind: &ExpressionKind, num: u32) {
-        if let ExpressionKind::Op(src1, op, src2) = &cond.kind {
-            if *op != Op::And && *op != Op::Or {
-                let val1 = self.get_value(src1);
-                let val2 = self.get_value(src2);
-                return self.ir.add_instruction(IrInstruction::IfGoto {
-                    src1: val1,
-                    src2: val2,
-                    cond: match op {
-                        Op::Eq => Op::Neq,
-                        Op::Neq => Op::Eq,
-                        Op::Lt => Op::Geq,
-                        Op::Leq => Op::Gt,
-                        Op::Gt => Op::Leq,
-                        Op::Geq => Op::Lt,
-                        _ => panic!("Must be a boolean expression"),
-                    },
-                    label: match kind {
-                        ExpressionKind::If(..) => format!("end_if_{}", num),
-                        ExpressionKind::IfElse(..) => format!("else_{}", num),
-                        ExpressionKind::While(..) => format!("loop_end_{}", num),
-                        _ => panic!("Not a condition expression"),
-                    },
-                    r#type: src1.r#type.clone(),
-                });
+    /// Jumps to `label` when the condition is false.
+    fn get_condition(&mut self, cond: &Expression, label: String) -> GenResult {
+        if let ExpressionKind::Call(name, args) = &cond.kind {
+            if let Some(op) = Op::from_name(name) {
+                if op.is_relational() {
+                    let src1 = self.get_expression(&args[0])?;
+                    let src2 = self.get_expression(&args[1])?;
+                    self.ir.add_instruction(IrInstruction::IfGoto {
+                        src1,
+                        src2,
+                        cond: op.negate(),
+                        label,
+                        r#type: get_type(&args[0]),
+                    });
+                    return Ok(());
+                }
             }
         }
 
-        let cond_value = self.get_value(cond);
+        let cond_value = self.get_expression(cond)?;
         self.ir.add_instruction(IrInstruction::IfGoto {
             src1: cond_value,
             src2: Value::Constant {
                 value: "0".to_string(),
             },
             cond: Op::Eq,
-            label: match kind {
-                ExpressionKind::If(..) => format!("end_if_{}", num),
-                ExpressionKind::IfElse(..) => format!("else_{}", num),
-                ExpressionKind::While(..) => format!("loop_end_{}", num),
-                _ => panic!("Not a condition expression"),
-            },
-            r#type: cond.r#type.clone(),
+            label,
+            r#type: get_type(cond),
         });
+        Ok(())
     }
 
-    fn get_loop(&mut self, block: &Vec<Statement>) {
+    fn get_loop(&mut self, block: &[Statement]) -> GenResult {
         self.nums.loops += 1;
         let current_loops = self.nums.loops;
+        self.loops.push(current_loops);
 
         self.ir
             .add_instruction(IrInstruction::Label(format!("loop_{}", current_loops)));
-        for statement in block {
-            self.get_statement(statement);
-        }
-
+        self.get_block(block)?;
         self.ir.add_instruction(IrInstruction::Goto {
             label: format!("loop_{}", current_loops),
         });
+        self.ir
+            .add_instruction(IrInstruction::Label(format!("loop_end_{}", current_loops)));
+
+        self.loops.pop();
+        Ok(())
+    }
+
+    fn get_while(&mut self, cond: &Expression, block: &[Statement]) -> GenResult {
+        self.nums.loops += 1;
+        let current_loops = self.nums.loops;
+        self.loops.push(current_loops);
 
+        self.ir
+            .add_instruction(IrInstruction::Label(format!("loop_{}", current_loops)));
+        self.get_condition(cond, format!("loop_end_{}", current_loops))?;
+        self.get_block(block)?;
+        self.ir.add_instruction(IrInstruction::Goto {
+            label: format!("loop_{}", current_loops),
+        });
         self.ir
             .add_instruction(IrInstruction::Label(format!("loop_end_{}", current_loops)));
+
+        self.loops.pop();
+        Ok(())
     }
 
-    fn get_while(&mut self, cond: &Expression, seq: &Expression, expr: &Expression) {
+    /// The counter is advanced before the block runs, so `continue` can jump
+    /// back to the start like in the other loops.
+    fn get_for(
+        &mut self,
+        var: &str,
+        binding: Binding,
+        iterable: &Expression,
+        block: &[Statement],
+    ) -> GenResult {
+        let int = PrimitiveType::Int;
+        let (counter, end, array) = match &iterable.kind {
+            ExpressionKind::Call(name, bounds) if name == ".." => {
+                let counter = self.new_tmp();
+                let start = self.get_expression(&bounds[0])?;
+                self.ir.add_instruction(IrInstruction::Ass {
+                    dest: counter.clone(),
+                    src: start,
+                    r#type: int.clone(),
+                });
+                // a variable bound could be changed by the block
+                let mut end = self.get_expression(&bounds[1])?;
+                if let Value::Identifier { .. } = end {
+                    let tmp = self.new_tmp();
+                    self.ir.add_instruction(IrInstruction::Ass {
+                        dest: tmp.clone(),
+                        src: end,
+                        r#type: int.clone(),
+                    });
+                    end = tmp;
+                }
+                (counter, end, None)
+            }
+            _ => {
+                // the loop sees the array as it was before the first iteration
+                let array = self.get_owned_expression(iterable)?;
+                let len = self.new_tmp();
+                self.ir.add_instruction(IrInstruction::Param {
+                    src: array.clone(),
+                    r#type: get_type(iterable),
+                });
+                self.ir.add_instruction(IrInstruction::Call {
+                    dest: Some(len.clone()),
+                    label: "array_len".to_string(),
+                });
+
+                let counter = self.new_tmp();
+                self.ir.add_instruction(IrInstruction::Ass {
+                    dest: counter.clone(),
+                    src: Value::Constant {
+                        value: "0".to_string(),
+                    },
+                    r#type: int.clone(),
+                });
+                (counter, len, Some(array))
+            }
+        };
+
         self.nums.loops += 1;
         let current_loops = self.nums.loops;
+        self.loops.push(current_loops);
 
         self.ir
             .add_instruction(IrInstruction::Label(format!("loop_{}", current_loops)));
+        self.ir.add_instruction(IrInstruction::IfGoto {
+            src1: counter.clone(),
+            src2: end,
+            cond: Op::Geq,
+            label: format!("loop_end_{}", current_loops),
+            r#type: int.clone(),
+        });
 
-        self.get_condition(cond, &expr.kind, current_loops);
+        let var = Value::Identifier {
+            id: self.declare_variable(var, binding.slot),
+        };
+        let (src, r#type) = match array {
+            Some(Value::Identifier { id }) => {
+                let PrimitiveType::Arr(elem_type) = get_type(iterable) else {
+                    unreachable!()
+                };
+                let elem = Value::MemPos {
+                    id,
+                    offset: Box::new(counter.clone()),
+                };
+                (elem, *elem_type)
+            }
+            Some(_) => panic!("Only arrays can be iterated over"),
+            None => (counter.clone(), int.clone()),
+        };
+        self.ir.add_instruction(IrInstruction::Ass {
+            dest: var,
+            src,
+            r#type,
+        });
+        self.ir.add_instruction(IrInstruction::Op {
+            dest: counter.clone(),
+            src1: counter,
+            op: Op::Add,
+            src2: Value::Constant {
+                value: "1".to_string(),
+            },
+            r#type: int,
+        });
+        for statement in block {
+            self.get_statement(statement)?;
+        }
 
-        let seq_value = self.get_value(seq);
         self.ir.add_instruction(IrInstruction::Goto {
             label: format!("loop_{}", current_loops),
         });
-
         self.ir
             .add_instruction(IrInstruction::Label(format!("loop_end_{}", current_loops)));
 
-        seq_value
+        self.loops.pop();
+        Ok(())
     }
 
     fn get_break(&mut self) {
         self.ir.add_instruction(IrInstruction::Goto {
-            label: format!("loop_end_{}", self.nums.loops),
+            label: format!(
+                "loop_end_{}",
+                self.loops.last().expect("Break outside loop")
+            ),
         });
     }
 
     fn get_continue(&mut self) {
         self.ir.add_instruction(IrInstruction::Goto {
-            label: format!("loop_{}", self.nums.loops),
+            label: format!("loop_{}", self.loops.last().expect("Continue outside loop")),
         });
     }
 
-    fn get_not(&mut self, arg: &Expression) -> Value {
-        self.nums.tmp += 1;
-        let destination = Value::Identifier {
-            id: format!("t{}", self.nums.tmp),
-        };
-
-        let val = self.get_value(arg);
+    fn get_not(&mut self, arg: &Expression) -> GenResult<Value> {
+        let dest = self.new_tmp();
+        let src = self.get_expression(arg)?;
 
         self.ir.add_instruction(IrInstruction::Not {
-            dest: destination.clone(),
-            src: val.clone(),
-            r#type: arg.r#type.clone(),
+            dest: dest.clone(),
+            src,
+            r#type: get_type(arg),
         });
 
-        destination
+        Ok(dest)
     }
 
-    fn get_idx(&mut self, id: &Expression, offset: &Expression) -> Value {
-        let offset_val = self.get_value(offset);
-        Value::MemPos {
-            id: id.to_str().to_string(),
-            offset: if matches!(offset_val, Value::MemPos { .. }) {
-                self.nums.tmp += 1;
+    fn get_operation(
+        &mut self,
+        src1: &Expression,
+        op: Op,
+        src2: &Expression,
+        r#type: PrimitiveType,
+    ) -> GenResult<Value> {
+        let src1 = self.get_expression(src1)?;
+        let src2 = self.get_expression(src2)?;
+        let dest = self.new_tmp();
 
-                let destination = Value::Identifier {
-                    id: format!("t{}", self.nums.tmp),
-                };
+        self.ir.add_instruction(IrInstruction::Op {
+            dest: dest.clone(),
+            src1,
+            op,
+            src2,
+            r#type,
+        });
 
-                self.ir.add_instruction(IrInstruction::Ass {
-                    dest: destination.clone(),
-                    src: offset_val,
-                    r#type: offset.r#type.clone(),
-                });
+        Ok(dest)
+    }
 
-                Box::new(destination)
-            } else {
-                Box::new(self.get_value(offset))
+    fn get_negation(&mut self, arg: &Expression) -> GenResult<Value> {
+        let src2 = self.get_expression(arg)?;
+        let dest = self.new_tmp();
+
+        self.ir.add_instruction(IrInstruction::Op {
+            dest: dest.clone(),
+            src1: Value::Constant {
+                value: "0".to_string(),
             },
+            op: Op::Sub,
+            src2,
+            r#type: PrimitiveType::Int,
+        });
+
+        Ok(dest)
+    }
+
+    fn get_idx(&mut self, array: &Expression, offset: &Expression) -> GenResult<Value> {
+        let Value::Identifier { id } = self.get_expression(array)? else {
+            panic!("Only arrays can be indexed");
+        };
+
+        Ok(Value::MemPos {
+            id,
+            offset: Box::new(self.get_expression(offset)?),
+        })
+    }
+
+    fn get_slice(&mut self, array: &Expression, range: &Expression) -> GenResult<Value> {
+        let PrimitiveType::Arr(elem_type) = get_type(array) else {
+            unreachable!()
+        };
+        let ExpressionKind::Call(_, bounds) = &range.kind else {
+            unreachable!()
+        };
+
+        let args = vec![
+            (self.get_expression(array)?, get_type(array)),
+            (self.get_expression(&bounds[0])?, PrimitiveType::Int),
+            (self.get_expression(&bounds[1])?, PrimitiveType::Int),
+            (
+                Value::Constant {
+                    value: elem_type.get_size().to_string(),
+                },
+                PrimitiveType::Int,
+            ),
+        ];
+        let dest = self.new_tmp();
+        self.get_runtime_call("array_slice", args, Some(dest.clone()));
+        Ok(dest)
+    }
+
+    fn get_array(&mut self, contents: &[Expression]) -> GenResult<Value> {
+        let elem_type = contents.first().map_or(PrimitiveType::Int, get_type);
+        let array = self.new_tmp();
+
+        self.get_array_new(
+            Value::Constant {
+                value: contents.len().to_string(),
+            },
+            &elem_type,
+            array.clone(),
+        );
+
+        for (i, expr) in contents.iter().enumerate() {
+            let src = self.get_owned_expression(expr)?;
+            let Value::Identifier { id } = &array else {
+                unreachable!()
+            };
+            self.ir.add_instruction(IrInstruction::Ass {
+                dest: Value::MemPos {
+                    id: id.clone(),
+                    offset: Box::new(Value::Constant {
+                        value: i.to_string(),
+                    }),
+                },
+                src,
+                r#type: elem_type.clone(),
+            });
+        }
+
+        Ok(array)
+    }
+
+    fn get_array_new(&mut self, len: Value, elem_type: &PrimitiveType, dest: Value) {
+        self.ir.add_instruction(IrInstruction::Param {
+            src: len,
+            r#type: PrimitiveType::Int,
+        });
+        self.ir.add_instruction(IrInstruction::Param {
+            src: Value::Constant {
+                value: elem_type.get_size().to_string(),
+            },
+            r#type: PrimitiveType::Int,
+        });
+        self.ir.add_instruction(IrInstruction::Call {
+            dest: Some(dest),
+            label: "array_new".to_string(),
+        });
+    }
+
+    fn get_string_lit(&mut self, lit: &str) -> Value {
+        let string = unescape_string_lit(lit);
+        let bytes: Vec<String> = string.bytes().map(|byte| byte.to_string()).collect();
+
+        self.nums.buf += 1;
+        self.ir.add_data(
+            format!("buf_{}", self.nums.buf),
+            bytes.join(", "),
+            bytes.len(),
+        );
+
+        Value::Identifier {
+            id: format!("buf_{}", self.nums.buf),
         }
     }
 
-    fn get_expression(&mut self, expr: &Expression) -> Value {
-        match &expr.kind {
-            ExpressionKind::Id(id) => Value::Identifier { id: id.to_string() },
-            ExpressionKind::Lit(lit) => match expr.r#type.unwrap() {
-                PrimitiveType::Int | PrimitiveType::Char => Value::Constant {
+    /// Arrays have value semantics, so an array that is already reachable from
+    /// somewhere else is copied before being stored. The arrays nested in it
+    /// are copied too.
+    fn get_owned_expression(&mut self, expr: &Expression) -> GenResult<Value> {
+        let value = self.get_expression(expr)?;
+
+        let PrimitiveType::Arr(mut elem_type) = get_type(expr) else {
+            return Ok(value);
+        };
+        if !matches!(expr.kind, ExpressionKind::Id(..) | ExpressionKind::Lit(..))
+            && !matches!(&expr.kind, ExpressionKind::Call(name, args) if name == "[]" && !args[1].is_range())
+        {
+            return Ok(value);
+        }
+
+        let mut depth = 0;
+        while let PrimitiveType::Arr(inner_type) = *elem_type {
+            elem_type = inner_type;
+            depth += 1;
+        }
+
+        let dest = self.new_tmp();
+        self.ir.add_instruction(IrInstruction::Param {
+            src: value,
+            r#type: get_type(expr),
+        });
+        self.ir.add_instruction(IrInstruction::Param {
+            src: Value::Constant {
+                value: elem_type.get_size().to_string(),
+            },
+            r#type: PrimitiveType::Int,
+        });
+        let label = if depth == 0 {
+            "array_copy"
+        } else {
+            self.ir.add_instruction(IrInstruction::Param {
+                src: Value::Constant {
+                    value: depth.to_string(),
+                },
+                r#type: PrimitiveType::Int,
+            });
+            "array_copy_nested"
+        };
+        self.ir.add_instruction(IrInstruction::Call {
+            dest: Some(dest.clone()),
+            label: label.to_string(),
+        });
+
+        Ok(dest)
+    }
+
+    fn get_expression(&mut self, expr: &Expression) -> GenResult<Value> {
+        let value = match &expr.kind {
+            ExpressionKind::Id(_) if matches!(get_type(expr), PrimitiveType::Pr(..)) => {
+                return unsupported("Procedure values")
+            }
+            ExpressionKind::Id(_) => Value::Identifier {
+                id: self.get_variable(expr.binding.unwrap())?,
+            },
+            ExpressionKind::Lit(lit) => match get_type(expr) {
+                PrimitiveType::Int => Value::Constant {
                     value: lit.to_string(),
                 },
+                PrimitiveType::Char => Value::Constant {
+                    value: (lit.chars().nth(1).unwrap() as u32).to_string(),
+                },
                 PrimitiveType::Bool => Value::Constant {
                     value: if lit == "true" { "1" } else { "0" }.to_string(),
                 },
-                PrimitiveType::Arr(..) => panic!("Lit expressions cannot have type array") 
+                PrimitiveType::Arr(..) => self.get_string_lit(lit),
+                PrimitiveType::Record(..)
+                | PrimitiveType::Enum(..)
+                | PrimitiveType::Pr(..)
+                | PrimitiveType::Var(_) => unreachable!(),
             },
-            ExpressionKind::Array(contents) => {
-                    self.nums.buf += 1;
-                    self.ir.add_data(
-                        format!("buf_{}", self.nums.buf),
-                        lit.to_string() + ", 10",
-                        lit.len() - 1,
-                    );
-                    Value::Identifier {
-                        id: format!("buf_{}", self.nums.buf),
-                    }
+            ExpressionKind::Array(contents) => self.get_array(contents)?,
+            ExpressionKind::Field(..) => return unsupported("Structs, enums and 'match'"),
+            ExpressionKind::Call(name, args) => match (name.as_str(), args.len()) {
+                ("!", 1) => self.get_not(&args[0])?,
+                ("-", 1) => self.get_negation(&args[0])?,
+                ("[]", 2) if args[1].is_range() => self.get_slice(&args[0], &args[1])?,
+                ("+", 2) if matches!(get_type(expr), PrimitiveType::Arr(..)) => {
+                    let PrimitiveType::Arr(elem_type) = get_type(expr) else {
+                        unreachable!()
+                    };
+                    let arrays = vec![
+                        (self.get_expression(&args[0])?, get_type(expr)),
+                        (self.get_expression(&args[1])?, get_type(expr)),
+                        (
+                            Value::Constant {
+                                value: elem_type.get_size().to_string(),
+                            },
+                            PrimitiveType::Int,
+                        ),
+                    ];
+                    let dest = self.new_tmp();
+                    self.get_runtime_call("array_concat", arrays, Some(dest.clone()));
+                    dest
+                }
+                ("[]", 2) => {
+                    let src = self.get_idx(&args[0], &args[1])?;
+                    let dest = self.new_tmp();
+                    self.ir.add_instruction(IrInstruction::Ass {
+                        dest: dest.clone(),
+                        src,
+                        r#type: get_type(expr),
+                    });
+                    dest
                 }
-            ExpressionKind::Call(name, args) => 
+                ("char_to_int" | "int_to_char", 1) => self.get_expression(&args[0])?,
+                (name, 2) if Op::from_name(name).is_some() => self.get_operation(
+                    &args[0],
+                    Op::from_name(name).unwrap(),
+                    &args[1],
+                    get_type(expr),
+                )?,
+                _ => {
+                    let dest = self.new_tmp();
+                    self.get_call(name, args, Some(dest.clone()))?;
+                    dest
+                }
+            },
+        };
+        Ok(value)
+    }
+
+    fn new_tmp(&mut self) -> Value {
+        self.nums.tmp += 1;
+        Value::Identifier {
+            id: format!("t{}", self.nums.tmp),
         }
     }
+
+    /// Gives the variable in the slot a name in the IR, which is a new one if
+    /// it hides a variable in scope. The slots after it belong to blocks
+    /// that ended.
+    fn declare_variable(&mut self, name: &str, slot: usize) -> String {
+        self.variables.truncate(slot);
+        let ir_name = if self.variables.iter().any(|var| var.0 == name) {
+            self.nums.vars += 1;
+            format!("{}.{}", name, self.nums.vars)
+        } else {
+            name.to_string()
+        };
+
+        self.variables.push((name.to_string(), ir_name.clone()));
+
+        ir_name
+    }
+
+    fn get_variable(&self, binding: Binding) -> GenResult<String> {
+        if self.in_procedure && binding.depth == 0 {
+            return unsupported("Procedures that use outer variables");
+        }
+        Ok(self.variables[binding.slot].1.clone())
+    }
+}
+
+fn get_type(expr: &Expression) -> PrimitiveType {
+    expr.r#type
+        .clone()
+        .expect("Expressions must be typed before generating IR")
 }
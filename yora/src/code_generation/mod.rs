@@ -0,0 +1,3 @@
+pub mod asm_gen;
+pub mod ir_gen;
+pub mod optimizer;
@@ -1,87 +1,421 @@
-use crate::ir_gen::ir::Ir;
+use std::collections::HashMap;
+
+use crate::code_generation::ir_gen::ir::*;
+
+pub struct Passes {
+    pub constant_folding: bool,
+    pub copy_propagation: bool,
+    pub dead_temporaries: bool,
+    pub unreachable_labels: bool,
+}
+
+impl Passes {
+    pub fn all() -> Passes {
+        Passes {
+            constant_folding: true,
+            copy_propagation: true,
+            dead_temporaries: true,
+            unreachable_labels: true,
+        }
+    }
+
+    pub fn none() -> Passes {
+        Passes {
+            constant_folding: false,
+            copy_propagation: false,
+            dead_temporaries: false,
+            unreachable_labels: false,
+        }
+    }
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Self::all()
+    }
+}
 
 pub fn optimize(ir: Ir) -> Ir {
-    //let mut optimized_ir = ir;
+    optimize_with(ir, &Passes::all())
+}
+
+pub fn optimize_with(ir: Ir, passes: &Passes) -> Ir {
+    let mut optimized_ir = ir;
 
-    //optimized_ir = remove_self_assign(optimized_ir);
-    //optimized_ir = constant_propagation(optimized_ir);
+    // every pass can expose more work for the others, so run until nothing changes
+    loop {
+        let previous_code = optimized_ir.code.clone();
 
-    //optimized_ir
-    ir
+        if passes.constant_folding {
+            optimized_ir.code = constant_folding(optimized_ir.code);
+        }
+        if passes.copy_propagation {
+            optimized_ir.code = copy_propagation(optimized_ir.code);
+        }
+        if passes.dead_temporaries {
+            optimized_ir.code = remove_dead_temporaries(optimized_ir.code);
+        }
+        if passes.unreachable_labels {
+            optimized_ir.code = remove_unreachable_code(optimized_ir.code);
+        }
+
+        if optimized_ir.code == previous_code {
+            return optimized_ir;
+        }
+    }
 }
 
-/*fn remove_self_assign(ir: Vec<Ir>) -> Vec<Ir> {
-    let mut new_ir = ir.clone();
-    let mut num_removed = 0;
+/// Evaluates operations, negations and conditional jumps whose operands are all constants.
+fn constant_folding(ir: Vec<IrInstruction>) -> Vec<IrInstruction> {
+    let mut new_ir = Vec::with_capacity(ir.len());
 
-    for (i, instruction) in ir.iter().enumerate() {
-        if let Ir::Op { dest, src, op } = instruction {
-            if *op == Op::Assign && dest == src {
-                new_ir.remove(i - num_removed);
-                num_removed += 1;
-            }
+    for instruction in ir {
+        match instruction {
+            IrInstruction::Op {
+                ref dest,
+                ref src1,
+                ref op,
+                ref src2,
+                ref r#type,
+            } => match fold(get_constant(src1), op, get_constant(src2)) {
+                Some(value) => new_ir.push(IrInstruction::Ass {
+                    dest: dest.clone(),
+                    src: Value::Constant {
+                        value: value.to_string(),
+                    },
+                    r#type: r#type.clone(),
+                }),
+                None => new_ir.push(instruction),
+            },
+            IrInstruction::Not {
+                ref dest,
+                ref src,
+                ref r#type,
+            } => match get_constant(src) {
+                Some(value) => new_ir.push(IrInstruction::Ass {
+                    dest: dest.clone(),
+                    src: Value::Constant {
+                        value: (value ^ 1).to_string(),
+                    },
+                    r#type: r#type.clone(),
+                }),
+                None => new_ir.push(instruction),
+            },
+            IrInstruction::IfGoto {
+                ref src1,
+                ref src2,
+                ref cond,
+                ref label,
+                ..
+            } => match fold(get_constant(src1), cond, get_constant(src2)) {
+                Some(0) => {}
+                Some(_) => new_ir.push(IrInstruction::Goto {
+                    label: label.clone(),
+                }),
+                None => new_ir.push(instruction),
+            },
+            _ => new_ir.push(instruction),
         }
     }
 
     new_ir
 }
 
-fn constant_propagation(ir: Vec<Ir>) -> Vec<Ir> {
-    let mut new_ir = ir.clone();
-    let mut num_removed = 0;
+fn fold(src1: Option<i64>, op: &Op, src2: Option<i64>) -> Option<i64> {
+    let (src1, src2) = (src1?, src2?);
+
+    Some(match op {
+        Op::Add => src1.wrapping_add(src2),
+        Op::Sub => src1.wrapping_sub(src2),
+        Op::Mul => src1.wrapping_mul(src2),
+        // division by zero is left for the program to fail on
+        Op::Div => src1.checked_div(src2)?,
+        Op::Mod => src1.checked_rem(src2)?,
+        Op::And => src1 & src2,
+        Op::Or => src1 | src2,
+        Op::Eq => (src1 == src2) as i64,
+        Op::Neq => (src1 != src2) as i64,
+        Op::Lt => (src1 < src2) as i64,
+        Op::Leq => (src1 <= src2) as i64,
+        Op::Gt => (src1 > src2) as i64,
+        Op::Geq => (src1 >= src2) as i64,
+    })
+}
 
-    for (i, instruction) in ir.iter().enumerate() {
-        if let Ir::Op { dest, src, op } = instruction {
-            if *op == Op::Assign && src.parse::<i64>().is_ok() {
-                let needed = replace_with_constants(&mut new_ir, dest, src);
-                if !needed {
-                    new_ir.remove(i - num_removed);
-                    num_removed += 1;
+/// Replaces uses of copied variables by their source inside each basic block,
+/// removes self assignments and writes the result of an operation straight into
+/// the variable it is copied to when the temporary is not used anywhere else.
+fn copy_propagation(ir: Vec<IrInstruction>) -> Vec<IrInstruction> {
+    let mut new_ir: Vec<IrInstruction> = Vec::with_capacity(ir.len());
+    let mut copies: HashMap<String, Value> = HashMap::new();
+    let uses = count_uses(&ir);
+
+    for mut instruction in ir {
+        if matches!(
+            instruction,
+            IrInstruction::Label(..)
+                | IrInstruction::Prologue { .. }
+                | IrInstruction::Epilogue { .. }
+        ) {
+            copies.clear();
+        }
+
+        replace_uses(&mut instruction, &copies);
+
+        if let Some(dest) = get_dest(&instruction) {
+            let dest = dest.clone();
+            copies.remove(&dest);
+            copies.retain(|_, src| !matches!(src, Value::Identifier { id } if *id == dest));
+        }
+
+        if let IrInstruction::Ass {
+            dest: Value::Identifier { id: dest },
+            src,
+            ..
+        } = &instruction
+        {
+            if matches!(src, Value::Identifier { id } if id == dest) {
+                continue;
+            }
+
+            if let Value::Identifier { id: src_id } = src {
+                if is_temporary(src_id) && uses.get(src_id) == Some(&1) {
+                    if let Some(previous) = new_ir.last_mut() {
+                        if retarget(previous, src_id, dest) {
+                            continue;
+                        }
+                    }
                 }
             }
+
+            if !matches!(src, Value::MemPos { .. }) {
+                copies.insert(dest.clone(), src.clone());
+            }
         }
+
+        new_ir.push(instruction);
     }
 
     new_ir
 }
 
-fn replace_with_constants(ir: &mut Vec<Ir>, source: &String, constant: &String) -> bool {
-    let mut needed = false;
+/// Makes `instruction` write to `new_dest` if it currently writes to `dest`.
+fn retarget(instruction: &mut IrInstruction, dest: &str, new_dest: &str) -> bool {
+    let current_dest = match instruction {
+        IrInstruction::Ass { dest, .. }
+        | IrInstruction::Not { dest, .. }
+        | IrInstruction::Op { dest, .. }
+        | IrInstruction::Call {
+            dest: Some(dest), ..
+        } => dest,
+        _ => return false,
+    };
+
+    if matches!(current_dest, Value::Identifier { id } if id == dest) {
+        *current_dest = Value::Identifier {
+            id: new_dest.to_string(),
+        };
+        true
+    } else {
+        false
+    }
+}
+
+/// Removes the computation of temporaries whose value is never read.
+fn remove_dead_temporaries(ir: Vec<IrInstruction>) -> Vec<IrInstruction> {
+    let uses = count_uses(&ir);
+    let is_dead = |dest: &Value| matches!(dest, Value::Identifier { id } if is_temporary(id) && !uses.contains_key(id));
+
+    let mut new_ir = Vec::with_capacity(ir.len());
+
     for instruction in ir {
         match instruction {
-            Ir::Op { src, dest, op } => match op {
-                Op::Assign | Op::Add | Op::Sub | Op::And | Op::Or | Op::Not | Op::Cmp => {
-                    if src == source {
-                        *instruction = Ir::Op {
-                            dest: dest.to_string(),
-                            src: constant.to_string(),
-                            op: op.clone(),
-                        };
-                    }
-                }
-                Op::Mul | Op::Div | Op::Mod => {
-                    if src == source || dest == source {
-                        needed = true;
-                    }
-                }
-            },
-            Ir::Exit { src } => {
-                if src == source {
-                    *instruction = Ir::Exit {
-                        src: constant.to_string(),
-                    };
+            IrInstruction::Ass { ref dest, .. } | IrInstruction::Not { ref dest, .. }
+                if is_dead(dest) => {}
+            IrInstruction::Op {
+                ref dest,
+                ref op,
+                ref src2,
+                ..
+            } if is_dead(dest)
+                && (!matches!(op, Op::Div | Op::Mod)
+                    || matches!(get_constant(src2), Some(divisor) if divisor != 0)) => {}
+            // the call itself may have side effects, only its result is discarded
+            IrInstruction::Call {
+                dest: Some(ref dest),
+                ref label,
+            } if is_dead(dest) => new_ir.push(IrInstruction::Call {
+                dest: None,
+                label: label.clone(),
+            }),
+            _ => new_ir.push(instruction),
+        }
+    }
+
+    new_ir
+}
+
+/// Removes instructions that follow an unconditional jump or return, jumps to
+/// the label right after them and labels that no jump refers to.
+fn remove_unreachable_code(ir: Vec<IrInstruction>) -> Vec<IrInstruction> {
+    let mut reachable_ir: Vec<IrInstruction> = Vec::with_capacity(ir.len());
+    let mut reachable = true;
+
+    for instruction in ir {
+        match instruction {
+            IrInstruction::Label(..)
+            | IrInstruction::Prologue { .. }
+            | IrInstruction::Epilogue { .. } => reachable = true,
+            _ if !reachable => continue,
+            IrInstruction::Goto { .. } | IrInstruction::Ret { .. } => reachable = false,
+            _ => {}
+        }
+        reachable_ir.push(instruction);
+    }
+
+    let mut new_ir: Vec<IrInstruction> = Vec::with_capacity(reachable_ir.len());
+    for (i, instruction) in reachable_ir.iter().enumerate() {
+        if let IrInstruction::Goto { label } = instruction {
+            if let Some(IrInstruction::Label(next_label)) = reachable_ir.get(i + 1) {
+                if label == next_label {
+                    continue;
                 }
             }
-            Ir::JmpCond { src, label } => {
-                if src == source {
-                    *instruction = Ir::JmpCond {
-                        src: constant.to_string(),
-                        label: label.to_string(),
-                    };
-                }
+        }
+        new_ir.push(instruction.clone());
+    }
+
+    let targets: Vec<String> = new_ir
+        .iter()
+        .filter_map(|instruction| match instruction {
+            IrInstruction::Goto { label } | IrInstruction::IfGoto { label, .. } => {
+                Some(label.clone())
             }
-            _ => {}
+            _ => None,
+        })
+        .collect();
+
+    new_ir.retain(|instruction| match instruction {
+        IrInstruction::Label(label) => targets.contains(label),
+        _ => true,
+    });
+
+    new_ir
+}
+
+fn count_uses(ir: &[IrInstruction]) -> HashMap<String, usize> {
+    let mut uses = HashMap::new();
+
+    for instruction in ir {
+        for value in get_uses(instruction) {
+            for id in get_ids(value) {
+                *uses.entry(id).or_insert(0) += 1;
+            }
+        }
+        // storing into an array element reads the array and the index
+        if let Some(Value::MemPos { .. }) = get_dest_value(instruction) {
+            for id in get_ids(get_dest_value(instruction).unwrap()) {
+                *uses.entry(id).or_insert(0) += 1;
+            }
+        }
+    }
+
+    uses
+}
+
+fn get_ids(value: &Value) -> Vec<String> {
+    match value {
+        Value::Identifier { id } => vec![id.clone()],
+        Value::Constant { .. } => Vec::new(),
+        Value::MemPos { id, offset } => {
+            let mut ids = get_ids(offset);
+            ids.push(id.clone());
+            ids
+        }
+    }
+}
+
+fn get_uses(instruction: &IrInstruction) -> Vec<&Value> {
+    match instruction {
+        IrInstruction::Ass { src, .. }
+        | IrInstruction::Not { src, .. }
+        | IrInstruction::Param { src, .. }
+        | IrInstruction::Ret { src, .. } => vec![src],
+        IrInstruction::Op { src1, src2, .. } | IrInstruction::IfGoto { src1, src2, .. } => {
+            vec![src1, src2]
         }
+        _ => Vec::new(),
+    }
+}
+
+fn get_dest_value(instruction: &IrInstruction) -> Option<&Value> {
+    match instruction {
+        IrInstruction::Ass { dest, .. }
+        | IrInstruction::Not { dest, .. }
+        | IrInstruction::Op { dest, .. }
+        | IrInstruction::Call {
+            dest: Some(dest), ..
+        } => Some(dest),
+        _ => None,
+    }
+}
+
+/// The variable written by the instruction, if it writes a whole variable.
+fn get_dest(instruction: &IrInstruction) -> Option<&String> {
+    match get_dest_value(instruction) {
+        Some(Value::Identifier { id }) => Some(id),
+        _ => None,
     }
-    needed
-}*/
+}
+
+fn replace_uses(instruction: &mut IrInstruction, copies: &HashMap<String, Value>) {
+    if copies.is_empty() {
+        return;
+    }
+
+    match instruction {
+        IrInstruction::Ass { dest, src, .. } => {
+            replace_value(src, copies);
+            if let Value::MemPos { .. } = dest {
+                replace_value(dest, copies);
+            }
+        }
+        IrInstruction::Not { src, .. }
+        | IrInstruction::Param { src, .. }
+        | IrInstruction::Ret { src, .. } => replace_value(src, copies),
+        IrInstruction::Op { src1, src2, .. } | IrInstruction::IfGoto { src1, src2, .. } => {
+            replace_value(src1, copies);
+            replace_value(src2, copies);
+        }
+        _ => {}
+    }
+}
+
+fn replace_value(value: &mut Value, copies: &HashMap<String, Value>) {
+    match value {
+        Value::Identifier { id } => {
+            if let Some(src) = copies.get(id) {
+                *value = src.clone();
+            }
+        }
+        Value::MemPos { id, offset } => {
+            // the array itself can only be replaced by another variable
+            if let Some(Value::Identifier { id: src_id }) = copies.get(id) {
+                *id = src_id.clone();
+            }
+            replace_value(offset, copies);
+        }
+        Value::Constant { .. } => {}
+    }
+}
+
+fn get_constant(value: &Value) -> Option<i64> {
+    match value {
+        Value::Constant { value } => value.parse().ok(),
+        _ => None,
+    }
+}
+
+fn is_temporary(id: &str) -> bool {
+    id.len() > 1 && id.starts_with('t') && id[1..].chars().all(|ch| ch.is_ascii_digit())
+}
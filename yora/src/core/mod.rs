@@ -19,14 +19,29 @@ pub enum PrimitiveType {
     Int,
     Char,
     Arr(Box<PrimitiveType>),
+    Record(String),
+    Enum(String),
+    /// A procedure, with the types of its arguments and of its return value.
+    Pr(Vec<PrimitiveType>, Option<Box<PrimitiveType>>),
+    /// A type the analyzer is still inferring. None are left once the
+    /// analysis succeeds.
+    Var(usize),
 }
 
 impl PrimitiveType {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(type_str: &str) -> PrimitiveType {
+        match PrimitiveType::try_from_str(type_str) {
+            Some(r#type) => r#type,
+            None => panic!("Invalid type '{type_str}'"),
+        }
+    }
+
+    pub fn try_from_str(type_str: &str) -> Option<PrimitiveType> {
         let mut type_copy = type_str;
         let mut array_count = 0;
 
-        while type_copy[type_copy.len() - 2..] == *"[]" {
+        while type_copy.ends_with("[]") {
             array_count += 1;
             type_copy = &type_copy[0..type_copy.len() - 2];
         }
@@ -35,14 +50,14 @@ impl PrimitiveType {
             "Bool" => PrimitiveType::Bool,
             "Char" => PrimitiveType::Char,
             "Int" => PrimitiveType::Int,
-            _ => panic!("Invalid type '{type_str}'"),
+            _ => return None,
         };
 
         for _ in 0..array_count {
             r#type = PrimitiveType::Arr(Box::new(r#type));
         }
 
-        r#type
+        Some(r#type)
     }
 
     pub fn as_string(&self) -> String {
@@ -51,6 +66,9 @@ impl PrimitiveType {
             PrimitiveType::Char => "Char",
             PrimitiveType::Int => "Int",
             PrimitiveType::Arr(r#type) => return format!("{}[]", r#type.deref().as_string()),
+            PrimitiveType::Record(name) | PrimitiveType::Enum(name) => name,
+            PrimitiveType::Pr(..) => return self.to_string(),
+            PrimitiveType::Var(_) => "_",
         }
         .to_string()
     }
@@ -61,6 +79,10 @@ impl PrimitiveType {
             PrimitiveType::Char => 1,
             PrimitiveType::Int => 8,
             PrimitiveType::Arr(..) => 8,
+            PrimitiveType::Record(..) => 8,
+            PrimitiveType::Enum(..) => 8,
+            PrimitiveType::Pr(..) => 8,
+            PrimitiveType::Var(_) => unreachable!(),
         }
     }
 }
@@ -74,12 +96,84 @@ impl fmt::Display for PrimitiveType {
                 PrimitiveType::Bool => "Bool",
                 PrimitiveType::Int => "Int",
                 PrimitiveType::Char => "Char",
+                // the brackets would belong to the return type
+                PrimitiveType::Arr(r#type) if matches!(**r#type, PrimitiveType::Pr(..)) => {
+                    return write!(f, "({})[]", r#type.deref());
+                }
                 PrimitiveType::Arr(r#type) => return write!(f, "{}[]", r#type.deref()),
+                PrimitiveType::Record(name) | PrimitiveType::Enum(name) => name,
+                PrimitiveType::Pr(args, ret) => {
+                    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                    write!(f, "Pr({})", args.join(", "))?;
+                    return match ret {
+                        Some(ret) => write!(f, " -> {}", ret),
+                        None => Ok(()),
+                    };
+                }
+                PrimitiveType::Var(_) => "_",
             }
         )
     }
 }
 
+/// The characters of a string literal without its quotes, with the escape
+/// codes replaced. Unknown escape codes are kept as they are, the lexer
+/// reports them.
+pub fn unescape_string_lit(lit: &str) -> String {
+    let lit = lit.strip_prefix('"').unwrap_or(lit);
+    let lit = lit.strip_suffix('"').unwrap_or(lit);
+
+    let mut string = String::new();
+    let mut char_it = lit.chars();
+    while let Some(ch) = char_it.next() {
+        if ch != '\\' {
+            string.push(ch);
+            continue;
+        }
+        match char_it.next() {
+            Some(next) => match escape_code(next) {
+                Some(escaped) => string.push(escaped),
+                None => {
+                    string.push(ch);
+                    string.push(next);
+                }
+            },
+            None => string.push(ch),
+        }
+    }
+    string
+}
+
+/// The first unknown escape code of a string literal, along with the
+/// position of its backslash in the characters of the literal.
+pub fn find_invalid_escape(lit: &str) -> Option<(usize, String)> {
+    let content = lit.strip_prefix('"').unwrap_or(lit);
+    let content = content.strip_suffix('"').unwrap_or(content);
+
+    let mut char_it = content.chars().enumerate();
+    while let Some((i, ch)) = char_it.next() {
+        if ch != '\\' {
+            continue;
+        }
+        match char_it.next() {
+            Some((_, next)) if escape_code(next).is_some() => {}
+            Some((_, next)) => return Some((i + 1, format!("\\{}", next))),
+            None => return Some((i + 1, "\\".to_string())),
+        }
+    }
+    None
+}
+
+fn escape_code(ch: char) -> Option<char> {
+    match ch {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        '0' => Some('\0'),
+        '\\' => Some('\\'),
+        _ => None,
+    }
+}
+
 pub fn is_valid_type(type_str: String) -> bool {
     matches!(type_str.as_str(), "Bool" | "Int" | "Byte")
 }
@@ -0,0 +1,503 @@
+use crate::syntax_analysis::lexer::{lex_with_comments, Comment};
+use crate::syntax_analysis::parser::expression::{Expression, ExpressionKind};
+use crate::syntax_analysis::parser::statement::{Statement, StatementKind};
+use crate::syntax_analysis::parser::{binding_power, parse};
+use crate::YoraError;
+
+const INDENT: &str = "    ";
+
+/// Writes the source back in the canonical format: blocks are indented with
+/// four spaces, binary operators are surrounded by spaces and runs of blank
+/// lines are collapsed into one. Procedures are separated from their
+/// neighbours by a blank line. Comments are kept where they were, at the
+/// indentation of the code around them.
+pub fn format(source: &str) -> Result<String, YoraError> {
+    let (tokens, comments) = lex_with_comments(source.to_string())?;
+    let ast = parse(tokens)?;
+
+    let lines: Vec<&str> = source.lines().collect();
+    let comments = comments
+        .into_iter()
+        .map(|comment| {
+            let own_line = lines[comment.line - 1]
+                .chars()
+                .take(comment.col - 1)
+                .all(char::is_whitespace);
+            (comment, own_line)
+        })
+        .collect();
+
+    let mut formatter = Formatter {
+        lines,
+        comments,
+        next_comment: 0,
+        line: 0,
+        output: String::new(),
+        at_block_start: true,
+    };
+    formatter.write_sequence(&ast, 0, usize::MAX);
+    formatter.write_gap(0, usize::MAX, 0, false, true);
+
+    Ok(formatter.output)
+}
+
+struct Formatter<'a> {
+    lines: Vec<&'a str>,
+    // every comment, and whether it is alone on its line
+    comments: Vec<(Comment, bool)>,
+    next_comment: usize,
+    // the last line of the source that has been written
+    line: usize,
+    output: String,
+    // nothing has been written since the header of the current block
+    at_block_start: bool,
+}
+
+impl Formatter<'_> {
+    /// Writes the statements of a block. `end` is the line of the statement
+    /// that follows the block, the comments of the block can't go past it.
+    fn write_sequence(&mut self, sequence: &[Statement], depth: usize, end: usize) {
+        for (i, statement) in sequence.iter().enumerate() {
+            let separated = i > 0 && (is_procedure(&sequence[i - 1]) || is_procedure(statement));
+            self.write_gap(depth, statement.line, 0, separated, false);
+
+            let next_line = sequence.get(i + 1).map_or(end, |next| next.line);
+            self.write_statement(statement, depth, next_line);
+            self.line = self.line.max(statement_end(statement));
+        }
+    }
+
+    fn write_statement(&mut self, statement: &Statement, depth: usize, end: usize) {
+        match &statement.kind {
+            StatementKind::Procedure {
+                name,
+                args,
+                ret,
+                block,
+                ..
+            } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                let ret = ret
+                    .as_ref()
+                    .map_or(String::new(), |ret| format!(" -> {}", ret));
+
+                self.write_header(
+                    depth,
+                    format!("pr {}({}){}:", name, args.join(", "), ret),
+                    statement.line,
+                );
+                self.write_block(block, depth, statement.col, end);
+            }
+            StatementKind::If { .. } | StatementKind::IfElse { .. } => {
+                self.write_if(statement, depth, end, "")
+            }
+            StatementKind::Struct { name, fields } => {
+                self.write_header(depth, format!("struct {}:", name), statement.line);
+                self.at_block_start = true;
+                for (field, r#type) in fields {
+                    let line = self.find_member(field);
+                    self.write_gap(depth + 1, line, 0, false, false);
+                    self.write_header(depth + 1, format!("{}: {}", field, r#type), line);
+                }
+                self.write_gap(depth + 1, end, statement.col, false, true);
+            }
+            StatementKind::For {
+                var,
+                iterable,
+                block,
+                ..
+            } => {
+                self.write_header(
+                    depth,
+                    format!("for {} in {}:", var, format_range(iterable)),
+                    statement.line,
+                );
+                self.write_block(block, depth, statement.col, end);
+            }
+            StatementKind::Enum { name, variants } => {
+                self.write_header(depth, format!("enum {}:", name), statement.line);
+                self.at_block_start = true;
+                for (variant, types) in variants {
+                    let line = self.find_member(variant);
+                    let text = if types.is_empty() {
+                        variant.to_string()
+                    } else {
+                        let types: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+                        format!("{}({})", variant, types.join(", "))
+                    };
+                    self.write_gap(depth + 1, line, 0, false, false);
+                    self.write_header(depth + 1, text, line);
+                }
+                self.write_gap(depth + 1, end, statement.col, false, true);
+            }
+            StatementKind::Match { value, arms } => {
+                self.write_header(
+                    depth,
+                    format!("match {}:", format_expression(value)),
+                    statement.line,
+                );
+                self.at_block_start = true;
+                for (i, (pattern, block)) in arms.iter().enumerate() {
+                    let next_line = arms.get(i + 1).map_or(end, |(next, _)| next.line);
+                    self.write_gap(depth + 1, pattern.line, 0, false, false);
+                    self.write_header(depth + 1, format!("{}:", pattern), pattern.line);
+                    self.write_block(block, depth + 1, pattern.col, next_line);
+                }
+                self.write_gap(depth + 1, end, statement.col, false, true);
+            }
+            StatementKind::Loop { block } => {
+                self.write_header(depth, "loop:".to_string(), statement.line);
+                self.write_block(block, depth, statement.col, end);
+            }
+            StatementKind::While { cond, block } => {
+                self.write_header(
+                    depth,
+                    format!("while {}:", format_expression(cond)),
+                    statement.line,
+                );
+                self.write_block(block, depth, statement.col, end);
+            }
+            _ => self.write_header(depth, format_simple(statement), statement.line),
+        }
+    }
+
+    /// `else if` chains are kept on the line of the `else`.
+    fn write_if(&mut self, statement: &Statement, depth: usize, end: usize, prefix: &str) {
+        match &statement.kind {
+            StatementKind::If { cond, block } => {
+                self.write_header(
+                    depth,
+                    format!("{}if {}:", prefix, format_expression(cond)),
+                    statement.line,
+                );
+                self.write_block(block, depth, statement.col, end);
+            }
+            StatementKind::IfElse {
+                cond,
+                true_block,
+                false_block,
+            } => {
+                self.write_header(
+                    depth,
+                    format!("{}if {}:", prefix, format_expression(cond)),
+                    statement.line,
+                );
+
+                let true_end = true_block.iter().map(statement_end).max().unwrap_or(0);
+                let else_line = self.find_else(true_end);
+                self.write_block(true_block, depth, statement.col, else_line);
+                // comments that aren't indented under the `if` stay before the `else`
+                self.write_gap(depth, else_line, 0, false, true);
+
+                match false_block.as_slice() {
+                    [nested] if nested.line == else_line => {
+                        self.write_if(nested, depth, end, "else ")
+                    }
+                    _ => {
+                        self.write_header(depth, "else:".to_string(), else_line);
+                        self.write_block(false_block, depth, statement.col, end);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_block(&mut self, block: &[Statement], depth: usize, header_col: usize, end: usize) {
+        self.at_block_start = true;
+        self.write_sequence(block, depth + 1, end);
+        // comments indented under the header still belong to the block
+        self.write_gap(depth + 1, end, header_col, false, true);
+    }
+
+    /// Writes a line with the comment that follows the code on `line`.
+    fn write_header(&mut self, depth: usize, mut text: String, line: usize) {
+        if let Some((comment, false)) = self.comments.get(self.next_comment) {
+            if comment.line == line {
+                text.push(' ');
+                text.push_str(&comment.text);
+                self.next_comment += 1;
+            }
+        }
+
+        self.write_line(depth, &text);
+        self.line = self.line.max(line);
+    }
+
+    /// Writes the comments before line `end`, keeping one blank line where
+    /// the source had any. With `blank` the gap is always separated by a
+    /// blank line. Only comments indented past `min_col` are taken, and a
+    /// `closing` gap has no blank line after its last comment.
+    fn write_gap(&mut self, depth: usize, end: usize, min_col: usize, blank: bool, closing: bool) {
+        let mut blank = blank;
+
+        while let Some((comment, _)) = self.comments.get(self.next_comment) {
+            if comment.line >= end || comment.col <= min_col {
+                break;
+            }
+            let (line, text) = (comment.line, comment.text.clone());
+            self.next_comment += 1;
+
+            blank |= self.has_blank_line(self.line + 1, line);
+            self.write_blank_line(blank);
+            blank = false;
+
+            self.write_line(depth, &text);
+            self.line = self.line.max(line);
+        }
+
+        if !closing {
+            blank |= self.has_blank_line(self.line + 1, end);
+            self.write_blank_line(blank);
+        }
+    }
+
+    fn write_blank_line(&mut self, blank: bool) {
+        if blank && !self.at_block_start {
+            self.output.push('\n');
+        }
+    }
+
+    fn write_line(&mut self, depth: usize, text: &str) {
+        self.output.push_str(&INDENT.repeat(depth));
+        self.output.push_str(text);
+        self.output.push('\n');
+        self.at_block_start = false;
+    }
+
+    fn has_blank_line(&self, from: usize, to: usize) -> bool {
+        (from..to.min(self.lines.len() + 1)).any(|line| self.lines[line - 1].trim().is_empty())
+    }
+
+    /// The line of the next field or variant declaration, which the AST
+    /// doesn't keep.
+    fn find_member(&self, member: &str) -> usize {
+        (self.line + 1..=self.lines.len())
+            .find(|&line| {
+                let text = self.lines[line - 1].trim_start();
+                text.strip_prefix(member).is_some_and(|rest| {
+                    !rest.starts_with(|ch: char| ch.is_alphanumeric() || ch == '_')
+                })
+            })
+            .unwrap_or(self.line + 1)
+    }
+
+    /// The line of the `else` that follows a block ending at `line`.
+    fn find_else(&self, line: usize) -> usize {
+        (line + 1..=self.lines.len())
+            .find(|&line| {
+                let text = self.lines[line - 1].trim_start();
+                text.starts_with("else")
+                    && !text[4..].starts_with(|ch: char| ch.is_alphanumeric() || ch == '_')
+            })
+            .unwrap_or(line)
+    }
+}
+
+fn format_simple(statement: &Statement) -> String {
+    match &statement.kind {
+        StatementKind::Call { name, args, .. } if name == "()" => format_indirect_call(args),
+        StatementKind::Call { name, args, .. } => format_call(name, args),
+        StatementKind::Return { value } => format!("return {}", format_expression(value)),
+        StatementKind::Declare {
+            name,
+            type_hint,
+            value,
+            ..
+        } => {
+            let mut text = format!("var {}", name);
+            if let Some(type_hint) = type_hint {
+                text.push_str(&format!(": {}", type_hint));
+            }
+            if let Some(value) = value {
+                text.push_str(&format!(" = {}", format_expression(value)));
+            }
+            text
+        }
+        StatementKind::Assign { dest, src } => match &src.kind {
+            // the parser turns `a += b` into `a = a + b`, reusing `dest`
+            ExpressionKind::Call(op, args)
+                if args.len() == 2 && args[0] == *dest && binding_power(op).is_some() =>
+            {
+                format!(
+                    "{} {}= {}",
+                    format_expression(dest),
+                    op,
+                    format_expression(&args[1])
+                )
+            }
+            _ => format!("{} = {}", format_expression(dest), format_expression(src)),
+        },
+        StatementKind::Continue => "continue".to_string(),
+        StatementKind::Break => "break".to_string(),
+        _ => unreachable!(),
+    }
+}
+
+/// Writes an expression with the fewest parentheses that keep its meaning.
+pub fn format_expression(expr: &Expression) -> String {
+    match &expr.kind {
+        ExpressionKind::Lit(lit) => lit.clone(),
+        ExpressionKind::Id(id) => id.clone(),
+        ExpressionKind::Array(values) => format!("[{}]", format_list(values)),
+        ExpressionKind::Field(record, field) => {
+            let needs_parens = is_binary(record) || is_unary(record);
+            format!("{}.{}", parenthesize(record, needs_parens), field)
+        }
+        ExpressionKind::Call(name, args) => match (name.as_str(), args.as_slice()) {
+            ("[]", [array, index]) => {
+                let needs_parens = is_binary(array) || is_unary(array);
+                format!(
+                    "{}[{}]",
+                    parenthesize(array, needs_parens),
+                    format_range(index)
+                )
+            }
+            ("()", _) => format_indirect_call(args),
+            ("-" | "!", [arg]) => {
+                // `--` would be read as a single operator
+                let needs_parens = is_binary(arg) || (name == "-" && is_unary(arg));
+                format!("{}{}", name, parenthesize(arg, needs_parens))
+            }
+            (op, [lhs, rhs]) if binding_power(op).is_some() => {
+                let power = binding_power(op);
+                let lhs_needs_parens = is_binary(lhs) && operator_power(lhs) < power;
+                let rhs_needs_parens = is_binary(rhs) && operator_power(rhs) <= power;
+                format!(
+                    "{} {} {}",
+                    parenthesize(lhs, lhs_needs_parens),
+                    op,
+                    parenthesize(rhs, rhs_needs_parens)
+                )
+            }
+            _ => format_call(name, args),
+        },
+    }
+}
+
+fn format_call(name: &str, args: &[Expression]) -> String {
+    format!("{}({})", name, format_list(args))
+}
+
+/// A call of the procedure given by the first argument.
+fn format_indirect_call(args: &[Expression]) -> String {
+    let (callee, args) = args.split_first().unwrap();
+    let needs_parens = is_binary(callee) || is_unary(callee);
+    format!(
+        "{}({})",
+        parenthesize(callee, needs_parens),
+        format_list(args)
+    )
+}
+
+/// Ranges are only written in `for` loops and slices, without spaces around
+/// the `..`.
+fn format_range(expr: &Expression) -> String {
+    match &expr.kind {
+        ExpressionKind::Call(name, bounds) if name == ".." => format!(
+            "{}..{}",
+            format_expression(&bounds[0]),
+            format_expression(&bounds[1])
+        ),
+        _ => format_expression(expr),
+    }
+}
+
+fn format_list(values: &[Expression]) -> String {
+    values
+        .iter()
+        .map(format_expression)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn parenthesize(expr: &Expression, needs_parens: bool) -> String {
+    if needs_parens {
+        format!("({})", format_expression(expr))
+    } else {
+        format_expression(expr)
+    }
+}
+
+fn operator_power(expr: &Expression) -> Option<u8> {
+    match &expr.kind {
+        ExpressionKind::Call(op, _) => binding_power(op),
+        _ => None,
+    }
+}
+
+fn is_binary(expr: &Expression) -> bool {
+    matches!(&expr.kind, ExpressionKind::Call(op, args) if args.len() == 2 && binding_power(op).is_some())
+}
+
+fn is_unary(expr: &Expression) -> bool {
+    matches!(&expr.kind, ExpressionKind::Call(op, args) if args.len() == 1 && (op == "-" || op == "!"))
+}
+
+fn is_procedure(statement: &Statement) -> bool {
+    matches!(statement.kind, StatementKind::Procedure { .. })
+}
+
+/// The last line of the source a statement is written on.
+fn statement_end(statement: &Statement) -> usize {
+    let end = match &statement.kind {
+        StatementKind::Procedure { block, .. }
+        | StatementKind::If { block, .. }
+        | StatementKind::Loop { block } => block.iter().map(statement_end).max(),
+        StatementKind::While { cond, block } => block
+            .iter()
+            .map(statement_end)
+            .max()
+            .max(Some(expression_end(cond))),
+        StatementKind::IfElse {
+            true_block,
+            false_block,
+            ..
+        } => true_block
+            .iter()
+            .chain(false_block)
+            .map(statement_end)
+            .max(),
+        StatementKind::Call { args, .. } => args.iter().map(expression_end).max(),
+        StatementKind::Return { value } => Some(expression_end(value)),
+        StatementKind::Declare { value, .. } => value.as_ref().map(expression_end),
+        StatementKind::Assign { dest, src } => Some(expression_end(dest).max(expression_end(src))),
+        StatementKind::Continue | StatementKind::Break => None,
+        // at least one line per field, the formatter finds the actual ones
+        StatementKind::For {
+            iterable, block, ..
+        } => block
+            .iter()
+            .map(statement_end)
+            .max()
+            .max(Some(expression_end(iterable))),
+        StatementKind::Struct { fields, .. } => Some(statement.line + fields.len()),
+        StatementKind::Enum { variants, .. } => Some(statement.line + variants.len()),
+        StatementKind::Match { value, arms } => arms
+            .iter()
+            .map(|(pattern, block)| {
+                block
+                    .iter()
+                    .map(statement_end)
+                    .max()
+                    .unwrap_or(0)
+                    .max(pattern.line)
+            })
+            .max()
+            .max(Some(expression_end(value))),
+    };
+
+    end.unwrap_or(0).max(statement.line)
+}
+
+fn expression_end(expr: &Expression) -> usize {
+    let end = match &expr.kind {
+        ExpressionKind::Call(_, args) | ExpressionKind::Array(args) => {
+            args.iter().map(expression_end).max()
+        }
+        ExpressionKind::Field(record, _) => Some(expression_end(record)),
+        _ => None,
+    };
+
+    end.unwrap_or(0).max(expr.line)
+}
@@ -1,47 +1,232 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::io::stdin;
 use std::io::Write;
-use std::process;
+use std::rc::Rc;
 
-use crate::core::PrimitiveType;
+use crate::core::*;
+use crate::syntax_analysis::errors::{Error, ErrorKind};
+use crate::syntax_analysis::parser::binding_power;
 use crate::syntax_analysis::parser::expression::*;
+use crate::syntax_analysis::parser::pattern::*;
 use crate::syntax_analysis::parser::statement::*;
+use crate::YoraError;
 
 #[derive(Debug, Clone, PartialEq)]
-enum Value {
+pub enum Value {
     Int(i64),
     Bool(bool),
     Char(char),
     Array(Vec<Value>),
+    /// The name of the struct and the values of its fields, in the order
+    /// they were declared.
+    Record(String, Vec<(String, Value)>),
+    /// The name of the enum, of the variant and its payload.
+    Variant(String, String, Vec<Value>),
+    Closure(Rc<Closure>),
+}
+
+/// A procedure, along with the values that the variables it captured had
+/// when it was declared.
+#[derive(Debug, PartialEq)]
+pub struct Closure {
+    pub name: String,
+    pub code: Code,
+    // in the order of the `captures` of the declaration
+    pub env: Vec<Value>,
+}
+
+/// What runs when a closure is called.
+#[derive(Debug, PartialEq)]
+pub enum Code {
+    /// The declaration of the procedure, run by the interpreter.
+    Tree(Rc<StatementKind>),
+    /// The position of the procedure compiled by the VM.
+    Bytecode(usize),
 }
 
 impl Value {
-    fn get_int(&self) -> i64 {
+    pub(crate) fn get_int(&self) -> Result<i64, RuntimeError> {
         match self {
-            Value::Int(int) => *int,
-            _ => panic!("Not an int"),
+            Value::Int(int) => Ok(*int),
+            _ => Err(self.mismatch("Int")),
         }
     }
-    fn get_bool(&self) -> bool {
+    pub(crate) fn get_bool(&self) -> Result<bool, RuntimeError> {
         match self {
-            Value::Bool(bool) => *bool,
-            _ => panic!("Not a bool"),
+            Value::Bool(bool) => Ok(*bool),
+            _ => Err(self.mismatch("Bool")),
         }
     }
-    fn get_char(&self) -> char {
+    pub(crate) fn get_char(&self) -> Result<char, RuntimeError> {
         match self {
-            Value::Char(char) => *char,
-            _ => panic!("Not a char"),
+            Value::Char(char) => Ok(*char),
+            _ => Err(self.mismatch("Char")),
         }
     }
-    fn get_array(&self) -> Vec<Value> {
+    pub(crate) fn get_array(&self) -> Result<Vec<Value>, RuntimeError> {
         match self {
-            Value::Array(array) => array.clone(),
-            _ => panic!("Not a string"),
+            Value::Array(array) => Ok(array.clone()),
+            _ => Err(self.mismatch("an array")),
+        }
+    }
+
+    pub(crate) fn get_field(&self, field: &str) -> Result<Value, RuntimeError> {
+        match self {
+            Value::Record(_, fields) => fields
+                .iter()
+                .find(|(name, _)| name == field)
+                .map(|(_, value)| value.clone())
+                .ok_or_else(|| RuntimeError::new(format!("no field '{}'", field))),
+            _ => Err(self.mismatch("a record")),
+        }
+    }
+
+    /// The element or field at `pos` of an array or a record.
+    pub(crate) fn get_part(&self, pos: usize) -> &Value {
+        match self {
+            Value::Array(values) => &values[pos],
+            Value::Record(_, fields) => &fields[pos].1,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn get_part_mut(&mut self, pos: usize) -> &mut Value {
+        match self {
+            Value::Array(values) => &mut values[pos],
+            Value::Record(_, fields) => &mut fields[pos].1,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn mismatch(&self, expected: &str) -> RuntimeError {
+        let found = match self {
+            Value::Int(_) => "Int",
+            Value::Bool(_) => "Bool",
+            Value::Char(_) => "Char",
+            Value::Array(_) => "an array",
+            Value::Record(name, _) | Value::Variant(name, ..) => name,
+            Value::Closure(_) => "a procedure",
+        };
+        RuntimeError::new(format!("expected {}, found {}", expected, found))
+    }
+}
+
+/// Shows a value the way it is written in the source, so strings are
+/// quoted instead of being printed like `print` does.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(int) => write!(f, "{}", int),
+            Value::Bool(bool) => write!(f, "{}", bool),
+            Value::Char(char) => write!(f, "'{}'", char.escape_default()),
+            Value::Array(values)
+                if !values.is_empty() && values.iter().all(|value| value.get_char().is_ok()) =>
+            {
+                let string: String = values
+                    .iter()
+                    .filter_map(|value| value.get_char().ok())
+                    .collect();
+                write!(f, "\"{}\"", string.escape_default())
+            }
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+            Value::Record(name, fields) => {
+                let values: Vec<String> =
+                    fields.iter().map(|(_, value)| value.to_string()).collect();
+                write!(f, "{}({})", name, values.join(", "))
+            }
+            Value::Variant(_, variant, payload) if payload.is_empty() => write!(f, "{}", variant),
+            Value::Variant(_, variant, payload) => {
+                let values: Vec<String> = payload.iter().map(|value| value.to_string()).collect();
+                write!(f, "{}({})", variant, values.join(", "))
+            }
+            Value::Closure(closure) => write!(f, "<pr {}>", closure.name),
         }
     }
 }
 
+/// An error that can only be detected while the program runs, like dividing
+/// by zero or indexing out of bounds. `line` and `col` point at the innermost
+/// expression that failed and `trace` holds the procedure calls that were
+/// active, the innermost one first.
+#[derive(Debug, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+    pub trace: Vec<Frame>,
+}
+
+/// A call to a procedure, at the position of the call.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    pub name: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl RuntimeError {
+    /// The position is filled in by the interpreter as the error unwinds.
+    pub(crate) fn new(message: String) -> RuntimeError {
+        RuntimeError {
+            message,
+            line: 0,
+            col: 0,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Error {
+        let mut error = Error::new(
+            ErrorKind::RuntimeError {
+                message: self.message.clone(),
+            },
+            self.line,
+            self.col,
+        );
+        // a deep recursion repeats the same call many times
+        for frames in self.trace.chunk_by(|frame1, frame2| frame1 == frame2) {
+            let frame = &frames[0];
+            let mut note = format!(
+                "in procedure '{}', called at {}:{}",
+                frame.name, frame.line, frame.col
+            );
+            if frames.len() > 1 {
+                note += &format!(" ({} times)", frames.len());
+            }
+            error = error.with_note(note);
+        }
+        error
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "runtime error at {}:{}: {}",
+            self.line, self.col, self.message
+        )
+    }
+}
+
+/// Stops the execution, leaving every procedure and block on the way out.
+pub(crate) enum Unwind {
+    Exit,
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(err: RuntimeError) -> Self {
+        Unwind::Error(err)
+    }
+}
+
+pub(crate) type RunResult<T> = Result<T, Unwind>;
+
 #[derive(Debug, PartialEq)]
 enum Signal {
     Normal,
@@ -50,189 +235,585 @@ enum Signal {
     Return(Value),
 }
 
+/// Runs analyzed code by walking its tree. `run` and the REPL use the `Vm`
+/// instead, so this is only a reference: the tests check that the VM gives
+/// the same results and the benchmarks compare their speed. New features
+/// only have to be supported by the VM.
 pub struct Interpreter {
-    variables: Vec<(String, Value)>,
-    num_vars_scope: Vec<i8>,
-    procedures: HashMap<String, StatementKind>,
+    // the variables of the main program, by slot
+    globals: Vec<Value>,
+    // the variables of every call being run, the innermost last
+    locals: Vec<Locals>,
+    structs: HashMap<String, Vec<(String, PrimitiveType)>>,
+    enums: HashMap<String, Vec<(String, Vec<PrimitiveType>)>>,
     signal: Signal,
+    call_stack: Vec<Frame>,
+    exited: bool,
+}
+
+/// The variables of a call by slot, which are the closure that was called,
+/// its arguments and then the variables of its block.
+struct Locals {
+    // the depth of the variables of the procedure
+    depth: usize,
+    procedure: Rc<StatementKind>,
+    closure: Rc<Closure>,
+    vars: Vec<Value>,
+}
+
+impl Locals {
+    /// The value of a variable of the enclosing code, if the procedure
+    /// captured it.
+    fn captured(&self, binding: Binding) -> Option<&Value> {
+        let StatementKind::Procedure { captures, .. } = &*self.procedure else {
+            unreachable!()
+        };
+        let pos = captures.iter().position(|capture| *capture == binding)?;
+        Some(&self.closure.env[pos])
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
         Interpreter {
-            variables: Vec::new(),
-            num_vars_scope: Vec::new(),
-            procedures: HashMap::new(),
+            // the globals outlive every run
+            globals: Vec::new(),
+            locals: Vec::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
             signal: Signal::Normal,
+            call_stack: Vec::new(),
+            exited: false,
+        }
+    }
+
+    /// Runs the program until its end or a call to `exit`. Global
+    /// declarations are kept for the following runs.
+    pub fn run(&mut self, ast: &[Statement]) -> Result<(), YoraError> {
+        let result = ast
+            .iter()
+            .try_for_each(|statement| self.run_statement(statement));
+
+        match self.recover(result) {
+            Ok(_) | Err(Unwind::Exit) => Ok(()),
+            Err(Unwind::Error(err)) => Err(YoraError::Runtime(err)),
         }
     }
 
-    pub fn run(&mut self, ast: &Vec<Statement>) {
-        self.start_scope();
-        for statement in ast {
-            self.run_statement(statement);
+    /// Evaluates an expression in the global scope. `None` if it called
+    /// `exit`.
+    pub fn evaluate(&mut self, expr: &Expression) -> Result<Option<Value>, YoraError> {
+        let result = self.eval_expression(expr);
+
+        match self.recover(result) {
+            Ok(value) => Ok(Some(value)),
+            Err(Unwind::Exit) => Ok(None),
+            Err(Unwind::Error(err)) => Err(YoraError::Runtime(err)),
         }
-        self.end_scope();
     }
 
-    fn run_statement(&mut self, statement: &Statement) {
+    /// Whether a previous run or evaluation called `exit`.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Returns to the global scope after a run.
+    fn recover<T>(&mut self, result: RunResult<T>) -> RunResult<T> {
+        if let Err(Unwind::Exit) = result {
+            self.exited = true;
+        }
+        self.locals.clear();
+        self.signal = Signal::Normal;
+        self.call_stack.clear();
+        result
+    }
+
+    fn run_statement(&mut self, statement: &Statement) -> RunResult<()> {
+        self.run_statement_kind(statement)
+            .map_err(|unwind| self.locate(unwind, statement.line, statement.col))
+    }
+
+    fn run_statement_kind(&mut self, statement: &Statement) -> RunResult<()> {
         match &statement.kind {
-            StatementKind::Procedure { name, .. } => {
-                self.procedures
-                    .insert(name.to_string(), statement.kind.clone());
+            StatementKind::Procedure {
+                name,
+                binding,
+                captures,
+                ..
+            } => {
+                let env = captures
+                    .iter()
+                    .map(|capture| self.var(*capture).clone())
+                    .collect();
+                let closure = Closure {
+                    name: name.to_string(),
+                    code: Code::Tree(Rc::new(statement.kind.clone())),
+                    env,
+                };
+                self.declare(binding.unwrap(), Value::Closure(Rc::new(closure)));
+            }
+            StatementKind::Struct { name, fields } => {
+                self.structs.insert(name.to_string(), fields.clone());
+            }
+            StatementKind::Enum { name, variants } => {
+                self.enums.insert(name.to_string(), variants.clone());
+            }
+            StatementKind::Call {
+                name,
+                args,
+                binding,
+            } => {
+                self.run_call(name, *binding, args, statement.line, statement.col)?;
+                // the returned value, if any, is discarded
+                if let Signal::Return(_) = self.signal {
+                    self.signal = Signal::Normal;
+                }
             }
-            StatementKind::Call { name, args } => self.run_call(name, args),
             StatementKind::Return { value } => {
-                self.signal = Signal::Return(self.eval_expression(value))
+                self.signal = Signal::Return(self.eval_expression(value)?)
             }
-            StatementKind::Declare { name, value, .. } => self.run_declare(name, value),
-            StatementKind::Assign { dest, src } => self.run_assign(dest, src),
-            StatementKind::If { cond, block } => self.run_if(cond, block),
+            StatementKind::Declare {
+                type_hint,
+                value,
+                binding,
+                ..
+            } => self.run_declare(binding.unwrap(), type_hint, value)?,
+            StatementKind::Assign { dest, src } => self.run_assign(dest, src)?,
+            StatementKind::If { cond, block } => self.run_if(cond, block)?,
             StatementKind::IfElse {
                 cond,
                 true_block,
                 false_block,
-            } => self.run_if_else(cond, true_block, false_block),
-            StatementKind::Loop { block } => self.run_loop(block),
-            StatementKind::While { cond, block } => self.run_while(cond, block),
+            } => self.run_if_else(cond, true_block, false_block)?,
+            StatementKind::Loop { block } => self.run_loop(block)?,
+            StatementKind::While { cond, block } => self.run_while(cond, block)?,
+            StatementKind::For {
+                binding,
+                iterable,
+                block,
+                ..
+            } => self.run_for(binding.unwrap(), iterable, block)?,
+            StatementKind::Match { value, arms } => self.run_match(value, arms)?,
             StatementKind::Continue => self.signal = Signal::Continue,
             StatementKind::Break => self.signal = Signal::Break,
         }
+        Ok(())
     }
 
-    fn run_call(&mut self, name: &String, call_args: &[Expression]) {
-        self.start_scope();
+    /// Runs a call to a builtin or a constructor, or to the procedure in
+    /// the variable `binding`.
+    fn run_call(
+        &mut self,
+        name: &String,
+        binding: Option<Binding>,
+        call_args: &[Expression],
+        line: usize,
+        col: usize,
+    ) -> RunResult<()> {
+        if let Some(binding) = binding {
+            let Value::Closure(closure) = self.var(binding).clone() else {
+                unreachable!()
+            };
+            return self.run_closure(closure, call_args, line, col);
+        }
+
         match name.as_str() {
-            "exit" => {
-                process::exit(0);
-            }
-            "print" => {
-                match self.eval_expression(&call_args[0]) {
-                    Value::Int(int) => print!("{}", int),
-                    Value::Bool(boolean) => print!("{}", boolean),
-                    Value::Char(character) => print!("{}", character),
-                    Value::Array(values) => {
-                        for value in values {
-                            print!("{}", value.get_char());
-                        }
-                    }
+            // a call whose value is discarded
+            "()" => {
+                let Value::Closure(closure) = self.eval_expression(&call_args[0])? else {
+                    unreachable!()
                 };
-                let _ = std::io::stdout().flush();
+                return self.run_closure(closure, &call_args[1..], line, col);
             }
-            "input" => {
-                let mut buffer = String::new();
-                let mut values = Vec::new();
-
-                let _ = stdin().read_line(&mut buffer);
-                let _ = buffer.strip_suffix('\n');
-                for ch in buffer.chars() {
-                    values.push(Value::Char(ch))
+            "map" | "filter" | "fold" => {
+                let mut values = Vec::with_capacity(call_args.len());
+                for arg in call_args {
+                    values.push(self.eval_expression(arg)?);
                 }
-                self.signal = Signal::Return(Value::Array(values));
+                let value = self.run_higher_order(name, values, line, col)?;
+                self.signal = Signal::Return(value);
             }
+            "exit" => return Err(Unwind::Exit),
+            "print" => print_value(self.eval_expression(&call_args[0])?)?,
+            "input" => self.signal = Signal::Return(read_input()),
             "string_to_int" => {
-                if let Value::Array(values) = self.eval_expression(&call_args[0]) {
-                    let mut string = String::new();
-                    for value in values {
-                        string.push(value.get_char());
-                    }
-                    string.pop();
-                    self.signal = Signal::Return(Value::Int(string.parse().unwrap()))
-                } else {
-                    panic!()
-                }
+                let string = self.eval_expression(&call_args[0])?;
+                self.signal = Signal::Return(string_to_int(&string)?)
             }
             "char_to_int" => {
-                if let Value::Char(ch) = self.eval_expression(&call_args[0]) {
-                    self.signal = Signal::Return(Value::Int(ch as i64))
-                } else {
-                    panic!()
-                }
+                let ch = self.eval_expression(&call_args[0])?.get_char()?;
+                self.signal = Signal::Return(Value::Int(ch as i64))
             }
             "int_to_char" => {
-                if let Value::Int(int) = self.eval_expression(&call_args[0]) {
-                    self.signal = Signal::Return(Value::Char(char::from_u32(int as u32).unwrap()))
-                } else {
-                    panic!()
+                let int = self.eval_expression(&call_args[0])?;
+                self.signal = Signal::Return(int_to_char(&int)?)
+            }
+            "string_len" | "len" => {
+                let contents = self.eval_expression(&call_args[0])?.get_array()?;
+                self.signal = Signal::Return(Value::Int(contents.len() as i64))
+            }
+            "push" | "pop" | "insert" | "remove" => self.run_array_call(name, call_args)?,
+            _ if self.enum_of(name).is_some() => {
+                let enum_name = self.enum_of(name).unwrap();
+                let mut payload = Vec::new();
+                for arg in call_args {
+                    payload.push(self.eval_expression(arg)?);
                 }
+                self.signal = Signal::Return(Value::Variant(enum_name, name.to_string(), payload));
             }
-            "string_len" => {
-                if let Value::Array(contents) = self.eval_expression(&call_args[0]) {
-                    self.signal = Signal::Return(Value::Int(contents.len() as i64))
-                } else {
-                    panic!()
+            _ if self.structs.contains_key(name) => {
+                let mut fields = Vec::new();
+                for (i, (field, _)) in self.structs[name].clone().into_iter().enumerate() {
+                    fields.push((field, self.eval_expression(&call_args[i])?));
                 }
+                self.signal = Signal::Return(Value::Record(name.to_string(), fields));
             }
-            _ => {
-                let StatementKind::Procedure {
-                    ref args,
-                    ref block,
-                    ..
-                } = self.procedures[name].clone()
-                else {
-                    panic!("Undeclared function {name}");
-                };
+            _ => panic!("Undeclared function {name}"),
+        }
+        Ok(())
+    }
+
+    fn run_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        call_args: &[Expression],
+        line: usize,
+        col: usize,
+    ) -> RunResult<()> {
+        let Code::Tree(procedure) = &closure.code else {
+            unreachable!()
+        };
+        let procedure = Rc::clone(procedure);
+        let StatementKind::Procedure { args, .. } = &*procedure else {
+            unreachable!()
+        };
+
+        // every argument is evaluated before the first one is bound, then
+        // the `ref` ones are moved out of their places instead of being
+        // copied
+        let mut places = Vec::new();
+        let mut arg_vals = Vec::with_capacity(args.len() + 1);
+        arg_vals.push(Value::Closure(Rc::clone(&closure)));
+        for (i, (arg, call_arg)) in args.iter().zip(call_args).enumerate() {
+            if arg.is_ref {
+                places.push((i + 1, self.find_place(call_arg)?));
+                arg_vals.push(Value::Int(0));
+            } else {
+                arg_vals.push(self.eval_expression(call_arg)?);
+            }
+        }
+        for (i, (binding, path)) in &places {
+            let place = self.part_mut(*binding, path);
+            arg_vals[*i] = std::mem::replace(place, Value::Int(0));
+        }
+
+        let (result, mut arg_vals) = self.enter_closure(closure, arg_vals, line, col);
+        // moved back even if the call failed, the caller may be the global
+        // scope of a REPL
+        for (i, (binding, path)) in places {
+            let value = std::mem::replace(&mut arg_vals[i], Value::Int(0));
+            *self.part_mut(binding, &path) = value;
+        }
+        result
+    }
+
+    /// Runs the block of a closure with its locals, the closure and its
+    /// arguments first, which are given back afterwards.
+    fn enter_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        vars: Vec<Value>,
+        line: usize,
+        col: usize,
+    ) -> (RunResult<()>, Vec<Value>) {
+        let Code::Tree(procedure) = &closure.code else {
+            unreachable!()
+        };
+        let procedure = Rc::clone(procedure);
+        let StatementKind::Procedure { block, binding, .. } = &*procedure else {
+            unreachable!()
+        };
+
+        self.call_stack.push(Frame {
+            name: closure.name.clone(),
+            line,
+            col,
+        });
+        self.locals.push(Locals {
+            depth: binding.unwrap().depth + 1,
+            procedure: Rc::clone(&procedure),
+            closure,
+            vars,
+        });
+        let result = self.run_procedure_block(block);
+        let vars = self.locals.pop().unwrap().vars;
+        self.call_stack.pop();
+        (result, vars)
+    }
+
+    /// Calls a procedure value with arguments that are already evaluated,
+    /// for the value it returns.
+    fn call_value(
+        &mut self,
+        procedure: &Value,
+        args: Vec<Value>,
+        line: usize,
+        col: usize,
+    ) -> RunResult<Value> {
+        let Value::Closure(closure) = procedure else {
+            unreachable!()
+        };
+        let mut vars = vec![procedure.clone()];
+        vars.extend(args);
+        self.enter_closure(Rc::clone(closure), vars, line, col).0?;
 
-                for (i, (arg_name, _)) in args.iter().enumerate() {
-                    let arg_val = self.eval_expression(&call_args[i]);
-                    let len = self.num_vars_scope.len();
-                    self.num_vars_scope[len - 1] += 1;
-                    self.variables.push((arg_name.to_string(), arg_val));
+        match std::mem::replace(&mut self.signal, Signal::Normal) {
+            Signal::Return(value) => Ok(value),
+            _ => Err(RuntimeError::new(format!(
+                "procedure '{}' ended without returning a value",
+                closure.name
+            ))
+            .into()),
+        }
+    }
+
+    /// Runs `map`, `filter` or `fold`, which call the procedure in their
+    /// last argument for every element of the array in their first one.
+    fn run_higher_order(
+        &mut self,
+        name: &str,
+        mut args: Vec<Value>,
+        line: usize,
+        col: usize,
+    ) -> RunResult<Value> {
+        let procedure = args.pop().unwrap();
+        let values = args[0].get_array()?;
+        let value = match name {
+            "map" => {
+                let mut mapped = Vec::with_capacity(values.len());
+                for value in values {
+                    mapped.push(self.call_value(&procedure, vec![value], line, col)?);
                 }
-                for statement in block {
-                    self.run_statement(statement);
-                    if let Signal::Return(_) = &self.signal {
-                        break;
+                Value::Array(mapped)
+            }
+            "filter" => {
+                let mut kept = Vec::new();
+                for value in values {
+                    if self
+                        .call_value(&procedure, vec![value.clone()], line, col)?
+                        .get_bool()?
+                    {
+                        kept.push(value);
                     }
                 }
+                Value::Array(kept)
+            }
+            _ => {
+                let mut acc = args.pop().unwrap();
+                for value in values {
+                    acc = self.call_value(&procedure, vec![acc, value], line, col)?;
+                }
+                acc
+            }
+        };
+        Ok(value)
+    }
+
+    fn run_procedure_block(&mut self, block: &[Statement]) -> RunResult<()> {
+        for statement in block {
+            self.run_statement(statement)?;
+            if let Signal::Return(_) = &self.signal {
+                break;
             }
         }
-        self.end_scope();
+        Ok(())
     }
 
-    fn run_declare(&mut self, name: &String, value: &Option<Expression>) {
-        let len = self.num_vars_scope.len();
-        self.num_vars_scope[len - 1] += 1;
-        if let Some(value) = value {
-            let value = self.eval_expression(value);
-            self.variables.push((name.to_string(), value));
-        } else {
-            self.variables.push((name.to_string(), Value::Int(0)));
+    /// Runs a builtin that changes the array in its first argument, in
+    /// place, after the other arguments are evaluated.
+    fn run_array_call(&mut self, name: &str, args: &[Expression]) -> RunResult<()> {
+        let mut values = Vec::new();
+        for arg in &args[1..] {
+            values.push(self.eval_expression(arg)?);
         }
+        let Value::Array(array) = self.get_place(&args[0])? else {
+            unreachable!()
+        };
+
+        let removed = array_call(name, array, &values)?;
+        if let Some(value) = removed {
+            self.signal = Signal::Return(value);
+        }
+        Ok(())
+    }
+
+    fn run_declare(
+        &mut self,
+        binding: Binding,
+        type_hint: &Option<PrimitiveType>,
+        value: &Option<Expression>,
+    ) -> RunResult<()> {
+        let value = match (value, type_hint) {
+            (Some(value), _) => self.eval_expression(value)?,
+            (None, Some(type_hint)) => default_value(type_hint, &self.structs, &self.enums),
+            (None, None) => Value::Int(0),
+        };
+        self.declare(binding, value);
+        Ok(())
+    }
+
+    fn run_assign(&mut self, dest: &Expression, src: &Expression) -> RunResult<()> {
+        let src_val = self.eval_expression(src)?;
+        self.assign(dest, src_val)
     }
 
-    fn run_assign(&mut self, dest: &Expression, src: &Expression) {
-        match &dest.kind {
-            ExpressionKind::Id(id) => {
-                let src_val = self.eval_expression(src);
-                self.set_value_by_name(id, src_val);
+    /// Stores a value in a variable, an element of an array or a field of a
+    /// record.
+    fn assign(&mut self, dest: &Expression, value: Value) -> RunResult<()> {
+        *self.get_place(dest)? = value;
+        Ok(())
+    }
+
+    /// The value stored in a variable, an element of an array or a field of
+    /// a record, to be changed in place.
+    fn get_place(&mut self, place: &Expression) -> RunResult<&mut Value> {
+        let (binding, path) = self.find_place(place)?;
+        Ok(self.part_mut(binding, &path))
+    }
+
+    /// The variable that holds a place and the positions of the elements and
+    /// fields that lead to it, which are checked before anything is borrowed.
+    fn find_place(&mut self, place: &Expression) -> RunResult<(Binding, Vec<usize>)> {
+        // the outermost index is evaluated first
+        let mut parts = Vec::new();
+        let mut holder = place;
+        let binding = loop {
+            match &holder.kind {
+                ExpressionKind::Id(_) => break holder.binding.unwrap(),
+                ExpressionKind::Call(name, args) if name == "[]" => {
+                    let idx = self.eval_expression(&args[1])?.get_int()?;
+                    parts.push((holder, idx));
+                    holder = &args[0];
+                }
+                ExpressionKind::Field(record, _) => {
+                    parts.push((holder, 0));
+                    holder = record;
+                }
+                _ => panic!("Invalid assignment"),
             }
-            ExpressionKind::Call(name, args) => {
-                if name == "[]" {
-                    let idx = self.eval_expression(&args[1]).get_int();
-                    if let ExpressionKind::Id(id) = &args[0].kind {
-                        let mut new_array = self.get_value_by_name(id).get_array();
-                        new_array[idx as usize] = self.eval_expression(src);
-                        self.set_value_by_name(id, Value::Array(new_array))
-                    }
-                } else {
-                    panic!("Invalid assignment");
+        };
+
+        let mut value = self.var(binding);
+        let mut path = Vec::new();
+        for (part, idx) in parts.into_iter().rev() {
+            let pos = match (&part.kind, value) {
+                (ExpressionKind::Field(_, field), Value::Record(_, fields)) => fields
+                    .iter()
+                    .position(|(name, _)| name == field)
+                    .ok_or_else(|| RuntimeError::new(format!("no field '{}'", field))),
+                (ExpressionKind::Field(..), _) => Err(value.mismatch("a record")),
+                (_, Value::Array(values)) => get_index(idx, values.len()),
+                _ => Err(value.mismatch("an array")),
+            }
+            .map_err(|err| self.locate(err.into(), part.line, part.col))?;
+            value = value.get_part(pos);
+            path.push(pos);
+        }
+        Ok((binding, path))
+    }
+
+    fn run_for(
+        &mut self,
+        binding: Binding,
+        iterable: &Expression,
+        block: &[Statement],
+    ) -> RunResult<()> {
+        // the iterable is evaluated once, before the first iteration
+        let values: Box<dyn Iterator<Item = Value>> = match &iterable.kind {
+            ExpressionKind::Call(name, bounds) if name == ".." => {
+                let start = self.eval_expression(&bounds[0])?.get_int()?;
+                let end = self.eval_expression(&bounds[1])?.get_int()?;
+                Box::new((start..end).map(Value::Int))
+            }
+            _ => Box::new(self.eval_expression(iterable)?.get_array()?.into_iter()),
+        };
+
+        for value in values {
+            self.declare(binding, value);
+            for statement in block {
+                self.run_statement(statement)?;
+                if self.signal != Signal::Normal {
+                    break;
+                }
+            }
+
+            match self.signal {
+                Signal::Continue => self.signal = Signal::Normal,
+                Signal::Break => {
+                    self.signal = Signal::Normal;
+                    break;
+                }
+                Signal::Return(_) => break,
+                Signal::Normal => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn run_match(
+        &mut self,
+        value: &Expression,
+        arms: &[(Pattern, Vec<Statement>)],
+    ) -> RunResult<()> {
+        let value = self.eval_expression(value)?;
+
+        for (pattern, block) in arms {
+            let mut bindings = Vec::new();
+            if !match_pattern(pattern, &value, &mut bindings) {
+                continue;
+            }
+
+            for (binding, value) in bindings {
+                self.declare(binding, value);
+            }
+            for statement in block {
+                self.run_statement(statement)?;
+                if self.signal != Signal::Normal {
+                    break;
                 }
             }
-            _ => panic!("Invalid assignment"),
+            return Ok(());
         }
+
+        Err(RuntimeError::new(format!("no pattern matched {}", value)).into())
+    }
+
+    /// The name of the enum a variant belongs to.
+    fn enum_of(&self, variant: &str) -> Option<String> {
+        self.enums
+            .iter()
+            .find(|(_, variants)| variants.iter().any(|(name, _)| name == variant))
+            .map(|(name, _)| name.to_string())
     }
 
-    fn run_if(&mut self, cond: &Expression, block: &Vec<Statement>) {
-        self.start_scope();
-        if let Value::Bool(cond) = self.eval_expression(cond) {
+    fn run_if(&mut self, cond: &Expression, block: &Vec<Statement>) -> RunResult<()> {
+        if let Value::Bool(cond) = self.eval_expression(cond)? {
             if cond {
                 for statement in block {
-                    self.run_statement(statement);
+                    self.run_statement(statement)?;
+                    if self.signal != Signal::Normal {
+                        break;
+                    }
                 }
             }
         }
-        self.end_scope();
+        Ok(())
     }
 
     fn run_if_else(
@@ -240,58 +821,65 @@ impl Interpreter {
         cond: &Expression,
         true_block: &Vec<Statement>,
         false_block: &Vec<Statement>,
-    ) {
-        self.start_scope();
-        if let Value::Bool(cond) = self.eval_expression(cond) {
+    ) -> RunResult<()> {
+        if let Value::Bool(cond) = self.eval_expression(cond)? {
             if cond {
                 for statement in true_block {
-                    self.run_statement(statement);
-                    if self.signal == Signal::Break || self.signal == Signal::Continue {
+                    self.run_statement(statement)?;
+                    if self.signal != Signal::Normal {
                         break;
                     }
                 }
             } else {
                 for statement in false_block {
-                    self.run_statement(statement);
-                    if self.signal == Signal::Break || self.signal == Signal::Continue {
+                    self.run_statement(statement)?;
+                    if self.signal != Signal::Normal {
                         break;
                     }
                 }
             }
         }
-        self.end_scope();
+        Ok(())
     }
 
-    fn run_loop(&mut self, block: &Vec<Statement>) {
-        self.start_scope();
+    fn run_loop(&mut self, block: &Vec<Statement>) -> RunResult<()> {
         'outer: loop {
             for statement in block {
-                self.run_statement(statement);
-                if self.signal == Signal::Break {
-                    self.signal = Signal::Normal;
-                    break 'outer;
-                } else if self.signal == Signal::Continue {
-                    self.signal = Signal::Normal;
-                    continue 'outer;
+                self.run_statement(statement)?;
+                match self.signal {
+                    Signal::Break => {
+                        self.signal = Signal::Normal;
+                        break 'outer;
+                    }
+                    Signal::Continue => {
+                        self.signal = Signal::Normal;
+                        continue 'outer;
+                    }
+                    Signal::Return(_) => break 'outer,
+                    Signal::Normal => {}
                 }
             }
         }
-        self.end_scope();
+        Ok(())
     }
 
-    fn run_while(&mut self, cond: &Expression, block: &Vec<Statement>) {
-        self.start_scope();
+    fn run_while(&mut self, cond: &Expression, block: &Vec<Statement>) -> RunResult<()> {
         'outer: loop {
-            if let Value::Bool(cond) = self.eval_expression(cond) {
+            if let Value::Bool(cond) = self.eval_expression(cond)? {
                 if cond {
                     for statement in block {
-                        self.run_statement(statement);
-                        if self.signal == Signal::Break {
-                            self.signal = Signal::Normal;
-                            break 'outer;
-                        } else if self.signal == Signal::Continue {
-                            self.signal = Signal::Normal;
-                            continue 'outer;
+                        self.run_statement(statement)?;
+                        match self.signal {
+                            Signal::Break => {
+                                self.signal = Signal::Normal;
+                                break 'outer;
+                            }
+                            Signal::Continue => {
+                                self.signal = Signal::Normal;
+                                continue 'outer;
+                            }
+                            Signal::Return(_) => break 'outer,
+                            Signal::Normal => {}
                         }
                     }
                 } else {
@@ -299,134 +887,395 @@ impl Interpreter {
                 }
             }
         }
-        self.end_scope();
-    }
-
-    fn eval_expression(&mut self, expr: &Expression) -> Value {
-        match &expr.kind {
-            ExpressionKind::Id(id) => self.get_value_by_name(id),
-            ExpressionKind::Lit(lit) => match &expr.r#type.clone().unwrap() {
-                PrimitiveType::Int => Value::Int(lit.parse::<i64>().unwrap()),
-                PrimitiveType::Bool => Value::Bool(lit == "true"),
-                PrimitiveType::Char => Value::Char(lit.chars().nth(1).unwrap()),
-                PrimitiveType::Arr(r#type) => match **r#type {
-                    PrimitiveType::Char => {
-                        let mut chars = Vec::new();
-                        let mut char_it = lit[1..lit.len() - 1].chars();
-                        while let Some(ch) = char_it.next() {
-                            if ch == '\\' {
-                                chars.push(Value::Char(match char_it.next().unwrap() {
-                                    'n' => '\n',
-                                    't' => '\t',
-                                    '0' => '\0',
-                                    '\\' => '\\',
-                                    _ => panic!("Unterminated escape code"),
-                                }))
-                            } else {
-                                chars.push(Value::Char(ch));
-                            }
-                        }
-                        Value::Array(chars)
-                    }
-                    _ => panic!(
-                        "Literal arrays can only be of type Char[], {}[] given",
-                        r#type
-                    ),
-                },
-            },
+        Ok(())
+    }
+
+    fn eval_expression(&mut self, expr: &Expression) -> RunResult<Value> {
+        self.eval_expression_kind(expr)
+            .map_err(|unwind| self.locate(unwind, expr.line, expr.col))
+    }
+
+    fn eval_expression_kind(&mut self, expr: &Expression) -> RunResult<Value> {
+        let value = match &expr.kind {
+            ExpressionKind::Id(_) => self.var(expr.binding.unwrap()).clone(),
+            ExpressionKind::Lit(lit) => literal(lit, expr.r#type.as_ref().unwrap()),
+            ExpressionKind::Call(name, args) if name == "[]" && args[1].is_range() => {
+                let values = self.eval_expression(&args[0])?.get_array()?;
+                let ExpressionKind::Call(_, bounds) = &args[1].kind else {
+                    unreachable!()
+                };
+                let start = self.eval_expression(&bounds[0])?.get_int()?;
+                let end = self.eval_expression(&bounds[1])?.get_int()?;
+                Value::Array(values[get_range(start, end, values.len())?].to_vec())
+            }
+            ExpressionKind::Call(name, args) if name == "()" => {
+                let procedure = self.eval_expression(&args[0])?;
+                let mut values = Vec::with_capacity(args.len() - 1);
+                for arg in &args[1..] {
+                    values.push(self.eval_expression(arg)?);
+                }
+                self.call_value(&procedure, values, expr.line, expr.col)?
+            }
             ExpressionKind::Call(name, args) => {
-                if args.len() == 2 {
-                    let arg0 = self.eval_expression(&args[0]);
-                    let arg1 = self.eval_expression(&args[1]);
+                // the arguments of a procedure are evaluated by its call
+                if args.len() == 2 && (binding_power(name).is_some() || name == "[]") {
+                    let arg0 = self.eval_expression(&args[0])?;
+                    let arg1 = self.eval_expression(&args[1])?;
 
                     match name.as_str() {
-                        "+" => Value::Int(arg0.get_int() + arg1.get_int()),
-                        "-" => Value::Int(arg0.get_int() - arg1.get_int()),
-                        "*" => Value::Int(arg0.get_int() * arg1.get_int()),
-                        "/" => Value::Int(arg0.get_int() / arg1.get_int()),
-                        "%" => Value::Int(arg0.get_int() % arg1.get_int()),
-                        "and" => Value::Bool(arg0.get_bool() && arg1.get_bool()),
-                        "or" => Value::Bool(arg0.get_bool() || arg1.get_bool()),
-                        "==" => Value::Bool(arg0.get_int() == arg1.get_int()),
-                        "!=" => Value::Bool(arg0.get_int() != arg1.get_int()),
-                        "<" => Value::Bool(arg0.get_int() < arg1.get_int()),
-                        "<=" => Value::Bool(arg0.get_int() <= arg1.get_int()),
-                        ">" => Value::Bool(arg0.get_int() > arg1.get_int()),
-                        ">=" => Value::Bool(arg0.get_int() >= arg1.get_int()),
+                        "+" if matches!(arg0, Value::Array(_)) => {
+                            let mut values = arg0.get_array()?;
+                            values.extend(arg1.get_array()?);
+                            Value::Array(values)
+                        }
+                        "+" | "-" | "*" | "/" | "%" => {
+                            Value::Int(eval_arithmetic(name, arg0.get_int()?, arg1.get_int()?)?)
+                        }
+                        "and" => Value::Bool(arg0.get_bool()? && arg1.get_bool()?),
+                        "or" => Value::Bool(arg0.get_bool()? || arg1.get_bool()?),
+                        "==" => Value::Bool(arg0.get_int()? == arg1.get_int()?),
+                        "!=" => Value::Bool(arg0.get_int()? != arg1.get_int()?),
+                        "<" => Value::Bool(arg0.get_int()? < arg1.get_int()?),
+                        "<=" => Value::Bool(arg0.get_int()? <= arg1.get_int()?),
+                        ">" => Value::Bool(arg0.get_int()? > arg1.get_int()?),
+                        ">=" => Value::Bool(arg0.get_int()? >= arg1.get_int()?),
                         "[]" => {
-                            if let ExpressionKind::Id(id) = &args[0].kind {
-                                if let Value::Array(values) = &self.get_value_by_name(id) {
-                                    values[arg1.get_int() as usize].clone()
-                                } else {
-                                    panic!("Not an array");
-                                }
-                            } else {
-                                panic!("Not an array")
-                            }
+                            let values = arg0.get_array()?;
+                            values[get_index(arg1.get_int()?, values.len())?].clone()
                         }
-                        _ => self.run_call_expr(name, args),
+                        _ => self.run_call_expr(name, args, expr)?,
                     }
                 } else if args.len() == 1 {
                     match name.as_str() {
-                        "!" => Value::Bool(!self.eval_expression(&args[0]).get_bool()),
-                        "-" => Value::Int(-self.eval_expression(&args[0]).get_int()),
-                        _ => self.run_call_expr(name, args),
+                        "!" => Value::Bool(!self.eval_expression(&args[0])?.get_bool()?),
+                        "-" => Value::Int(eval_arithmetic(
+                            "-",
+                            0,
+                            self.eval_expression(&args[0])?.get_int()?,
+                        )?),
+                        _ => self.run_call_expr(name, args, expr)?,
                     }
                 } else {
-                    self.run_call_expr(name, args)
+                    self.run_call_expr(name, args, expr)?
                 }
             }
+            ExpressionKind::Field(record, field) => {
+                self.eval_expression(record)?.get_field(field)?
+            }
             ExpressionKind::Array(contents) => {
                 let mut values = Vec::new();
 
                 for expr in contents {
-                    values.push(self.eval_expression(expr));
+                    values.push(self.eval_expression(expr)?);
                 }
 
                 Value::Array(values)
             }
-        }
+        };
+        Ok(value)
     }
 
-    fn run_call_expr(&mut self, name: &String, args: &[Expression]) -> Value {
-        self.run_call(name, args);
+    fn run_call_expr(
+        &mut self,
+        name: &String,
+        args: &[Expression],
+        expr: &Expression,
+    ) -> RunResult<Value> {
+        self.run_call(name, expr.binding, args, expr.line, expr.col)?;
         let ret_value = if let Signal::Return(value) = &self.signal {
             value.clone()
         } else {
-            panic!("Procedure '{name}' did not return")
+            return Err(RuntimeError::new(format!(
+                "procedure '{}' ended without returning a value",
+                name
+            ))
+            .into());
         };
         self.signal = Signal::Normal;
-        ret_value
+        Ok(ret_value)
+    }
+
+    /// The value of a variable of the call being run, of the main program
+    /// or captured by the procedure.
+    fn var(&self, binding: Binding) -> &Value {
+        match self.locals.last() {
+            Some(locals) if locals.depth == binding.depth => &locals.vars[binding.slot],
+            Some(locals) => locals
+                .captured(binding)
+                .unwrap_or_else(|| &self.globals[binding.slot]),
+            None => &self.globals[binding.slot],
+        }
+    }
+
+    /// The variables that can be changed, of the call being run or of the
+    /// main program, as the captured ones are only copies.
+    fn vars_mut(&mut self, depth: usize) -> &mut Vec<Value> {
+        match self.locals.last_mut() {
+            Some(locals) if locals.depth == depth => &mut locals.vars,
+            _ => &mut self.globals,
+        }
+    }
+
+    /// The variable, or the element or field of it at the end of `path`.
+    fn part_mut(&mut self, binding: Binding, path: &[usize]) -> &mut Value {
+        let mut value = &mut self.vars_mut(binding.depth)[binding.slot];
+        for pos in path {
+            value = value.get_part_mut(*pos);
+        }
+        value
+    }
+
+    /// Stores the value of a new variable in its slot, which may have been
+    /// used by a block that ended.
+    fn declare(&mut self, binding: Binding, value: Value) {
+        let vars = self.vars_mut(binding.depth);
+        if vars.len() <= binding.slot {
+            // the variables before it may be globals of a failed run
+            vars.resize(binding.slot + 1, Value::Int(0));
+        }
+        vars[binding.slot] = value;
+    }
+
+    /// Gives an error the position of the innermost expression or statement
+    /// it goes through, along with the calls that are active there.
+    fn locate(&self, mut unwind: Unwind, line: usize, col: usize) -> Unwind {
+        if let Unwind::Error(err) = &mut unwind {
+            if err.line == 0 {
+                err.line = line;
+                err.col = col;
+                err.trace = self.call_stack.iter().rev().cloned().collect();
+            }
+        }
+        unwind
     }
+}
 
-    fn get_value_by_name(&self, name: &String) -> Value {
-        let vars = self.variables.iter().rev();
-        for var in vars {
-            if var.0 == *name {
-                return var.1.clone();
+/// The value of a literal of the given type.
+pub(crate) fn literal(lit: &str, r#type: &PrimitiveType) -> Value {
+    match r#type {
+        PrimitiveType::Int => Value::Int(lit.parse::<i64>().unwrap()),
+        PrimitiveType::Bool => Value::Bool(lit == "true"),
+        PrimitiveType::Char => Value::Char(lit.chars().nth(1).unwrap()),
+        PrimitiveType::Arr(r#type) => match **r#type {
+            PrimitiveType::Char => {
+                Value::Array(unescape_string_lit(lit).chars().map(Value::Char).collect())
             }
+            _ => panic!(
+                "Literal arrays can only be of type Char[], {}[] given",
+                r#type
+            ),
+        },
+        PrimitiveType::Record(..)
+        | PrimitiveType::Enum(..)
+        | PrimitiveType::Pr(..)
+        | PrimitiveType::Var(_) => unreachable!(),
+    }
+}
+
+/// The value of a variable declared without one.
+pub(crate) fn default_value(
+    r#type: &PrimitiveType,
+    structs: &HashMap<String, Vec<(String, PrimitiveType)>>,
+    enums: &HashMap<String, Vec<(String, Vec<PrimitiveType>)>>,
+) -> Value {
+    let default = |r#type| default_value(r#type, structs, enums);
+    match r#type {
+        PrimitiveType::Int => Value::Int(0),
+        PrimitiveType::Bool => Value::Bool(false),
+        PrimitiveType::Char => Value::Char('\0'),
+        PrimitiveType::Arr(_) => Value::Array(Vec::new()),
+        PrimitiveType::Record(name) => Value::Record(
+            name.to_string(),
+            structs[name]
+                .iter()
+                .map(|(field, r#type)| (field.to_string(), default(r#type)))
+                .collect(),
+        ),
+        // procedures have no default, the analyzer asks for a value
+        PrimitiveType::Pr(..) | PrimitiveType::Var(_) => unreachable!(),
+        // the first variant that doesn't contain the enum again
+        PrimitiveType::Enum(name) => {
+            let (variant, types) = enums[name]
+                .iter()
+                .find(|(_, types)| !types.contains(r#type))
+                .unwrap();
+            Value::Variant(
+                name.to_string(),
+                variant.to_string(),
+                types.iter().map(default).collect(),
+            )
         }
-        panic!("Undeclared variable '{name}' used");
     }
+}
 
-    fn set_value_by_name(&mut self, name: &String, value: Value) {
-        let vars = self.variables.iter().enumerate().rev();
-        for (i, var) in vars {
-            if var.0 == *name {
-                self.variables[i].1 = value;
-                return;
+/// Writes a value to stdout the way `print` does, strings without quotes.
+pub(crate) fn print_value(value: Value) -> Result<(), RuntimeError> {
+    match value {
+        Value::Int(int) => print!("{}", int),
+        Value::Bool(boolean) => print!("{}", boolean),
+        Value::Char(character) => print!("{}", character),
+        Value::Array(values) => {
+            for value in values {
+                print!("{}", value.get_char()?);
             }
         }
-        panic!("Undeclared variable '{name}' used");
+        value @ (Value::Record(..) | Value::Variant(..) | Value::Closure(_)) => {
+            print!("{}", value)
+        }
+    };
+    let _ = std::io::stdout().flush();
+    Ok(())
+}
+
+/// A line of stdin, as `input` returns it.
+pub(crate) fn read_input() -> Value {
+    let mut buffer = String::new();
+    let mut values = Vec::new();
+
+    let _ = stdin().read_line(&mut buffer);
+    let _ = buffer.strip_suffix('\n');
+    for ch in buffer.chars() {
+        values.push(Value::Char(ch))
     }
+    Value::Array(values)
+}
 
-    fn start_scope(&mut self) {
-        self.num_vars_scope.push(0);
+pub(crate) fn string_to_int(string: &Value) -> Result<Value, RuntimeError> {
+    let mut chars = String::new();
+    for value in string.get_array()? {
+        chars.push(value.get_char()?);
     }
+    // the line break that `input` keeps
+    let text = chars.strip_suffix('\n').unwrap_or(&chars);
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    let int = text
+        .parse()
+        .map_err(|_| RuntimeError::new(format!("'{}' is not a valid integer", text)))?;
+    Ok(Value::Int(int))
+}
+
+pub(crate) fn int_to_char(int: &Value) -> Result<Value, RuntimeError> {
+    let int = int.get_int()?;
+    let ch = u32::try_from(int)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| RuntimeError::new(format!("{} is not a valid character", int)))?;
+    Ok(Value::Char(ch))
+}
 
-    fn end_scope(&mut self) {
-        for _ in 0..self.num_vars_scope.pop().unwrap() {
-            self.variables.pop();
+/// Runs `push`, `pop`, `insert` or `remove` on an array, given the rest of
+/// the arguments. Returns the removed element, if any.
+pub(crate) fn array_call(
+    name: &str,
+    array: &mut Vec<Value>,
+    values: &[Value],
+) -> Result<Option<Value>, RuntimeError> {
+    let removed = match (name, values) {
+        ("push", [value]) => {
+            array.push(value.clone());
+            None
         }
+        ("pop", []) => Some(
+            array
+                .pop()
+                .ok_or_else(|| RuntimeError::new("pop from an empty array".to_string()))?,
+        ),
+        ("insert", [idx, value]) => {
+            // an element can also be inserted at the end
+            let idx = idx.get_int()?;
+            let pos = get_index(idx, array.len() + 1).map_err(|_| {
+                RuntimeError::new(format!(
+                    "insertion index out of bounds: the length is {} but the index is {}",
+                    array.len(),
+                    idx
+                ))
+            })?;
+            array.insert(pos, value.clone());
+            None
+        }
+        ("remove", [idx]) => Some(array.remove(get_index(idx.get_int()?, array.len())?)),
+        _ => unreachable!(),
+    };
+    Ok(removed)
+}
+
+pub(crate) fn eval_arithmetic(op: &str, a: i64, b: i64) -> Result<i64, RuntimeError> {
+    let result = match op {
+        "/" | "%" if b == 0 => return Err(RuntimeError::new("division by zero".to_string())),
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" => a.checked_div(b),
+        "%" => a.checked_rem(b),
+        _ => unreachable!(),
+    };
+    result.ok_or_else(|| RuntimeError::new("integer overflow".to_string()))
+}
+
+/// Whether the value has the shape of the pattern, collecting the values of
+/// the names it binds.
+pub(crate) fn match_pattern(
+    pattern: &Pattern,
+    value: &Value,
+    bindings: &mut Vec<(Binding, Value)>,
+) -> bool {
+    match (&pattern.kind, value) {
+        (PatternKind::Wildcard, _) => true,
+        (PatternKind::Binding(_), _) => {
+            bindings.push((pattern.binding.unwrap(), value.clone()));
+            true
+        }
+        (PatternKind::Lit(lit), _) => *value == lit_value(lit),
+        (PatternKind::Variant(name, fields), Value::Variant(_, variant, payload)) => {
+            name == variant
+                && fields
+                    .iter()
+                    .zip(payload)
+                    .all(|(field, value)| match_pattern(field, value, bindings))
+        }
+        (PatternKind::Variant(name, fields), Value::Record(record, values)) => {
+            name == record
+                && fields
+                    .iter()
+                    .zip(values)
+                    .all(|(field, (_, value))| match_pattern(field, value, bindings))
+        }
+        (PatternKind::Variant(..), _) => false,
+    }
+}
+
+fn lit_value(lit: &str) -> Value {
+    match lit {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ if lit.starts_with('\'') => Value::Char(lit.chars().nth(1).unwrap()),
+        _ => Value::Int(lit.parse().unwrap()),
+    }
+}
+
+/// Checks that `idx` is inside an array of length `len`.
+pub(crate) fn get_index(idx: i64, len: usize) -> Result<usize, RuntimeError> {
+    usize::try_from(idx)
+        .ok()
+        .filter(|pos| *pos < len)
+        .ok_or_else(|| {
+            RuntimeError::new(format!(
+                "index out of bounds: the length is {} but the index is {}",
+                len, idx
+            ))
+        })
+}
+
+pub(crate) fn get_range(
+    start: i64,
+    end: i64,
+    len: usize,
+) -> Result<std::ops::Range<usize>, RuntimeError> {
+    match (usize::try_from(start), usize::try_from(end)) {
+        (Ok(start), Ok(end)) if start <= end && end <= len => Ok(start..end),
+        _ => Err(RuntimeError::new(format!(
+            "range {}..{} out of bounds for length {}",
+            start, end, len
+        ))),
     }
 }
@@ -1,22 +1,199 @@
-use interpreter::Interpreter;
+use code_generation::asm_gen::generate_asm;
+use code_generation::ir_gen::generate_ir;
+use code_generation::optimizer::optimize;
+use interpreter::RuntimeError;
+use std::fmt;
 use std::fs;
-use std::process;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use syntax_analysis::errors::{ErrorFormat, Errors};
+use vm::Vm;
 
+pub mod code_generation;
 pub mod core;
+pub mod formatter;
 pub mod interpreter;
+pub mod lsp;
+pub mod repl;
 pub mod syntax_analysis;
+pub mod vm;
 
-pub fn run(filename: String, debug_ast: bool) {
-    let source = fs::read_to_string(filename).unwrap();
-    let ast = syntax_analysis::produce_ast(source);
-    let mut interpreter = Interpreter::new();
+/// Everything that can stop a program from being built or run. The
+/// diagnostics of the lexer, parser and analyzer are kept apart so callers
+/// know which stage failed.
+#[derive(Debug)]
+pub enum YoraError {
+    Io(io::Error),
+    Lex(Errors),
+    Parse(Errors),
+    Semantic(Errors),
+    Runtime(RuntimeError),
+    Build(String),
+}
+
+impl YoraError {
+    pub fn diagnostics(&self) -> Option<&Errors> {
+        match self {
+            YoraError::Lex(errors) | YoraError::Parse(errors) | YoraError::Semantic(errors) => {
+                Some(errors)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for YoraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YoraError::Io(err) => write!(f, "{}", err),
+            YoraError::Runtime(err) => write!(f, "{}", err),
+            YoraError::Build(message) => write!(f, "{}", message),
+            YoraError::Lex(errors) | YoraError::Parse(errors) | YoraError::Semantic(errors) => {
+                for (i, err) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}:{}: {}", err.line, err.col, err.kind.message())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for YoraError {}
+
+impl From<io::Error> for YoraError {
+    fn from(err: io::Error) -> Self {
+        YoraError::Io(err)
+    }
+}
+
+impl From<RuntimeError> for YoraError {
+    fn from(err: RuntimeError) -> Self {
+        YoraError::Runtime(err)
+    }
+}
+
+/// Interprets the file. Errors are printed to stderr, in `error_format` for
+/// diagnostics, before being returned.
+pub fn run(filename: String, debug_ast: bool, error_format: ErrorFormat) -> Result<(), YoraError> {
+    let source = read_source(&filename)?;
+    let ast = syntax_analysis::produce_ast(source.clone())
+        .inspect_err(|err| report(err, &filename, &source, error_format))?;
 
     if debug_ast {
         for statement in &ast {
             print!("{}", statement);
         }
-        process::exit(0);
+        return Ok(());
     }
 
-    interpreter.run(&ast);
+    Vm::new()
+        .run(&ast)
+        .inspect_err(|err| report(err, &filename, &source, error_format))
+}
+
+/// Compiles the file to an executable next to it. Errors are printed to
+/// stderr, like in `run`.
+pub fn build(filename: String, debug_ir: bool, error_format: ErrorFormat) -> Result<(), YoraError> {
+    let source = read_source(&filename)?;
+    let ast = syntax_analysis::produce_ast(source.clone())
+        .inspect_err(|err| report(err, &filename, &source, error_format))?;
+
+    let ir = generate_ir(&ast).map_err(|message| {
+        let err = YoraError::Build(message);
+        eprintln!("{}", err);
+        err
+    })?;
+    let ir = optimize(ir);
+
+    if debug_ir {
+        print!("{}", ir);
+        return Ok(());
+    }
+
+    let path = Path::new(&filename);
+    let asm_path = path.with_extension("asm");
+    let obj_path = path.with_extension("o");
+    let bin_path = path.with_extension("");
+
+    fs::write(&asm_path, generate_asm(ir)).inspect_err(|err| eprintln!("{}", err))?;
+
+    let assembled = Command::new("nasm")
+        .arg("-felf64")
+        .arg(&asm_path)
+        .arg("-o")
+        .arg(&obj_path)
+        .status();
+    match assembled {
+        Ok(status) if status.success() => {}
+        Ok(_) => {
+            let err = YoraError::Build(format!("Failed to assemble '{}'", asm_path.display()));
+            eprintln!("{}", err);
+            return Err(err);
+        }
+        Err(_) => {
+            eprintln!(
+                "nasm not found, assembly written to '{}'",
+                asm_path.display()
+            );
+            return Ok(());
+        }
+    }
+
+    let linked = Command::new("ld")
+        .arg(&obj_path)
+        .arg("-o")
+        .arg(&bin_path)
+        .status();
+    if !matches!(linked, Ok(status) if status.success()) {
+        let err = YoraError::Build(format!("Failed to link '{}'", obj_path.display()));
+        eprintln!("{}", err);
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// Rewrites the file in the canonical format. With `check` the file is left
+/// as it is and the result is whether it was already formatted.
+pub fn fmt(filename: String, check: bool, error_format: ErrorFormat) -> Result<bool, YoraError> {
+    let source = read_source(&filename)?;
+    let formatted = formatter::format(&source)
+        .inspect_err(|err| report(err, &filename, &source, error_format))?;
+
+    if formatted == source {
+        return Ok(true);
+    }
+    if check {
+        eprintln!("'{}' is not formatted", filename);
+        return Ok(false);
+    }
+
+    fs::write(&filename, formatted).inspect_err(|err| eprintln!("{}", err))?;
+    Ok(true)
+}
+
+fn read_source(filename: &str) -> Result<String, YoraError> {
+    fs::read_to_string(filename).map_err(|err| {
+        eprintln!("Could not read '{}': {}", filename, err);
+        YoraError::Io(err)
+    })
+}
+
+/// Prints an error to stderr, with the line of `source` it points at.
+pub(crate) fn report(err: &YoraError, filename: &str, source: &str, error_format: ErrorFormat) {
+    match err {
+        YoraError::Runtime(runtime_error) => {
+            let mut errors = Errors::new();
+            errors.push(runtime_error.to_diagnostic());
+            eprint!("{}", errors.emit(filename, source, error_format));
+        }
+        _ => match err.diagnostics() {
+            Some(errors) => eprint!("{}", errors.emit(filename, source, error_format)),
+            None => eprintln!("{}", err),
+        },
+    }
 }
@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+use crate::core::PrimitiveType;
+use crate::syntax_analysis::analyzer::{analyze, get_len, is_procedure_name, Analyzer};
+use crate::syntax_analysis::errors::{Error, Errors, Severity};
+use crate::syntax_analysis::lexer::lex;
+use crate::syntax_analysis::parser::expression::*;
+use crate::syntax_analysis::parser::parse;
+use crate::syntax_analysis::parser::statement::*;
+use crate::YoraError;
+use json::Json;
+
+pub mod json;
+
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+const COMPLETION_KIND_FUNCTION: usize = 3;
+/// Longer messages are skipped instead of read into memory.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// Keeps the text of the open documents, by uri. Every change is sent whole.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    exited: bool,
+}
+
+/// Serves the messages of `input` until the client asks to exit. Returns
+/// whether it was shut down first, as the exit code depends on it.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<bool> {
+    let mut server = Server::new();
+
+    while !server.exited {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => Json::parse(&message),
+            Ok(None) => break,
+            // the message is lost, but the next one can still be read
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => Err(err.to_string()),
+            Err(err) => return Err(err),
+        };
+        // a message that can't be read is answered without an id, as it is unknown
+        let replies = match message {
+            Ok(message) => server.handle(&message),
+            Err(err) => vec![error_reply(Json::Null, PARSE_ERROR, &err)],
+        };
+
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+    }
+
+    Ok(server.shutdown)
+}
+
+/// Reads the content of a message with a `Content-Length` header. `None` at
+/// the end of the input. A message without a valid length, or one that is too
+/// long, is an `InvalidData` error, and the input is left at the next one.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut len = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            len = value.trim().parse().ok();
+        }
+    }
+
+    let Some(len) = len else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing Content-Length header",
+        ));
+    };
+    if len > MAX_MESSAGE_LEN {
+        io::copy(&mut input.by_ref().take(len as u64), &mut io::sink())?;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too long", len),
+        ));
+    }
+    let mut content = vec![0; len];
+    input.read_exact(&mut content)?;
+    String::from_utf8(content)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+fn error_reply(id: Json, code: i32, message: &str) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        (
+            "error",
+            Json::object([
+                ("code", Json::Number(code.into())),
+                ("message", message.into()),
+            ]),
+        ),
+    ])
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /// Handles a request or notification, returning the messages to send
+    /// back.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let id = message.get("id").clone();
+        let params = message.get("params");
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+
+        let result = match message.get("method").as_str().unwrap_or("") {
+            "initialize" => Json::object([
+                (
+                    "capabilities",
+                    Json::object([
+                        ("textDocumentSync", Json::Number(1.0)),
+                        ("hoverProvider", true.into()),
+                        ("definitionProvider", true.into()),
+                        ("completionProvider", Json::object([])),
+                    ]),
+                ),
+                ("serverInfo", Json::object([("name", "yora-lsp".into())])),
+            ]),
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "exit" => {
+                self.exited = true;
+                return Vec::new();
+            }
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text");
+                return self.update(uri, text.as_str().map(str::to_string));
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").as_array();
+                let text = changes.and_then(|changes| changes.last()?.get("text").as_str());
+                return self.update(uri, text.map(str::to_string));
+            }
+            "textDocument/didClose" => return self.update(uri, None),
+            "textDocument/hover" => self.hover(uri, params),
+            "textDocument/definition" => self.definition(uri, params),
+            "textDocument/completion" => completion(),
+            _ if id == Json::Null => return Vec::new(),
+            _ => return vec![error_reply(id, METHOD_NOT_FOUND, "method not found")],
+        };
+
+        vec![Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id),
+            ("result", result),
+        ])]
+    }
+
+    /// Stores the new text of a document, `None` if it was closed, and
+    /// publishes its diagnostics.
+    fn update(&mut self, uri: &str, text: Option<String>) -> Vec<Json> {
+        let errors = match text {
+            Some(text) => {
+                let (_, errors) = check(&text);
+                self.documents.insert(uri.to_string(), text);
+                errors
+            }
+            None => {
+                self.documents.remove(uri);
+                Errors::new()
+            }
+        };
+
+        let diagnostics = errors.iter().map(to_diagnostic).collect();
+        vec![Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            (
+                "params",
+                Json::object([
+                    ("uri", uri.into()),
+                    ("diagnostics", Json::Array(diagnostics)),
+                ]),
+            ),
+        ])]
+    }
+
+    fn hover(&self, uri: &str, params: &Json) -> Json {
+        let Some((ast, (line, col))) = self.analyzed(uri, params) else {
+            return Json::Null;
+        };
+
+        let mut found = None;
+        visit_expressions(&ast, &mut |expr| {
+            if contains(expr, line, col) {
+                found = Some(expr.clone());
+            }
+        });
+
+        let Some(expr) = found else {
+            return Json::Null;
+        };
+        let Some(text) = describe(&expr) else {
+            return Json::Null;
+        };
+
+        Json::object([
+            (
+                "contents",
+                Json::object([
+                    ("kind", "markdown".into()),
+                    ("value", format!("```yora\n{}\n```", text).into()),
+                ]),
+            ),
+            ("range", to_range(expr.line, expr.col, get_len(&expr))),
+        ])
+    }
+
+    fn definition(&self, uri: &str, params: &Json) -> Json {
+        let Some((ast, (line, col))) = self.analyzed(uri, params) else {
+            return Json::Null;
+        };
+        let lines: Vec<&str> = self.documents[uri].lines().collect();
+
+        let mut resolver = Resolver {
+            lines: &lines,
+            target: (line, col),
+            scopes: vec![Vec::new()],
+            procedures: HashMap::new(),
+            found: None,
+        };
+        resolver.collect_procedures(&ast);
+        resolver.resolve_sequence(&ast);
+
+        match resolver.found {
+            Some((line, col, len)) => {
+                Json::object([("uri", uri.into()), ("range", to_range(line, col, len))])
+            }
+            None => Json::Null,
+        }
+    }
+
+    /// The analyzed statements of a document, even if they have errors,
+    /// and the 1 based position of the request.
+    fn analyzed(&self, uri: &str, params: &Json) -> Option<(Vec<Statement>, (usize, usize))> {
+        let text = self.documents.get(uri)?;
+        let position = params.get("position");
+        let line = position.get("line").as_usize()? + 1;
+        let col = position.get("character").as_usize()? + 1;
+
+        let (ast, _) = check(text);
+        Some((ast?, (line, col)))
+    }
+}
+
+/// Analyzes a document. The statements are returned if it parsed, typed
+/// as far as the analyzer got.
+fn check(text: &str) -> (Option<Vec<Statement>>, Errors) {
+    let mut ast = match lex(text.to_string()).and_then(parse) {
+        Ok(ast) => ast,
+        Err(YoraError::Lex(errors) | YoraError::Parse(errors)) => return (None, errors),
+        Err(_) => return (None, Errors::new()),
+    };
+
+    match analyze(&mut ast) {
+        Err(YoraError::Semantic(errors)) => (Some(ast), errors),
+        _ => (Some(ast), Errors::new()),
+    }
+}
+
+fn completion() -> Json {
+    let analyzer = Analyzer::new();
+    let items = analyzer
+        .procedures()
+        .into_iter()
+        .map(|(name, args, ret)| {
+            Json::object([
+                ("label", name.into()),
+                ("kind", COMPLETION_KIND_FUNCTION.into()),
+                ("detail", format_signature(name, &args, ret.as_ref()).into()),
+            ])
+        })
+        .collect();
+
+    Json::Array(items)
+}
+
+fn to_diagnostic(error: &Error) -> Json {
+    let mut message = error.kind.message();
+    if let Some(label) = error.kind.label().filter(|label| !message.contains(label)) {
+        message.push_str(&format!(": {}", label));
+    }
+    for note in &error.notes {
+        message.push_str(&format!("\nnote: {}", note));
+    }
+    if let Some(help) = &error.help {
+        message.push_str(&format!("\nhelp: {}", help));
+    }
+
+    let severity: usize = match error.kind.severity() {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+
+    Json::object([
+        ("range", to_range(error.line, error.col, error.len)),
+        ("severity", severity.into()),
+        ("code", error.kind.code().into()),
+        ("source", "yora".into()),
+        ("message", message.into()),
+    ])
+}
+
+/// Converts a 1 based position to a 0 based LSP range on a single line.
+fn to_range(line: usize, col: usize, len: usize) -> Json {
+    let position =
+        |col: usize| Json::object([("line", (line - 1).into()), ("character", (col - 1).into())]);
+    Json::object([("start", position(col)), ("end", position(col + len))])
+}
+
+fn format_signature(name: &str, args: &[PrimitiveType], ret: Option<&PrimitiveType>) -> String {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    match ret {
+        Some(ret) => format!("{}({}) -> {}", name, args.join(", "), ret),
+        None => format!("{}({})", name, args.join(", ")),
+    }
+}
+
+fn describe(expr: &Expression) -> Option<String> {
+    let r#type = expr.r#type.as_ref();
+
+    match &expr.kind {
+        ExpressionKind::Id(name) | ExpressionKind::Field(_, name) => {
+            Some(format!("{}: {}", name, r#type?))
+        }
+        ExpressionKind::Call(name, args) if is_procedure_name(name) => {
+            let args: Option<Vec<PrimitiveType>> =
+                args.iter().map(|arg| arg.r#type.clone()).collect();
+            Some(format_signature(name, &args?, r#type))
+        }
+        _ => r#type.map(|r#type| r#type.to_string()),
+    }
+}
+
+fn contains(expr: &Expression, line: usize, col: usize) -> bool {
+    expr.line == line && expr.col <= col && col < expr.col + get_len(expr)
+}
+
+/// A 1 based `(line, col, len)` position.
+type Span = (usize, usize, usize);
+
+/// Finds the declaration of the name at `target`, following the scopes of
+/// the analyzer.
+struct Resolver<'a> {
+    lines: &'a [&'a str],
+    target: (usize, usize),
+    scopes: Vec<Vec<(String, Span)>>,
+    procedures: HashMap<String, Span>,
+    found: Option<Span>,
+}
+
+impl Resolver<'_> {
+    fn collect_procedures(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            // a struct is found through the calls to its constructor
+            if let StatementKind::Procedure { name, .. } | StatementKind::Struct { name, .. } =
+                &statement.kind
+            {
+                let position = self.find_name(name, statement.line, statement.col);
+                self.procedures.insert(name.clone(), position);
+            }
+        }
+    }
+
+    fn resolve_sequence(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_block(&mut self, statements: &[Statement]) {
+        self.scopes.push(Vec::new());
+        self.resolve_sequence(statements);
+        self.scopes.pop();
+    }
+
+    fn resolve_statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Procedure {
+                name, args, block, ..
+            } => {
+                // the block sees the procedure itself, its arguments and the
+                // variables around it
+                let position = self.find_name(name, statement.line, statement.col);
+                let mut scope = vec![(name.clone(), position)];
+                for arg in args {
+                    let arg_position = self.find_name(&arg.name, statement.line, position.1);
+                    scope.push((arg.name.clone(), arg_position));
+                }
+                self.scopes.push(scope);
+                self.resolve_sequence(block);
+                self.scopes.pop();
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .push((name.clone(), position));
+            }
+            StatementKind::Call { name, args, .. } => {
+                if statement.line == self.target.0
+                    && (statement.col..statement.col + name.chars().count())
+                        .contains(&self.target.1)
+                {
+                    self.found = self.find_procedure(name);
+                }
+                for arg in args {
+                    self.resolve_expression(arg);
+                }
+            }
+            StatementKind::Return { value } => self.resolve_expression(value),
+            StatementKind::Declare { name, value, .. } => {
+                if let Some(value) = value {
+                    self.resolve_expression(value);
+                }
+                let position = self.find_name(name, statement.line, statement.col);
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .push((name.clone(), position));
+            }
+            StatementKind::Assign { dest, src } => {
+                self.resolve_expression(dest);
+                self.resolve_expression(src);
+            }
+            StatementKind::If { cond, block } | StatementKind::While { cond, block } => {
+                self.resolve_expression(cond);
+                self.resolve_block(block);
+            }
+            StatementKind::IfElse {
+                cond,
+                true_block,
+                false_block,
+            } => {
+                self.resolve_expression(cond);
+                self.resolve_block(true_block);
+                self.resolve_block(false_block);
+            }
+            StatementKind::Loop { block } => self.resolve_block(block),
+            StatementKind::For {
+                var,
+                iterable,
+                block,
+                ..
+            } => {
+                self.resolve_expression(iterable);
+                let position = self.find_name(var, statement.line, statement.col);
+                self.scopes.push(vec![(var.clone(), position)]);
+                self.resolve_sequence(block);
+                self.scopes.pop();
+            }
+            StatementKind::Match { value, arms } => {
+                self.resolve_expression(value);
+                for (pattern, block) in arms {
+                    let bindings = pattern
+                        .bindings()
+                        .into_iter()
+                        .map(|binding| {
+                            let name = binding.to_string();
+                            let len = name.chars().count();
+                            (name, (binding.line, binding.col, len))
+                        })
+                        .collect();
+                    self.scopes.push(bindings);
+                    self.resolve_sequence(block);
+                    self.scopes.pop();
+                }
+            }
+            StatementKind::Struct { .. }
+            | StatementKind::Enum { .. }
+            | StatementKind::Continue
+            | StatementKind::Break => {}
+        }
+    }
+
+    fn resolve_expression(&mut self, expr: &Expression) {
+        visit_expression(expr, &mut |expr| {
+            if !contains(expr, self.target.0, self.target.1) {
+                return;
+            }
+            match &expr.kind {
+                ExpressionKind::Id(id) => {
+                    self.found = self
+                        .scopes
+                        .iter()
+                        .flatten()
+                        .rev()
+                        .find(|(name, _)| name == id)
+                        .map(|(_, position)| *position);
+                }
+                ExpressionKind::Call(name, _) if is_procedure_name(name) => {
+                    self.found = self.find_procedure(name);
+                }
+                _ => {}
+            }
+        });
+    }
+
+    /// The innermost declaration in scope with the name, or otherwise the
+    /// struct or procedure of the global scope with it.
+    fn find_procedure(&self, name: &str) -> Option<Span> {
+        self.scopes
+            .iter()
+            .flatten()
+            .rev()
+            .find(|(var, _)| var == name)
+            .map(|(_, position)| *position)
+            .or_else(|| self.procedures.get(name).copied())
+    }
+
+    /// The position of `name` as a whole word on `line`, at or after `col`.
+    fn find_name(&self, name: &str, line: usize, col: usize) -> Span {
+        let len = name.chars().count();
+        let Some(text) = self.lines.get(line - 1) else {
+            return (line, col, len);
+        };
+        let chars: Vec<char> = text.chars().collect();
+        let is_word = |ch: Option<&char>| ch.is_some_and(|ch| ch.is_alphanumeric() || *ch == '_');
+
+        for start in col.saturating_sub(1)..chars.len() {
+            let end = start + len;
+            if end <= chars.len()
+                && chars[start..end].iter().copied().eq(name.chars())
+                && !is_word(start.checked_sub(1).and_then(|i| chars.get(i)))
+                && !is_word(chars.get(end))
+            {
+                return (line, start + 1, len);
+            }
+        }
+
+        (line, col, len)
+    }
+}
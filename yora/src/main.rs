@@ -1,22 +1,71 @@
 use std::env;
 use std::process;
-use yora::run;
+use yora::repl::repl;
+use yora::syntax_analysis::errors::ErrorFormat;
+use yora::{build, fmt, run};
+
+enum Command {
+    Run,
+    Build,
+    Fmt,
+}
+
+struct Args {
+    filename: String,
+    command: Command,
+    check: bool,
+    debug_ast: bool,
+    debug_ir: bool,
+    error_format: ErrorFormat,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.is_empty() {
+        repl();
+        return;
+    }
+
+    let args = parse_args(args);
 
-    let (filename, debug_ast) = parse_args(args);
+    let result = match args.command {
+        Command::Run => run(args.filename, args.debug_ast, args.error_format),
+        Command::Build => build(args.filename, args.debug_ir, args.error_format),
+        Command::Fmt => fmt(args.filename, args.check, args.error_format).map(|formatted| {
+            if !formatted {
+                process::exit(1);
+            }
+        }),
+    };
 
-    run(filename, debug_ast);
+    // the errors have already been reported
+    if result.is_err() {
+        process::exit(1);
+    }
 }
 
-fn parse_args(args: Vec<String>) -> (String, bool) {
+fn parse_args(args: Vec<String>) -> Args {
     let mut filename = String::new();
+    let mut command = Command::Run;
+    let mut check = false;
     let mut debug_ast = false;
+    let mut debug_ir = false;
+    let mut error_format = ErrorFormat::Human;
 
-    for arg in args {
+    for (i, arg) in args.into_iter().enumerate() {
         match arg.as_str() {
+            "build" if i == 0 => command = Command::Build,
+            "fmt" if i == 0 => command = Command::Fmt,
+            "--check" => check = true,
             "--ast" => debug_ast = true,
+            "--ir" => debug_ir = true,
+            "--error-format=human" => error_format = ErrorFormat::Human,
+            "--error-format=json" => error_format = ErrorFormat::Json,
+            _ if arg.starts_with("--error-format=") => {
+                eprintln!("Unknown error format, expected 'human' or 'json'.");
+                process::exit(1);
+            }
             _ => filename = arg,
         }
     }
@@ -26,5 +75,12 @@ fn parse_args(args: Vec<String>) -> (String, bool) {
         process::exit(1);
     }
 
-    (filename, debug_ast)
+    Args {
+        filename,
+        command,
+        check,
+        debug_ast,
+        debug_ir,
+        error_format,
+    }
 }
@@ -0,0 +1,157 @@
+use std::io::{stdin, stdout, IsTerminal, Write};
+
+use crate::syntax_analysis::analyzer::Analyzer;
+use crate::syntax_analysis::errors::ErrorFormat;
+use crate::syntax_analysis::lexer::lex;
+use crate::syntax_analysis::parser::{parse, parse_expression};
+use crate::vm::Vm;
+use crate::{report, YoraError};
+
+/// Name the inputs are reported with in diagnostics.
+const INPUT_NAME: &str = "<input>";
+
+/// Keeps the declarations of every input, so later inputs can use the
+/// variables and procedures of earlier ones.
+pub struct Repl {
+    analyzer: Analyzer,
+    vm: Vm,
+    show_ast: bool,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads inputs from stdin until its end or a call to `exit`. A line ending
+/// with ':' opens a block, which is closed by an empty line.
+pub fn repl() {
+    let mut repl = Repl::new();
+    let interactive = stdin().is_terminal();
+
+    while !repl.vm.has_exited() {
+        let Some(mut input) = read_line("> ", interactive) else {
+            break;
+        };
+        if opens_block(&input) {
+            while let Some(line) = read_line("... ", interactive) {
+                if line.trim().is_empty() {
+                    break;
+                }
+                input.push_str(&line);
+            }
+        }
+
+        repl.eval(&input);
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            analyzer: Analyzer::new(),
+            vm: Vm::new(),
+            show_ast: false,
+        }
+    }
+
+    /// Runs an input, printing the value of expressions and the errors.
+    pub fn eval(&mut self, input: &str) {
+        let command = input.trim();
+        if command.is_empty() {
+            return;
+        }
+
+        if let Some(command) = command.strip_prefix(':') {
+            self.run_command(command);
+            return;
+        }
+
+        if let Err(err) = self.eval_input(input) {
+            report(&err, INPUT_NAME, input, ErrorFormat::Human);
+        }
+    }
+
+    fn run_command(&mut self, command: &str) {
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+
+        match name {
+            "ast" => {
+                self.show_ast = !self.show_ast;
+                let state = if self.show_ast { "on" } else { "off" };
+                println!("AST printing is {}", state);
+            }
+            "type" => match self.type_of(arg) {
+                Ok(Some(r#type)) => println!("{}", r#type),
+                Ok(None) => println!("no value"),
+                Err(err) => report(&err, INPUT_NAME, arg, ErrorFormat::Human),
+            },
+            "reset" => *self = Repl::new(),
+            _ => eprintln!(
+                "Unknown command ':{}', expected ':ast', ':type <expr>' or ':reset'.",
+                name
+            ),
+        }
+    }
+
+    /// Expressions that have a value are printed, anything else is run as
+    /// statements.
+    fn eval_input(&mut self, input: &str) -> Result<(), YoraError> {
+        let tokens = lex(input.to_string())?;
+
+        if let Ok(mut expr) = parse_expression(tokens.clone()) {
+            match self.analyzer.analyze_global_expression(&mut expr) {
+                Ok(Some(_)) => {
+                    if self.show_ast {
+                        print!("{}", expr.format(""));
+                    }
+                    if let Some(value) = self.vm.evaluate(&expr)? {
+                        println!("{}", value);
+                    }
+                    return Ok(());
+                }
+                // calls like `print(x)` are only valid as statements
+                Err(err) if parse(tokens.clone()).is_err() => return Err(err),
+                _ => {}
+            }
+        }
+
+        let mut ast = parse(tokens)?;
+        let snapshot = self.analyzer.snapshot();
+        self.analyzer.analyze_program(&mut ast)?;
+        if self.show_ast {
+            for statement in &ast {
+                print!("{}", statement);
+            }
+        }
+        // the variables declared by the input may not have a value
+        self.vm
+            .run(&ast)
+            .inspect_err(|_| self.analyzer.restore(snapshot))
+    }
+
+    fn type_of(&mut self, input: &str) -> Result<Option<String>, YoraError> {
+        let mut expr = parse_expression(lex(input.to_string())?)?;
+        let r#type = self.analyzer.analyze_global_expression(&mut expr)?;
+        Ok(r#type.map(|r#type| r#type.to_string()))
+    }
+}
+
+fn read_line(prompt: &str, interactive: bool) -> Option<String> {
+    if interactive {
+        print!("{}", prompt);
+        let _ = stdout().flush();
+    }
+
+    let mut line = String::new();
+    match stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+fn opens_block(line: &str) -> bool {
+    let code = line.split('#').next().unwrap_or_default();
+    code.trim_end().ends_with(':')
+}
@@ -2,32 +2,171 @@ use std::collections::HashMap;
 
 use super::errors::*;
 use super::parser::expression::*;
+use super::parser::pattern::*;
 use super::parser::statement::*;
 use crate::core::*;
+use crate::YoraError;
 
-struct Analyzer<'a> {
+/// The declarations of the global scope at some point, see
+/// `Analyzer::snapshot`.
+pub struct Snapshot(
+    Vec<(String, PrimitiveType)>,
+    Vec<usize>,
+    HashMap<(String, Vec<PrimitiveType>), Option<PrimitiveType>>,
+    HashMap<String, Vec<(String, PrimitiveType)>>,
+    HashMap<String, Vec<(String, Vec<PrimitiveType>)>>,
+    HashMap<Binding, Vec<bool>>,
+    HashMap<Binding, Vec<Binding>>,
+);
+
+/// The builtins that work on arrays of any type, see `analyze_array_call`.
+const ARRAY_PROCEDURES: [&str; 5] = ["len", "push", "pop", "insert", "remove"];
+
+/// Stands for the fields of a constructor that a pattern doesn't look into.
+static WILDCARD: Pattern = Pattern {
+    kind: PatternKind::Wildcard,
+    line: 0,
+    col: 0,
+    binding: None,
+};
+
+/// Keeps the declarations of the global scope between calls, so a program
+/// can be analyzed in pieces, like the inputs of the REPL.
+pub struct Analyzer {
+    // the variables in scope, those of the innermost procedure last
     variables: Vec<(String, PrimitiveType)>,
-    num_vars_scope: Vec<i8>,
+    num_vars_scope: Vec<usize>,
+    // where the variables of every procedure being analyzed start, one
+    // inside the other
+    frames: Vec<usize>,
+    // the variables of the enclosing code used by every procedure being
+    // analyzed
+    captures: Vec<Vec<Binding>>,
+    // the variables of the main program used by every procedure being
+    // analyzed, also through the procedures it calls
+    global_uses: Vec<Vec<Binding>>,
     call_signatures: HashMap<(String, Vec<PrimitiveType>), Option<PrimitiveType>>,
-    errors: &'a mut Errors,
+    structs: HashMap<String, Vec<(String, PrimitiveType)>>,
+    enums: HashMap<String, Vec<(String, Vec<PrimitiveType>)>>,
+    // which arguments of the procedures in scope are passed by reference,
+    // for those that have any
+    ref_params: HashMap<Binding, Vec<bool>>,
+    // the variables of the main program used by the procedures in scope
+    procedure_globals: HashMap<Binding, Vec<Binding>>,
+    // what every `PrimitiveType::Var` was inferred to be, if anything yet
+    type_vars: Vec<Option<PrimitiveType>>,
+    // the name and the return type of the procedure being analyzed
+    procedure: Option<(String, Option<PrimitiveType>)>,
+    // the loops around the code being analyzed in its procedure, and
+    // whether each of them has a `break`
+    loops: Vec<bool>,
+    errors: Errors,
 }
 
-pub fn analyze(ast: &mut Vec<Statement>, errors: &mut Errors) {
-    let mut analyzer = Analyzer::new(errors);
+/// Checks the types of the program, filling in the type of every expression.
+pub fn analyze(ast: &mut [Statement]) -> Result<(), YoraError> {
+    Analyzer::new().analyze_program(ast)
+}
 
-    analyzer.start_scope();
-    for statement in ast {
-        analyzer.analyze_statement(statement);
+impl Default for Analyzer {
+    fn default() -> Self {
+        Self::new()
     }
-    analyzer.end_scope();
+}
+
+impl Analyzer {
+    /// Analyzes statements in the global scope. If there are errors, the
+    /// declarations they made are discarded.
+    pub fn analyze_program(&mut self, ast: &mut [Statement]) -> Result<(), YoraError> {
+        let snapshot = self.snapshot();
+        self.analyze_block(ast);
 
-    if analyzer.errors.should_abort() {
-        analyzer.errors.print_and_abort();
+        let mut unknown = Vec::new();
+        for statement in ast {
+            self.resolve_statement(statement, &mut unknown);
+        }
+        self.report_unknown(unknown);
+        self.finish(snapshot)
+    }
+
+    /// Analyzes an expression in the global scope and returns its type,
+    /// which is `None` for calls to procedures without a return value.
+    pub fn analyze_global_expression(
+        &mut self,
+        expr: &mut Expression,
+    ) -> Result<Option<PrimitiveType>, YoraError> {
+        let snapshot = self.snapshot();
+        self.analyze_expression(expr);
+
+        let unknown = self.resolve_expression(expr).into_iter();
+        self.report_unknown(unknown.map(unknown_type).collect());
+        self.finish(snapshot)?;
+        Ok(expr.r#type.clone())
+    }
+
+    /// The procedures that can be called by name, like the builtins, with
+    /// the types of their arguments and of their return value, sorted by name.
+    pub fn procedures(&self) -> Vec<(&str, Vec<PrimitiveType>, Option<PrimitiveType>)> {
+        let array_procedures = ARRAY_PROCEDURES.into_iter().filter_map(|name| {
+            let (args, ret) = array_signature(name, PrimitiveType::Var(0))?;
+            Some((name, args, ret))
+        });
+        let mut procedures: Vec<_> = self
+            .call_signatures
+            .iter()
+            .filter(|((name, _), _)| is_procedure_name(name))
+            .map(|((name, args), ret)| (name.as_str(), args.clone(), ret.clone()))
+            .chain(array_procedures)
+            .collect();
+        procedures.sort_by(|(name1, args1, _), (name2, args2, _)| {
+            (name1, args1.len()).cmp(&(name2, args2.len()))
+        });
+        procedures
+    }
+
+    /// Saves the declarations of the global scope, so they can be restored
+    /// if the code analyzed after it fails to run.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot(
+            self.variables.clone(),
+            self.num_vars_scope.clone(),
+            self.call_signatures.clone(),
+            self.structs.clone(),
+            self.enums.clone(),
+            self.ref_params.clone(),
+            self.procedure_globals.clone(),
+        )
     }
-}
 
-impl Analyzer<'_> {
-    fn new(errors: &mut Errors) -> Analyzer {
+    fn finish(&mut self, snapshot: Snapshot) -> Result<(), YoraError> {
+        // the global variables outlive the type variables
+        for (_, r#type) in self.variables.iter_mut() {
+            *r#type = resolve(&self.type_vars, r#type);
+        }
+        self.type_vars.clear();
+
+        if self.errors.should_abort() {
+            self.restore(snapshot);
+            Err(YoraError::Semantic(std::mem::take(&mut self.errors)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Discards the declarations made since the snapshot was taken.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        Snapshot(
+            self.variables,
+            self.num_vars_scope,
+            self.call_signatures,
+            self.structs,
+            self.enums,
+            self.ref_params,
+            self.procedure_globals,
+        ) = snapshot;
+    }
+
+    pub fn new() -> Analyzer {
         let call_signatures = HashMap::from([
             (
                 ("+".to_string(), vec![PrimitiveType::Int; 2]),
@@ -89,26 +228,7 @@ impl Analyzer<'_> {
                 ("!".to_string(), vec![PrimitiveType::Bool]),
                 Some(PrimitiveType::Bool),
             ),
-            (
-                (
-                    "[]".to_string(),
-                    vec![
-                        PrimitiveType::Arr(Box::new(PrimitiveType::Int)),
-                        PrimitiveType::Int,
-                    ],
-                ),
-                Some(PrimitiveType::Int),
-            ),
-            (
-                (
-                    "[]".to_string(),
-                    vec![
-                        PrimitiveType::Arr(Box::new(PrimitiveType::Char)),
-                        PrimitiveType::Int,
-                    ],
-                ),
-                Some(PrimitiveType::Char),
-            ),
+            (("exit".to_string(), vec![PrimitiveType::Int]), None),
             (
                 ("input".to_string(), vec![]),
                 Some(PrimitiveType::Arr(Box::new(PrimitiveType::Char))),
@@ -135,96 +255,141 @@ impl Analyzer<'_> {
                 ),
                 Some(PrimitiveType::Int),
             ),
+            // the type variables of a builtin stand for any type, see
+            // `find_signature`
+            (("print".to_string(), vec![PrimitiveType::Var(0)]), None),
+            (
+                (
+                    "map".to_string(),
+                    vec![
+                        PrimitiveType::Arr(Box::new(PrimitiveType::Var(0))),
+                        PrimitiveType::Pr(
+                            vec![PrimitiveType::Var(0)],
+                            Some(Box::new(PrimitiveType::Var(1))),
+                        ),
+                    ],
+                ),
+                Some(PrimitiveType::Arr(Box::new(PrimitiveType::Var(1)))),
+            ),
+            (
+                (
+                    "filter".to_string(),
+                    vec![
+                        PrimitiveType::Arr(Box::new(PrimitiveType::Var(0))),
+                        PrimitiveType::Pr(
+                            vec![PrimitiveType::Var(0)],
+                            Some(Box::new(PrimitiveType::Bool)),
+                        ),
+                    ],
+                ),
+                Some(PrimitiveType::Arr(Box::new(PrimitiveType::Var(0)))),
+            ),
+            (
+                (
+                    "fold".to_string(),
+                    vec![
+                        PrimitiveType::Arr(Box::new(PrimitiveType::Var(0))),
+                        PrimitiveType::Var(1),
+                        PrimitiveType::Pr(
+                            vec![PrimitiveType::Var(1), PrimitiveType::Var(0)],
+                            Some(Box::new(PrimitiveType::Var(1))),
+                        ),
+                    ],
+                ),
+                Some(PrimitiveType::Var(1)),
+            ),
         ]);
         Analyzer {
             variables: Vec::new(),
-            num_vars_scope: Vec::new(),
+            // the global scope
+            num_vars_scope: vec![0],
+            frames: Vec::new(),
+            captures: Vec::new(),
+            global_uses: Vec::new(),
             call_signatures,
-            errors,
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            ref_params: HashMap::new(),
+            procedure_globals: HashMap::new(),
+            type_vars: Vec::new(),
+            procedure: None,
+            loops: Vec::new(),
+            errors: Errors::new(),
+        }
+    }
+
+    /// Analyzes the statements of a block, reporting the first one that
+    /// can't be reached. Returns whether the end of the block can be reached.
+    fn analyze_block(&mut self, block: &mut [Statement]) -> bool {
+        let mut reachable = true;
+        let mut reported = false;
+        for statement in block {
+            // only the first one is reported
+            if !reachable && !reported {
+                self.errors
+                    .add(ErrorKind::UnreachableCode, statement.line, statement.col);
+                reported = true;
+            }
+            reachable &= self.analyze_statement(statement);
         }
+        reachable
     }
 
-    fn analyze_statement(&mut self, statement: &mut Statement) {
+    /// Returns whether the code after the statement can be reached, which
+    /// isn't the case after a `return`, a `break`, a `continue` or a `loop`
+    /// that is never broken.
+    fn analyze_statement(&mut self, statement: &mut Statement) -> bool {
         match &mut statement.kind {
             StatementKind::Declare {
                 name,
                 type_hint,
                 value,
+                binding,
             } => {
+                if let Some(type_hint) = type_hint {
+                    if !self.check_type(type_hint, statement.line, statement.col) {
+                        return true;
+                    }
+                }
+
+                // without a value or a type hint, the type is inferred from
+                // the uses of the variable
+                let var_type = type_hint.clone().unwrap_or_else(|| self.new_type_var());
+
                 if let Some(value) = value {
                     self.analyze_expression(value);
-
+                    // an untyped value was already reported
                     if let Some(value_type) = &value.r#type {
-                        if let Some(type_hint) = type_hint {
-                            if *type_hint != *value_type {
-                                self.errors.add(
-                                    ErrorKind::MismatchedTypes {
-                                        expected: value_type.to_string(),
-                                        found: type_hint.to_string(),
-                                    },
-                                    statement.line,
-                                    statement.col,
-                                )
-                            } else {
-                                self.add_variable(name.clone(), value_type.clone());
-                            }
-                        } else {
-                            self.add_variable(name.clone(), value_type.clone());
-                        }
-                    } else {
-                        todo!("Infere a type in the expression");
+                        self.expect_type(&var_type, value_type, statement.line, statement.col);
                     }
-                } else if let Some(type_hint) = type_hint {
-                    self.add_variable(name.clone(), type_hint.clone());
-                } else {
-                    todo!("Insert type hint on uninitialized variables");
                 }
+
+                // the interpreter and the code generator read the type here
+                *type_hint = Some(var_type.clone());
+                *binding = Some(self.add_variable(name.clone(), var_type));
             }
             StatementKind::Assign { dest, src } => {
                 self.analyze_expression(dest);
                 self.analyze_expression(src);
 
-                let src_type = src.r#type.clone().unwrap();
-                if let ExpressionKind::Id(id) = &dest.kind {
-                    if self.contains_variable(id.clone()) {
-                        let dest_type = dest.r#type.clone().unwrap();
-                        if dest_type != src_type {
-                            self.errors.add(
-                                ErrorKind::MismatchedTypes {
-                                    expected: dest_type.to_string(),
-                                    found: src_type.to_string(),
-                                },
-                                dest.line,
-                                dest.col,
-                            );
-                        }
-                    } else {
-                        self.errors.add(
-                            ErrorKind::UndeclaredVariable { var: id.clone() },
-                            dest.line,
-                            dest.col,
-                        );
-                    }
-                } else if let ExpressionKind::Call(name, ref mut exprs) = &mut dest.kind {
-                    if name == "[]" {
-                        for expr in exprs {
-                            self.analyze_expression(expr);
-                        }
-                    }
-                } else {
-                    dbg!(&dest);
-                    self.errors
-                        .add(ErrorKind::InvalidIdentifier, dest.line, dest.col);
+                if let Some(root) = place_root(dest) {
+                    self.check_changeable(root);
+                }
+                if !is_place(dest) {
+                    // a field is reported at the value it is read from
+                    let (line, col) = match &dest.kind {
+                        ExpressionKind::Field(record, _) => (record.line, record.col),
+                        _ => (dest.line, dest.col),
+                    };
+                    self.errors.add(ErrorKind::InvalidIdentifier, line, col);
+                } else if let (Some(dest_type), Some(src_type)) = (&dest.r#type, &src.r#type) {
+                    self.expect_type(dest_type, src_type, dest.line, dest.col);
                 }
             }
             StatementKind::If { cond, block } => {
                 self.start_scope();
                 self.analyze_condition(cond);
-
-                for statement in block {
-                    self.analyze_statement(statement);
-                }
-
+                self.analyze_block(block);
                 self.end_scope();
             }
             StatementKind::IfElse {
@@ -235,37 +400,82 @@ impl Analyzer<'_> {
                 self.analyze_condition(cond);
 
                 self.start_scope();
-                for statement in true_block {
-                    self.analyze_statement(statement);
-                }
-
+                let true_reachable = self.analyze_block(true_block);
                 self.end_scope();
 
                 self.start_scope();
-                for statement in false_block {
-                    self.analyze_statement(statement);
-                }
+                let false_reachable = self.analyze_block(false_block);
                 self.end_scope();
+
+                return true_reachable || false_reachable;
             }
             StatementKind::Loop { block } => {
                 self.start_scope();
-                for statement in block {
-                    self.analyze_statement(statement);
-                }
+                self.loops.push(false);
+                self.analyze_block(block);
                 self.end_scope();
+
+                // only a `break` gets out of it
+                return self.loops.pop().unwrap();
             }
             StatementKind::While { cond, block } => {
                 self.start_scope();
                 self.analyze_condition(cond);
 
-                for statement in block {
-                    self.analyze_statement(statement);
+                self.loops.push(false);
+                self.analyze_block(block);
+                self.loops.pop();
+                self.end_scope();
+            }
+            StatementKind::For {
+                var,
+                binding,
+                iterable,
+                block,
+            } => {
+                let var_type = self.analyze_iterable(iterable);
+
+                self.start_scope();
+                // the variable of an invalid iterable has no type
+                if let Some(var_type) = var_type {
+                    *binding = Some(self.add_variable(var.clone(), var_type));
+                    self.loops.push(false);
+                    self.analyze_block(block);
+                    self.loops.pop();
                 }
                 self.end_scope();
             }
-            StatementKind::Call { name: _, args } => {
-                for arg in args {
+            StatementKind::Call { name, args, .. } if name == "()" => {
+                self.analyze_indirect_call(args);
+            }
+            StatementKind::Call {
+                name,
+                args,
+                binding,
+            } => {
+                let mut args_types = Vec::new();
+                for arg in args.iter_mut() {
                     self.analyze_expression(arg);
+                    args_types.extend(arg.r#type.clone());
+                }
+
+                let known = match self.find_callee(name, args, &args_types) {
+                    Some((callee, _)) => {
+                        *binding = callee;
+                        true
+                    }
+                    None => false,
+                };
+                // an untyped argument was already reported
+                if !known && args_types.len() == args.len() {
+                    let len = name.chars().count();
+                    self.add_undefined_procedure(
+                        name,
+                        &args_types,
+                        statement.line,
+                        statement.col,
+                        len,
+                    );
                 }
             }
             StatementKind::Procedure {
@@ -273,124 +483,1439 @@ impl Analyzer<'_> {
                 args,
                 ret,
                 block,
+                binding,
+                captures,
             } => {
-                //todo change this approach
-                let variables_copy = self.variables.clone();
-                self.variables.clear();
-
-                self.start_scope();
+                // procedures can hide other procedures, but not constructors
+                if let Some(note) = self.taken_name(name, false, false) {
+                    self.add_redefinition(name, note, statement.line, statement.col, 2);
+                }
 
                 let mut args_types: Vec<PrimitiveType> = Vec::with_capacity(args.len());
-                for arg in args {
-                    if let Some(arg_type) = &arg.1 {
+                for arg in args.iter_mut() {
+                    if let Some(arg_type) = &mut arg.r#type {
+                        self.check_type(arg_type, statement.line, statement.col);
                         args_types.push(arg_type.clone());
-                        self.add_variable(arg.0.clone(), arg_type.clone());
                     } else {
-                        panic!("Insert type hint in procedure declaration");
+                        self.errors.push(
+                            Error::new(
+                                ErrorKind::TypeAnnotationsNeeded,
+                                statement.line,
+                                statement.col,
+                            )
+                            .with_len(2)
+                            .with_help(format!(
+                                "give the argument a type, like '{}: Int'",
+                                arg.name
+                            )),
+                        );
+                        // the block is still analyzed, without more errors
+                        args_types.push(self.new_type_var());
                     }
                 }
 
-                for statement in block {
-                    self.analyze_statement(statement);
+                if let Some(ret) = ret {
+                    self.check_type(ret, statement.line, statement.col);
+                }
+
+                let r#type = PrimitiveType::Pr(args_types.clone(), ret.clone().map(Box::new));
+                let refs: Vec<bool> = args.iter().map(|arg| arg.is_ref).collect();
+
+                self.frames.push(self.variables.len());
+                self.captures.push(Vec::new());
+                self.global_uses.push(Vec::new());
+                self.start_scope();
+
+                // the procedure itself comes before its arguments, so that
+                // recursive calls find it
+                let itself = self.add_variable(name.clone(), r#type.clone());
+                self.add_ref_params(itself, &refs);
+                for (arg, arg_type) in args.iter().zip(args_types) {
+                    self.add_variable(arg.name.clone(), arg_type);
                 }
 
-                self.call_signatures
-                    .insert((name.to_string(), args_types), ret.clone());
+                // the loops around the declaration can't be broken from
+                // inside the procedure
+                let outer_procedure = self.procedure.replace((name.clone(), ret.clone()));
+                let outer_loops = std::mem::take(&mut self.loops);
+                if self.analyze_block(block) && ret.is_some() {
+                    self.errors.push(
+                        Error::new(
+                            ErrorKind::MissingReturn { name: name.clone() },
+                            statement.line,
+                            statement.col,
+                        )
+                        .with_len(2)
+                        .with_help("add a 'return' at the end of its block".to_string()),
+                    );
+                }
+                self.procedure = outer_procedure;
+                self.loops = outer_loops;
 
                 self.end_scope();
+                *captures = self.captures.pop().unwrap();
+                let globals = self.global_uses.pop().unwrap();
+                self.frames.pop();
+
+                let outer = self.add_variable(name.clone(), r#type);
+                self.add_ref_params(outer, &refs);
+                self.procedure_globals.insert(outer, globals);
+                *binding = Some(outer);
+            }
+            StatementKind::Return { value } => {
+                self.analyze_expression(value);
+                match self.procedure.clone() {
+                    Some((_, Some(ret))) => {
+                        // an untyped value was already reported
+                        if let Some(value_type) = &value.r#type {
+                            self.expect_type(&ret, value_type, value.line, value.col);
+                        }
+                    }
+                    Some((name, None)) => self.errors.push(
+                        Error::new(ErrorKind::NoReturnValue { name }, value.line, value.col)
+                            .with_len(get_len(value))
+                            .with_help("add a return type to its declaration".to_string()),
+                    ),
+                    None => {
+                        self.errors.push(
+                            Error::new(
+                                ErrorKind::ReturnOutsideProcedure,
+                                statement.line,
+                                statement.col,
+                            )
+                            .with_len(6),
+                        );
+                        return true;
+                    }
+                }
+                return false;
+            }
+            StatementKind::Break | StatementKind::Continue => {
+                let keyword = match statement.kind {
+                    StatementKind::Break => "break",
+                    _ => "continue",
+                };
+                let Some(broken) = self.loops.last_mut() else {
+                    self.errors.push(
+                        Error::new(
+                            ErrorKind::OutsideLoop {
+                                keyword: keyword.to_string(),
+                            },
+                            statement.line,
+                            statement.col,
+                        )
+                        .with_len(keyword.len()),
+                    );
+                    return true;
+                };
+                if keyword == "break" {
+                    *broken = true;
+                }
+                return false;
+            }
+            StatementKind::Struct { name, fields } => {
+                if let Some(note) = self.taken_name(name, true, true) {
+                    self.add_redefinition(name, note, statement.line, statement.col, 6);
+                    return true;
+                }
+                for (i, (field, _)) in fields.iter().enumerate() {
+                    if fields[..i].iter().any(|(other, _)| other == field) {
+                        let note = format!("'{name}' has another field '{field}'");
+                        self.add_redefinition(field, note, statement.line, statement.col, 6);
+                    }
+                }
 
-                self.variables = variables_copy;
+                // a struct can only contain itself in an array, which can
+                // be empty
+                let itself = PrimitiveType::Record(name.clone());
+                if let Some((field, _)) = fields.iter().find(|(_, r#type)| *r#type == itself) {
+                    self.errors.push(
+                        Error::new(
+                            ErrorKind::RecursiveType { name: name.clone() },
+                            statement.line,
+                            statement.col,
+                        )
+                        .with_len(6)
+                        .with_note(format!("its field '{field}' is a '{name}' too"))
+                        .with_help(format!("use an array instead, like '{field}: {name}[]'")),
+                    );
+                    return true;
+                }
+                self.structs.insert(name.clone(), Vec::new());
+                for (_, r#type) in fields.iter_mut() {
+                    self.check_type(r#type, statement.line, statement.col);
+                }
+
+                let fields_types = fields.iter().map(|(_, r#type)| r#type.clone()).collect();
+                self.call_signatures.insert(
+                    (name.clone(), fields_types),
+                    Some(PrimitiveType::Record(name.clone())),
+                );
+                self.structs.insert(name.clone(), fields.clone());
+            }
+            StatementKind::Enum { name, variants } => {
+                if let Some(note) = self.taken_name(name, true, false) {
+                    self.add_redefinition(name, note, statement.line, statement.col, 4);
+                    return true;
+                }
+                // the variants are called like procedures
+                let mut valid = true;
+                for (i, (variant, _)) in variants.iter().enumerate() {
+                    let note = if variants[..i].iter().any(|(other, _)| other == variant) {
+                        Some(format!("'{name}' has another variant '{variant}'"))
+                    } else {
+                        self.taken_name(variant, false, true)
+                    };
+                    if let Some(note) = note {
+                        self.add_redefinition(variant, note, statement.line, statement.col, 4);
+                        valid = false;
+                    }
+                }
+                if !valid {
+                    return true;
+                }
+
+                // the payloads can contain the enum itself
+                self.enums.insert(name.clone(), Vec::new());
+                for (_, types) in variants.iter_mut() {
+                    for r#type in types {
+                        self.check_type(r#type, statement.line, statement.col);
+                    }
+                }
+                let itself = PrimitiveType::Enum(name.clone());
+                if variants.iter().all(|(_, types)| types.contains(&itself)) {
+                    self.errors.push(
+                        Error::new(
+                            ErrorKind::RecursiveType { name: name.clone() },
+                            statement.line,
+                            statement.col,
+                        )
+                        .with_len(4)
+                        .with_note(format!("every variant of '{name}' contains a '{name}'"))
+                        .with_help("add a variant without it, like 'Empty'".to_string()),
+                    );
+                    return true;
+                }
+
+                // the variants are constructed like structs, without the
+                // parentheses when they have no payload
+                for (variant, types) in variants.iter() {
+                    self.call_signatures.insert(
+                        (variant.clone(), types.clone()),
+                        Some(PrimitiveType::Enum(name.clone())),
+                    );
+                }
+                self.enums.insert(name.clone(), variants.clone());
+            }
+            StatementKind::Match { value, arms } => {
+                self.analyze_expression(value);
+                // an untyped value was already reported
+                let Some(value_type) = value.r#type.clone() else {
+                    return true;
+                };
+                let value_type = self.resolve(&value_type);
+
+                let mut valid = true;
+                let mut reachable = false;
+                for (pattern, block) in arms.iter_mut() {
+                    self.start_scope();
+                    // the names of an invalid pattern have no types
+                    if self.analyze_pattern(pattern, &value_type) {
+                        reachable |= self.analyze_block(block);
+                    } else {
+                        valid = false;
+                    }
+                    self.end_scope();
+                }
+
+                let rows: Vec<Vec<&Pattern>> =
+                    arms.iter().map(|(pattern, _)| vec![pattern]).collect();
+                if let Some(missing) = valid
+                    .then(|| self.find_missing(&rows, &[value_type]))
+                    .flatten()
+                {
+                    self.errors.push(
+                        Error::new(
+                            ErrorKind::NonExhaustiveMatch {
+                                missing: missing[0].clone(),
+                            },
+                            statement.line,
+                            statement.col,
+                        )
+                        .with_len(5),
+                    );
+                    return true;
+                }
+                return reachable || !valid;
             }
-            _ => {}
         }
+        true
     }
 
-    fn analyze_condition(&mut self, cond: &mut Expression) {
-        self.analyze_expression(cond);
+    /// Returns the type of the elements of a `for` loop's iterable.
+    fn analyze_iterable(&mut self, iterable: &mut Expression) -> Option<PrimitiveType> {
+        if iterable.is_range() {
+            self.analyze_range(iterable);
+            return Some(PrimitiveType::Int);
+        }
+
+        self.analyze_expression(iterable);
+        let iterable_type = iterable.r#type.clone()?;
+        let elem_type = self.new_type_var();
+        match self.unify(
+            &PrimitiveType::Arr(Box::new(elem_type.clone())),
+            &iterable_type,
+        ) {
+            true => Some(elem_type),
+            false => {
+                self.errors.push(
+                    Error::new(
+                        ErrorKind::NotIterable {
+                            r#type: self.resolve(&iterable_type).to_string(),
+                        },
+                        iterable.line,
+                        iterable.col,
+                    )
+                    .with_len(get_len(iterable))
+                    .with_help(
+                        "only arrays and ranges like '0..n' can be iterated over".to_string(),
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    /// Checks that the bounds of a range are integers. The range itself
+    /// has no type.
+    fn analyze_range(&mut self, range: &mut Expression) {
+        let ExpressionKind::Call(_, bounds) = &mut range.kind else {
+            unreachable!()
+        };
+        for bound in bounds {
+            self.analyze_expression(bound);
+            if let Some(bound_type) = &bound.r#type {
+                self.expect_type(&PrimitiveType::Int, bound_type, bound.line, bound.col);
+            }
+        }
+    }
+
+    /// Checks a call to one of the builtins that work on arrays of any type.
+    /// Returns `None` if it isn't one of them, or if its first argument
+    /// isn't an array, and otherwise the return type of the builtin.
+    fn analyze_array_call(
+        &mut self,
+        name: &str,
+        args: &[Expression],
+    ) -> Option<Option<PrimitiveType>> {
+        let elem_type = self.new_type_var();
+        let (args_types, ret) = array_signature(name, elem_type)?;
+        if args_types.len() != args.len() {
+            return None;
+        }
+
+        if !self.unify(&args_types[0], args[0].r#type.as_ref()?) {
+            return None;
+        }
+        // all but 'len' change the array they are given
+        if let Some(root) = place_root(&args[0]).filter(|_| name != "len") {
+            self.check_changeable(root);
+        }
+        if name != "len" && !is_place(&args[0]) {
+            self.errors.push(
+                Error::new(ErrorKind::InvalidIdentifier, args[0].line, args[0].col)
+                    .with_len(get_len(&args[0]))
+                    .with_help(format!("'{}' needs a variable to change", name)),
+            );
+        }
+        for (arg, r#type) in args[1..].iter().zip(&args_types[1..]) {
+            if let Some(arg_type) = &arg.r#type {
+                self.expect_type(r#type, arg_type, arg.line, arg.col);
+            }
+        }
+        Some(ret)
+    }
 
-        let cond_type = cond.r#type.clone().unwrap();
+    /// Finds what a call by name runs: the innermost procedure in scope with
+    /// the name, or otherwise a builtin or a constructor. Returns the
+    /// variable of the procedure, if it is one, and the return type, or
+    /// `None` if nothing takes arguments of these types.
+    #[allow(clippy::type_complexity)]
+    fn find_callee(
+        &mut self,
+        name: &str,
+        args: &[Expression],
+        args_types: &[PrimitiveType],
+    ) -> Option<(Option<Binding>, Option<PrimitiveType>)> {
+        let Some(index) = self.find_variable(name, true) else {
+            return match self.analyze_array_call(name, args) {
+                Some(ret) => Some((None, ret)),
+                None => self.find_signature(name, args_types).map(|ret| (None, ret)),
+            };
+        };
 
-        if cond_type != PrimitiveType::Bool {
+        let binding = self.use_variable(index);
+        let PrimitiveType::Pr(params, ret) = self.resolve(&self.variables[index].1) else {
+            unreachable!()
+        };
+        let args_types: Vec<PrimitiveType> =
+            args_types.iter().map(|arg| self.resolve(arg)).collect();
+        if params.len() != args_types.len()
+            || !params
+                .iter()
+                .zip(&args_types)
+                .all(|(param, r#type)| fits(param, r#type))
+        {
+            return None;
+        }
+        for (param, r#type) in params.iter().zip(&args_types) {
+            self.unify(param, r#type);
+        }
+
+        self.check_ref_args(name, binding, args);
+        Some((Some(binding), ret.map(|ret| *ret)))
+    }
+
+    /// Checks a call of the procedure given by the first argument, which is
+    /// not a name. Returns its return type, or `None` if it can't be called
+    /// with the other arguments.
+    fn analyze_indirect_call(&mut self, args: &mut [Expression]) -> Option<Option<PrimitiveType>> {
+        for arg in args.iter_mut() {
+            self.analyze_expression(arg);
+        }
+        let (callee, args) = args.split_first().unwrap();
+        // an untyped callee was already reported
+        let callee_type = self.resolve(callee.r#type.as_ref()?);
+
+        let (params, ret) = match &callee_type {
+            PrimitiveType::Pr(params, ret) => (params, ret),
+            PrimitiveType::Var(_) => {
+                self.errors.push(
+                    Error::new(ErrorKind::TypeAnnotationsNeeded, callee.line, callee.col)
+                        .with_len(get_len(callee)),
+                );
+                return None;
+            }
+            _ => {
+                self.errors.push(
+                    Error::new(
+                        ErrorKind::NotCallable {
+                            r#type: callee_type.to_string(),
+                        },
+                        callee.line,
+                        callee.col,
+                    )
+                    .with_len(get_len(callee)),
+                );
+                return None;
+            }
+        };
+
+        if params.len() != args.len() {
+            let found: Vec<PrimitiveType> = args
+                .iter()
+                .map(|arg| match &arg.r#type {
+                    Some(r#type) => self.resolve(r#type),
+                    None => PrimitiveType::Var(0),
+                })
+                .collect();
             self.errors.add(
                 ErrorKind::MismatchedTypes {
-                    expected: "bool".to_string(),
-                    found: cond_type.to_string(),
+                    expected: callee_type.to_string(),
+                    found: PrimitiveType::Pr(found, ret.clone()).to_string(),
                 },
-                cond.line,
-                cond.col,
+                callee.line,
+                callee.col,
             );
+            return None;
+        }
+        for (param, arg) in params.iter().zip(args) {
+            if let Some(arg_type) = &arg.r#type {
+                self.expect_type(param, arg_type, arg.line, arg.col);
+            }
+        }
+        Some(ret.clone().map(|ret| *ret))
+    }
+
+    /// Checks that the arguments given to the `ref` parameters of a procedure
+    /// can be changed, that no two of them are the same variable, and that
+    /// the procedure doesn't use them by name while it holds them.
+    fn check_ref_args(&mut self, name: &str, binding: Binding, args: &[Expression]) {
+        let Some(refs) = self.ref_params.get(&binding).cloned() else {
+            return;
+        };
+
+        let globals = self.globals_of(binding);
+        let mut vars = Vec::new();
+        for (arg, _) in args.iter().zip(refs).filter(|(_, is_ref)| *is_ref) {
+            let len = get_len(arg);
+            match place_root(arg) {
+                None => self.errors.push(
+                    Error::new(ErrorKind::InvalidReference, arg.line, arg.col)
+                        .with_len(len)
+                        .with_help(format!(
+                            "store it in a variable before passing it to '{}'",
+                            name
+                        )),
+                ),
+                Some(root) if vars.contains(&root.binding) => self.errors.push(
+                    Error::new(
+                        ErrorKind::AliasedReference {
+                            var: root.to_str().to_string(),
+                        },
+                        arg.line,
+                        arg.col,
+                    )
+                    .with_len(len),
+                ),
+                Some(root)
+                    if root
+                        .binding
+                        .is_some_and(|binding| globals.contains(&binding)) =>
+                {
+                    self.errors.push(
+                        Error::new(
+                            ErrorKind::SharedReference {
+                                var: root.to_str().to_string(),
+                                name: name.to_string(),
+                            },
+                            arg.line,
+                            arg.col,
+                        )
+                        .with_len(len)
+                        .with_note(format!(
+                            "'{}' can't reach '{}' while it holds it by reference",
+                            name,
+                            root.to_str()
+                        )),
+                    )
+                }
+                Some(root) => {
+                    self.check_changeable(root);
+                    vars.push(root.binding);
+                }
+            }
         }
     }
 
+    /// Reports a change to a variable that a procedure only has a copy of.
+    fn check_changeable(&mut self, var: &Expression) {
+        if !var.binding.is_some_and(|binding| self.is_captured(binding)) {
+            return;
+        }
+        self.errors.push(
+            Error::new(
+                ErrorKind::ChangedCapture {
+                    var: var.to_str().to_string(),
+                },
+                var.line,
+                var.col,
+            )
+            .with_len(get_len(var))
+            .with_note(
+                "procedures get a copy of the variables of the procedures and blocks around them"
+                    .to_string(),
+            ),
+        );
+    }
+
+    /// Checks a pattern against the type of the value it matches and
+    /// declares the names it binds. Returns whether it was valid.
+    fn analyze_pattern(&mut self, pattern: &mut Pattern, r#type: &PrimitiveType) -> bool {
+        match &mut pattern.kind {
+            PatternKind::Wildcard => true,
+            PatternKind::Binding(name) => {
+                if self
+                    .variant_fields(r#type, name)
+                    .is_some_and(|fields| fields.is_empty())
+                {
+                    pattern.kind = PatternKind::Variant(name.clone(), Vec::new());
+                } else {
+                    pattern.binding = Some(self.add_variable(name.clone(), r#type.clone()));
+                }
+                true
+            }
+            PatternKind::Lit(lit) => {
+                let lit_type = if lit.starts_with('\'') {
+                    PrimitiveType::Char
+                } else if lit == "true" || lit == "false" {
+                    PrimitiveType::Bool
+                } else {
+                    PrimitiveType::Int
+                };
+                if self.unify(&lit_type, r#type) {
+                    return true;
+                }
+
+                let len = lit.chars().count();
+                self.errors.push(
+                    Error::new(
+                        ErrorKind::MismatchedTypes {
+                            expected: self.resolve(r#type).to_string(),
+                            found: lit_type.to_string(),
+                        },
+                        pattern.line,
+                        pattern.col,
+                    )
+                    .with_len(len),
+                );
+                false
+            }
+            PatternKind::Variant(name, fields) => {
+                let Some(field_types) = self.variant_fields(r#type, name) else {
+                    let mut error = Error::new(
+                        ErrorKind::UndefinedVariant {
+                            r#type: r#type.to_string(),
+                            variant: name.clone(),
+                        },
+                        pattern.line,
+                        pattern.col,
+                    )
+                    .with_len(name.chars().count());
+                    let constructors = self.constructors(r#type).unwrap_or_default();
+                    let names = constructors.iter().map(|(name, _)| name.as_str());
+                    if let Some(similar) = find_similar(name, names) {
+                        error = error.with_help(format!("did you mean '{similar}'?"));
+                    }
+                    self.errors.push(error);
+                    return false;
+                };
+
+                if field_types.len() != fields.len() {
+                    self.errors.push(
+                        Error::new(
+                            ErrorKind::WrongNumberOfFields {
+                                name: name.clone(),
+                                expected: field_types.len(),
+                                found: fields.len(),
+                            },
+                            pattern.line,
+                            pattern.col,
+                        )
+                        .with_len(name.chars().count()),
+                    );
+                    return false;
+                }
+
+                let mut valid = true;
+                for (field, field_type) in fields.iter_mut().zip(&field_types) {
+                    valid &= self.analyze_pattern(field, field_type);
+                }
+                valid
+            }
+        }
+    }
+
+    /// The types of the fields of the constructor `name` of a type.
+    fn variant_fields(&self, r#type: &PrimitiveType, name: &str) -> Option<Vec<PrimitiveType>> {
+        self.constructors(r#type)?
+            .into_iter()
+            .find(|(constructor, _)| constructor == name)
+            .map(|(_, fields)| fields)
+    }
+
+    /// Every way to build a value of the type, or `None` if there are too
+    /// many to list, like for integers.
+    fn constructors(&self, r#type: &PrimitiveType) -> Option<Vec<(String, Vec<PrimitiveType>)>> {
+        match self.resolve(r#type) {
+            PrimitiveType::Bool => Some(vec![
+                ("true".to_string(), Vec::new()),
+                ("false".to_string(), Vec::new()),
+            ]),
+            PrimitiveType::Enum(name) => self.enums.get(&name).cloned(),
+            PrimitiveType::Record(name) => {
+                let fields = self.structs.get(&name)?;
+                let types = fields.iter().map(|(_, r#type)| r#type.clone()).collect();
+                Some(vec![(name, types)])
+            }
+            _ => None,
+        }
+    }
+
+    /// A list of values of `types` that no row of patterns matches, written
+    /// as patterns, or `None` if the rows cover every value.
+    fn find_missing(&self, rows: &[Vec<&Pattern>], types: &[PrimitiveType]) -> Option<Vec<String>> {
+        let Some((r#type, rest)) = types.split_first() else {
+            return rows.is_empty().then(Vec::new);
+        };
+
+        let constructors = self.constructors(r#type);
+        let heads: Vec<&str> = rows.iter().filter_map(|row| head(row[0])).collect();
+
+        if let Some(constructors) = &constructors {
+            if constructors
+                .iter()
+                .all(|(name, _)| heads.contains(&name.as_str()))
+            {
+                // every constructor is matched somewhere, look into their fields
+                for (name, fields) in constructors {
+                    let specialized: Vec<Vec<&Pattern>> = rows
+                        .iter()
+                        .filter_map(|row| specialize(row, name, fields.len()))
+                        .collect();
+                    let types: Vec<PrimitiveType> = fields.iter().chain(rest).cloned().collect();
+
+                    if let Some(mut missing) = self.find_missing(&specialized, &types) {
+                        let rest = missing.split_off(fields.len());
+                        missing = [vec![format_constructor(name, &missing)], rest].concat();
+                        return Some(missing);
+                    }
+                }
+                return None;
+            }
+        }
+
+        // the rows that match any value
+        let rows: Vec<Vec<&Pattern>> = rows
+            .iter()
+            .filter(|row| head(row[0]).is_none())
+            .map(|row| row[1..].to_vec())
+            .collect();
+        let mut missing = self.find_missing(&rows, rest)?;
+
+        let first = match constructors {
+            Some(constructors) => {
+                let (name, fields) = constructors
+                    .iter()
+                    .find(|(name, _)| !heads.contains(&name.as_str()))
+                    .unwrap();
+                format_constructor(name, &vec!["_".to_string(); fields.len()])
+            }
+            None => "_".to_string(),
+        };
+        missing.insert(0, first);
+        Some(missing)
+    }
+
+    fn analyze_condition(&mut self, cond: &mut Expression) {
+        self.analyze_expression(cond);
+
+        let Some(cond_type) = cond.r#type.clone() else {
+            return;
+        };
+
+        self.expect_type(&PrimitiveType::Bool, &cond_type, cond.line, cond.col);
+    }
+
     fn analyze_expression(&mut self, expr: &mut Expression) {
         match &mut expr.kind {
+            ExpressionKind::Call(name, args) if name == "[]" => {
+                self.analyze_expression(&mut args[0]);
+                if args[1].is_range() {
+                    self.analyze_range(&mut args[1]);
+                } else {
+                    self.analyze_expression(&mut args[1]);
+                }
+
+                let elem_type = self.new_type_var();
+                let array_type = PrimitiveType::Arr(Box::new(elem_type.clone()));
+                if let Some(arg_type) = &args[0].r#type {
+                    if !self.unify(&array_type, arg_type) {
+                        let (type1, type2) = (
+                            self.resolve(arg_type),
+                            args[1].r#type.clone().unwrap_or(PrimitiveType::Int),
+                        );
+                        self.errors.add(
+                            ErrorKind::OperationNotImplemented {
+                                op: name.clone(),
+                                type1: type1.to_string(),
+                                type2: self.resolve(&type2).to_string(),
+                            },
+                            expr.line,
+                            expr.col,
+                        );
+                    }
+                }
+                if let Some(idx_type) = &args[1].r#type {
+                    self.expect_type(&PrimitiveType::Int, idx_type, args[1].line, args[1].col);
+                }
+
+                // a slice is an array of the same type
+                if args[1].is_range() {
+                    expr.r#type = Some(array_type);
+                    return;
+                }
+
+                expr.r#type = Some(elem_type);
+            }
+            ExpressionKind::Call(name, args) if name == "()" => {
+                match self.analyze_indirect_call(args) {
+                    Some(Some(ret)) => expr.r#type = Some(ret),
+                    Some(None) => {
+                        let r#type = self.resolve(args[0].r#type.as_ref().unwrap());
+                        self.errors.push(
+                            Error::new(
+                                ErrorKind::NoReturnValue {
+                                    name: r#type.to_string(),
+                                },
+                                expr.line,
+                                expr.col,
+                            )
+                            .with_len(1),
+                        );
+                    }
+                    None => {}
+                }
+            }
             ExpressionKind::Call(name, args) => {
                 let mut args_types: Vec<PrimitiveType> = Vec::new();
-                for arg in args {
+                for arg in args.iter_mut() {
                     self.analyze_expression(arg);
-                    if let ExpressionKind::Id(id) = &arg.kind {
-                        args_types.push(self.get_type_by_name(id));
-                    } else if let Some(arg_type) = &arg.r#type {
-                        args_types.push(arg_type.clone());
-                    }
+                    args_types.extend(arg.r#type.clone());
                 }
-                if self
-                    .call_signatures
-                    .contains_key(&(name.clone(), args_types.clone()))
+                let (name, num_args) = (name.clone(), args.len());
+
+                // '+' also joins two arrays of the same type
+                let is_array = |r#type: &PrimitiveType| matches!(r#type, PrimitiveType::Arr(..));
+                if name == "+"
+                    && args_types.len() == 2
+                    && args_types.iter().any(|arg| is_array(&self.resolve(arg)))
                 {
-                    expr.r#type = self.call_signatures[&(name.clone(), args_types)].clone();
-                } else {
-                    self.errors.add(
-                        ErrorKind::UndefinedProcedure { name: name.clone() },
-                        expr.line,
-                        expr.col,
-                    );
+                    if self.unify(&args_types[0], &args_types[1]) {
+                        expr.r#type = Some(args_types[0].clone());
+                    } else {
+                        self.errors.add(
+                            ErrorKind::OperationNotImplemented {
+                                op: name,
+                                type1: self.resolve(&args_types[0]).to_string(),
+                                type2: self.resolve(&args_types[1]).to_string(),
+                            },
+                            expr.line,
+                            expr.col,
+                        );
+                    }
+                    return;
+                }
+
+                let callee = self.find_callee(&name, args, &args_types);
+                let len = get_len(expr);
+                match callee {
+                    Some((binding, Some(ret))) => {
+                        expr.r#type = Some(ret);
+                        expr.binding = binding;
+                    }
+                    Some((_, None)) => self.errors.push(
+                        Error::new(ErrorKind::NoReturnValue { name }, expr.line, expr.col)
+                            .with_len(len),
+                    ),
+                    // the errors of untyped arguments were already reported
+                    None if args_types.len() == num_args => {
+                        self.add_undefined_procedure(&name, &args_types, expr.line, expr.col, len)
+                    }
+                    None => {}
                 }
             }
             ExpressionKind::Lit(..) => {}
             ExpressionKind::Id(id) => {
-                if self.contains_variable(id.clone()) {
-                    expr.r#type = Some(self.get_type_by_name(id));
+                if let Some(index) = self.find_variable(id, false) {
+                    let binding = self.use_variable(index);
+                    if self.ref_params.contains_key(&binding) {
+                        self.errors.push(
+                            Error::new(
+                                ErrorKind::RefProcedureValue { name: id.clone() },
+                                expr.line,
+                                expr.col,
+                            )
+                            .with_len(get_len(expr)),
+                        );
+                    }
+                    expr.r#type = Some(self.variables[index].1.clone());
+                    expr.binding = Some(binding);
+                } else if let Some(r#type) = self.unit_variant(id) {
+                    // a variant without payload, built like a call without arguments
+                    expr.kind = ExpressionKind::Call(id.clone(), Vec::new());
+                    expr.r#type = Some(r#type);
                 } else {
-                    self.errors.add(
-                        ErrorKind::UndeclaredVariable { var: id.clone() },
-                        expr.line,
-                        expr.col,
-                    );
+                    let id = id.clone();
+                    self.add_undeclared_variable(&id, expr);
                 }
             }
             ExpressionKind::Array(values) => {
-                for value in values {
-                    self.analyze_expression(value)
+                // the type of the elements of an empty array comes from its uses
+                let elem_type = self.new_type_var();
+                for value in values.iter_mut() {
+                    self.analyze_expression(value);
+                    let Some(value_type) = &value.r#type else {
+                        continue;
+                    };
+                    if !self.unify(&elem_type, value_type) {
+                        let note = format!(
+                            "expected '{}', found '{}'",
+                            self.resolve(&elem_type),
+                            self.resolve(value_type)
+                        );
+                        self.errors.push(
+                            Error::new(ErrorKind::InvalidArray, value.line, value.col)
+                                .with_len(get_len(value))
+                                .with_note(note),
+                        );
+                    }
                 }
+                expr.r#type = Some(PrimitiveType::Arr(Box::new(elem_type)));
+            }
+            ExpressionKind::Field(record, field) => {
+                self.analyze_expression(record);
+                // an untyped record was already reported
+                let Some(record_type) = record.r#type.clone() else {
+                    return;
+                };
+                let record_type = self.resolve(&record_type);
+
+                let fields = match &record_type {
+                    PrimitiveType::Record(name) => self.structs.get(name),
+                    _ => None,
+                };
+                let field_type = fields.and_then(|fields| {
+                    fields
+                        .iter()
+                        .find(|(name, _)| name == field)
+                        .map(|(_, r#type)| r#type.clone())
+                });
+                if field_type.is_some() {
+                    expr.r#type = field_type;
+                    return;
+                }
+
+                let mut error = Error::new(
+                    ErrorKind::UndefinedField {
+                        r#type: record_type.to_string(),
+                        field: field.clone(),
+                    },
+                    expr.line,
+                    expr.col,
+                )
+                .with_len(field.chars().count());
+                if let Some(fields) = fields {
+                    let names = fields.iter().map(|(name, _)| name.as_str());
+                    if let Some(similar) = find_similar(field, names) {
+                        error = error.with_help(format!("did you mean '{similar}'?"));
+                    }
+                }
+                self.errors.push(error);
             }
         }
     }
 
-    fn add_variable(&mut self, name: String, r#type: PrimitiveType) {
+    /// Reports the structs and enums in a type that were never declared.
+    /// The parser reads both as records, so the enums are fixed here.
+    fn check_type(&mut self, r#type: &mut PrimitiveType, line: usize, col: usize) -> bool {
+        match r#type {
+            PrimitiveType::Arr(r#type) => self.check_type(r#type, line, col),
+            PrimitiveType::Pr(args, ret) => {
+                let mut valid = true;
+                for r#type in args.iter_mut().chain(ret.as_deref_mut()) {
+                    valid &= self.check_type(r#type, line, col);
+                }
+                valid
+            }
+            PrimitiveType::Record(name) if self.enums.contains_key(name) => {
+                *r#type = PrimitiveType::Enum(name.clone());
+                true
+            }
+            PrimitiveType::Record(name) if !self.structs.contains_key(name) => {
+                let mut error = Error::new(
+                    ErrorKind::UndefinedType {
+                        type1: name.clone(),
+                    },
+                    line,
+                    col,
+                );
+                let names = self.structs.keys().chain(self.enums.keys());
+                if let Some(similar) = find_similar(name, names.map(String::as_str)) {
+                    error = error.with_help(format!("did you mean '{similar}'?"));
+                }
+                self.errors.push(error);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn new_type_var(&mut self) -> PrimitiveType {
+        self.type_vars.push(None);
+        PrimitiveType::Var(self.type_vars.len() - 1)
+    }
+
+    /// The type with the type variables inferred so far replaced.
+    fn resolve(&self, r#type: &PrimitiveType) -> PrimitiveType {
+        resolve(&self.type_vars, r#type)
+    }
+
+    /// Makes two types equal by inferring their type variables. Returns
+    /// false if they can't be.
+    fn unify(&mut self, type1: &PrimitiveType, type2: &PrimitiveType) -> bool {
+        match (self.resolve(type1), self.resolve(type2)) {
+            (type1, type2) if type1 == type2 => true,
+            (PrimitiveType::Var(var), r#type) | (r#type, PrimitiveType::Var(var)) => {
+                // a type can't contain itself
+                if contains_var(&r#type, Some(var)) {
+                    return false;
+                }
+                self.type_vars[var] = Some(r#type);
+                true
+            }
+            (PrimitiveType::Arr(elem1), PrimitiveType::Arr(elem2)) => self.unify(&elem1, &elem2),
+            (PrimitiveType::Pr(args1, ret1), PrimitiveType::Pr(args2, ret2)) => {
+                args1.len() == args2.len()
+                    && args1
+                        .iter()
+                        .zip(&args2)
+                        .all(|(arg1, arg2)| self.unify(arg1, arg2))
+                    && match (ret1, ret2) {
+                        (Some(ret1), Some(ret2)) => self.unify(&ret1, &ret2),
+                        (ret1, ret2) => ret1.is_none() && ret2.is_none(),
+                    }
+            }
+            _ => false,
+        }
+    }
+
+    /// Unifies the types, reporting a mismatch at the position if they differ.
+    fn expect_type(
+        &mut self,
+        expected: &PrimitiveType,
+        found: &PrimitiveType,
+        line: usize,
+        col: usize,
+    ) {
+        if !self.unify(expected, found) {
+            self.errors.add(
+                ErrorKind::MismatchedTypes {
+                    expected: self.resolve(expected).to_string(),
+                    found: self.resolve(found).to_string(),
+                },
+                line,
+                col,
+            );
+        }
+    }
+
+    /// The return type of the procedure `name` for arguments of these types,
+    /// or `None` if there is no such procedure. Arguments whose type isn't
+    /// known yet take the types of the only procedure they fit.
+    ///
+    /// The type variables in a signature, like those of `map`, stand for any
+    /// type, and get new type variables on every call.
+    fn find_signature(
+        &mut self,
+        name: &str,
+        args_types: &[PrimitiveType],
+    ) -> Option<Option<PrimitiveType>> {
+        let args_types: Vec<PrimitiveType> =
+            args_types.iter().map(|arg| self.resolve(arg)).collect();
+        // types with variables could be taken for a signature with them
+        let is_known = !args_types.iter().any(|arg| contains_var(arg, None));
+        if let Some(ret) = self
+            .call_signatures
+            .get(&(name.to_string(), args_types.clone()))
+            .filter(|_| is_known)
+        {
+            return Some(ret.clone());
+        }
+
+        let mut candidates = self
+            .call_signatures
+            .iter()
+            .filter(|((candidate, args), _)| {
+                candidate == name
+                    && args.len() == args_types.len()
+                    && args
+                        .iter()
+                        .zip(&args_types)
+                        .all(|(arg, r#type)| fits(arg, r#type))
+            });
+        let ((_, args), ret) = candidates.next()?;
+        if candidates.next().is_some() {
+            return None;
+        }
+
+        let (args, ret) = (args.clone(), ret.clone());
+        let mut vars = HashMap::new();
+        let args: Vec<PrimitiveType> = args
+            .iter()
+            .map(|arg| self.instantiate(arg, &mut vars))
+            .collect();
+        let ret = ret.map(|ret| self.instantiate(&ret, &mut vars));
+
+        // the variables of a signature may take different types in its
+        // arguments, and the call is then undone
+        let type_vars = (!vars.is_empty()).then(|| self.type_vars.clone());
+        for (arg, r#type) in args.iter().zip(&args_types) {
+            if !self.unify(arg, r#type) {
+                self.type_vars = type_vars?;
+                return None;
+            }
+        }
+        Some(ret)
+    }
+
+    /// The type with a new type variable for every variable of a signature,
+    /// the same one for all uses of it, as kept in `vars`.
+    fn instantiate(
+        &mut self,
+        r#type: &PrimitiveType,
+        vars: &mut HashMap<usize, PrimitiveType>,
+    ) -> PrimitiveType {
+        match r#type {
+            PrimitiveType::Var(var) => {
+                if let Some(new) = vars.get(var) {
+                    return new.clone();
+                }
+                let new = self.new_type_var();
+                vars.insert(*var, new.clone());
+                new
+            }
+            PrimitiveType::Arr(elem_type) => {
+                PrimitiveType::Arr(Box::new(self.instantiate(elem_type, vars)))
+            }
+            PrimitiveType::Pr(args, ret) => PrimitiveType::Pr(
+                args.iter().map(|arg| self.instantiate(arg, vars)).collect(),
+                ret.as_ref()
+                    .map(|ret| Box::new(self.instantiate(ret, vars))),
+            ),
+            _ => r#type.clone(),
+        }
+    }
+
+    /// Writes the inferred types into the statement. The first type in it
+    /// that couldn't be inferred is added to `unknown`.
+    fn resolve_statement(&self, statement: &mut Statement, unknown: &mut Vec<Error>) {
+        let mut first = None;
+        let mut resolve_expression = |expr: &mut Expression| {
+            let position = self.resolve_expression(expr);
+            first = first.or(position);
+        };
+
+        match &mut statement.kind {
+            StatementKind::Declare {
+                name,
+                type_hint,
+                value,
+                ..
+            } => {
+                if let Some(value) = value {
+                    resolve_expression(value);
+                }
+                let type_hint = type_hint.as_mut().unwrap();
+                *type_hint = self.resolve(type_hint);
+                if contains_var(type_hint, None) {
+                    unknown.push(
+                        Error::new(
+                            ErrorKind::TypeAnnotationsNeeded,
+                            statement.line,
+                            statement.col,
+                        )
+                        .with_len(3)
+                        .with_help(format!("give '{}' a type, like 'var {}: Int'", name, name)),
+                    );
+                    return;
+                }
+                if value.is_none() && matches!(type_hint, PrimitiveType::Pr(..)) {
+                    unknown.push(
+                        Error::new(
+                            ErrorKind::NoDefaultValue { var: name.clone() },
+                            statement.line,
+                            statement.col,
+                        )
+                        .with_len(3),
+                    );
+                    return;
+                }
+            }
+            StatementKind::Call { args, .. } => args.iter_mut().for_each(resolve_expression),
+            StatementKind::Return { value } => resolve_expression(value),
+            StatementKind::Assign { dest, src } => {
+                resolve_expression(dest);
+                resolve_expression(src);
+            }
+            StatementKind::If { cond, block } | StatementKind::While { cond, block } => {
+                resolve_expression(cond);
+                self.resolve_block(block, unknown);
+            }
+            StatementKind::IfElse {
+                cond,
+                true_block,
+                false_block,
+            } => {
+                resolve_expression(cond);
+                self.resolve_block(true_block, unknown);
+                self.resolve_block(false_block, unknown);
+            }
+            StatementKind::For {
+                iterable, block, ..
+            } => {
+                resolve_expression(iterable);
+                self.resolve_block(block, unknown);
+            }
+            StatementKind::Match { value, arms } => {
+                resolve_expression(value);
+                for (_, block) in arms {
+                    self.resolve_block(block, unknown);
+                }
+            }
+            StatementKind::Procedure { block, .. } | StatementKind::Loop { block } => {
+                self.resolve_block(block, unknown)
+            }
+            StatementKind::Struct { .. }
+            | StatementKind::Enum { .. }
+            | StatementKind::Continue
+            | StatementKind::Break => {}
+        }
+
+        unknown.extend(first.map(unknown_type));
+    }
+
+    fn resolve_block(&self, block: &mut [Statement], unknown: &mut Vec<Error>) {
+        for statement in block {
+            self.resolve_statement(statement, unknown);
+        }
+    }
+
+    /// Writes the inferred types into the expression and returns the
+    /// position of the first one that couldn't be inferred.
+    fn resolve_expression(&self, expr: &mut Expression) -> Option<(usize, usize, usize)> {
+        let mut first = None;
+        match &mut expr.kind {
+            ExpressionKind::Call(_, args) | ExpressionKind::Array(args) => {
+                for arg in args {
+                    first = first.or(self.resolve_expression(arg));
+                }
+            }
+            ExpressionKind::Field(record, _) => first = self.resolve_expression(record),
+            ExpressionKind::Lit(_) | ExpressionKind::Id(_) => {}
+        }
+
+        if let Some(r#type) = &mut expr.r#type {
+            *r#type = self.resolve(r#type);
+            if contains_var(r#type, None) {
+                return Some((expr.line, expr.col, get_len(expr)));
+            }
+        }
+        first
+    }
+
+    /// Reports the types that couldn't be inferred, unless they are caused
+    /// by other errors.
+    fn report_unknown(&mut self, unknown: Vec<Error>) {
+        if !self.errors.should_abort() {
+            for error in unknown {
+                self.errors.push(error);
+            }
+        }
+    }
+
+    /// The type of the variant named `name`, if it has no payload.
+    fn unit_variant(&self, name: &str) -> Option<PrimitiveType> {
+        self.enums.iter().find_map(|(r#enum, variants)| {
+            variants
+                .iter()
+                .any(|(variant, types)| variant == name && types.is_empty())
+                .then(|| PrimitiveType::Enum(r#enum.clone()))
+        })
+    }
+
+    fn add_undeclared_variable(&mut self, id: &str, expr: &Expression) {
+        let mut error = Error::new(
+            ErrorKind::UndeclaredVariable {
+                var: id.to_string(),
+            },
+            expr.line,
+            expr.col,
+        )
+        .with_len(get_len(expr));
+
+        let names = self.variables.iter().map(|var| var.0.as_str());
+        if let Some(similar) = find_similar(id, names) {
+            error = error.with_help(format!("did you mean '{similar}'?"));
+        }
+
+        self.errors.push(error);
+    }
+
+    fn add_undefined_procedure(
+        &mut self,
+        name: &str,
+        args_types: &[PrimitiveType],
+        line: usize,
+        col: usize,
+        len: usize,
+    ) {
+        let mut error = Error::new(
+            ErrorKind::UndefinedProcedure {
+                name: name.to_string(),
+            },
+            line,
+            col,
+        )
+        .with_len(len);
+
+        let procedures = self
+            .variables
+            .iter()
+            .filter(|(_, r#type)| matches!(self.resolve(r#type), PrimitiveType::Pr(..)));
+        let names = self
+            .call_signatures
+            .keys()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| is_procedure_name(name));
+        let names = names
+            .chain(ARRAY_PROCEDURES)
+            .chain(procedures.map(|(name, _)| name.as_str()));
+        if ARRAY_PROCEDURES.contains(&name)
+            || self.find_variable(name, true).is_some()
+            || self
+                .call_signatures
+                .keys()
+                .any(|signature| signature.0 == name)
+        {
+            let types: Vec<String> = args_types
+                .iter()
+                .map(|arg| self.resolve(arg).to_string())
+                .collect();
+            error = error.with_note(format!(
+                "'{name}' is not defined for arguments of types ({})",
+                types.join(", ")
+            ));
+        } else if let Some(similar) = find_similar(name, names) {
+            error = error.with_help(format!("did you mean '{similar}'?"));
+        }
+
+        self.errors.push(error);
+    }
+
+    /// Why a new struct, enum, variant or procedure can't be called `name`,
+    /// if it can't. Struct and variant names are always taken, type names
+    /// with `as_type` and procedure names with `as_procedure`.
+    fn taken_name(&self, name: &str, as_type: bool, as_procedure: bool) -> Option<String> {
+        let r#enum = self
+            .enums
+            .iter()
+            .find(|(_, variants)| variants.iter().any(|(variant, _)| variant == name));
+
+        if as_type && (PrimitiveType::try_from_str(name).is_some() || name == "Pr") {
+            Some(format!("'{name}' is a builtin type"))
+        } else if self.structs.contains_key(name) {
+            Some(format!("'{name}' is already a struct"))
+        } else if as_type && self.enums.contains_key(name) {
+            Some(format!("'{name}' is already an enum"))
+        } else if let Some((r#enum, _)) = r#enum {
+            Some(format!("'{name}' is already a variant of '{}'", r#enum))
+        } else if as_procedure && self.find_variable(name, true).is_some() {
+            Some(format!("'{name}' is already a procedure"))
+        } else {
+            None
+        }
+    }
+
+    fn add_redefinition(&mut self, name: &str, note: String, line: usize, col: usize, len: usize) {
+        self.errors.push(
+            Error::new(
+                ErrorKind::Redefinition {
+                    name: name.to_string(),
+                },
+                line,
+                col,
+            )
+            .with_len(len)
+            .with_note(note),
+        );
+    }
+
+    /// Declares a variable in the current scope, in the next free slot.
+    fn add_variable(&mut self, name: String, r#type: PrimitiveType) -> Binding {
+        let binding = self.binding_of(self.variables.len());
         let len = self.num_vars_scope.len();
         self.num_vars_scope[len - 1] += 1;
         self.variables.push((name, r#type));
+        // the slot may have held a procedure of a block that ended
+        self.ref_params.remove(&binding);
+        self.procedure_globals.remove(&binding);
+        binding
+    }
+
+    fn add_ref_params(&mut self, binding: Binding, refs: &[bool]) {
+        if refs.contains(&true) {
+            self.ref_params.insert(binding, refs.to_vec());
+        }
+    }
+
+    /// The position in `variables` of the innermost variable in scope with
+    /// the name, which hides the outer ones. With `procedures_only`,
+    /// variables that don't hold a procedure are skipped, so a variable
+    /// can have the name of a procedure that is still called.
+    fn find_variable(&self, name: &str, procedures_only: bool) -> Option<usize> {
+        self.variables.iter().rposition(|(var, r#type)| {
+            var == name
+                && (!procedures_only || matches!(self.resolve(r#type), PrimitiveType::Pr(..)))
+        })
     }
 
-    fn contains_variable(&self, name: String) -> bool {
-        let vars = self.variables.iter().rev();
-        for var in vars {
-            if var.0 == *name {
-                return true;
+    /// The binding of the variable at `index`, which is captured by every
+    /// procedure being analyzed that is inside of its declaration.
+    fn use_variable(&mut self, index: usize) -> Binding {
+        let binding = self.binding_of(index);
+        // a procedure uses what the procedures it calls use
+        let mut globals = self.procedure_globals.get(&binding).cloned();
+        if self.is_global(binding) {
+            globals.get_or_insert_with(Vec::new).push(binding);
+        }
+        for uses in &mut self.global_uses {
+            for global in globals.iter().flatten() {
+                if !uses.contains(global) {
+                    uses.push(*global);
+                }
             }
         }
-        false
+
+        if !self.is_global(binding) {
+            for captures in &mut self.captures[binding.depth..] {
+                if !captures.contains(&binding) {
+                    captures.push(binding);
+                }
+            }
+        }
+        binding
     }
 
-    fn get_type_by_name(&self, name: &String) -> PrimitiveType {
-        let vars = self.variables.iter().rev();
-        for var in vars {
-            if var.0 == *name {
-                return var.1.clone();
+    /// The variables of the main program used by the procedure. If it is
+    /// still being analyzed, those it used so far.
+    fn globals_of(&self, binding: Binding) -> Vec<Binding> {
+        if let Some(globals) = self.procedure_globals.get(&binding) {
+            return globals.clone();
+        }
+        // a procedure comes first in its own frame
+        match binding.depth.checked_sub(1) {
+            Some(frame) if binding.slot == 0 && frame < self.global_uses.len() => {
+                self.global_uses[frame].clone()
             }
+            _ => Vec::new(),
         }
-        panic!("Undeclared variable '{name}' used");
+    }
+
+    /// The binding of the variable at `index` in `variables`.
+    fn binding_of(&self, index: usize) -> Binding {
+        let depth = self.frames.partition_point(|start| *start <= index);
+        let start = match depth {
+            0 => 0,
+            _ => self.frames[depth - 1],
+        };
+        Binding {
+            depth,
+            slot: index - start,
+        }
+    }
+
+    /// Whether the variable belongs to the main program and to none of its
+    /// blocks, so it lives as long as the program and procedures use it
+    /// directly instead of capturing it.
+    fn is_global(&self, binding: Binding) -> bool {
+        binding.depth == 0 && binding.slot < self.num_vars_scope[0]
+    }
+
+    /// Whether the variable is a copy made when the procedure being
+    /// analyzed was declared.
+    fn is_captured(&self, binding: Binding) -> bool {
+        binding.depth < self.frames.len() && !self.is_global(binding)
     }
 
     fn start_scope(&mut self) {
@@ -403,3 +1928,139 @@ impl Analyzer<'_> {
         }
     }
 }
+
+/// The types of the arguments and of the return value of a builtin that
+/// works on arrays, for elements of `elem_type`.
+fn array_signature(
+    name: &str,
+    elem_type: PrimitiveType,
+) -> Option<(Vec<PrimitiveType>, Option<PrimitiveType>)> {
+    let array_type = PrimitiveType::Arr(Box::new(elem_type.clone()));
+    let signature = match name {
+        "len" => (vec![array_type], Some(PrimitiveType::Int)),
+        "push" => (vec![array_type, elem_type], None),
+        "pop" => (vec![array_type], Some(elem_type)),
+        "insert" => (vec![array_type, PrimitiveType::Int, elem_type], None),
+        "remove" => (vec![array_type, PrimitiveType::Int], Some(elem_type)),
+        _ => return None,
+    };
+    Some(signature)
+}
+
+/// Whether a procedure is called by name, unlike the operators.
+pub fn is_procedure_name(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_alphabetic() || ch == '_') && name != "and" && name != "or"
+}
+
+/// Length of the token an expression is reported at.
+pub fn get_len(expr: &Expression) -> usize {
+    match &expr.kind {
+        ExpressionKind::Call(name, _) if name == "[]" || name == "()" => 1,
+        ExpressionKind::Call(name, _) => name.chars().count(),
+        ExpressionKind::Lit(lit) => lit.chars().count(),
+        ExpressionKind::Id(id) => id.chars().count(),
+        ExpressionKind::Array(..) => 1,
+        ExpressionKind::Field(_, field) => field.chars().count(),
+    }
+}
+
+fn resolve(type_vars: &[Option<PrimitiveType>], r#type: &PrimitiveType) -> PrimitiveType {
+    match r#type {
+        PrimitiveType::Var(var) => match &type_vars[*var] {
+            Some(r#type) => resolve(type_vars, r#type),
+            None => r#type.clone(),
+        },
+        PrimitiveType::Arr(elem_type) => {
+            PrimitiveType::Arr(Box::new(resolve(type_vars, elem_type)))
+        }
+        PrimitiveType::Pr(args, ret) => PrimitiveType::Pr(
+            args.iter().map(|arg| resolve(type_vars, arg)).collect(),
+            ret.as_ref().map(|ret| Box::new(resolve(type_vars, ret))),
+        ),
+        _ => r#type.clone(),
+    }
+}
+
+/// Whether the type contains the type variable `var`, or any if it is `None`.
+fn contains_var(r#type: &PrimitiveType, var: Option<usize>) -> bool {
+    match r#type {
+        PrimitiveType::Var(other) => var.is_none_or(|var| var == *other),
+        PrimitiveType::Arr(elem_type) => contains_var(elem_type, var),
+        PrimitiveType::Pr(args, ret) => args
+            .iter()
+            .chain(ret.as_deref())
+            .any(|r#type| contains_var(r#type, var)),
+        _ => false,
+    }
+}
+
+/// Whether a value of the second type could be passed as the first one,
+/// once its type variables are inferred.
+fn fits(r#type: &PrimitiveType, found: &PrimitiveType) -> bool {
+    match (r#type, found) {
+        (PrimitiveType::Var(_), _) | (_, PrimitiveType::Var(_)) => true,
+        (PrimitiveType::Arr(elem_type), PrimitiveType::Arr(found)) => fits(elem_type, found),
+        (PrimitiveType::Pr(args, ret), PrimitiveType::Pr(found_args, found_ret)) => {
+            args.len() == found_args.len()
+                && args
+                    .iter()
+                    .zip(found_args)
+                    .all(|(arg, found)| fits(arg, found))
+                && match (ret, found_ret) {
+                    (Some(ret), Some(found)) => fits(ret, found),
+                    (ret, found) => ret.is_none() && found.is_none(),
+                }
+        }
+        _ => r#type == found,
+    }
+}
+
+fn unknown_type((line, col, len): (usize, usize, usize)) -> Error {
+    Error::new(ErrorKind::TypeAnnotationsNeeded, line, col).with_len(len)
+}
+
+/// The constructor a pattern matches, if it doesn't match anything.
+fn head(pattern: &Pattern) -> Option<&str> {
+    match &pattern.kind {
+        PatternKind::Variant(name, _) | PatternKind::Lit(name) => Some(name),
+        PatternKind::Wildcard | PatternKind::Binding(_) => None,
+    }
+}
+
+/// The rest of a row after matching its first pattern against the
+/// constructor `name`, with the patterns of its fields in front, or `None`
+/// if the row can't match it.
+fn specialize<'a>(row: &[&'a Pattern], name: &str, arity: usize) -> Option<Vec<&'a Pattern>> {
+    let fields = match &row[0].kind {
+        PatternKind::Variant(variant, fields) if variant == name => fields.iter().collect(),
+        PatternKind::Lit(lit) if lit == name => Vec::new(),
+        PatternKind::Wildcard | PatternKind::Binding(_) => vec![&WILDCARD; arity],
+        _ => return None,
+    };
+    Some([fields, row[1..].to_vec()].concat())
+}
+
+fn format_constructor(name: &str, fields: &[String]) -> String {
+    if fields.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}({})", name, fields.join(", "))
+    }
+}
+
+/// Whether an expression names something that can be assigned to.
+fn is_place(expr: &Expression) -> bool {
+    place_root(expr).is_some()
+}
+
+/// The variable that holds a place, if the expression is one.
+fn place_root(expr: &Expression) -> Option<&Expression> {
+    match &expr.kind {
+        ExpressionKind::Id(_) => Some(expr),
+        ExpressionKind::Call(name, args) if name == "[]" && !args[1].is_range() => {
+            place_root(&args[0])
+        }
+        ExpressionKind::Field(record, _) => place_root(record),
+        _ => None,
+    }
+}
@@ -1,11 +1,18 @@
 use std::cmp::Ordering;
+use std::collections::btree_set;
 use std::collections::BTreeSet;
-use std::process;
 
+#[derive(Debug)]
 pub struct Errors {
     errors: BTreeSet<Error>,
 }
 
+impl Default for Errors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Errors {
     pub fn new() -> Errors {
         Errors {
@@ -14,70 +21,230 @@ impl Errors {
     }
 
     pub fn add(&mut self, kind: ErrorKind, line: usize, col: usize) {
-        self.errors.insert(Error { kind, line, col });
+        self.push(Error::new(kind, line, col));
+    }
+
+    pub fn push(&mut self, error: Error) {
+        self.errors.insert(error);
     }
 
     pub fn should_abort(&self) -> bool {
-        !self.errors.is_empty()
+        self.errors
+            .iter()
+            .any(|err| err.kind.severity() == Severity::Error)
+    }
+
+    pub fn iter(&self) -> btree_set::Iter<'_, Error> {
+        self.errors.iter()
+    }
+
+    pub fn emit(&self, filename: &str, source: &str, format: ErrorFormat) -> String {
+        match format {
+            ErrorFormat::Human => self.render(filename, source),
+            ErrorFormat::Json => self.render_json(filename),
+        }
     }
 
-    pub fn print_and_abort(&self) {
+    /// Renders every diagnostic with the line of `source` it points at.
+    pub fn render(&self, filename: &str, source: &str) -> String {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut string = String::new();
+
         for err in &self.errors {
-            Errors::print_error(err);
+            string.push_str(&err.render(filename, &lines));
+            string.push('\n');
         }
-        process::exit(1);
+
+        string
     }
 
-    fn print_error(error: &Error) {
-        match &error.kind {
-            ErrorKind::UndeclaredVariable { var } => {
-                println!(
-                    "{}:{}: use of undeclared variable '{var}'",
-                    error.line, error.col
-                )
-            }
-            ErrorKind::OperationNotImplemented { op, type1, type2 } => {
-                println!(
-                    "{}:{}: operation '{op}' not implemented between types '{type1}' and '{type2}'",
-                    error.line, error.col
-                )
-            }
-            ErrorKind::MismatchedTypes { expected, found } => {
-                println!(
-                    "{}:{}: mismatched types\n\texpected '{}', found '{}'",
-                    error.line, error.col, expected, found
-                )
-            }
-            ErrorKind::InvalidArray => println!(
-                "{}:{}: All array elements must have the same type",
-                error.line, error.col
-            ),
-            ErrorKind::InvalidIdentifier => {
-                println!("{}:{}: invalid identifier", error.line, error.col)
-            }
-            ErrorKind::UndefinedType { type1 } => {
-                println!("{}:{}: undefined type '{}'", error.line, error.col, type1);
-            }
-            ErrorKind::UndefinedProcedure { name } => {
-                println!(
-                    "{}:{}: undefined procedure '{}'",
-                    error.line, error.col, name
-                );
-            }
+    /// Renders every diagnostic as a JSON object on its own line.
+    pub fn render_json(&self, filename: &str) -> String {
+        let mut string = String::new();
+
+        for err in &self.errors {
+            string.push_str(&err.render_json(filename));
+            string.push('\n');
+        }
+
+        string
+    }
+}
+
+impl IntoIterator for Errors {
+    type Item = Error;
+    type IntoIter = btree_set::IntoIter<Error>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Errors {
+    type Item = &'a Error;
+    type IntoIter = btree_set::Iter<'a, Error>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.iter()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
         }
     }
 }
 
-#[derive(PartialEq, Eq)]
-struct Error {
-    kind: ErrorKind,
-    line: usize,
-    col: usize,
+#[derive(Debug, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
 }
 
+impl Error {
+    pub fn new(kind: ErrorKind, line: usize, col: usize) -> Error {
+        Error {
+            kind,
+            line,
+            col,
+            len: 1,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    /// Number of characters underlined, starting at the column.
+    pub fn with_len(mut self, len: usize) -> Error {
+        self.len = len.max(1);
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Error {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Error {
+        self.help = Some(help);
+        self
+    }
+
+    fn render(&self, filename: &str, lines: &[&str]) -> String {
+        let severity = self.kind.severity().as_str();
+        let gutter = " ".repeat(self.line.to_string().len());
+
+        let mut string = format!(
+            "{}[{}]: {}\n{gutter}--> {}:{}:{}\n",
+            severity,
+            self.kind.code(),
+            self.kind.message(),
+            filename,
+            self.line,
+            self.col
+        );
+
+        if let Some(line) = lines.get(self.line - 1) {
+            // tabs are expanded, so the carets have to be shifted the same way
+            let prefix: String = line.chars().take(self.col - 1).collect();
+            let offset = expand_tabs(&prefix).chars().count();
+
+            string.push_str(&format!("{gutter} |\n"));
+            string.push_str(&format!("{} | {}\n", self.line, expand_tabs(line)));
+            string.push_str(&format!(
+                "{gutter} | {}{}",
+                " ".repeat(offset),
+                "^".repeat(self.len)
+            ));
+            if let Some(label) = self.kind.label() {
+                string.push_str(&format!(" {}", label));
+            }
+            string.push('\n');
+        }
+
+        for note in &self.notes {
+            string.push_str(&format!("{gutter} = note: {}\n", note));
+        }
+        if let Some(help) = &self.help {
+            string.push_str(&format!("{gutter} = help: {}\n", help));
+        }
+
+        string
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn sort_key(
+        &self,
+    ) -> (
+        usize,
+        usize,
+        &str,
+        &ErrorKind,
+        usize,
+        &[String],
+        &Option<String>,
+    ) {
+        (
+            self.line,
+            self.col,
+            self.kind.code(),
+            &self.kind,
+            self.len,
+            &self.notes,
+            &self.help,
+        )
+    }
+
+    /// The span covers the underlined characters, `end` is exclusive.
+    fn render_json(&self, filename: &str) -> String {
+        let notes: Vec<String> = self.notes.iter().map(|note| json_string(note)).collect();
+
+        format!(
+            "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\
+            \"span\":{{\"start\":{{\"line\":{},\"col\":{}}},\"end\":{{\"line\":{},\"col\":{}}}}},\
+            \"label\":{},\"notes\":[{}],\"help\":{}}}",
+            json_string(self.kind.severity().as_str()),
+            json_string(self.kind.code()),
+            json_string(&self.kind.message()),
+            json_string(filename),
+            self.line,
+            self.col,
+            self.line,
+            self.col + self.len,
+            self.kind
+                .label()
+                .map_or("null".to_string(), |label| json_string(&label)),
+            notes.join(","),
+            self.help
+                .as_ref()
+                .map_or("null".to_string(), |help| json_string(help)),
+        )
+    }
+}
+
+/// By position, and then by everything else, so different errors at the
+/// same position are all kept.
 impl Ord for Error {
     fn cmp(&self, other: &Self) -> Ordering {
-        (self.line, &self.col).cmp(&(other.line, &other.col))
+        self.sort_key().cmp(&other.sort_key())
     }
 }
 
@@ -87,7 +254,7 @@ impl PartialOrd for Error {
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ErrorKind {
     UndeclaredVariable {
         var: String,
@@ -109,4 +276,259 @@ pub enum ErrorKind {
     UndefinedProcedure {
         name: String,
     },
+    UndefinedField {
+        r#type: String,
+        field: String,
+    },
+    UndefinedVariant {
+        r#type: String,
+        variant: String,
+    },
+    WrongNumberOfFields {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    NonExhaustiveMatch {
+        missing: String,
+    },
+    NotIterable {
+        r#type: String,
+    },
+    NoReturnValue {
+        name: String,
+    },
+    TypeAnnotationsNeeded,
+    InvalidReference,
+    AliasedReference {
+        var: String,
+    },
+    ChangedCapture {
+        var: String,
+    },
+    RefProcedureValue {
+        name: String,
+    },
+    NoDefaultValue {
+        var: String,
+    },
+    NotCallable {
+        r#type: String,
+    },
+    MissingReturn {
+        name: String,
+    },
+    ReturnOutsideProcedure,
+    OutsideLoop {
+        keyword: String,
+    },
+    UnreachableCode,
+    InvalidEscape {
+        escape: String,
+    },
+    UnterminatedString,
+    Redefinition {
+        name: String,
+    },
+    SharedReference {
+        var: String,
+        name: String,
+    },
+    RecursiveType {
+        name: String,
+    },
+    UnexpectedToken {
+        expected: String,
+        found: String,
+    },
+    UnexpectedEnd {
+        expected: String,
+    },
+    InconsistentIndentation,
+    RuntimeError {
+        message: String,
+    },
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::UnexpectedToken { .. } => "E0001",
+            ErrorKind::UnexpectedEnd { .. } => "E0002",
+            ErrorKind::InconsistentIndentation => "E0003",
+            ErrorKind::InvalidIdentifier => "E0004",
+            ErrorKind::UndefinedType { .. } => "E0005",
+            ErrorKind::UndeclaredVariable { .. } => "E0006",
+            ErrorKind::UndefinedProcedure { .. } => "E0007",
+            ErrorKind::MismatchedTypes { .. } => "E0008",
+            ErrorKind::OperationNotImplemented { .. } => "E0009",
+            ErrorKind::InvalidArray => "E0010",
+            ErrorKind::RuntimeError { .. } => "E0011",
+            ErrorKind::UndefinedField { .. } => "E0012",
+            ErrorKind::UndefinedVariant { .. } => "E0013",
+            ErrorKind::WrongNumberOfFields { .. } => "E0014",
+            ErrorKind::NonExhaustiveMatch { .. } => "E0015",
+            ErrorKind::NotIterable { .. } => "E0016",
+            ErrorKind::NoReturnValue { .. } => "E0017",
+            ErrorKind::TypeAnnotationsNeeded => "E0018",
+            ErrorKind::InvalidReference => "E0019",
+            ErrorKind::AliasedReference { .. } => "E0020",
+            ErrorKind::ChangedCapture { .. } => "E0021",
+            ErrorKind::RefProcedureValue { .. } => "E0022",
+            ErrorKind::NoDefaultValue { .. } => "E0023",
+            ErrorKind::NotCallable { .. } => "E0024",
+            ErrorKind::MissingReturn { .. } => "E0025",
+            ErrorKind::ReturnOutsideProcedure => "E0026",
+            ErrorKind::OutsideLoop { .. } => "E0027",
+            ErrorKind::UnreachableCode => "E0028",
+            ErrorKind::InvalidEscape { .. } => "E0029",
+            ErrorKind::UnterminatedString => "E0030",
+            ErrorKind::Redefinition { .. } => "E0031",
+            ErrorKind::SharedReference { .. } => "E0032",
+            ErrorKind::RecursiveType { .. } => "E0033",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ErrorKind::UndeclaredVariable { var } => format!("use of undeclared variable '{var}'"),
+            ErrorKind::OperationNotImplemented { op, type1, type2 } => {
+                format!("operation '{op}' not implemented between types '{type1}' and '{type2}'")
+            }
+            ErrorKind::MismatchedTypes { .. } => "mismatched types".to_string(),
+            ErrorKind::InvalidArray => "all array elements must have the same type".to_string(),
+            ErrorKind::InvalidIdentifier => "invalid identifier".to_string(),
+            ErrorKind::UndefinedType { type1 } => format!("undefined type '{type1}'"),
+            ErrorKind::UndefinedProcedure { name } => format!("undefined procedure '{name}'"),
+            ErrorKind::UndefinedField { r#type, field } => {
+                format!("no field '{}' on type '{}'", field, r#type)
+            }
+            ErrorKind::UndefinedVariant { r#type, variant } => {
+                format!("no variant '{}' in type '{}'", variant, r#type)
+            }
+            ErrorKind::WrongNumberOfFields {
+                name,
+                expected,
+                found,
+            } => format!("'{name}' has {expected} fields, but the pattern has {found}"),
+            ErrorKind::NonExhaustiveMatch { missing } => {
+                format!("non-exhaustive patterns: '{missing}' not covered")
+            }
+            ErrorKind::NotIterable { r#type } => {
+                format!("values of type '{}' can't be iterated over", r#type)
+            }
+            ErrorKind::NoReturnValue { name } => format!("'{name}' doesn't return a value"),
+            ErrorKind::TypeAnnotationsNeeded => "type annotations needed".to_string(),
+            ErrorKind::InvalidReference => {
+                "only variables, elements and fields can be passed by reference".to_string()
+            }
+            ErrorKind::AliasedReference { var } => {
+                format!("'{var}' is passed by reference more than once")
+            }
+            ErrorKind::ChangedCapture { var } => {
+                format!("can't change '{var}', which the procedure captured by value")
+            }
+            ErrorKind::RefProcedureValue { name } => {
+                format!("'{name}' has ref parameters, so it can only be called")
+            }
+            ErrorKind::NoDefaultValue { var } => {
+                format!("'{var}' holds a procedure, so it needs a value")
+            }
+            ErrorKind::NotCallable { r#type } => {
+                format!("values of type '{}' can't be called", r#type)
+            }
+            ErrorKind::MissingReturn { name } => {
+                format!("procedure '{name}' may end without returning a value")
+            }
+            ErrorKind::ReturnOutsideProcedure => "'return' outside of a procedure".to_string(),
+            ErrorKind::OutsideLoop { keyword } => format!("'{keyword}' outside of a loop"),
+            ErrorKind::UnreachableCode => "unreachable statement".to_string(),
+            ErrorKind::InvalidEscape { escape } => format!("unknown escape code '{escape}'"),
+            ErrorKind::UnterminatedString => "unterminated string literal".to_string(),
+            ErrorKind::Redefinition { name } => format!("'{name}' is already defined"),
+            ErrorKind::SharedReference { var, name } => {
+                format!("'{var}' is passed by reference to '{name}', which uses it too")
+            }
+            ErrorKind::RecursiveType { name } => {
+                format!("recursive type '{name}' has no finite value")
+            }
+            ErrorKind::UnexpectedToken { expected, found } => {
+                format!("expected {expected}, found '{found}'")
+            }
+            ErrorKind::UnexpectedEnd { expected } => {
+                format!("expected {expected}, found end of line")
+            }
+            ErrorKind::InconsistentIndentation => {
+                "indentation does not match any outer block".to_string()
+            }
+            ErrorKind::RuntimeError { message } => message.clone(),
+        }
+    }
+
+    /// Short text printed next to the carets.
+    pub fn label(&self) -> Option<String> {
+        match self {
+            ErrorKind::MismatchedTypes { expected, found } => {
+                Some(format!("expected '{expected}', found '{found}'"))
+            }
+            ErrorKind::UnexpectedToken { expected, .. } | ErrorKind::UnexpectedEnd { expected } => {
+                Some(format!("expected {expected}"))
+            }
+            ErrorKind::NonExhaustiveMatch { missing } => {
+                Some(format!("pattern '{missing}' not covered"))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The candidate closest to `name`, if it is close enough to be a typo.
+pub fn find_similar<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= (name.chars().count() / 3).max(1))
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ch_a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, ch_b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ch_a != *ch_b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+fn expand_tabs(string: &str) -> String {
+    string.replace('\t', "    ")
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::from("\"");
+    for ch in string.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\t' => json.push_str("\\t"),
+            ch if ch.is_control() => json.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
 }
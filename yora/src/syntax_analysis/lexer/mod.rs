@@ -1,4 +1,6 @@
-use std::process;
+use super::errors::*;
+use crate::core::find_invalid_escape;
+use crate::YoraError;
 
 pub mod tokens_pretty;
 
@@ -22,50 +24,53 @@ pub enum TokenKind {
     BoolLit,
     CharLit,
     StringLit,
+
+    // Layout
+    Newline,
+    Indent,
+    Dedent,
 }
 
 impl Token {
-    pub fn new(buffer: &Buffer) -> Token {
-        Token {
+    /// `None` if the buffer holds an invalid identifier.
+    pub fn new(buffer: &Buffer) -> Option<Token> {
+        Some(Token {
             str: buffer.str.clone(),
-            kind: Token::get_token_kind(&buffer.str),
+            kind: Token::get_token_kind(&buffer.str)?,
             line: buffer.y,
             col: buffer.x,
-        }
+        })
     }
 
-    fn get_token_kind(string: &String) -> TokenKind {
+    fn get_token_kind(string: &String) -> Option<TokenKind> {
         if string.parse::<i64>().is_ok() {
-            return TokenKind::IntLit;
+            return Some(TokenKind::IntLit);
         }
 
-        if string[0..1] == *"\"" {
-            return TokenKind::StringLit;
+        if string.starts_with('"') {
+            return Some(TokenKind::StringLit);
         }
 
-        if string.len() == 3 && string[0..1] == *"'" && string[2..3] == *"'" {
-            return TokenKind::CharLit;
+        if string.chars().count() == 3 && string.starts_with('\'') && string.ends_with('\'') {
+            return Some(TokenKind::CharLit);
         }
 
-        match string.as_str() {
-            "var" | "if" | "else" | "loop" | "while" | "continue" | "break" => TokenKind::Keyword,
+        let kind = match string.as_str() {
+            "var" | "if" | "else" | "loop" | "while" | "for" | "in" | "continue" | "break"
+            | "ref" | "_" => TokenKind::Keyword,
 
             "=" | "+" | "-" | "*" | "/" | "%" | "+=" | "-=" | "*=" | "/=" | "%=" | "!" | "and"
             | "or" | "==" | "!=" | "<" | "<=" | ">" | ">=" => TokenKind::Operator,
 
-            ":" | "(" | ")" | "[" | "]" | "," | "->" => TokenKind::Separator,
+            ":" | "(" | ")" | "[" | "]" | "," | "." | ".." | "->" => TokenKind::Separator,
 
             "true" | "false" => TokenKind::BoolLit,
 
-            _ => {
-                if Token::is_valid_identifier(string) {
-                    TokenKind::Identifier
-                } else {
-                    println!("Invalid identifier:\n{}", string);
-                    process::exit(1);
-                }
-            }
-        }
+            _ if Token::is_valid_identifier(string) => TokenKind::Identifier,
+            _ => return None,
+        };
+
+        Some(kind)
     }
 
     fn is_valid_identifier(string: &String) -> bool {
@@ -78,6 +83,14 @@ impl Token {
     }
 }
 
+/// A comment, kept apart from the tokens. `text` starts at the `#`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub line: usize,
+    pub col: usize,
+}
+
 pub struct Buffer {
     pub str: String,
     pub first_ch: char,
@@ -111,15 +124,18 @@ impl Buffer {
     fn should_tokenize(&self, ch: char) -> bool {
         !(self.str.is_empty()
             || (Buffer::is_id_or_num(self.first_ch) && Buffer::is_id_or_num(ch))
+            || (self.str == "." && ch == '.')
             || (Buffer::is_symbol(self.first_ch)
                 && Buffer::is_symbol(ch)
-                && ch != '['
-                && ch != ']'
-                && ch != '('
-                && ch != ')'
-                && ch != ','
-                && ch != ':'
-                && ch != '!'))
+                && !Buffer::is_separator(self.first_ch)
+                && !Buffer::is_separator(ch)
+                && ch != '!'
+                && (self.first_ch != '!' || ch == '=')))
+    }
+
+    /// Brackets, commas and colons are never part of a longer symbol.
+    fn is_separator(ch: char) -> bool {
+        "()[],:".contains(ch)
     }
 
     fn is_symbol(ch: char) -> bool {
@@ -145,66 +161,234 @@ impl Cursor {
         if ch == '\n' {
             self.x = 1;
             self.y += 1;
-        } else if ch == '\t' {
-            self.x += 4;
         } else {
             self.x += 1;
         }
     }
 }
 
-pub fn lex(source: String) -> Vec<Token> {
-    let mut tokens: Vec<Token> = Vec::new();
+/// Splits the source into tokens. Every logical line ends with a `Newline`
+/// and changes of indentation between lines produce `Indent` and `Dedent`
+/// tokens, which are balanced by the end of the source. Line breaks inside
+/// parentheses and brackets are ignored.
+pub fn lex(source: String) -> Result<Vec<Token>, YoraError> {
+    lex_with_comments(source).map(|(tokens, _)| tokens)
+}
+
+/// Like `lex`, but also returns the comments, in the order they appear, for
+/// tools that have to write the source back.
+pub fn lex_with_comments(source: String) -> Result<(Vec<Token>, Vec<Comment>), YoraError> {
+    let mut lexer = Lexer {
+        tokens: Vec::new(),
+        comments: Vec::new(),
+        indent_stack: vec![String::new()],
+        nesting: 0,
+        errors: Errors::new(),
+    };
     let mut buffer = Buffer::new();
     let mut cursor = Cursor::new();
-    let mut chars = source.chars();
+    let mut chars = source.chars().peekable();
+    let mut indent = String::new();
+    let mut at_line_start = true;
 
     while let Some(ch) = chars.next() {
-        if buffer.str == "#" {
-            for next_ch in chars.by_ref() {
-                cursor.advance(next_ch);
-                if next_ch == '\n' {
-                    break;
-                }
-            }
-            buffer.clear();
-            continue;
-        }
         if buffer.str == "\"" {
             buffer.push(ch, &cursor);
-            for next_ch in chars.by_ref() {
-                buffer.push(next_ch, &cursor);
-                cursor.advance(next_ch);
-                if next_ch == '\"' {
-                    break;
+            cursor.advance(ch);
+            if ch != '\"' {
+                for next_ch in chars.by_ref() {
+                    buffer.push(next_ch, &cursor);
+                    cursor.advance(next_ch);
+                    if next_ch == '\"' {
+                        break;
+                    }
                 }
             }
-            tokens.push(Token::new(&buffer));
+            lexer.push(&buffer);
             buffer.clear();
             continue;
         }
         if buffer.str == "'" {
             buffer.push(ch, &cursor);
-            for next_ch in chars.by_ref() {
-                buffer.push(next_ch, &cursor);
-                cursor.advance(next_ch);
-                if next_ch == '\'' {
-                    break;
+            cursor.advance(ch);
+            if ch != '\'' {
+                for next_ch in chars.by_ref() {
+                    buffer.push(next_ch, &cursor);
+                    cursor.advance(next_ch);
+                    if next_ch == '\'' {
+                        break;
+                    }
                 }
             }
-            tokens.push(Token::new(&buffer));
+            lexer.push(&buffer);
             buffer.clear();
             continue;
         }
+        if at_line_start {
+            if ch == ' ' || ch == '\t' {
+                indent.push(ch);
+                cursor.advance(ch);
+                continue;
+            }
+            // blank lines and comments don't change the indentation
+            if ch != '\n' && ch != '#' && lexer.nesting == 0 {
+                lexer.indent(&indent, &cursor);
+            }
+            indent.clear();
+            at_line_start = false;
+        }
+        if ch == '#' {
+            if !buffer.str.is_empty() {
+                lexer.push(&buffer);
+                buffer.clear();
+            }
+            let mut text = String::from('#');
+            while let Some(next_ch) = chars.next_if(|next_ch| *next_ch != '\n') {
+                text.push(next_ch);
+            }
+            lexer.comments.push(Comment {
+                text: text.trim_end().to_string(),
+                line: cursor.y,
+                col: cursor.x,
+            });
+            continue;
+        }
         if buffer.should_tokenize(ch) {
-            tokens.push(Token::new(&buffer));
+            lexer.push(&buffer);
             buffer.clear();
         }
-        if !ch.is_whitespace() {
+        if ch == '\n' {
+            lexer.end_line(&cursor);
+            at_line_start = true;
+        } else if !ch.is_whitespace() {
             buffer.push(ch, &cursor);
         }
         cursor.advance(ch);
     }
 
-    tokens
+    if !buffer.str.is_empty() {
+        lexer.push(&buffer);
+    }
+    // unclosed brackets are left to the parser to report, right after the
+    // last token
+    if lexer.nesting > 0 {
+        let last = lexer.tokens.last().unwrap();
+        cursor = Cursor {
+            x: last.col + last.str.chars().count(),
+            y: last.line,
+        };
+        lexer.nesting = 0;
+    }
+    lexer.end_line(&cursor);
+    lexer.indent("", &cursor);
+
+    if lexer.errors.should_abort() {
+        Err(YoraError::Lex(lexer.errors))
+    } else {
+        Ok((lexer.tokens, lexer.comments))
+    }
+}
+
+struct Lexer {
+    tokens: Vec<Token>,
+    comments: Vec<Comment>,
+    // the indentation of every enclosing block, the innermost one last
+    indent_stack: Vec<String>,
+    // number of open parentheses and brackets
+    nesting: usize,
+    errors: Errors,
+}
+
+impl Lexer {
+    fn push(&mut self, buffer: &Buffer) {
+        let Some(token) = Token::new(buffer) else {
+            self.errors.push(
+                Error::new(ErrorKind::InvalidIdentifier, buffer.y, buffer.x)
+                    .with_len(buffer.str.chars().count()),
+            );
+            return;
+        };
+        if token.kind == TokenKind::StringLit && !self.check_string(&token) {
+            return;
+        }
+        match token.str.as_str() {
+            "(" | "[" => self.nesting += 1,
+            ")" | "]" => self.nesting = self.nesting.saturating_sub(1),
+            _ => {}
+        }
+        self.tokens.push(token);
+    }
+
+    /// Reports a string literal that isn't closed or has an unknown escape
+    /// code. Returns whether it was valid.
+    fn check_string(&mut self, token: &Token) -> bool {
+        if token.str.len() < 2 || !token.str.ends_with('"') {
+            self.errors
+                .add(ErrorKind::UnterminatedString, token.line, token.col);
+            return false;
+        }
+        let Some((pos, escape)) = find_invalid_escape(&token.str) else {
+            return true;
+        };
+
+        // the literal can span several lines
+        let (mut line, mut col) = (token.line, token.col);
+        for ch in token.str.chars().take(pos) {
+            if ch == '\n' {
+                (line, col) = (line + 1, 1);
+            } else {
+                col += 1;
+            }
+        }
+        let len = escape.chars().count();
+        self.errors
+            .push(Error::new(ErrorKind::InvalidEscape { escape }, line, col).with_len(len));
+        false
+    }
+
+    fn push_layout(&mut self, kind: TokenKind, cursor: &Cursor) {
+        self.tokens.push(Token {
+            str: String::new(),
+            kind,
+            line: cursor.y,
+            col: cursor.x,
+        });
+    }
+
+    fn end_line(&mut self, cursor: &Cursor) {
+        if self.nesting > 0 {
+            return;
+        }
+        if let Some(token) = self.tokens.last() {
+            if token.kind != TokenKind::Newline {
+                self.push_layout(TokenKind::Newline, cursor);
+            }
+        }
+    }
+
+    /// Compares the indentation of a new line with the enclosing blocks. An
+    /// indentation is only deeper if it extends the current one, so a tab
+    /// and four spaces are never mistaken for the same level.
+    fn indent(&mut self, indent: &str, cursor: &Cursor) {
+        let current = self.indent_stack.last().unwrap();
+
+        if indent == current {
+            return;
+        }
+        if indent.starts_with(current.as_str()) {
+            self.indent_stack.push(indent.to_string());
+            self.push_layout(TokenKind::Indent, cursor);
+            return;
+        }
+
+        while self.indent_stack.last().unwrap().len() > indent.len() {
+            self.indent_stack.pop();
+            self.push_layout(TokenKind::Dedent, cursor);
+        }
+
+        if self.indent_stack.last().unwrap() != indent {
+            self.errors
+                .add(ErrorKind::InconsistentIndentation, cursor.y, cursor.x);
+        }
+    }
 }
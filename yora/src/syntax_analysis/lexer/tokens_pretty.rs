@@ -27,6 +27,9 @@ impl fmt::Display for TokenKind {
                 TokenKind::BoolLit => "bool",
                 TokenKind::StringLit => "string",
                 TokenKind::CharLit => "char",
+                TokenKind::Newline => "newline",
+                TokenKind::Indent => "indent",
+                TokenKind::Dedent => "dedent",
             }
         )
     }
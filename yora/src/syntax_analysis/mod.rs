@@ -1,5 +1,5 @@
+use crate::YoraError;
 use analyzer::analyze;
-use errors::Errors;
 use lexer::lex;
 use parser::parse;
 use parser::statement::Statement;
@@ -9,12 +9,12 @@ pub mod errors;
 pub mod lexer;
 pub mod parser;
 
-pub fn produce_ast(source: String) -> Vec<Statement> {
-    let mut errors = Errors::new();
+/// Lexes, parses and analyzes `source`. The diagnostics of the first stage
+/// that fails are returned instead of the statements.
+pub fn produce_ast(source: String) -> Result<Vec<Statement>, YoraError> {
+    let tokens = lex(source)?;
+    let mut ast = parse(tokens)?;
+    analyze(&mut ast)?;
 
-    let tokens = lex(source);
-    let mut ast = parse(tokens);
-    analyze(&mut ast, &mut errors);
-
-    ast
+    Ok(ast)
 }
@@ -1,6 +1,5 @@
 use crate::core::PrimitiveType;
 use crate::syntax_analysis::lexer::*;
-use std::process;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Expression {
@@ -8,6 +7,25 @@ pub struct Expression {
     pub line: usize,
     pub col: usize,
     pub r#type: Option<PrimitiveType>,
+    // the variable of an `Id`, found by the analyzer
+    pub binding: Option<Binding>,
+}
+
+/// Where a variable is stored, given by the analyzer to its declaration and
+/// to every use of it.
+///
+/// A declaration always takes a new slot, even if a variable with the same
+/// name is in scope. It hides that variable from the next statement to the
+/// end of its block, so `var a = a + 1` reads the outer `a`. The slots of a
+/// block are reused once it ends.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Binding {
+    /// How many procedures the declaration is inside of, 0 for the main
+    /// program, whose variables are globals.
+    pub depth: usize,
+    /// How many variables of the same depth are in scope at the
+    /// declaration. The arguments of a procedure take its first slots.
+    pub slot: usize,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -16,6 +34,7 @@ pub enum ExpressionKind {
     Lit(String),
     Id(String),
     Array(Vec<Expression>),
+    Field(Box<Expression>, String),
 }
 
 impl Expression {
@@ -25,14 +44,7 @@ impl Expression {
             TokenKind::IntLit => Some(PrimitiveType::Int),
             TokenKind::CharLit => Some(PrimitiveType::Char),
             TokenKind::StringLit => Some(PrimitiveType::Arr(Box::new(PrimitiveType::Char))),
-            TokenKind::Operator => Some(Self::get_type(&token.str)),
-            TokenKind::Separator => {
-                if token.str == "[" {
-                    Some(PrimitiveType::Arr(Box::new(PrimitiveType::Int)))
-                } else {
-                    None
-                } // TODO: move type detecting of indexing to analyzer
-            }
+            // the rest are inferred by the analyzer
             _ => None,
         };
 
@@ -41,19 +53,14 @@ impl Expression {
             line: token.line,
             col: token.col,
             r#type,
+            binding: None,
         }
     }
 
-    fn get_type(str: &str) -> PrimitiveType {
-        match str {
-            "[]" => PrimitiveType::Int, // TODO: move type detecting of indexing to analyzer
-            "+" | "-" | "*" | "/" | "%" => PrimitiveType::Int,
-            "and" | "or" | "!" | "==" | "!=" | "<" | "<=" | ">" | ">=" => PrimitiveType::Bool,
-            _ => {
-                println!("Unrecognized operation:\n\t{}", str);
-                process::exit(1);
-            }
-        }
+    /// Whether this is a range `start..end`, as iterated over by `for` or
+    /// used to slice arrays.
+    pub fn is_range(&self) -> bool {
+        matches!(&self.kind, ExpressionKind::Call(name, _) if name == "..")
     }
 
     pub fn format(&self, prefix: &str) -> String {
@@ -91,6 +98,7 @@ impl Expression {
             ExpressionKind::Lit(lit) => lit,
             ExpressionKind::Id(id) => id,
             ExpressionKind::Array(..) => "array",
+            ExpressionKind::Field(..) => ".",
         }
     }
 
@@ -100,6 +108,16 @@ impl Expression {
             ExpressionKind::Lit(..) => Vec::new(),
             ExpressionKind::Id(..) => Vec::new(),
             ExpressionKind::Array(values) => values.to_vec(),
+            ExpressionKind::Field(record, field) => vec![
+                *record.clone(),
+                Expression {
+                    kind: ExpressionKind::Id(field.clone()),
+                    line: self.line,
+                    col: self.col,
+                    r#type: self.r#type.clone(),
+                    binding: None,
+                },
+            ],
         }
     }
 }
@@ -1,596 +1,881 @@
-use std::process;
-
 use self::expression::*;
+use self::pattern::*;
 use self::statement::*;
+use super::errors::*;
 use super::lexer::*;
 use crate::core::PrimitiveType;
+use crate::YoraError;
 
 pub mod expression;
+pub mod pattern;
 pub mod statement;
 
-pub fn parse(tokens: Vec<Token>) -> Vec<Statement> {
-    Parser::parse(&tokens)
+/// Parses the tokens into a sequence of statements. On a syntax error the
+/// rest of the line is skipped, so every error in the source is reported.
+pub fn parse(tokens: Vec<Token>) -> Result<Vec<Statement>, YoraError> {
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        errors: Errors::new(),
+    };
+
+    let sequence = parser.parse_sequence();
+
+    if parser.errors.should_abort() {
+        Err(YoraError::Parse(parser.errors))
+    } else {
+        Ok(sequence)
+    }
 }
 
-pub struct Parser;
+/// Parses tokens that hold a single expression, on a single line.
+pub fn parse_expression(tokens: Vec<Token>) -> Result<Expression, YoraError> {
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        errors: Errors::new(),
+    };
+
+    let expression = parser.parse_expression().and_then(|expression| {
+        parser.expect_line_end()?;
+        match parser.peek() {
+            Some(_) => Err(parser.unexpected("end of input")),
+            None => Ok(expression),
+        }
+    });
 
-impl Parser {
-    fn parse(tokens: &[Token]) -> Vec<Statement> {
-        Self::get_sequence(tokens)
-    }
+    expression.map_err(|err| {
+        parser.errors.push(*err);
+        YoraError::Parse(parser.errors)
+    })
+}
 
-    fn get_sequence(tokens: &[Token]) -> Vec<Statement> {
-        let mut sequence: Vec<Statement> = Vec::new();
-        let mut start = 0;
-        let mut end = 1;
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    errors: Errors,
+}
 
-        if tokens.len() == 1 {
-            sequence.push(Self::get_statement(tokens));
-        }
-        while end < tokens.len() + 1 {
-            if tokens[start].str == "if"
-                || tokens[start].str == "loop"
-                || tokens[start].str == "while"
-                || tokens[start].str == "pr"
-            {
-                // find last token in the block
-                while end < tokens.len()
-                    && (tokens[end].str == "else" || tokens[start].col < tokens[end].col)
-                {
-                    end += 1;
-                }
+type ParseResult<T> = Result<T, Box<Error>>;
 
-                sequence.push(match tokens[start].str.as_str() {
-                    "if" => Self::get_if(&tokens[start..end]),
-                    "loop" => Self::get_loop(&tokens[start..end]),
-                    "while" => Self::get_while(&tokens[start..end]),
-                    "pr" => Self::get_procedure(&tokens[start..end]),
-                    _ => panic!(),
-                })
-            } else {
-                while end < tokens.len() && tokens[start].line == tokens[end].line {
-                    end += 1;
-                }
-                sequence.push(Self::get_statement(&tokens[start..end]));
+impl Parser {
+    /// Parses statements until the end of the tokens or of the block.
+    fn parse_sequence(&mut self) -> Vec<Statement> {
+        let mut sequence = Vec::new();
+
+        while let Some(token) = self.tokens.get(self.pos) {
+            if token.kind == TokenKind::Dedent {
+                break;
             }
 
-            start = end;
-            end += 1;
+            let start = self.pos;
+            match self.parse_statement() {
+                Ok(statement) => sequence.push(statement),
+                Err(err) => {
+                    self.errors.push(*err);
+                    self.synchronize(start);
+                }
+            }
         }
 
         sequence
     }
 
-    fn get_if(tokens: &[Token]) -> Statement {
-        let start = if tokens[0].str == "else" { 2 } else { 1 };
-        let mut start_seq = start;
-        let mut end_seq = start + 1;
-
-        // find end of condition
-        while start_seq + 1 < tokens.len() && tokens[start_seq].str != ":" {
-            start_seq += 1;
-        }
-
-        // find end of first block
-        while end_seq + 1 < tokens.len() && tokens[0].col < tokens[end_seq].col {
-            end_seq += 1;
+    /// Skips the rest of a statement that failed to parse, and the block
+    /// under it if it was the header of one.
+    fn synchronize(&mut self, start: usize) {
+        // the error may have been found at the start of the next line
+        let line_ended = self.pos > start && self.tokens[self.pos - 1].kind == TokenKind::Newline;
+
+        if !line_ended {
+            while let Some(token) = self.tokens.get(self.pos) {
+                match token.kind {
+                    TokenKind::Newline => {
+                        self.pos += 1;
+                        break;
+                    }
+                    TokenKind::Indent | TokenKind::Dedent => break,
+                    _ => self.pos += 1,
+                }
+            }
         }
 
-        Statement::new(
-            if tokens[end_seq].str == "else" {
-                StatementKind::IfElse {
-                    cond: Self::get_expression(&tokens[start..start_seq]),
-                    true_block: Self::get_sequence(&tokens[start_seq + 1..end_seq]),
-                    false_block: Self::get_else(&tokens[end_seq..]),
-                }
-            } else {
-                StatementKind::If {
-                    cond: Self::get_expression(&tokens[start..start_seq]),
-                    block: Self::get_sequence(&tokens[start_seq + 1..]),
+        while self.check_kind(TokenKind::Indent) {
+            let mut depth = 0;
+            while let Some(token) = self.tokens.get(self.pos) {
+                self.pos += 1;
+                match token.kind {
+                    TokenKind::Indent => depth += 1,
+                    TokenKind::Dedent => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
                 }
-            },
-            &tokens[0],
-        )
+            }
+        }
     }
 
-    fn get_else(tokens: &[Token]) -> Vec<Statement> {
-        match tokens[1].str.as_str() {
-            "if" => vec![Self::get_if(tokens)],
-            ":" => Self::get_sequence(&tokens[2..]),
-            _ => {
-                dbg!(tokens);
-                panic!("Throw a useful error");
+    fn parse_statement(&mut self) -> ParseResult<Statement> {
+        let token = self.tokens[self.pos].clone();
+
+        match token.str.as_str() {
+            "var" => self.parse_declare(),
+            "if" => self.parse_if(),
+            "loop" => self.parse_loop(),
+            "while" => self.parse_while(),
+            "for" => self.parse_for(),
+            "pr" => self.parse_procedure(),
+            "struct" => self.parse_struct(),
+            "enum" => self.parse_enum(),
+            "match" => self.parse_match(),
+            "return" => self.parse_return(),
+            "continue" | "break" => {
+                self.pos += 1;
+                self.expect_line_end()?;
+                Ok(Statement::new(
+                    if token.str == "continue" {
+                        StatementKind::Continue
+                    } else {
+                        StatementKind::Break
+                    },
+                    &token,
+                ))
             }
+            _ => self.parse_call_or_assign(),
         }
     }
 
-    fn get_loop(tokens: &[Token]) -> Statement {
-        if tokens[1].str != ":" {
-            panic!("Throw a useful error");
-        }
-        Statement::new(
-            StatementKind::Loop {
-                block: Self::get_sequence(&tokens[2..]),
+    fn parse_declare(&mut self) -> ParseResult<Statement> {
+        let var = self.next_token();
+        let name = self.expect_identifier("a variable name")?;
+
+        let type_hint = if self.check(":") {
+            self.pos += 1;
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        let value = if self.check("=") {
+            self.pos += 1;
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        self.expect_line_end()?;
+
+        Ok(Statement::new(
+            StatementKind::Declare {
+                name: name.str,
+                type_hint,
+                value,
+                binding: None,
             },
-            &tokens[0],
-        )
+            &var,
+        ))
     }
 
-    fn get_while(tokens: &[Token]) -> Statement {
-        let mut start_seq = 0;
+    fn parse_if(&mut self) -> ParseResult<Statement> {
+        let r#if = self.next_token();
+        let cond = self.parse_expression()?;
+        let true_block = self.parse_block()?;
 
-        // find end of condition
-        while start_seq < tokens.len() && tokens[start_seq].str != ":" {
-            start_seq += 1;
+        if !self.check("else") {
+            return Ok(Statement::new(
+                StatementKind::If {
+                    cond,
+                    block: true_block,
+                },
+                &r#if,
+            ));
         }
-        Statement::new(
-            StatementKind::While {
-                cond: Self::get_expression(&tokens[1..start_seq]),
-                block: Self::get_sequence(&tokens[start_seq + 1..]),
+        self.pos += 1;
+
+        let false_block = if self.check("if") {
+            vec![self.parse_if()?]
+        } else {
+            self.parse_block()?
+        };
+
+        Ok(Statement::new(
+            StatementKind::IfElse {
+                cond,
+                true_block,
+                false_block,
             },
-            &tokens[start_seq],
-        )
+            &r#if,
+        ))
     }
 
-    fn get_procedure(tokens: &[Token]) -> Statement {
-        let mut start_seq = 0;
-        let mut args = Vec::new();
+    fn parse_loop(&mut self) -> ParseResult<Statement> {
+        let r#loop = self.next_token();
+        let block = self.parse_block()?;
 
-        // find end of arguments
-        while start_seq < tokens.len() && tokens[start_seq].str != ")" {
-            start_seq += 1;
-        }
-
-        // parse args
-        let mut arg_tokens = tokens[3..=start_seq].iter().peekable();
-        while let Some(token) = arg_tokens.next() {
-            let arg_name = token.str.clone();
-            let mut arg_type = None;
-            if let Some(token) = arg_tokens.next() {
-                match token.str.as_str() {
-                    ":" => {
-                        let mut type_string = String::new();
-                        while let Some(token) = arg_tokens.peek() {
-                            match token.str.as_str() {
-                                "," | ")" => {
-                                    arg_type = Some(PrimitiveType::from_str(&type_string));
-                                    break;
-                                }
-                                _ => type_string.push_str(&arg_tokens.next().unwrap().str.clone()),
-                            }
-                        }
-                        if arg_tokens.next().is_some() {
-                            args.push((arg_name, arg_type));
-                            continue;
-                        }
-                    }
-                    "," | ")" => {
-                        args.push((arg_name, arg_type));
-                        continue;
-                    }
-                    _ => panic!("Error parsing procedure arguments"),
-                }
-            }
+        Ok(Statement::new(StatementKind::Loop { block }, &r#loop))
+    }
+
+    fn parse_while(&mut self) -> ParseResult<Statement> {
+        let r#while = self.next_token();
+        let cond = self.parse_expression()?;
+        let block = self.parse_block()?;
+
+        Ok(Statement::new(
+            StatementKind::While { cond, block },
+            &r#while,
+        ))
+    }
+
+    /// The iterable is an array or a range.
+    fn parse_for(&mut self) -> ParseResult<Statement> {
+        let r#for = self.next_token();
+        let var = self.expect_identifier("a variable name")?;
+        self.expect("in")?;
+
+        let iterable = self.parse_range()?;
+        let block = self.parse_block()?;
+
+        Ok(Statement::new(
+            StatementKind::For {
+                var: var.str,
+                binding: None,
+                iterable,
+                block,
+            },
+            &r#for,
+        ))
+    }
+
+    /// An expression or a range `start..end`, which is kept as a call to `..`.
+    fn parse_range(&mut self) -> ParseResult<Expression> {
+        let start = self.parse_expression()?;
+        if !self.check("..") {
+            return Ok(start);
         }
 
-        let ret = if tokens[start_seq + 1].str == "->" {
-            let mut ret_type = String::new();
-            start_seq += 2;
-            for token in &tokens[start_seq..] {
-                if token.str != ":" {
-                    ret_type.push_str(&token.str);
-                    start_seq += 1;
+        let range = self.next_token();
+        let end = self.parse_expression()?;
+        Ok(Expression::new(
+            ExpressionKind::Call("..".to_string(), vec![start, end]),
+            &range,
+        ))
+    }
+
+    fn parse_procedure(&mut self) -> ParseResult<Statement> {
+        let pr = self.next_token();
+        let name = self.expect_identifier("a procedure name")?;
+
+        self.expect("(")?;
+        let mut args = Vec::new();
+        if self.check(")") {
+            self.pos += 1;
+        } else {
+            loop {
+                let is_ref = self.check("ref");
+                if is_ref {
+                    self.pos += 1;
+                }
+                let arg = self.expect_identifier("an argument name")?;
+                let arg_type = if self.check(":") {
+                    self.pos += 1;
+                    Some(self.parse_type()?)
                 } else {
+                    None
+                };
+                args.push(Param {
+                    name: arg.str,
+                    r#type: arg_type,
+                    is_ref,
+                });
+
+                if self.check(",") {
+                    self.pos += 1;
+                } else {
+                    self.expect(")")?;
                     break;
                 }
             }
-            Some(PrimitiveType::from_str(&ret_type))
+        }
+
+        let ret = if self.check("->") {
+            self.pos += 1;
+            Some(self.parse_type()?)
         } else {
-            start_seq += 1;
             None
         };
 
-        Statement::new(
+        let block = self.parse_block()?;
+
+        Ok(Statement::new(
             StatementKind::Procedure {
-                name: tokens[1].str.to_string(),
+                name: name.str,
                 args,
                 ret,
-                block: Self::get_sequence(&tokens[start_seq + 1..]),
+                block,
+                binding: None,
+                captures: Vec::new(),
             },
-            &tokens[0],
-        )
+            &pr,
+        ))
     }
 
-    fn get_statement(tokens: &[Token]) -> Statement {
-        let len = tokens.len();
+    fn parse_struct(&mut self) -> ParseResult<Statement> {
+        let r#struct = self.next_token();
+        let name = self.expect_identifier("a struct name")?;
 
-        if len == 1 {
-            Statement::new(
-                if tokens[0].str == "continue" {
-                    StatementKind::Continue
-                } else if tokens[0].str == "break" {
-                    StatementKind::Break
-                } else {
-                    println!("unrecognized expression:");
-                    dbg!(tokens);
-                    process::exit(1);
-                },
-                &tokens[0],
-            )
-        } else if tokens[0].str == "var" {
-            let mut colon_pos = 0;
-            let mut assign_pos = tokens.len();
-            for (i, token) in tokens.iter().enumerate() {
-                match token.str.as_str() {
-                    ":" => {
-                        if colon_pos == 0 {
-                            colon_pos = i;
-                        } else {
-                            panic!("Invalid declaration, too many colons");
-                        }
+        self.expect(":")?;
+        self.expect_line_end()?;
+        if !self.check_kind(TokenKind::Indent) {
+            return Err(self.unexpected("an indented block"));
+        }
+        self.pos += 1;
+
+        let mut fields = Vec::new();
+        while !self.check_kind(TokenKind::Dedent) {
+            let field = self.expect_identifier("a field name")?;
+            self.expect(":")?;
+            fields.push((field.str, self.parse_type()?));
+            self.expect_line_end()?;
+        }
+        self.pos += 1;
+
+        Ok(Statement::new(
+            StatementKind::Struct {
+                name: name.str,
+                fields,
+            },
+            &r#struct,
+        ))
+    }
+
+    fn parse_enum(&mut self) -> ParseResult<Statement> {
+        let r#enum = self.next_token();
+        let name = self.expect_identifier("an enum name")?;
+
+        self.expect(":")?;
+        self.expect_line_end()?;
+        if !self.check_kind(TokenKind::Indent) {
+            return Err(self.unexpected("an indented block"));
+        }
+        self.pos += 1;
+
+        let mut variants = Vec::new();
+        while !self.check_kind(TokenKind::Dedent) {
+            let variant = self.expect_identifier("a variant name")?;
+            let mut types = Vec::new();
+            if self.check("(") {
+                self.pos += 1;
+                loop {
+                    types.push(self.parse_type()?);
+                    if self.check(",") {
+                        self.pos += 1;
+                    } else {
+                        self.expect(")")?;
+                        break;
                     }
-                    "=" => {
-                        if assign_pos == tokens.len() {
-                            assign_pos = i;
+                }
+            }
+            variants.push((variant.str, types));
+            self.expect_line_end()?;
+        }
+        self.pos += 1;
+
+        Ok(Statement::new(
+            StatementKind::Enum {
+                name: name.str,
+                variants,
+            },
+            &r#enum,
+        ))
+    }
+
+    fn parse_match(&mut self) -> ParseResult<Statement> {
+        let r#match = self.next_token();
+        let value = self.parse_expression()?;
+
+        self.expect(":")?;
+        self.expect_line_end()?;
+        if !self.check_kind(TokenKind::Indent) {
+            return Err(self.unexpected("an indented block"));
+        }
+        self.pos += 1;
+
+        let mut arms = Vec::new();
+        while !self.check_kind(TokenKind::Dedent) {
+            let pattern = self.parse_pattern()?;
+            arms.push((pattern, self.parse_block()?));
+        }
+        self.pos += 1;
+
+        Ok(Statement::new(
+            StatementKind::Match { value, arms },
+            &r#match,
+        ))
+    }
+
+    fn parse_pattern(&mut self) -> ParseResult<Pattern> {
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.unexpected("a pattern"));
+        };
+
+        match token.kind {
+            _ if token.str == "_" => {
+                self.pos += 1;
+                Ok(Pattern::new(PatternKind::Wildcard, &token))
+            }
+            TokenKind::IntLit | TokenKind::BoolLit | TokenKind::CharLit => {
+                self.pos += 1;
+                Ok(Pattern::new(PatternKind::Lit(token.str.clone()), &token))
+            }
+            _ if token.str == "-"
+                && self
+                    .tokens
+                    .get(self.pos + 1)
+                    .is_some_and(|next| next.kind == TokenKind::IntLit) =>
+            {
+                let int = self.tokens[self.pos + 1].str.clone();
+                self.pos += 2;
+                Ok(Pattern::new(PatternKind::Lit(format!("-{}", int)), &token))
+            }
+            TokenKind::Identifier => {
+                self.pos += 1;
+                if !self.check("(") {
+                    return Ok(Pattern::new(
+                        PatternKind::Binding(token.str.clone()),
+                        &token,
+                    ));
+                }
+                self.pos += 1;
+
+                let mut fields = Vec::new();
+                if self.check(")") {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        fields.push(self.parse_pattern()?);
+                        if self.check(",") {
+                            self.pos += 1;
                         } else {
-                            panic!("Invalid declaration, too many assigns");
+                            self.expect(")")?;
+                            break;
                         }
                     }
-                    _ => {}
                 }
+                Ok(Pattern::new(
+                    PatternKind::Variant(token.str.clone(), fields),
+                    &token,
+                ))
             }
+            _ => Err(self.unexpected("a pattern")),
+        }
+    }
+
+    fn parse_return(&mut self) -> ParseResult<Statement> {
+        let r#return = self.next_token();
+        let value = self.parse_expression()?;
+        self.expect_line_end()?;
 
-            let type_hint = if colon_pos != 0 {
-                let mut type_string = String::new();
-                for token in &tokens[colon_pos + 1..assign_pos] {
-                    type_string.push_str(&token.str.clone());
+        Ok(Statement::new(StatementKind::Return { value }, &r#return))
+    }
+
+    fn parse_call_or_assign(&mut self) -> ParseResult<Statement> {
+        let first = self.tokens[self.pos].clone();
+        let dest = self.parse_expression()?;
+
+        if self.check_kind(TokenKind::Newline) {
+            if let ExpressionKind::Call(name, args) = dest.kind {
+                if (first.kind == TokenKind::Identifier && name == first.str) || name == "()" {
+                    self.pos += 1;
+                    return Ok(Statement::new(
+                        StatementKind::Call {
+                            name,
+                            args,
+                            binding: None,
+                        },
+                        &first,
+                    ));
                 }
-                Some(PrimitiveType::from_str(&type_string))
-            } else {
-                None
-            };
+            }
+            return Err(self.unexpected("'='"));
+        }
 
-            let value = if assign_pos != tokens.len() {
-                Some(Self::get_expression(&tokens[assign_pos + 1..]))
-            } else {
-                None
+        let assign = self.expect_one_of(&["=", "+=", "-=", "*=", "/=", "%="], "'='")?;
+        let mut src = self.parse_expression()?;
+        self.expect_line_end()?;
+
+        // `a += b` is `a = a + b`
+        if assign.str != "=" {
+            let op = Token {
+                str: assign.str[0..1].to_string(),
+                kind: TokenKind::Operator,
+                line: assign.line,
+                col: assign.col,
             };
+            src = Expression::new(
+                ExpressionKind::Call(op.str.clone(), vec![dest.clone(), src]),
+                &op,
+            );
+        }
 
-            Statement::new(
-                StatementKind::Declare {
-                    name: tokens[1].str.clone(),
-                    type_hint,
-                    value,
-                },
-                &tokens[0],
-            )
-        } else if tokens[1].str == "(" && tokens[len - 1].str == ")" {
-            let mut args = Vec::new();
-            let mut buffer = Vec::new();
-            let mut num_paren = 0;
-            for token in &tokens[2..] {
-                match token.str.as_str() {
-                    "," => {
-                        if num_paren == 0 {
-                            args.push(Self::get_expression(&buffer));
-                            buffer.clear()
-                        } else {
-                            buffer.push(token.clone());
-                        }
-                    }
-                    "(" => {
-                        buffer.push(token.clone());
-                        num_paren += 1;
-                    }
-                    ")" => {
-                        if num_paren > 0 {
-                            buffer.push(token.clone());
-                            num_paren -= 1;
-                        }
-                    }
-                    _ => buffer.push(token.clone()),
-                }
+        Ok(Statement::new(StatementKind::Assign { dest, src }, &first))
+    }
+
+    /// Parses the `:` ending the header of a block and the indented
+    /// statements on the following lines.
+    fn parse_block(&mut self) -> ParseResult<Vec<Statement>> {
+        self.expect(":")?;
+        self.expect_line_end()?;
+
+        if !self.check_kind(TokenKind::Indent) {
+            return Err(self.unexpected("an indented block"));
+        }
+        self.pos += 1;
+
+        let block = self.parse_sequence();
+        // the lexer closes every block, so this is its dedent
+        self.pos += 1;
+
+        Ok(block)
+    }
+
+    fn parse_type(&mut self) -> ParseResult<PrimitiveType> {
+        // an array of procedures, like `(Pr(Int) -> Int)[]`
+        if self.check("(") {
+            self.pos += 1;
+            let mut r#type = self.parse_type()?;
+            self.expect(")")?;
+            while self.check("[") {
+                self.pos += 1;
+                self.expect("]")?;
+                r#type = PrimitiveType::Arr(Box::new(r#type));
             }
-            if !buffer.is_empty() {
-                args.push(Self::get_expression(&buffer));
+            return Ok(r#type);
+        }
+
+        let name = self.expect_identifier("a type")?;
+        if name.str == "Pr" && self.check("(") {
+            return self.parse_procedure_type();
+        }
+
+        let mut type_str = name.str.clone();
+        while self.check("[") {
+            self.pos += 1;
+            self.expect("]")?;
+            type_str.push_str("[]");
+        }
+
+        if let Some(r#type) = PrimitiveType::try_from_str(&type_str) {
+            return Ok(r#type);
+        }
+        // records are checked by the analyzer, once every struct is known
+        if name.str.starts_with(|ch: char| ch.is_uppercase()) {
+            let mut r#type = PrimitiveType::Record(name.str.clone());
+            for _ in 0..(type_str.len() - name.str.len()) / 2 {
+                r#type = PrimitiveType::Arr(Box::new(r#type));
             }
-            Statement::new(
-                StatementKind::Call {
-                    name: tokens[0].str.clone(),
-                    args,
-                },
-                &tokens[0],
-            )
-        } else if tokens[0].str == "return" {
-            Statement::new(
-                StatementKind::Return {
-                    value: Self::get_expression(&tokens[1..]),
-                },
-                &tokens[0],
+            return Ok(r#type);
+        }
+
+        let len = type_str.len();
+        Err(Box::new(
+            Error::new(
+                ErrorKind::UndefinedType { type1: type_str },
+                name.line,
+                name.col,
             )
+            .with_len(len)
+            .with_help(
+                "the types are 'Int', 'Bool', 'Char', structs, procedures and arrays of them"
+                    .to_string(),
+            ),
+        ))
+    }
+
+    /// Parses the rest of a procedure type, like `Pr(Int, Int) -> Bool`.
+    /// The `[]` after it belong to its return type.
+    fn parse_procedure_type(&mut self) -> ParseResult<PrimitiveType> {
+        self.expect("(")?;
+        let mut args = Vec::new();
+        if self.check(")") {
+            self.pos += 1;
         } else {
-            for (i, token) in tokens.iter().enumerate() {
-                match token.str.as_str() {
-                    "=" | "+=" | "-=" | "*=" | "/=" | "%=" => {
-                        return Self::get_assign(i, tokens);
-                    }
-                    _ => continue,
+            loop {
+                args.push(self.parse_type()?);
+                if self.check(",") {
+                    self.pos += 1;
+                } else {
+                    self.expect(")")?;
+                    break;
                 }
             }
+        }
+
+        let ret = if self.check("->") {
+            self.pos += 1;
+            Some(Box::new(self.parse_type()?))
+        } else {
+            None
+        };
+        Ok(PrimitiveType::Pr(args, ret))
+    }
+
+    fn parse_expression(&mut self) -> ParseResult<Expression> {
+        self.parse_binary(0)
+    }
+
+    /// Precedence climbing over the binary operators, all of them are left
+    /// associative.
+    fn parse_binary(&mut self, min_power: u8) -> ParseResult<Expression> {
+        let mut lhs = self.parse_unary()?;
+
+        while let Some(op) = self.peek().cloned() {
+            let Some(power) = get_binding_power(&op) else {
+                break;
+            };
+            if power <= min_power {
+                break;
+            }
+            self.pos += 1;
 
-            println!("Parser: Unrecognized statement:");
-            dbg!(tokens);
-            process::exit(1);
+            let rhs = self.parse_binary(power)?;
+            lhs = Expression::new(ExpressionKind::Call(op.str.clone(), vec![lhs, rhs]), &op);
         }
+
+        Ok(lhs)
     }
 
-    fn get_assign(assign_pos: usize, tokens: &[Token]) -> Statement {
-        match tokens[assign_pos].str.as_str() {
-            "=" => Statement::new(
-                StatementKind::Assign {
-                    dest: Self::get_expression(&tokens[0..assign_pos]),
-                    src: Self::get_expression(&tokens[assign_pos + 1..]),
-                },
-                &tokens[0],
-            ),
-            "+=" | "-=" | "*=" | "/=" | "%=" => {
-                let mut new_tokens = Vec::from(tokens);
-                new_tokens.remove(assign_pos);
-                new_tokens.insert(
-                    assign_pos,
-                    Token::new(&Buffer {
-                        str: String::from("="),
-                        first_ch: '\0',
-                        x: tokens[assign_pos].col,
-                        y: tokens[assign_pos].line,
-                    }),
+    fn parse_unary(&mut self) -> ParseResult<Expression> {
+        if self.check("-") || self.check("!") {
+            let op = self.next_token();
+            let arg = self.parse_unary()?;
+            return Ok(Expression::new(
+                ExpressionKind::Call(op.str.clone(), vec![arg]),
+                &op,
+            ));
+        }
+
+        let mut expr = self.parse_primary()?;
+
+        while self.check("[") || self.check(".") || self.check("(") {
+            // a call of the procedure the expression gives, which comes
+            // first in the arguments
+            if self.check("(") {
+                let paren = self.next_token();
+                let mut args = vec![expr];
+                args.extend(self.parse_list(")")?);
+                expr = Expression::new(
+                    ExpressionKind::Call("()".to_string(), args),
+                    &Token {
+                        str: "()".to_string(),
+                        kind: TokenKind::Operator,
+                        line: paren.line,
+                        col: paren.col,
+                    },
                 );
-                new_tokens.insert(assign_pos + 1, tokens[0].clone());
-                new_tokens.insert(
-                    assign_pos + 2,
-                    Token::new(&Buffer {
-                        str: tokens[1].str[0..1].to_string(),
-                        first_ch: '\0',
-                        x: tokens[1].col,
-                        y: tokens[1].line,
-                    }),
+                continue;
+            }
+            if self.check(".") {
+                self.pos += 1;
+                let field = self.expect_identifier("a field name")?;
+                expr = Expression::new(
+                    ExpressionKind::Field(Box::new(expr), field.str.clone()),
+                    &field,
                 );
-                Self::get_statement(&new_tokens)
+                continue;
             }
-            _ => panic!("Not an assign operation"),
-        }
-    }
-
-    fn get_expression(tokens: &[Token]) -> Expression {
-        let len = tokens.len();
-        if len == 1 {
-            Expression::new(
-                match &tokens[0].kind {
-                    TokenKind::Identifier => ExpressionKind::Id(tokens[0].str.to_string()),
-                    TokenKind::BoolLit
-                    | TokenKind::IntLit
-                    | TokenKind::StringLit
-                    | TokenKind::CharLit => ExpressionKind::Lit(tokens[0].str.to_string()),
-                    _ => {
-                        println!("Parser: Unrecognized expression:");
-                        dbg!(tokens);
-                        process::exit(1);
-                    }
-                },
-                &tokens[0],
-            )
-        } else if tokens[1].str == "[" && tokens[len - 1].str == "]" {
-            Expression::new(
-                ExpressionKind::Call(
-                    "[]".to_string(),
-                    vec![
-                        Self::get_expression(&tokens[0..1]),
-                        Self::get_expression(&tokens[2..len - 1]),
-                    ],
-                ),
+
+            // a range gives a slice of the array
+            let bracket = self.next_token();
+            let index = self.parse_range()?;
+            self.expect("]")?;
+
+            expr = Expression::new(
+                ExpressionKind::Call("[]".to_string(), vec![expr, index]),
                 &Token {
                     str: "[]".to_string(),
                     kind: TokenKind::Operator,
-                    line: tokens[1].line,
-                    col: tokens[1].col,
+                    line: bracket.line,
+                    col: bracket.col,
                 },
-            )
-        } else if tokens[1].str == "(" && tokens[len - 1].str == ")" && Self::is_single_call(tokens)
-        {
-            let mut args = Vec::new();
-            let mut buffer = Vec::new();
-            let mut num_paren = 0;
-            for token in &tokens[2..] {
-                match token.str.as_str() {
-                    "," => {
-                        if num_paren == 0 {
-                            args.push(Self::get_expression(&buffer));
-                            buffer.clear()
-                        } else {
-                            buffer.push(token.clone());
-                        }
-                    }
-                    "(" => {
-                        buffer.push(token.clone());
-                        num_paren += 1;
-                    }
-                    ")" => {
-                        if num_paren > 0 {
-                            buffer.push(token.clone());
-                            num_paren -= 1;
-                        }
-                    }
-                    _ => buffer.push(token.clone()),
-                }
-            }
-            if !buffer.is_empty() {
-                args.push(Self::get_expression(&buffer));
+            );
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<Expression> {
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.unexpected("an expression"));
+        };
+
+        match token.kind {
+            TokenKind::IntLit | TokenKind::BoolLit | TokenKind::CharLit | TokenKind::StringLit => {
+                self.pos += 1;
+                Ok(Expression::new(
+                    ExpressionKind::Lit(token.str.clone()),
+                    &token,
+                ))
             }
-            Expression::new(
-                ExpressionKind::Call(tokens[0].str.to_string(), args),
-                &tokens[0],
-            )
-        } else if tokens[0].str == "[" && tokens[len - 1].str == "]" {
-            let mut contents = Vec::new();
-            let mut buffer = Vec::new();
-            let mut num_brackets = 0;
-            for token in &tokens[1..] {
-                match token.str.as_str() {
-                    "," => {
-                        if num_brackets == 0 {
-                            contents.push(Self::get_expression(&buffer));
-                            buffer.clear()
-                        } else {
-                            buffer.push(token.clone());
-                        }
-                    }
-                    "[" => {
-                        buffer.push(token.clone());
-                        num_brackets += 1;
-                    }
-                    "]" => {
-                        if num_brackets > 0 {
-                            buffer.push(token.clone());
-                            num_brackets -= 1;
-                        }
-                    }
-                    _ => buffer.push(token.clone()),
+            TokenKind::Identifier => {
+                self.pos += 1;
+                if self.check("(") {
+                    self.pos += 1;
+                    let args = self.parse_list(")")?;
+                    Ok(Expression::new(
+                        ExpressionKind::Call(token.str.clone(), args),
+                        &token,
+                    ))
+                } else {
+                    Ok(Expression::new(
+                        ExpressionKind::Id(token.str.clone()),
+                        &token,
+                    ))
                 }
             }
-            contents.push(Self::get_expression(&buffer));
-            Expression::new(ExpressionKind::Array(contents), &tokens[0])
-        } else {
-            let mut pos = 0;
-            let mut priority = 0;
-            for (i, token) in tokens.iter().enumerate() {
-                if token.str == "(" {
-                    return if i > 0 && tokens[i - 1].kind == TokenKind::Identifier {
-                        Self::get_call_expr(tokens)
-                    } else {
-                        Self::get_parentheses(tokens)
-                    };
-                }
-                if token.kind == TokenKind::Operator && priority <= Self::get_op_priority(token) {
-                    pos = i;
-                    priority = Self::get_op_priority(token);
-                }
+            _ if token.str == "(" => {
+                self.pos += 1;
+                let expr = self.parse_expression()?;
+                self.expect(")")?;
+                Ok(expr)
             }
-            if priority != 0 {
-                if pos > 0 {
-                    let arg1 = Self::get_expression(&tokens[0..pos]);
-                    let arg2 = Self::get_expression(&tokens[pos + 1..]);
-                    Self::get_operation(&tokens[pos], arg1, arg2)
-                } else {
-                    let arg = Self::get_expression(&tokens[pos + 1..]);
-                    Expression::new(
-                        ExpressionKind::Call(tokens[pos].str.clone(), vec![arg]),
-                        &tokens[pos],
-                    )
-                }
-            } else {
-                println!("Parser: Unrecognized expression:");
-                dbg!(tokens);
-                process::exit(1);
+            _ if token.str == "[" => {
+                self.pos += 1;
+                let values = self.parse_list("]")?;
+                Ok(Expression::new(ExpressionKind::Array(values), &token))
             }
+            _ => Err(self.unexpected("an expression")),
         }
     }
 
-    fn get_parentheses(tokens: &[Token]) -> Expression {
-        let mut start = 0;
-        while start < tokens.len() && tokens[start].str != "(" {
-            start += 1;
+    /// Parses comma separated expressions up to and including `close`.
+    fn parse_list(&mut self, close: &str) -> ParseResult<Vec<Expression>> {
+        let mut list = Vec::new();
+
+        if self.check(close) {
+            self.pos += 1;
+            return Ok(list);
         }
-        let mut end = start + 1;
-        let mut num_paren = 1;
-        while end < tokens.len() && num_paren > 0 {
-            if tokens[end].str == "(" {
-                num_paren += 1;
-            } else if tokens[end].str == ")" {
-                num_paren -= 1;
+
+        loop {
+            list.push(self.parse_expression()?);
+
+            if self.check(",") {
+                self.pos += 1;
+            } else {
+                self.expect(close)?;
+                return Ok(list);
             }
-            end += 1;
-        }
-        if num_paren != 0 {
-            panic!("Unmatched parentheses");
         }
+    }
 
-        let mut expr = Self::get_expression(&tokens[start + 1..end - 1]);
-        if start > 1 {
-            let left_of_paren = Self::get_expression(&tokens[0..start - 1]);
-            expr = Self::get_operation(&tokens[start - 1], left_of_paren, expr);
-        }
-        if end < tokens.len() {
-            let right_of_paren = Self::get_expression(&tokens[end + 1..]);
-            expr = Self::get_operation(&tokens[end], expr, right_of_paren);
-        }
-        expr
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
     }
 
-    fn get_call_expr(tokens: &[Token]) -> Expression {
-        let mut start = 0;
-        while start < tokens.len() && tokens[start].str != "(" {
-            start += 1;
-        }
-        let mut end = start + 1;
-        let mut num_paren = 1;
-        while end < tokens.len() && num_paren > 0 {
-            if tokens[end].str == "(" {
-                num_paren += 1;
-            } else if tokens[end].str == ")" {
-                num_paren -= 1;
-            }
-            end += 1;
-        }
-        if num_paren != 0 {
-            panic!("Unmatched parentheses");
-        }
+    fn next_token(&mut self) -> Token {
+        self.pos += 1;
+        self.tokens[self.pos - 1].clone()
+    }
 
-        let mut call = Self::get_expression(&tokens[start - 1..end]);
-        if start > 2 {
-            let left_of_paren = Self::get_expression(&tokens[0..start - 2]);
-            call = Self::get_operation(&tokens[start - 2], left_of_paren, call);
-        }
-        if end < tokens.len() {
-            let right_of_paren = Self::get_expression(&tokens[end + 1..]);
-            call = Self::get_operation(&tokens[end], call, right_of_paren);
-        }
-        call
+    fn check(&self, str: &str) -> bool {
+        self.peek()
+            .is_some_and(|token| token.str == str && !is_layout(token))
     }
 
-    fn is_single_call(tokens: &[Token]) -> bool {
-        let mut end = 2;
-        let mut num_paren = 1;
-        while end < tokens.len() && num_paren > 0 {
-            if tokens[end].str == "(" {
-                num_paren += 1;
-            } else if tokens[end].str == ")" {
-                num_paren -= 1;
-            }
-            end += 1;
+    fn check_kind(&self, kind: TokenKind) -> bool {
+        self.peek().is_some_and(|token| token.kind == kind)
+    }
+
+    fn expect(&mut self, str: &str) -> ParseResult<Token> {
+        self.expect_one_of(&[str], &format!("'{str}'"))
+    }
+
+    fn expect_one_of(&mut self, strs: &[&str], expected: &str) -> ParseResult<Token> {
+        match self.peek() {
+            Some(token) if strs.contains(&token.str.as_str()) => Ok(self.next_token()),
+            _ => Err(self.unexpected(expected)),
         }
+    }
 
-        end == tokens.len()
+    fn expect_identifier(&mut self, expected: &str) -> ParseResult<Token> {
+        match self.peek() {
+            Some(token) if token.kind == TokenKind::Identifier => Ok(self.next_token()),
+            _ => Err(self.unexpected(expected)),
+        }
     }
 
-    fn get_operation(operation: &Token, arg1: Expression, arg2: Expression) -> Expression {
-        Expression::new(
-            ExpressionKind::Call(operation.str.clone(), vec![arg1, arg2]),
-            operation,
-        )
+    fn expect_line_end(&mut self) -> ParseResult<()> {
+        if self.check_kind(TokenKind::Newline) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected("end of line"))
+        }
     }
 
-    fn get_op_priority(operation: &Token) -> u8 {
-        match operation.str.as_str() {
-            "*" | "/" => 1,
-            "+" | "%" | "-" => 2,
-            "!" => 3,
-            "==" | "!=" | "<" | "<=" | ">" | ">=" => 4,
-            "and" | "or" => 5,
+    fn unexpected(&self, expected: &str) -> Box<Error> {
+        Box::new(match self.peek() {
+            Some(token) if !is_layout(token) => Error::new(
+                ErrorKind::UnexpectedToken {
+                    expected: expected.to_string(),
+                    found: token.str.clone(),
+                },
+                token.line,
+                token.col,
+            )
+            .with_len(token.str.chars().count()),
+            Some(token) if token.kind == TokenKind::Indent => Error::new(
+                ErrorKind::UnexpectedToken {
+                    expected: expected.to_string(),
+                    found: "indentation".to_string(),
+                },
+                token.line,
+                token.col,
+            ),
             _ => {
-                println!("Parser: Unrecognized operation:");
-                dbg!(operation);
-                process::exit(1);
+                // a dedent comes after the end of the line it was found at
+                let newline = self.tokens[..=self.pos.min(self.tokens.len() - 1)]
+                    .iter()
+                    .rev()
+                    .find(|token| token.kind == TokenKind::Newline)
+                    .unwrap();
+                Error::new(
+                    ErrorKind::UnexpectedEnd {
+                        expected: expected.to_string(),
+                    },
+                    newline.line,
+                    newline.col,
+                )
             }
-        }
+        })
+    }
+}
+
+fn is_layout(token: &Token) -> bool {
+    matches!(
+        token.kind,
+        TokenKind::Newline | TokenKind::Indent | TokenKind::Dedent
+    )
+}
+
+fn get_binding_power(op: &Token) -> Option<u8> {
+    if op.kind != TokenKind::Operator {
+        return None;
+    }
+
+    binding_power(&op.str)
+}
+
+/// How tightly a binary operator holds its operands, `None` for anything
+/// that is not a binary operator.
+pub(crate) fn binding_power(op: &str) -> Option<u8> {
+    match op {
+        "and" | "or" => Some(1),
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => Some(2),
+        "+" | "-" => Some(3),
+        "*" | "/" | "%" => Some(4),
+        _ => None,
     }
 }
@@ -0,0 +1,64 @@
+use std::fmt;
+
+use super::expression::Binding;
+use crate::syntax_analysis::lexer::Token;
+
+/// The left side of an arm of a `match`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub line: usize,
+    pub col: usize,
+    // the variable declared by a `Binding`, given by the analyzer
+    pub binding: Option<Binding>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatternKind {
+    /// `_`, matches anything.
+    Wildcard,
+    /// Matches anything and binds it to the name. The analyzer turns the
+    /// names of variants without payload into `Variant`s.
+    Binding(String),
+    Lit(String),
+    /// A variant of an enum or a struct, with a pattern for every field.
+    Variant(String, Vec<Pattern>),
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, token: &Token) -> Pattern {
+        Pattern {
+            kind,
+            line: token.line,
+            col: token.col,
+            binding: None,
+        }
+    }
+
+    /// The names bound by the pattern, from left to right.
+    pub fn bindings(&self) -> Vec<&Pattern> {
+        match &self.kind {
+            PatternKind::Binding(_) => vec![self],
+            PatternKind::Variant(_, fields) => {
+                fields.iter().flat_map(|field| field.bindings()).collect()
+            }
+            PatternKind::Wildcard | PatternKind::Lit(_) => Vec::new(),
+        }
+    }
+}
+
+/// Writes the pattern as it is written in the source.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            PatternKind::Wildcard => write!(f, "_"),
+            PatternKind::Binding(name) => write!(f, "{}", name),
+            PatternKind::Lit(lit) => write!(f, "{}", lit),
+            PatternKind::Variant(name, fields) if fields.is_empty() => write!(f, "{}", name),
+            PatternKind::Variant(name, fields) => {
+                let fields: Vec<String> = fields.iter().map(|field| field.to_string()).collect();
+                write!(f, "{}({})", name, fields.join(", "))
+            }
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use std::fmt;
 
+use super::Binding;
 use super::Expression;
+use super::ExpressionKind;
+use super::Pattern;
 use super::Token;
 use crate::core::PrimitiveType;
 
@@ -11,28 +14,56 @@ pub struct Statement {
     pub col: usize,
 }
 
+/// An argument of a procedure. A `ref` argument is the variable given by
+/// the caller instead of a copy of its value.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Param {
+    pub name: String,
+    pub r#type: Option<PrimitiveType>,
+    pub is_ref: bool,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum StatementKind {
     // Procedures
     Procedure {
         name: String,
-        args: Vec<(String, Option<PrimitiveType>)>,
+        args: Vec<Param>,
         ret: Option<PrimitiveType>,
         block: Vec<Statement>,
+        // the variable that holds the procedure
+        binding: Option<Binding>,
+        // the variables of the enclosing code that the block uses, which
+        // are copied when the procedure is declared
+        captures: Vec<Binding>,
     },
     Call {
         name: String,
         args: Vec<Expression>,
+        // the variable of the procedure, if it isn't a builtin or a
+        // constructor
+        binding: Option<Binding>,
     },
     Return {
         value: Expression,
     },
 
+    // Records
+    Struct {
+        name: String,
+        fields: Vec<(String, PrimitiveType)>,
+    },
+    Enum {
+        name: String,
+        variants: Vec<(String, Vec<PrimitiveType>)>,
+    },
+
     // Variables
     Declare {
         name: String,
         type_hint: Option<PrimitiveType>,
         value: Option<Expression>,
+        binding: Option<Binding>,
     },
     Assign {
         dest: Expression,
@@ -56,10 +87,90 @@ pub enum StatementKind {
         cond: Expression,
         block: Vec<Statement>,
     },
+    /// Runs the block once for every element of an array or number of a
+    /// range, which is a call to `..`.
+    For {
+        var: String,
+        binding: Option<Binding>,
+        iterable: Expression,
+        block: Vec<Statement>,
+    },
+    Match {
+        value: Expression,
+        arms: Vec<(Pattern, Vec<Statement>)>,
+    },
     Continue,
     Break,
 }
 
+/// Calls `f` on every expression, parents before their children.
+pub fn visit_expressions(statements: &[Statement], f: &mut impl FnMut(&Expression)) {
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::Procedure { block, .. } | StatementKind::Loop { block } => {
+                visit_expressions(block, f)
+            }
+            StatementKind::Call { args, .. } => {
+                for arg in args {
+                    visit_expression(arg, f);
+                }
+            }
+            StatementKind::Return { value } => visit_expression(value, f),
+            StatementKind::Declare { value, .. } => {
+                if let Some(value) = value {
+                    visit_expression(value, f);
+                }
+            }
+            StatementKind::Assign { dest, src } => {
+                visit_expression(dest, f);
+                visit_expression(src, f);
+            }
+            StatementKind::If { cond, block } | StatementKind::While { cond, block } => {
+                visit_expression(cond, f);
+                visit_expressions(block, f);
+            }
+            StatementKind::IfElse {
+                cond,
+                true_block,
+                false_block,
+            } => {
+                visit_expression(cond, f);
+                visit_expressions(true_block, f);
+                visit_expressions(false_block, f);
+            }
+            StatementKind::For {
+                iterable, block, ..
+            } => {
+                visit_expression(iterable, f);
+                visit_expressions(block, f);
+            }
+            StatementKind::Match { value, arms } => {
+                visit_expression(value, f);
+                for (_, block) in arms {
+                    visit_expressions(block, f);
+                }
+            }
+            StatementKind::Struct { .. }
+            | StatementKind::Enum { .. }
+            | StatementKind::Continue
+            | StatementKind::Break => {}
+        }
+    }
+}
+
+pub fn visit_expression(expr: &Expression, f: &mut impl FnMut(&Expression)) {
+    f(expr);
+    match &expr.kind {
+        ExpressionKind::Call(_, args) | ExpressionKind::Array(args) => {
+            for arg in args {
+                visit_expression(arg, f);
+            }
+        }
+        ExpressionKind::Field(record, _) => visit_expression(record, f),
+        ExpressionKind::Lit(_) | ExpressionKind::Id(_) => {}
+    }
+}
+
 impl Statement {
     pub fn new(kind: StatementKind, token: &Token) -> Statement {
         Statement {
@@ -76,13 +187,17 @@ impl Statement {
                 args,
                 ret,
                 block,
+                ..
             } => Self::format_procedure(prefix, name, args, ret, block),
-            StatementKind::Call { name, args } => Self::format_call(prefix, name, args),
+            StatementKind::Call { name, args, .. } => Self::format_call(prefix, name, args),
             StatementKind::Return { value } => Self::format_return(prefix, value),
+            StatementKind::Struct { name, fields } => Self::format_struct(prefix, name, fields),
+            StatementKind::Enum { name, variants } => Self::format_enum(prefix, name, variants),
             StatementKind::Declare {
                 name,
                 type_hint,
                 value,
+                ..
             } => Self::format_declare(prefix, name, type_hint, value),
             StatementKind::Assign { dest, src } => Self::format_assign(prefix, dest, src),
             StatementKind::If { cond, block } => Self::format_if(prefix, cond, block),
@@ -93,6 +208,13 @@ impl Statement {
             } => Self::format_if_else(prefix, cond, true_block, false_block),
             StatementKind::Loop { block } => Self::format_loop(prefix, block),
             StatementKind::While { cond, block } => Self::format_while(prefix, cond, block),
+            StatementKind::For {
+                var,
+                iterable,
+                block,
+                ..
+            } => Self::format_for(prefix, var, iterable, block),
+            StatementKind::Match { value, arms } => Self::format_match(prefix, value, arms),
             StatementKind::Continue => "continue\n".to_string(),
             StatementKind::Break => "break\n".to_string(),
         }
@@ -101,7 +223,7 @@ impl Statement {
     fn format_procedure(
         prefix: &str,
         name: &String,
-        args: &[(String, Option<PrimitiveType>)],
+        args: &[Param],
         ret: &Option<PrimitiveType>,
         block: &[Statement],
     ) -> String {
@@ -110,18 +232,9 @@ impl Statement {
         string.push_str(&format!("{prefix}├── {name}\n"));
         if !args.is_empty() {
             string.push_str(&format!("{prefix}├── args\n"));
-            for (i, (name, type_hint)) in args.iter().enumerate() {
-                if i < args.len() - 1 {
-                    if let Some(type_hint) = type_hint {
-                        string.push_str(&format!("{prefix}│   ├── {name}: {type_hint}\n"));
-                    } else {
-                        string.push_str(&format!("{prefix}│   ├── {name}\n"));
-                    }
-                } else if let Some(type_hint) = type_hint {
-                    string.push_str(&format!("{prefix}│   └── {name}: {type_hint}\n"));
-                } else {
-                    string.push_str(&format!("{prefix}│   └── {name}\n"));
-                }
+            for (i, arg) in args.iter().enumerate() {
+                let branch = if i < args.len() - 1 { "├" } else { "└" };
+                string.push_str(&format!("{prefix}│   {branch}── {arg}\n"));
             }
         }
 
@@ -175,6 +288,45 @@ impl Statement {
         )
     }
 
+    fn format_struct(prefix: &str, name: &String, fields: &[(String, PrimitiveType)]) -> String {
+        let mut string = format!("{prefix}├── {name}\n{prefix}└── fields\n");
+
+        for (i, (field, r#type)) in fields.iter().enumerate() {
+            if i < fields.len() - 1 {
+                string.push_str(&format!("{prefix}    ├── {field}: {type}\n"));
+            } else {
+                string.push_str(&format!("{prefix}    └── {field}: {type}\n"));
+            }
+        }
+
+        format!("struct\n{string}")
+    }
+
+    fn format_enum(
+        prefix: &str,
+        name: &String,
+        variants: &[(String, Vec<PrimitiveType>)],
+    ) -> String {
+        let mut string = format!("{prefix}├── {name}\n{prefix}└── variants\n");
+
+        for (i, (variant, types)) in variants.iter().enumerate() {
+            let types: Vec<String> = types.iter().map(|r#type| r#type.to_string()).collect();
+            let variant = if types.is_empty() {
+                variant.to_string()
+            } else {
+                format!("{variant}({})", types.join(", "))
+            };
+
+            if i < variants.len() - 1 {
+                string.push_str(&format!("{prefix}    ├── {variant}\n"));
+            } else {
+                string.push_str(&format!("{prefix}    └── {variant}\n"));
+            }
+        }
+
+        format!("enum\n{string}")
+    }
+
     fn format_declare(
         prefix: &str,
         name: &String,
@@ -302,6 +454,51 @@ impl Statement {
         format!("loop\n{string}")
     }
 
+    fn format_match(
+        prefix: &str,
+        value: &Expression,
+        arms: &[(Pattern, Vec<Statement>)],
+    ) -> String {
+        let mut string = String::new();
+
+        if arms.is_empty() {
+            string.push_str(&format!(
+                "{prefix}└── {}",
+                value.format(&format!("{prefix}    "))
+            ));
+        } else {
+            string.push_str(&format!(
+                "{prefix}├── {}",
+                value.format(&format!("{prefix}│   "))
+            ));
+        }
+
+        for (i, (pattern, block)) in arms.iter().enumerate() {
+            let (branch, indent) = if i < arms.len() - 1 {
+                ("├──", "│   ")
+            } else {
+                ("└──", "    ")
+            };
+            string.push_str(&format!("{prefix}{branch} {pattern}\n"));
+
+            for (j, statement) in block.iter().enumerate() {
+                if j < block.len() - 1 {
+                    string.push_str(&format!(
+                        "{prefix}{indent}├── {}",
+                        statement.format(&format!("{prefix}{indent}│   "))
+                    ));
+                } else {
+                    string.push_str(&format!(
+                        "{prefix}{indent}└── {}",
+                        statement.format(&format!("{prefix}{indent}    "))
+                    ));
+                }
+            }
+        }
+
+        format!("match\n{string}")
+    }
+
     fn format_while(prefix: &str, cond: &Expression, block: &[Statement]) -> String {
         let mut string = String::new();
 
@@ -327,6 +524,37 @@ impl Statement {
 
         format!("while\n{string}")
     }
+
+    fn format_for(
+        prefix: &str,
+        var: &String,
+        iterable: &Expression,
+        block: &[Statement],
+    ) -> String {
+        let mut string = format!("{prefix}├── {var}\n");
+
+        string.push_str(&format!(
+            "{prefix}├── {}",
+            iterable.format(&format!("{prefix}│   "))
+        ));
+
+        string.push_str(&format!("{prefix}└── then\n"));
+        for (i, statement) in block.iter().enumerate() {
+            if i < block.len() - 1 {
+                string.push_str(&format!(
+                    "{prefix}    ├── {}",
+                    statement.format(&format!("{prefix}    │   "))
+                ));
+            } else {
+                string.push_str(&format!(
+                    "{prefix}    └── {}",
+                    statement.format(&format!("{prefix}        "))
+                ));
+            }
+        }
+
+        format!("for\n{string}")
+    }
 }
 
 impl fmt::Display for Statement {
@@ -334,3 +562,15 @@ impl fmt::Display for Statement {
         write!(f, "{}", self.format(""))
     }
 }
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_ref {
+            write!(f, "ref ")?;
+        }
+        match &self.r#type {
+            Some(r#type) => write!(f, "{}: {}", self.name, r#type),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
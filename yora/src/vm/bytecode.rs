@@ -0,0 +1,305 @@
+use std::fmt;
+
+use crate::interpreter::Value;
+use crate::syntax_analysis::parser::pattern::Pattern;
+
+/// Where a variable is stored. The variables of the main program are
+/// globals and the ones of a procedure, the closure that was called and its
+/// arguments first, are locals of its call.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Var {
+    Local(usize),
+    Global(usize),
+    /// A copy of a variable of the enclosing code, by its position in the
+    /// `captures` of the procedure being run.
+    Captured(usize),
+}
+
+/// A variable, or an element or field inside of it, that can be used in
+/// place. Its indices are on the stack from the outermost to the innermost,
+/// the order in which they are evaluated.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Place {
+    pub var: Var,
+    pub path: Vec<Step>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Step {
+    /// An element of an array, whose index is on the stack. The position is
+    /// the one of the indexing, where an out of bounds error is reported.
+    Index(usize, usize),
+    /// A field of a record, by its position in the struct.
+    Field(usize),
+}
+
+impl Place {
+    /// How many indices the place takes from the stack.
+    pub fn num_indices(&self) -> usize {
+        self.path
+            .iter()
+            .filter(|step| matches!(step, Step::Index(..)))
+            .count()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Instruction {
+    // Values
+    Push(Value),
+    Pop,
+    Load(Var),
+    Store(Var),
+    /// Pushes the value at a place, after taking its indices from the stack.
+    LoadPlace(Place),
+    /// Takes the indices of the place and then a value from the stack.
+    StorePlace(Place),
+    /// Checks that the indices on top of the stack lead somewhere, without
+    /// taking them.
+    CheckPlace(Place),
+
+    // Operations
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Neg,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Not,
+    Index,
+    Slice,
+    GetField(usize),
+    Array(usize),
+    Record(String, Vec<String>),
+    Variant(String, String, usize),
+
+    // Builtins
+    Print,
+    Input,
+    StringToInt,
+    CharToInt,
+    IntToChar,
+    Len,
+    /// The length of the array at a place, read in place.
+    LenOf(Place),
+    /// `push`, `pop`, `insert` or `remove`, on the place of the array after
+    /// the rest of the arguments.
+    ArrayCall(String, Place),
+    Exit,
+
+    // Control flow
+    Jump(usize),
+    JumpIfFalse(usize),
+    /// Pops a value and binds it to the variables of the pattern if it
+    /// matches, otherwise jumps.
+    Match(Box<Pattern>, Vec<Var>, usize),
+    NoMatch,
+    /// Stores the next integer of `counter..end` in the variable, or jumps
+    /// once there are no more.
+    NextInRange {
+        counter: Var,
+        end: Var,
+        var: Var,
+        exit: usize,
+    },
+    /// Stores the next element of an array in the variable, or jumps once
+    /// there are no more.
+    NextInArray {
+        array: Var,
+        counter: Var,
+        var: Var,
+        exit: usize,
+    },
+
+    // Procedures
+    /// Pushes a closure of a procedure, with the values of the variables it
+    /// captures.
+    Closure {
+        procedure: usize,
+        captures: Vec<Var>,
+    },
+    /// Calls the closure on the stack with the arguments above it. A `ref`
+    /// argument is given by the indices of its place instead of its value.
+    Call {
+        refs: Vec<Option<Place>>,
+        wants_value: bool,
+    },
+    Return,
+    ReturnValue,
+}
+
+/// Instructions along with the position of the code each one comes from,
+/// where the errors it raises are reported.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    pub positions: Vec<(usize, usize)>,
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk {
+            code: Vec::new(),
+            positions: Vec::new(),
+        }
+    }
+
+    pub fn add_instruction(&mut self, instruction: Instruction, line: usize, col: usize) -> usize {
+        self.code.push(instruction);
+        self.positions.push((line, col));
+        self.code.len() - 1
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Procedure {
+    pub name: String,
+    /// Includes the arguments and the variables of every block.
+    pub num_locals: usize,
+    pub chunk: Chunk,
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, instruction) in self.code.iter().enumerate() {
+            writeln!(f, "{:>4} {}", i, instruction)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Procedure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({} locals):", self.name, self.num_locals)?;
+        write!(f, "{}", self.chunk)
+    }
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Var::Local(slot) => write!(f, "local {}", slot),
+            Var::Global(slot) => write!(f, "global {}", slot),
+            Var::Captured(pos) => write!(f, "captured {}", pos),
+        }
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.var)?;
+        for step in &self.path {
+            match step {
+                Step::Index(..) => write!(f, "[_]")?,
+                Step::Field(pos) => write!(f, ".{}", pos)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Push(value) => write!(f, "push {}", value),
+            Instruction::Load(var) => write!(f, "load {}", var),
+            Instruction::Store(var) => write!(f, "store {}", var),
+            Instruction::LoadPlace(place) => write!(f, "load {}", place),
+            Instruction::StorePlace(place) => write!(f, "store {}", place),
+            Instruction::CheckPlace(place) => write!(f, "check {}", place),
+            Instruction::GetField(pos) => write!(f, "field {}", pos),
+            Instruction::Array(len) => write!(f, "array {}", len),
+            Instruction::Record(name, _) => write!(f, "record {}", name),
+            Instruction::Variant(_, variant, len) => write!(f, "variant {} {}", variant, len),
+            Instruction::LenOf(place) => write!(f, "len {}", place),
+            Instruction::ArrayCall(name, place) => write!(f, "{} {}", name, place),
+            Instruction::Jump(target) => write!(f, "jump {}", target),
+            Instruction::JumpIfFalse(target) => write!(f, "jump_if_false {}", target),
+            Instruction::Match(pattern, _, target) => {
+                write!(f, "match {} else {}", pattern, target)
+            }
+            Instruction::NextInRange {
+                counter,
+                end,
+                var,
+                exit,
+            } => write!(
+                f,
+                "next_in_range {} to {}, {} else {}",
+                counter, end, var, exit
+            ),
+            Instruction::NextInArray {
+                array,
+                counter,
+                var,
+                exit,
+            } => write!(
+                f,
+                "next_in_array {} at {}, {} else {}",
+                array, counter, var, exit
+            ),
+            Instruction::Closure {
+                procedure,
+                captures,
+            } => {
+                write!(f, "closure {}", procedure)?;
+                let captures: Vec<String> = captures.iter().map(|var| var.to_string()).collect();
+                if !captures.is_empty() {
+                    write!(f, " with {}", captures.join(", "))?;
+                }
+                Ok(())
+            }
+            Instruction::Call { refs, wants_value } => {
+                write!(f, "call {}", refs.len())?;
+                if !wants_value {
+                    write!(f, " discard")?;
+                }
+                Ok(())
+            }
+            _ => {
+                let name = match self {
+                    Instruction::Pop => "pop",
+                    Instruction::Add => "add",
+                    Instruction::Sub => "sub",
+                    Instruction::Mul => "mul",
+                    Instruction::Div => "div",
+                    Instruction::Rem => "rem",
+                    Instruction::Neg => "neg",
+                    Instruction::Concat => "concat",
+                    Instruction::Eq => "eq",
+                    Instruction::Ne => "ne",
+                    Instruction::Lt => "lt",
+                    Instruction::Le => "le",
+                    Instruction::Gt => "gt",
+                    Instruction::Ge => "ge",
+                    Instruction::And => "and",
+                    Instruction::Or => "or",
+                    Instruction::Not => "not",
+                    Instruction::Index => "index",
+                    Instruction::Slice => "slice",
+                    Instruction::Print => "print",
+                    Instruction::Input => "input",
+                    Instruction::StringToInt => "string_to_int",
+                    Instruction::CharToInt => "char_to_int",
+                    Instruction::IntToChar => "int_to_char",
+                    Instruction::Len => "len",
+                    Instruction::Exit => "exit",
+                    Instruction::NoMatch => "no_match",
+                    Instruction::Return => "return",
+                    Instruction::ReturnValue => "return_value",
+                    _ => unreachable!(),
+                };
+                write!(f, "{}", name)
+            }
+        }
+    }
+}
@@ -0,0 +1,831 @@
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use super::bytecode::*;
+use crate::core::*;
+use crate::interpreter::{default_value, literal, Value};
+use crate::syntax_analysis::parser::expression::*;
+use crate::syntax_analysis::parser::pattern::*;
+use crate::syntax_analysis::parser::statement::*;
+
+/// Turns analyzed code into bytecode. Declarations are kept between calls,
+/// so that later code can use the globals and procedures of earlier code.
+pub struct Compiler {
+    pub procedures: Vec<Rc<Procedure>>,
+    // which arguments of the procedures in scope are passed by reference,
+    // for those that have any
+    ref_params: HashMap<Binding, Vec<bool>>,
+    structs: HashMap<String, Vec<(String, PrimitiveType)>>,
+    enums: HashMap<String, Vec<(String, Vec<PrimitiveType>)>>,
+    num_globals: usize,
+    // how many procedures are being compiled, one inside the other
+    depth: usize,
+    // the variables captured by the innermost of them
+    captures: Vec<Binding>,
+    // the hidden variables, like the counter of a `for`, are locals that go
+    // after the variables of the procedure being compiled
+    first_hidden: usize,
+    num_hidden: usize,
+    num_locals: usize,
+    loops: Vec<Loop>,
+    chunk: Chunk,
+}
+
+/// Where `continue` jumps to and the jumps of every `break`, which are
+/// patched once the end of the loop is known.
+struct Loop {
+    start: usize,
+    breaks: Vec<usize>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler {
+            procedures: Vec::new(),
+            ref_params: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            num_globals: 0,
+            depth: 0,
+            captures: Vec::new(),
+            // the variables of the main program are globals
+            first_hidden: 0,
+            num_hidden: 0,
+            num_locals: 0,
+            loops: Vec::new(),
+            chunk: Chunk::new(),
+        }
+    }
+
+    /// Compiles the main program, whose variables are globals.
+    pub fn compile_program(&mut self, ast: &[Statement]) -> Procedure {
+        for statement in ast {
+            self.compile_statement(statement);
+        }
+        self.emit(Instruction::Return, 0, 0);
+        self.take_main()
+    }
+
+    /// Compiles an expression that returns its value, in the global scope.
+    pub fn compile_expression(&mut self, expr: &Expression) -> Procedure {
+        self.compile_expr(expr);
+        self.emit(Instruction::ReturnValue, expr.line, expr.col);
+        self.take_main()
+    }
+
+    /// How many globals the code compiled so far needs.
+    pub fn num_globals(&self) -> usize {
+        self.num_globals
+    }
+
+    fn take_main(&mut self) -> Procedure {
+        Procedure {
+            name: String::new(),
+            num_locals: mem::take(&mut self.num_locals),
+            chunk: mem::take(&mut self.chunk),
+        }
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) {
+        let (line, col) = (statement.line, statement.col);
+        match &statement.kind {
+            StatementKind::Procedure {
+                name,
+                args,
+                block,
+                binding,
+                captures,
+                ..
+            } => {
+                let procedure = self.compile_procedure(name, args, block, captures);
+                // the closure is made where the procedure is declared
+                let captures = captures
+                    .iter()
+                    .map(|capture| self.get_variable(*capture))
+                    .collect();
+                self.emit(
+                    Instruction::Closure {
+                        procedure,
+                        captures,
+                    },
+                    line,
+                    col,
+                );
+                let var = self.declare_procedure(binding.unwrap(), args);
+                self.emit(Instruction::Store(var), line, col);
+            }
+            StatementKind::Struct { name, fields } => {
+                self.structs.insert(name.to_string(), fields.clone());
+            }
+            StatementKind::Enum { name, variants } => {
+                self.enums.insert(name.to_string(), variants.clone());
+            }
+            StatementKind::Call {
+                name,
+                args,
+                binding,
+            } => self.compile_call(name, *binding, args, line, col, false),
+            StatementKind::Return { value } => {
+                self.compile_expr(value);
+                self.emit(Instruction::ReturnValue, line, col);
+            }
+            StatementKind::Declare {
+                type_hint,
+                value,
+                binding,
+                ..
+            } => {
+                match (value, type_hint) {
+                    (Some(value), _) => self.compile_expr(value),
+                    (None, Some(type_hint)) => {
+                        let value = default_value(type_hint, &self.structs, &self.enums);
+                        self.emit(Instruction::Push(value), line, col);
+                    }
+                    (None, None) => {
+                        self.emit(Instruction::Push(Value::Int(0)), line, col);
+                    }
+                }
+                let var = self.declare_variable(binding.unwrap());
+                self.emit(Instruction::Store(var), line, col);
+            }
+            StatementKind::Assign { dest, src } => {
+                self.compile_expr(src);
+                match &dest.kind {
+                    ExpressionKind::Id(_) => {
+                        let var = self.get_variable(dest.binding.unwrap());
+                        self.emit(Instruction::Store(var), line, col);
+                    }
+                    _ => {
+                        let place = self.compile_place(dest);
+                        self.emit(Instruction::StorePlace(place), line, col);
+                    }
+                }
+            }
+            StatementKind::If { cond, block } => {
+                self.compile_expr(cond);
+                let jump = self.emit(Instruction::JumpIfFalse(0), line, col);
+                self.compile_block(block);
+                self.patch(jump);
+            }
+            StatementKind::IfElse {
+                cond,
+                true_block,
+                false_block,
+            } => {
+                self.compile_expr(cond);
+                let jump_else = self.emit(Instruction::JumpIfFalse(0), line, col);
+                self.compile_block(true_block);
+                let jump_end = self.emit(Instruction::Jump(0), line, col);
+                self.patch(jump_else);
+                self.compile_block(false_block);
+                self.patch(jump_end);
+            }
+            StatementKind::Loop { block } => {
+                let start = self.start_loop();
+                self.compile_block(block);
+                self.emit(Instruction::Jump(start), line, col);
+                self.end_loop();
+            }
+            StatementKind::While { cond, block } => {
+                let start = self.start_loop();
+                self.compile_expr(cond);
+                let jump = self.emit(Instruction::JumpIfFalse(0), line, col);
+                self.loops.last_mut().unwrap().breaks.push(jump);
+                self.compile_block(block);
+                self.emit(Instruction::Jump(start), line, col);
+                self.end_loop();
+            }
+            StatementKind::For {
+                binding,
+                iterable,
+                block,
+                ..
+            } => self.compile_for(binding.unwrap(), iterable, block, line, col),
+            StatementKind::Match { value, arms } => self.compile_match(value, arms, line, col),
+            StatementKind::Continue => {
+                let start = self.loops.last().expect("Continue outside loop").start;
+                self.emit(Instruction::Jump(start), line, col);
+            }
+            StatementKind::Break => {
+                let jump = self.emit(Instruction::Jump(0), line, col);
+                self.loops
+                    .last_mut()
+                    .expect("Break outside loop")
+                    .breaks
+                    .push(jump);
+            }
+        }
+    }
+
+    /// Compiles the block of a procedure and returns its position.
+    fn compile_procedure(
+        &mut self,
+        name: &str,
+        args: &[Param],
+        block: &[Statement],
+        captures: &[Binding],
+    ) -> usize {
+        // its position is taken before the procedures inside of it
+        let id = self.procedures.len();
+        self.procedures.push(Rc::new(Procedure {
+            name: name.to_string(),
+            num_locals: 0,
+            chunk: Chunk::new(),
+        }));
+
+        let chunk = mem::take(&mut self.chunk);
+        let loops = mem::take(&mut self.loops);
+        let captures = mem::replace(&mut self.captures, captures.to_vec());
+        let first_hidden = num_slots(block).max(args.len() + 1);
+        let first_hidden = mem::replace(&mut self.first_hidden, first_hidden);
+        let num_hidden = mem::take(&mut self.num_hidden);
+        let num_locals = mem::replace(&mut self.num_locals, self.first_hidden);
+        self.depth += 1;
+
+        // the closure that was called is in the first slot, so that the
+        // procedure can call itself
+        let depth = self.depth;
+        self.declare_procedure(Binding { depth, slot: 0 }, args);
+        for slot in 1..=args.len() {
+            self.declare_variable(Binding { depth, slot });
+        }
+        for statement in block {
+            self.compile_statement(statement);
+        }
+        self.emit(Instruction::Return, 0, 0);
+
+        self.procedures[id] = Rc::new(Procedure {
+            name: name.to_string(),
+            num_locals: mem::replace(&mut self.num_locals, num_locals),
+            chunk: mem::replace(&mut self.chunk, chunk),
+        });
+        self.depth -= 1;
+        self.loops = loops;
+        self.captures = captures;
+        self.first_hidden = first_hidden;
+        self.num_hidden = num_hidden;
+        id
+    }
+
+    fn compile_block(&mut self, block: &[Statement]) {
+        for statement in block {
+            self.compile_statement(statement);
+        }
+    }
+
+    fn compile_for(
+        &mut self,
+        binding: Binding,
+        iterable: &Expression,
+        block: &[Statement],
+        line: usize,
+        col: usize,
+    ) {
+        // the iterable is evaluated once, before the first iteration
+        let num_hidden = self.num_hidden;
+        let next = match &iterable.kind {
+            ExpressionKind::Call(name, bounds) if name == ".." => {
+                self.compile_expr(&bounds[0]);
+                let counter = self.declare_hidden();
+                self.emit(Instruction::Store(counter), line, col);
+                self.compile_expr(&bounds[1]);
+                let end = self.declare_hidden();
+                self.emit(Instruction::Store(end), line, col);
+                Instruction::NextInRange {
+                    counter,
+                    end,
+                    var: self.declare_variable(binding),
+                    exit: 0,
+                }
+            }
+            _ => {
+                self.compile_expr(iterable);
+                let array = self.declare_hidden();
+                self.emit(Instruction::Store(array), line, col);
+                self.emit(Instruction::Push(Value::Int(0)), line, col);
+                let counter = self.declare_hidden();
+                self.emit(Instruction::Store(counter), line, col);
+                Instruction::NextInArray {
+                    array,
+                    counter,
+                    var: self.declare_variable(binding),
+                    exit: 0,
+                }
+            }
+        };
+
+        let start = self.start_loop();
+        let jump = self.emit(next, line, col);
+        self.loops.last_mut().unwrap().breaks.push(jump);
+        self.compile_block(block);
+        self.emit(Instruction::Jump(start), line, col);
+        self.end_loop();
+        self.num_hidden = num_hidden;
+    }
+
+    fn compile_match(
+        &mut self,
+        value: &Expression,
+        arms: &[(Pattern, Vec<Statement>)],
+        line: usize,
+        col: usize,
+    ) {
+        let num_hidden = self.num_hidden;
+        self.compile_expr(value);
+        let scrutinee = self.declare_hidden();
+        self.emit(Instruction::Store(scrutinee), line, col);
+
+        let mut jumps_end = Vec::new();
+        for (pattern, block) in arms {
+            self.emit(Instruction::Load(scrutinee), line, col);
+            let bindings = pattern
+                .bindings()
+                .into_iter()
+                .map(|binding| self.declare_variable(binding.binding.unwrap()))
+                .collect();
+            let jump_next = self.emit(
+                Instruction::Match(Box::new(pattern.clone()), bindings, 0),
+                line,
+                col,
+            );
+            for statement in block {
+                self.compile_statement(statement);
+            }
+            jumps_end.push(self.emit(Instruction::Jump(0), line, col));
+            self.patch(jump_next);
+        }
+
+        self.emit(Instruction::Load(scrutinee), line, col);
+        self.emit(Instruction::NoMatch, line, col);
+        for jump in jumps_end {
+            self.patch(jump);
+        }
+        self.num_hidden = num_hidden;
+    }
+
+    /// Compiles a call to a builtin, a constructor or the procedure in the
+    /// variable `binding`. The value it returns, if any, is only left on the
+    /// stack if `wants_value`.
+    fn compile_call(
+        &mut self,
+        name: &str,
+        binding: Option<Binding>,
+        args: &[Expression],
+        line: usize,
+        col: usize,
+        wants_value: bool,
+    ) {
+        if let Some(binding) = binding {
+            let var = self.get_variable(binding);
+            self.emit(Instruction::Load(var), line, col);
+            // a `ref` argument is checked where it is given, but only moved
+            // once every argument is evaluated
+            let ref_params = self.ref_params.get(&binding).cloned();
+            let mut refs = Vec::with_capacity(args.len());
+            for (i, arg) in args.iter().enumerate() {
+                if ref_params.as_ref().is_some_and(|ref_params| ref_params[i]) {
+                    let place = self.compile_place(arg);
+                    self.emit(Instruction::CheckPlace(place.clone()), arg.line, arg.col);
+                    refs.push(Some(place));
+                } else {
+                    self.compile_expr(arg);
+                    refs.push(None);
+                }
+            }
+            self.emit(Instruction::Call { refs, wants_value }, line, col);
+            return;
+        }
+
+        let returns = match name {
+            "()" => {
+                for arg in args {
+                    self.compile_expr(arg);
+                }
+                let refs = vec![None; args.len() - 1];
+                self.emit(Instruction::Call { refs, wants_value }, line, col);
+                return;
+            }
+            "map" | "filter" | "fold" => {
+                self.compile_higher_order(name, args, line, col);
+                true
+            }
+            // the argument is never evaluated
+            "exit" => {
+                self.emit(Instruction::Exit, line, col);
+                return;
+            }
+            "print" => {
+                self.compile_expr(&args[0]);
+                self.emit(Instruction::Print, line, col);
+                false
+            }
+            "input" => {
+                self.emit(Instruction::Input, line, col);
+                true
+            }
+            "string_to_int" | "char_to_int" | "int_to_char" => {
+                self.compile_expr(&args[0]);
+                let instruction = match name {
+                    "string_to_int" => Instruction::StringToInt,
+                    "char_to_int" => Instruction::CharToInt,
+                    _ => Instruction::IntToChar,
+                };
+                self.emit(instruction, line, col);
+                true
+            }
+            "string_len" | "len" if is_pure_place_or_id(&args[0]) => {
+                let place = self.compile_place(&args[0]);
+                self.emit(Instruction::LenOf(place), line, col);
+                true
+            }
+            "string_len" | "len" => {
+                self.compile_expr(&args[0]);
+                self.emit(Instruction::Len, line, col);
+                true
+            }
+            "push" | "pop" | "insert" | "remove" => {
+                for arg in &args[1..] {
+                    self.compile_expr(arg);
+                }
+                let place = self.compile_place(&args[0]);
+                self.emit(Instruction::ArrayCall(name.to_string(), place), line, col);
+                name == "pop" || name == "remove"
+            }
+            _ if self.enum_of(name).is_some() => {
+                for arg in args {
+                    self.compile_expr(arg);
+                }
+                let enum_name = self.enum_of(name).unwrap();
+                let variant = Instruction::Variant(enum_name, name.to_string(), args.len());
+                self.emit(variant, line, col);
+                true
+            }
+            _ if self.structs.contains_key(name) => {
+                for arg in args {
+                    self.compile_expr(arg);
+                }
+                let fields = self.structs[name]
+                    .iter()
+                    .map(|(field, _)| field.to_string())
+                    .collect();
+                self.emit(Instruction::Record(name.to_string(), fields), line, col);
+                true
+            }
+            _ => panic!("Undeclared function {name}"),
+        };
+
+        if returns && !wants_value {
+            self.emit(Instruction::Pop, line, col);
+        }
+    }
+
+    /// Compiles `map`, `filter` or `fold` to a loop over the array that calls
+    /// the procedure for every element, leaving the result on the stack.
+    fn compile_higher_order(&mut self, name: &str, args: &[Expression], line: usize, col: usize) {
+        let num_hidden = self.num_hidden;
+        let mut hidden = Vec::with_capacity(args.len());
+        for arg in args {
+            self.compile_expr(arg);
+            let var = self.declare_hidden();
+            self.emit(Instruction::Store(var), line, col);
+            hidden.push(var);
+        }
+        let (array, procedure) = (hidden[0], hidden[args.len() - 1]);
+        // the accumulator of `fold`, or the array that is built
+        let result = match name {
+            "fold" => hidden[1],
+            _ => {
+                self.emit(Instruction::Array(0), line, col);
+                let result = self.declare_hidden();
+                self.emit(Instruction::Store(result), line, col);
+                result
+            }
+        };
+        self.emit(Instruction::Push(Value::Int(0)), line, col);
+        let counter = self.declare_hidden();
+        self.emit(Instruction::Store(counter), line, col);
+        let var = self.declare_hidden();
+
+        let start = self.emit(
+            Instruction::NextInArray {
+                array,
+                counter,
+                var,
+                exit: 0,
+            },
+            line,
+            col,
+        );
+        self.emit(Instruction::Load(procedure), line, col);
+        if name == "fold" {
+            self.emit(Instruction::Load(result), line, col);
+        }
+        self.emit(Instruction::Load(var), line, col);
+        let refs = vec![None; if name == "fold" { 2 } else { 1 }];
+        let call = Instruction::Call {
+            refs,
+            wants_value: true,
+        };
+        self.emit(call, line, col);
+        let push = Instruction::ArrayCall(
+            "push".to_string(),
+            Place {
+                var: result,
+                path: Vec::new(),
+            },
+        );
+        match name {
+            "map" => {
+                self.emit(push, line, col);
+            }
+            "filter" => {
+                self.emit(Instruction::JumpIfFalse(start), line, col);
+                self.emit(Instruction::Load(var), line, col);
+                self.emit(push, line, col);
+            }
+            _ => {
+                self.emit(Instruction::Store(result), line, col);
+            }
+        }
+        self.emit(Instruction::Jump(start), line, col);
+        self.patch(start);
+        self.emit(Instruction::Load(result), line, col);
+        self.num_hidden = num_hidden;
+    }
+
+    fn compile_expr(&mut self, expr: &Expression) {
+        let (line, col) = (expr.line, expr.col);
+        match &expr.kind {
+            ExpressionKind::Id(_) => {
+                let var = self.get_variable(expr.binding.unwrap());
+                self.emit(Instruction::Load(var), line, col);
+            }
+            ExpressionKind::Lit(lit) => {
+                let value = literal(lit, expr.r#type.as_ref().unwrap());
+                self.emit(Instruction::Push(value), line, col);
+            }
+            ExpressionKind::Array(contents) => {
+                for value in contents {
+                    self.compile_expr(value);
+                }
+                self.emit(Instruction::Array(contents.len()), line, col);
+            }
+            // the element or field is read in place, without copying the
+            // whole variable first
+            ExpressionKind::Field(..) | ExpressionKind::Call(..) if is_pure_place(expr) => {
+                let place = self.compile_place(expr);
+                self.emit(Instruction::LoadPlace(place), line, col);
+            }
+            ExpressionKind::Field(record, field) => {
+                self.compile_expr(record);
+                let pos = self.field_pos(record, field);
+                self.emit(Instruction::GetField(pos), line, col);
+            }
+            ExpressionKind::Call(name, args) if name == "[]" && args[1].is_range() => {
+                let ExpressionKind::Call(_, bounds) = &args[1].kind else {
+                    unreachable!()
+                };
+                self.compile_expr(&args[0]);
+                self.compile_expr(&bounds[0]);
+                self.compile_expr(&bounds[1]);
+                self.emit(Instruction::Slice, line, col);
+            }
+            ExpressionKind::Call(name, args) => {
+                let instruction = match (name.as_str(), args.len()) {
+                    ("+", 2) if matches!(args[0].r#type, Some(PrimitiveType::Arr(_))) => {
+                        Instruction::Concat
+                    }
+                    ("+", 2) => Instruction::Add,
+                    ("-", 2) => Instruction::Sub,
+                    ("*", 2) => Instruction::Mul,
+                    ("/", 2) => Instruction::Div,
+                    ("%", 2) => Instruction::Rem,
+                    ("and", 2) => Instruction::And,
+                    ("or", 2) => Instruction::Or,
+                    ("==", 2) => Instruction::Eq,
+                    ("!=", 2) => Instruction::Ne,
+                    ("<", 2) => Instruction::Lt,
+                    ("<=", 2) => Instruction::Le,
+                    (">", 2) => Instruction::Gt,
+                    (">=", 2) => Instruction::Ge,
+                    ("[]", 2) => Instruction::Index,
+                    ("!", 1) => Instruction::Not,
+                    ("-", 1) => Instruction::Neg,
+                    _ => return self.compile_call(name, expr.binding, args, line, col, true),
+                };
+                // both sides are always evaluated, even by `and` and `or`
+                for arg in args {
+                    self.compile_expr(arg);
+                }
+                self.emit(instruction, line, col);
+            }
+        }
+    }
+
+    /// Pushes the indices of a place, from the outermost to the innermost,
+    /// and returns where they lead.
+    fn compile_place(&mut self, place: &Expression) -> Place {
+        let mut path = Vec::new();
+        let mut holder = place;
+        let binding = loop {
+            match &holder.kind {
+                ExpressionKind::Id(_) => break holder.binding.unwrap(),
+                ExpressionKind::Call(name, args) if name == "[]" => {
+                    self.compile_expr(&args[1]);
+                    path.push(Step::Index(holder.line, holder.col));
+                    holder = &args[0];
+                }
+                ExpressionKind::Field(record, field) => {
+                    path.push(Step::Field(self.field_pos(record, field)));
+                    holder = record;
+                }
+                _ => panic!("Invalid assignment"),
+            }
+        };
+        path.reverse();
+
+        Place {
+            var: self.get_variable(binding),
+            path,
+        }
+    }
+
+    /// The position of a field in the struct of the record.
+    fn field_pos(&self, record: &Expression, field: &str) -> usize {
+        let Some(PrimitiveType::Record(name)) = &record.r#type else {
+            panic!("Expressions must be typed before being compiled");
+        };
+        self.structs[name]
+            .iter()
+            .position(|(name, _)| name == field)
+            .unwrap()
+    }
+
+    /// The name of the enum a variant belongs to.
+    fn enum_of(&self, variant: &str) -> Option<String> {
+        self.enums
+            .iter()
+            .find(|(_, variants)| variants.iter().any(|(name, _)| name == variant))
+            .map(|(name, _)| name.to_string())
+    }
+
+    fn start_loop(&mut self) -> usize {
+        self.loops.push(Loop {
+            start: self.chunk.code.len(),
+            breaks: Vec::new(),
+        });
+        self.chunk.code.len()
+    }
+
+    fn end_loop(&mut self) {
+        for jump in self.loops.pop().unwrap().breaks {
+            self.patch(jump);
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction, line: usize, col: usize) -> usize {
+        self.chunk.add_instruction(instruction, line, col)
+    }
+
+    /// Makes a jump go to the next instruction.
+    fn patch(&mut self, jump: usize) {
+        let target = self.chunk.code.len();
+        match &mut self.chunk.code[jump] {
+            Instruction::Jump(to)
+            | Instruction::JumpIfFalse(to)
+            | Instruction::Match(_, _, to)
+            | Instruction::NextInRange { exit: to, .. }
+            | Instruction::NextInArray { exit: to, .. } => *to = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn declare_variable(&mut self, binding: Binding) -> Var {
+        if binding.depth == 0 {
+            self.num_globals = self.num_globals.max(binding.slot + 1);
+        }
+        // the slot may have held a procedure of a block that ended
+        self.ref_params.remove(&binding);
+        self.get_variable(binding)
+    }
+
+    fn declare_procedure(&mut self, binding: Binding, args: &[Param]) -> Var {
+        let var = self.declare_variable(binding);
+        if args.iter().any(|arg| arg.is_ref) {
+            let refs = args.iter().map(|arg| arg.is_ref).collect();
+            self.ref_params.insert(binding, refs);
+        }
+        var
+    }
+
+    /// Where a variable is read from by the code being compiled.
+    fn get_variable(&self, binding: Binding) -> Var {
+        if binding.depth == self.depth {
+            return match binding.depth {
+                0 => Var::Global(binding.slot),
+                _ => Var::Local(binding.slot),
+            };
+        }
+        match self.captures.iter().position(|capture| *capture == binding) {
+            Some(pos) => Var::Captured(pos),
+            // the variables of the main program outside of every block
+            None => Var::Global(binding.slot),
+        }
+    }
+
+    /// Gives a hidden variable the next free local, until the end of the
+    /// statement that declares it.
+    fn declare_hidden(&mut self) -> Var {
+        let slot = self.first_hidden + self.num_hidden;
+        self.num_hidden += 1;
+        self.num_locals = self.num_locals.max(slot + 1);
+        Var::Local(slot)
+    }
+}
+
+/// How many slots the variables declared in the block take, without those
+/// of the procedures declared in it, which are their own.
+fn num_slots(block: &[Statement]) -> usize {
+    let slots = |binding: &Option<Binding>| binding.unwrap().slot + 1;
+    block
+        .iter()
+        .map(|statement| match &statement.kind {
+            StatementKind::Declare { binding, .. } | StatementKind::Procedure { binding, .. } => {
+                slots(binding)
+            }
+            StatementKind::For { binding, block, .. } => slots(binding).max(num_slots(block)),
+            StatementKind::If { block, .. }
+            | StatementKind::Loop { block }
+            | StatementKind::While { block, .. } => num_slots(block),
+            StatementKind::IfElse {
+                true_block,
+                false_block,
+                ..
+            } => num_slots(true_block).max(num_slots(false_block)),
+            StatementKind::Match { arms, .. } => arms
+                .iter()
+                .map(|(pattern, block)| {
+                    let bindings = pattern.bindings().into_iter();
+                    let bindings = bindings.map(|binding| slots(&binding.binding)).max();
+                    bindings.unwrap_or(0).max(num_slots(block))
+                })
+                .max()
+                .unwrap_or(0),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Whether the expression is an element or a field of a variable whose
+/// indices can't have side effects, so it can be read in place.
+fn is_pure_place(expr: &Expression) -> bool {
+    match &expr.kind {
+        ExpressionKind::Call(name, args) if name == "[]" => {
+            !args[1].is_range() && is_pure(&args[1]) && is_pure_place_or_id(&args[0])
+        }
+        ExpressionKind::Field(record, _) => is_pure_place_or_id(record),
+        _ => false,
+    }
+}
+
+fn is_pure_place_or_id(expr: &Expression) -> bool {
+    matches!(expr.kind, ExpressionKind::Id(_)) || is_pure_place(expr)
+}
+
+/// Whether evaluating the expression only reads variables, without calling
+/// anything that could change them or do input and output.
+fn is_pure(expr: &Expression) -> bool {
+    match &expr.kind {
+        ExpressionKind::Lit(_) | ExpressionKind::Id(_) => true,
+        ExpressionKind::Field(record, _) => is_pure(record),
+        ExpressionKind::Array(values) => values.iter().all(is_pure),
+        ExpressionKind::Call(name, args) => {
+            matches!(
+                name.as_str(),
+                "+" | "-"
+                    | "*"
+                    | "/"
+                    | "%"
+                    | "and"
+                    | "or"
+                    | "=="
+                    | "!="
+                    | "<"
+                    | "<="
+                    | ">"
+                    | ">="
+                    | "!"
+                    | "[]"
+                    | ".."
+            ) && args.iter().all(is_pure)
+        }
+    }
+}
@@ -0,0 +1,633 @@
+use std::mem;
+use std::rc::Rc;
+
+use self::bytecode::*;
+use self::compiler::Compiler;
+use crate::interpreter::*;
+use crate::syntax_analysis::parser::expression::Expression;
+use crate::syntax_analysis::parser::statement::Statement;
+use crate::YoraError;
+
+pub mod bytecode;
+pub mod compiler;
+
+/// Runs the bytecode of analyzed code on a stack of values, with the same
+/// results as the `Interpreter`. Globals and procedures are kept for the
+/// following runs.
+pub struct Vm {
+    compiler: Compiler,
+    globals: Vec<Value>,
+    // the locals of every call, each followed by the values its code is
+    // working on
+    stack: Vec<Value>,
+    // the calls waiting for the current one to return
+    frames: Vec<CallFrame>,
+    exited: bool,
+}
+
+/// A call to a procedure, with what is needed to go back to its caller.
+struct CallFrame {
+    caller: Rc<Procedure>,
+    ip: usize,
+    base: usize,
+    procedure: usize,
+    line: usize,
+    col: usize,
+    wants_value: bool,
+    // the local every `ref` argument is in and the place of the caller it
+    // is moved back to
+    refs: Vec<(usize, Var, Vec<usize>)>,
+}
+
+type VmResult = Result<(), RuntimeError>;
+
+/// How many calls can wait for the current one before it is a stack
+/// overflow, most likely a recursion that never ends.
+const MAX_FRAMES: usize = 10_000;
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm {
+            compiler: Compiler::new(),
+            globals: Vec::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            exited: false,
+        }
+    }
+
+    /// Runs the program until its end or a call to `exit`.
+    pub fn run(&mut self, ast: &[Statement]) -> Result<(), YoraError> {
+        let main = self.compiler.compile_program(ast);
+
+        match self.execute(main) {
+            Ok(_) | Err(Unwind::Exit) => Ok(()),
+            Err(Unwind::Error(err)) => Err(YoraError::Runtime(err)),
+        }
+    }
+
+    /// Evaluates an expression in the global scope. `None` if it called
+    /// `exit`.
+    pub fn evaluate(&mut self, expr: &Expression) -> Result<Option<Value>, YoraError> {
+        let main = self.compiler.compile_expression(expr);
+
+        match self.execute(main) {
+            Ok(value) => Ok(value),
+            Err(Unwind::Exit) => Ok(None),
+            Err(Unwind::Error(err)) => Err(YoraError::Runtime(err)),
+        }
+    }
+
+    /// Whether a previous run or evaluation called `exit`.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    fn execute(&mut self, main: Procedure) -> RunResult<Option<Value>> {
+        let num_globals = self.compiler.num_globals().max(self.globals.len());
+        self.globals.resize(num_globals, Value::Int(0));
+
+        let mut procedure = Rc::new(main);
+        let mut ip = 0;
+        let mut base = self.stack.len();
+        // the hidden variables of the main program
+        self.stack
+            .resize(base + procedure.num_locals, Value::Int(0));
+        loop {
+            let pos = ip;
+            ip += 1;
+
+            let result: VmResult = match &procedure.chunk.code[pos] {
+                Instruction::Push(value) => {
+                    self.stack.push(value.clone());
+                    Ok(())
+                }
+                Instruction::Pop => {
+                    self.pop();
+                    Ok(())
+                }
+                Instruction::Load(var) => {
+                    let value = self.var(*var, base).clone();
+                    self.stack.push(value);
+                    Ok(())
+                }
+                Instruction::Store(var) => {
+                    let value = self.pop();
+                    *self.var_mut(*var, base) = value;
+                    Ok(())
+                }
+                Instruction::LoadPlace(place) => {
+                    let start = self.stack.len() - place.num_indices();
+                    let value = self.place_ref(place, base, start).cloned();
+                    self.stack.truncate(start);
+                    value.map(|value| self.stack.push(value))
+                }
+                Instruction::StorePlace(place) => {
+                    let indices = self.split_off(place.num_indices());
+                    let value = self.pop();
+                    self.place_mut(place, base, &indices)
+                        .map(|dest| *dest = value)
+                }
+                Instruction::CheckPlace(place) => {
+                    let start = self.stack.len() - place.num_indices();
+                    self.place_ref(place, base, start).map(|_| ())
+                }
+
+                Instruction::Add => self.arithmetic("+"),
+                Instruction::Sub => self.arithmetic("-"),
+                Instruction::Mul => self.arithmetic("*"),
+                Instruction::Div => self.arithmetic("/"),
+                Instruction::Rem => self.arithmetic("%"),
+                Instruction::Neg => self.pop().get_int().and_then(|int| {
+                    let int = eval_arithmetic("-", 0, int)?;
+                    self.stack.push(Value::Int(int));
+                    Ok(())
+                }),
+                Instruction::Concat => match (self.pop(), self.pop()) {
+                    (Value::Array(tail), Value::Array(mut values)) => {
+                        values.extend(tail);
+                        self.stack.push(Value::Array(values));
+                        Ok(())
+                    }
+                    (_, value) => Err(value.mismatch("an array")),
+                },
+                Instruction::Eq => self.compare(|a, b| a == b),
+                Instruction::Ne => self.compare(|a, b| a != b),
+                Instruction::Lt => self.compare(|a, b| a < b),
+                Instruction::Le => self.compare(|a, b| a <= b),
+                Instruction::Gt => self.compare(|a, b| a > b),
+                Instruction::Ge => self.compare(|a, b| a >= b),
+                Instruction::And => self.logic(|a, b| a && b),
+                Instruction::Or => self.logic(|a, b| a || b),
+                Instruction::Not => self.pop().get_bool().map(|bool| {
+                    self.stack.push(Value::Bool(!bool));
+                }),
+                Instruction::Index => self.index(),
+                Instruction::Slice => self.slice(),
+                Instruction::GetField(pos) => match self.pop() {
+                    Value::Record(_, mut fields) => {
+                        self.stack.push(fields.swap_remove(*pos).1);
+                        Ok(())
+                    }
+                    value => Err(value.mismatch("a record")),
+                },
+                Instruction::Array(len) => {
+                    let values = self.split_off(*len);
+                    self.stack.push(Value::Array(values));
+                    Ok(())
+                }
+                Instruction::Record(name, fields) => {
+                    let values = self.split_off(fields.len());
+                    let fields = fields.iter().cloned().zip(values).collect();
+                    self.stack.push(Value::Record(name.to_string(), fields));
+                    Ok(())
+                }
+                Instruction::Variant(enum_name, variant, len) => {
+                    let payload = self.split_off(*len);
+                    let value = Value::Variant(enum_name.to_string(), variant.to_string(), payload);
+                    self.stack.push(value);
+                    Ok(())
+                }
+
+                Instruction::Print => print_value(self.pop()),
+                Instruction::Input => {
+                    self.stack.push(read_input());
+                    Ok(())
+                }
+                Instruction::StringToInt => string_to_int(&self.pop()).map(|int| {
+                    self.stack.push(int);
+                }),
+                Instruction::CharToInt => self.pop().get_char().map(|ch| {
+                    self.stack.push(Value::Int(ch as i64));
+                }),
+                Instruction::IntToChar => int_to_char(&self.pop()).map(|ch| {
+                    self.stack.push(ch);
+                }),
+                Instruction::Len => match self.pop() {
+                    Value::Array(values) => {
+                        self.stack.push(Value::Int(values.len() as i64));
+                        Ok(())
+                    }
+                    value => Err(value.mismatch("an array")),
+                },
+                Instruction::LenOf(place) => {
+                    let start = self.stack.len() - place.num_indices();
+                    let len = match self.place_ref(place, base, start) {
+                        Ok(Value::Array(values)) => Ok(values.len()),
+                        Ok(value) => Err(value.mismatch("an array")),
+                        Err(err) => Err(err),
+                    };
+                    self.stack.truncate(start);
+                    len.map(|len| self.stack.push(Value::Int(len as i64)))
+                }
+                Instruction::ArrayCall(name, place) => self.array_call(name, place, base),
+                Instruction::Exit => {
+                    self.exited = true;
+                    self.unwind(base);
+                    return Err(Unwind::Exit);
+                }
+
+                Instruction::Jump(target) => {
+                    ip = *target;
+                    Ok(())
+                }
+                Instruction::JumpIfFalse(target) => self.pop().get_bool().map(|cond| {
+                    if !cond {
+                        ip = *target;
+                    }
+                }),
+                Instruction::Match(pattern, vars, target) => {
+                    let value = self.pop();
+                    let mut bindings = Vec::new();
+                    if match_pattern(pattern, &value, &mut bindings) {
+                        for (var, (_, value)) in vars.iter().zip(bindings) {
+                            *self.var_mut(*var, base) = value;
+                        }
+                    } else {
+                        ip = *target;
+                    }
+                    Ok(())
+                }
+                Instruction::NoMatch => {
+                    let value = self.pop();
+                    Err(RuntimeError::new(format!("no pattern matched {}", value)))
+                }
+                Instruction::NextInRange {
+                    counter,
+                    end,
+                    var,
+                    exit,
+                } => (|| {
+                    let next = self.var(*counter, base).get_int()?;
+                    if next < self.var(*end, base).get_int()? {
+                        *self.var_mut(*var, base) = Value::Int(next);
+                        *self.var_mut(*counter, base) = Value::Int(next + 1);
+                    } else {
+                        ip = *exit;
+                    }
+                    Ok(())
+                })(),
+                Instruction::NextInArray {
+                    array,
+                    counter,
+                    var,
+                    exit,
+                } => (|| {
+                    let next = self.var(*counter, base).get_int()?;
+                    let value = match self.var(*array, base) {
+                        Value::Array(values) => values.get(next as usize).cloned(),
+                        value => return Err(value.mismatch("an array")),
+                    };
+                    match value {
+                        Some(value) => {
+                            *self.var_mut(*var, base) = value;
+                            *self.var_mut(*counter, base) = Value::Int(next + 1);
+                        }
+                        None => ip = *exit,
+                    }
+                    Ok(())
+                })(),
+
+                Instruction::Closure {
+                    procedure: id,
+                    captures,
+                } => {
+                    let env = captures
+                        .iter()
+                        .map(|var| self.var(*var, base).clone())
+                        .collect();
+                    let closure = Closure {
+                        name: self.compiler.procedures[*id].name.clone(),
+                        code: Code::Bytecode(*id),
+                        env,
+                    };
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                    Ok(())
+                }
+                Instruction::Call { .. } if self.frames.len() >= MAX_FRAMES => {
+                    Err(RuntimeError::new("stack overflow".to_string()))
+                }
+                Instruction::Call { refs, wants_value } => {
+                    let wants_value = *wants_value;
+                    match self.bind_args(refs, base) {
+                        Ok((callee_base, moved)) => {
+                            let Value::Closure(closure) = &self.stack[callee_base] else {
+                                unreachable!("only procedures are called")
+                            };
+                            let Code::Bytecode(id) = closure.code else {
+                                unreachable!("the VM only makes closures of bytecode")
+                            };
+                            let callee = Rc::clone(&self.compiler.procedures[id]);
+                            let (line, col) = procedure.chunk.positions[pos];
+                            self.stack
+                                .resize(callee_base + callee.num_locals, Value::Int(0));
+                            self.frames.push(CallFrame {
+                                caller: mem::replace(&mut procedure, callee),
+                                ip,
+                                base,
+                                procedure: id,
+                                line,
+                                col,
+                                wants_value,
+                                refs: moved,
+                            });
+                            ip = 0;
+                            base = callee_base;
+                            Ok(())
+                        }
+                        Err(err) => Err(err),
+                    }
+                }
+                instruction @ (Instruction::Return | Instruction::ReturnValue) => {
+                    let value = match instruction {
+                        Instruction::ReturnValue => Some(self.pop()),
+                        _ => None,
+                    };
+                    let Some(frame) = self.frames.pop() else {
+                        self.stack.truncate(base);
+                        return Ok(value);
+                    };
+
+                    self.move_back(&frame.refs, base, frame.base);
+                    self.stack.truncate(base);
+                    procedure = frame.caller;
+                    ip = frame.ip;
+                    base = frame.base;
+                    match value {
+                        Some(value) if frame.wants_value => {
+                            self.stack.push(value);
+                            Ok(())
+                        }
+                        None if frame.wants_value => Err(RuntimeError {
+                            message: format!(
+                                "procedure '{}' ended without returning a value",
+                                self.compiler.procedures[frame.procedure].name
+                            ),
+                            line: frame.line,
+                            col: frame.col,
+                            trace: Vec::new(),
+                        }),
+                        _ => Ok(()),
+                    }
+                }
+            };
+
+            if let Err(err) = result {
+                let (line, col) = procedure.chunk.positions[pos];
+                let err = self.locate(err, line, col);
+                self.unwind(base);
+                return Err(Unwind::Error(err));
+            }
+        }
+    }
+
+    /// Moves the arguments of a call to the locals of a new frame on top of
+    /// the stack, after the closure that is called, returning where it
+    /// starts and the `ref` arguments that were moved out of their places.
+    #[allow(clippy::type_complexity)]
+    fn bind_args(
+        &mut self,
+        refs: &[Option<Place>],
+        base: usize,
+    ) -> Result<(usize, Vec<(usize, Var, Vec<usize>)>), RuntimeError> {
+        // the arguments are already where the locals go
+        if refs.iter().all(Option::is_none) {
+            return Ok((self.stack.len() - refs.len() - 1, Vec::new()));
+        }
+
+        let num_items = refs
+            .iter()
+            .map(|place| place.as_ref().map_or(1, Place::num_indices))
+            .sum();
+        let mut items = self.split_off(num_items).into_iter();
+        let mut args = Vec::with_capacity(refs.len());
+        let mut moved = Vec::new();
+        for (slot, place) in refs.iter().enumerate() {
+            match place {
+                None => args.push(items.next().unwrap()),
+                Some(place) => {
+                    let indices: Vec<Value> = items.by_ref().take(place.num_indices()).collect();
+                    moved.push((slot + 1, place.var, self.resolve(place, base, &indices)?));
+                    args.push(Value::Int(0));
+                }
+            }
+        }
+        // once every place is found, so none of them sees another moved
+        for (slot, var, path) in &moved {
+            args[*slot - 1] = mem::replace(self.part_mut(*var, base, path), Value::Int(0));
+        }
+
+        let callee_base = self.stack.len() - 1;
+        self.stack.extend(args);
+        Ok((callee_base, moved))
+    }
+
+    /// Gives the `ref` arguments of a call back to the places of its caller.
+    fn move_back(&mut self, refs: &[(usize, Var, Vec<usize>)], base: usize, caller_base: usize) {
+        for (slot, var, path) in refs {
+            let value = mem::replace(&mut self.stack[base + slot], Value::Int(0));
+            *self.part_mut(*var, caller_base, path) = value;
+        }
+    }
+
+    /// Leaves every call after an error or an `exit`, moving their `ref`
+    /// arguments back, as the caller may be the global scope of a REPL.
+    fn unwind(&mut self, mut base: usize) {
+        while let Some(frame) = self.frames.pop() {
+            self.move_back(&frame.refs, base, frame.base);
+            base = frame.base;
+        }
+        self.stack.clear();
+    }
+
+    fn array_call(&mut self, name: &str, place: &Place, base: usize) -> VmResult {
+        let indices = self.split_off(place.num_indices());
+        let num_values = match name {
+            "pop" => 0,
+            "insert" => 2,
+            _ => 1,
+        };
+        let values = self.split_off(num_values);
+        let Value::Array(array) = self.place_mut(place, base, &indices)? else {
+            unreachable!()
+        };
+
+        if let Some(removed) = array_call(name, array, &values)? {
+            self.stack.push(removed);
+        }
+        Ok(())
+    }
+
+    fn arithmetic(&mut self, op: &str) -> VmResult {
+        let b = self.pop().get_int()?;
+        let a = self.pop().get_int()?;
+        self.stack.push(Value::Int(eval_arithmetic(op, a, b)?));
+        Ok(())
+    }
+
+    fn compare(&mut self, cmp: fn(i64, i64) -> bool) -> VmResult {
+        let b = self.pop().get_int()?;
+        let a = self.pop().get_int()?;
+        self.stack.push(Value::Bool(cmp(a, b)));
+        Ok(())
+    }
+
+    fn logic(&mut self, op: fn(bool, bool) -> bool) -> VmResult {
+        let b = self.pop().get_bool()?;
+        let a = self.pop().get_bool()?;
+        self.stack.push(Value::Bool(op(a, b)));
+        Ok(())
+    }
+
+    fn index(&mut self) -> VmResult {
+        let idx = self.pop().get_int()?;
+        match self.pop() {
+            Value::Array(mut values) => {
+                let pos = get_index(idx, values.len())?;
+                self.stack.push(values.swap_remove(pos));
+                Ok(())
+            }
+            value => Err(value.mismatch("an array")),
+        }
+    }
+
+    fn slice(&mut self) -> VmResult {
+        let end = self.pop().get_int()?;
+        let start = self.pop().get_int()?;
+        match self.pop() {
+            Value::Array(values) => {
+                let range = get_range(start, end, values.len())?;
+                self.stack.push(Value::Array(values[range].to_vec()));
+                Ok(())
+            }
+            value => Err(value.mismatch("an array")),
+        }
+    }
+
+    /// The value at a place, with its indices on the stack from `start`.
+    fn place_ref(&self, place: &Place, base: usize, start: usize) -> Result<&Value, RuntimeError> {
+        let mut indices = self.stack[start..].iter().rev();
+        let mut value = self.var(place.var, base);
+        for step in &place.path {
+            let pos = step_pos(step, value, &mut indices)?;
+            value = value.get_part(pos);
+        }
+        Ok(value)
+    }
+
+    fn place_mut(
+        &mut self,
+        place: &Place,
+        base: usize,
+        indices: &[Value],
+    ) -> Result<&mut Value, RuntimeError> {
+        let mut indices = indices.iter().rev();
+        let mut value = self.var_mut(place.var, base);
+        for step in &place.path {
+            let pos = step_pos(step, value, &mut indices)?;
+            value = value.get_part_mut(pos);
+        }
+        Ok(value)
+    }
+
+    /// The positions of the elements and fields that lead to a place.
+    fn resolve(
+        &self,
+        place: &Place,
+        base: usize,
+        indices: &[Value],
+    ) -> Result<Vec<usize>, RuntimeError> {
+        let mut indices = indices.iter().rev();
+        let mut value = self.var(place.var, base);
+        let mut path = Vec::with_capacity(place.path.len());
+        for step in &place.path {
+            let pos = step_pos(step, value, &mut indices)?;
+            value = value.get_part(pos);
+            path.push(pos);
+        }
+        Ok(path)
+    }
+
+    fn part_mut(&mut self, var: Var, base: usize, path: &[usize]) -> &mut Value {
+        let mut value = self.var_mut(var, base);
+        for pos in path {
+            value = value.get_part_mut(*pos);
+        }
+        value
+    }
+
+    fn var(&self, var: Var, base: usize) -> &Value {
+        match var {
+            Var::Local(slot) => &self.stack[base + slot],
+            Var::Global(slot) => &self.globals[slot],
+            Var::Captured(pos) => match &self.stack[base] {
+                Value::Closure(closure) => &closure.env[pos],
+                _ => unreachable!("captured variables are only used by procedures"),
+            },
+        }
+    }
+
+    fn var_mut(&mut self, var: Var, base: usize) -> &mut Value {
+        match var {
+            Var::Local(slot) => &mut self.stack[base + slot],
+            Var::Global(slot) => &mut self.globals[slot],
+            Var::Captured(_) => unreachable!("captured variables can't be changed"),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    /// Takes the last `len` values of the stack, in order.
+    fn split_off(&mut self, len: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - len)
+    }
+
+    /// Gives an error the position of the instruction that raised it, if it
+    /// doesn't have a more precise one, and the calls that are active.
+    fn locate(&self, mut err: RuntimeError, line: usize, col: usize) -> RuntimeError {
+        if err.line == 0 {
+            err.line = line;
+            err.col = col;
+        }
+        err.trace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| Frame {
+                name: self.compiler.procedures[frame.procedure].name.clone(),
+                line: frame.line,
+                col: frame.col,
+            })
+            .collect();
+        err
+    }
+}
+
+/// The position of the element or field a step of a place goes to, taking
+/// its index if it has one.
+fn step_pos<'a>(
+    step: &Step,
+    value: &Value,
+    indices: &mut impl Iterator<Item = &'a Value>,
+) -> Result<usize, RuntimeError> {
+    match (step, value) {
+        (Step::Field(pos), _) => Ok(*pos),
+        (Step::Index(line, col), Value::Array(values)) => {
+            let idx = indices.next().unwrap().get_int()?;
+            get_index(idx, values.len()).map_err(|mut err| {
+                err.line = *line;
+                err.col = *col;
+                err
+            })
+        }
+        (Step::Index(..), _) => Err(value.mismatch("an array")),
+    }
+}
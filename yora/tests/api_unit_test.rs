@@ -0,0 +1,1024 @@
+use yora::interpreter::Frame;
+use yora::syntax_analysis::analyzer::Analyzer;
+use yora::syntax_analysis::errors::{ErrorFormat, ErrorKind};
+use yora::syntax_analysis::lexer::lex;
+use yora::syntax_analysis::parser::expression::{Binding, ExpressionKind};
+use yora::syntax_analysis::parser::statement::{Statement, StatementKind};
+use yora::syntax_analysis::parser::{parse, parse_expression};
+use yora::syntax_analysis::produce_ast;
+use yora::vm::Vm;
+use yora::{build, run, YoraError};
+
+fn run_source(source: &str) -> Result<(), YoraError> {
+    Vm::new().run(&produce_ast(source.to_string())?)
+}
+
+#[test]
+fn test_invalid_identifier() {
+    let Err(YoraError::Lex(errors)) = lex("var a$b = 1\n".to_string()) else {
+        panic!("expected a lexing error");
+    };
+
+    let error = errors.iter().next().unwrap();
+    assert_eq!(error.kind, ErrorKind::InvalidIdentifier);
+    assert_eq!((error.line, error.col), (1, 6));
+}
+
+#[test]
+fn test_stages() {
+    assert!(matches!(
+        produce_ast("var a = (1\n".to_string()),
+        Err(YoraError::Parse(_))
+    ));
+    assert!(matches!(
+        produce_ast("var a = 1 + true\n".to_string()),
+        Err(YoraError::Semantic(_))
+    ));
+    assert!(matches!(
+        run_source("var a = 0\nprint(1 / a)\n"),
+        Err(YoraError::Runtime(_))
+    ));
+}
+
+#[test]
+fn test_runtime_errors() {
+    let message = |source: &str| match run_source(source) {
+        Err(YoraError::Runtime(err)) => err.message,
+        _ => panic!("expected a runtime error"),
+    };
+
+    assert_eq!(message("var a = 0\nprint(5 % a)\n"), "division by zero");
+    assert_eq!(
+        message("var s = \"abc\"\nprint(s[3])\n"),
+        "index out of bounds: the length is 3 but the index is 3"
+    );
+    assert_eq!(
+        message("print(int_to_char(0 - 1))\n"),
+        "-1 is not a valid character"
+    );
+}
+
+#[test]
+fn test_stack_trace() {
+    let source = "pr divide(a: Int, b: Int) -> Int:\n\
+                  \treturn a / b\n\
+                  pr average(total: Int, n: Int) -> Int:\n\
+                  \treturn divide(total, n)\n\
+                  print(average(10, 0))\n";
+
+    let Err(YoraError::Runtime(err)) = run_source(source) else {
+        panic!("expected a runtime error");
+    };
+
+    assert_eq!(err.message, "division by zero");
+    assert_eq!((err.line, err.col), (2, 11));
+    assert_eq!(
+        err.trace,
+        vec![
+            Frame {
+                name: "divide".to_string(),
+                line: 4,
+                col: 9,
+            },
+            Frame {
+                name: "average".to_string(),
+                line: 5,
+                col: 7,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_missing_return_value() {
+    let Err(YoraError::Semantic(errors)) = run_source("pr f() -> Int:\n\tprint(1)\nprint(f())\n")
+    else {
+        panic!("expected a semantic error");
+    };
+
+    let err = errors.iter().next().unwrap();
+    assert_eq!(
+        err.kind.message(),
+        "procedure 'f' may end without returning a value"
+    );
+    assert_eq!((err.line, err.col), (1, 1));
+}
+
+#[test]
+fn test_exit_does_not_end_the_process() {
+    assert!(run_source("print(1)\nexit(0)\nprint(2)\n").is_ok());
+}
+
+#[test]
+fn test_missing_file() {
+    let result = run("does_not_exist.yr".to_string(), false, ErrorFormat::Human);
+    assert!(matches!(result, Err(YoraError::Io(_))));
+}
+
+/// Builds the source from a temporary file and returns the build error.
+fn build_error(name: &str, source: &str) -> String {
+    let path = std::env::temp_dir().join(format!("yora_{}.yr", name));
+    std::fs::write(&path, source).unwrap();
+    let result = build(path.display().to_string(), true, ErrorFormat::Human);
+    std::fs::remove_file(&path).unwrap();
+
+    match result {
+        Err(YoraError::Build(message)) => message,
+        _ => panic!("expected a build error"),
+    }
+}
+
+#[test]
+fn test_interpreter_only_build() {
+    assert_eq!(
+        build_error("ref", "pr inc(ref x: Int):\n\tx += 1\nvar a = 1\ninc(a)\n"),
+        "'ref' parameters are not supported by the compiler yet"
+    );
+    assert_eq!(
+        build_error(
+            "nested",
+            "pr f() -> Int:\n\tpr g() -> Int:\n\t\treturn 1\n\treturn g()\nprint(f())\n"
+        ),
+        "Nested procedures are not supported by the compiler yet"
+    );
+    assert_eq!(
+        build_error(
+            "outer",
+            "var total = 1\npr f() -> Int:\n\treturn total\nprint(f())\n"
+        ),
+        "Procedures that use outer variables are not supported by the compiler yet"
+    );
+    let source = "pr dbl(n: Int) -> Int:\n\treturn n * 2\n";
+    for (name, program) in [
+        ("map", "var a = map([1], dbl)\n"),
+        ("var", "var f: Pr(Int) -> Int = dbl\n"),
+        ("param", "pr apply(f: Pr(Int) -> Int):\n\tprint(1)\n"),
+    ] {
+        assert_eq!(
+            build_error(name, &format!("{source}{program}")),
+            "Procedure values are not supported by the compiler yet"
+        );
+    }
+    // calling a procedure of the main program is supported
+    assert_eq!(
+        build_error(
+            "calls",
+            "pr f() -> Int:\n\treturn 1\npr g() -> Int:\n\treturn f()\nstruct P:\n\tx: Int\n"
+        ),
+        "Structs, enums and 'match' are not supported by the compiler yet"
+    );
+}
+
+#[test]
+fn test_records() {
+    let source = "struct Point:\n\tx: Int\n\ty: Int\n\
+                  struct Line:\n\tstart: Point\n\tend: Point\n\tclosed: Bool\n\
+                  var line: Line\n\
+                  line.end = Point(1, 2)\n\
+                  line.end.y += 5\n\
+                  var p = line.end\n\
+                  p.x = 0\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    let mut evaluate = |source: &str| {
+        let mut expr = parse_expression(lex(source.to_string()).unwrap()).unwrap();
+        analyzer.analyze_global_expression(&mut expr).unwrap();
+        vm.evaluate(&expr).unwrap().unwrap().to_string()
+    };
+
+    assert_eq!(evaluate("line"), "Line(Point(0, 0), Point(1, 7), false)");
+    // records are copied like the other values
+    assert_eq!(evaluate("p"), "Point(0, 7)");
+    assert_eq!(evaluate("line.end.x + p.y"), "8");
+}
+
+#[test]
+fn test_enums() {
+    let source = "enum Shape:\n\tCircle(Int)\n\tRect(Int, Int)\n\tEmpty\n\
+                  struct Point:\n\tx: Int\n\ty: Int\n\
+                  pr area(s: Shape) -> Int:\n\
+                  \tmatch s:\n\
+                  \t\tCircle(r):\n\t\t\treturn 3 * r * r\n\
+                  \t\tRect(w, h):\n\t\t\treturn w * h\n\
+                  \t\tEmpty:\n\t\t\treturn 0\n\
+                  pr describe(p: Point, s: Shape) -> Int:\n\
+                  \tmatch p:\n\
+                  \t\tPoint(0, y):\n\t\t\treturn y\n\
+                  \t\tPoint(x, 1):\n\
+                  \t\t\tmatch s:\n\
+                  \t\t\t\tRect(_, 2):\n\t\t\t\t\treturn x\n\
+                  \t\t\t\t_:\n\t\t\t\t\treturn -x\n\
+                  \t\t_:\n\t\t\treturn 100\n\
+                  var nothing: Shape\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    let mut evaluate = |source: &str| {
+        let mut expr = parse_expression(lex(source.to_string()).unwrap()).unwrap();
+        analyzer.analyze_global_expression(&mut expr).unwrap();
+        vm.evaluate(&expr).unwrap().unwrap().to_string()
+    };
+
+    assert_eq!(evaluate("area(Circle(2))"), "12");
+    assert_eq!(evaluate("area(Rect(2, 5))"), "10");
+    assert_eq!(evaluate("area(Empty)"), "0");
+    assert_eq!(evaluate("Rect(1, 2)"), "Rect(1, 2)");
+    // variables start as the first variant
+    assert_eq!(evaluate("nothing"), "Circle(0)");
+    assert_eq!(evaluate("describe(Point(0, 7), Empty)"), "7");
+    assert_eq!(evaluate("describe(Point(3, 1), Rect(1, 2))"), "3");
+    assert_eq!(evaluate("describe(Point(3, 1), Rect(2, 1))"), "-3");
+    assert_eq!(evaluate("describe(Point(3, 2), Empty)"), "100");
+}
+
+#[test]
+fn test_recursive_types() {
+    let source = "enum List:\n\tCons(Int, List)\n\tNil\n\
+                  struct Tree:\n\tvalue: Int\n\tchildren: Tree[]\n\
+                  pr sum(l: List) -> Int:\n\
+                  \tmatch l:\n\
+                  \t\tCons(x, rest):\n\t\t\treturn x + sum(rest)\n\
+                  \t\tNil:\n\t\t\treturn 0\n\
+                  pr size(t: Tree) -> Int:\n\
+                  \tvar total = 1\n\
+                  \tfor child in t.children:\n\t\ttotal += size(child)\n\
+                  \treturn total\n\
+                  var empty: List\n\
+                  var leaf: Tree\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    let mut evaluate = |source: &str| {
+        let mut expr = parse_expression(lex(source.to_string()).unwrap()).unwrap();
+        analyzer.analyze_global_expression(&mut expr).unwrap();
+        vm.evaluate(&expr).unwrap().unwrap().to_string()
+    };
+
+    assert_eq!(evaluate("sum(Cons(1, Cons(2, Cons(3, Nil))))"), "6");
+    assert_eq!(evaluate("Cons(1, Nil)"), "Cons(1, Nil)");
+    // variables start as the first variant that doesn't contain the enum
+    assert_eq!(evaluate("empty"), "Nil");
+    assert_eq!(
+        evaluate("size(Tree(1, [Tree(2, []), Tree(3, [Tree(4, [])])]))"),
+        "4"
+    );
+    assert_eq!(evaluate("size(leaf)"), "1");
+}
+
+#[test]
+fn test_for() {
+    let source = "var arr = [3, 1, 4, 1, 5]\n\
+                  var sum = 0\n\
+                  for x in arr:\n\tsum += x\n\
+                  var odd = 0\n\
+                  for i in 0..10:\n\
+                  \tif i % 2 == 0:\n\t\tcontinue\n\
+                  \tif i > 7:\n\t\tbreak\n\
+                  \todd = odd * 10 + i\n\
+                  var n = 2\n\
+                  var count = 0\n\
+                  for i in 0..n:\n\tn += 1\n\tcount += 1\n\
+                  var word = \"\"\n\
+                  for c in \"abc\":\n\tword = word\n\
+                  pr find(a: Int[], value: Int) -> Int:\n\
+                  \tfor i in 0..5:\n\
+                  \t\tif a[i] == value:\n\t\t\treturn i\n\
+                  \treturn -1\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    let mut evaluate = |source: &str| {
+        let mut expr = parse_expression(lex(source.to_string()).unwrap()).unwrap();
+        analyzer.analyze_global_expression(&mut expr).unwrap();
+        vm.evaluate(&expr).unwrap().unwrap().to_string()
+    };
+
+    assert_eq!(evaluate("sum"), "14");
+    assert_eq!(evaluate("odd"), "1357");
+    // the range is evaluated once
+    assert_eq!(evaluate("count"), "2");
+    assert_eq!(evaluate("find(arr, 4)"), "2");
+    assert_eq!(evaluate("find(arr, 9)"), "-1");
+
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{} {}", error.kind.code(), error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+    assert_eq!(
+        errors("for x in 5:\n\tprint(x)\n"),
+        ["E0016 values of type 'Int' can't be iterated over"]
+    );
+    assert_eq!(
+        errors("for i in 0..'a':\n\tprint(i)\n"),
+        ["E0008 mismatched types"]
+    );
+    // the variable only lives inside the loop
+    assert_eq!(
+        errors("for i in 0..2:\n\tprint(i)\nprint(i)\n"),
+        ["E0006 use of undeclared variable 'i'"]
+    );
+}
+
+#[test]
+fn test_type_inference() {
+    let source = "var a = []\n\
+                  a = [3, 4]\n\
+                  var x\n\
+                  if a[0] > 1:\n\tx = a[1]\nelse:\n\tx = 0\n\
+                  pr letters() -> Char[]:\n\treturn []\n\
+                  var word = letters()\n\
+                  var grid = [[], [true]]\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    let mut evaluate = |source: &str| {
+        let mut expr = parse_expression(lex(source.to_string()).unwrap()).unwrap();
+        analyzer.analyze_global_expression(&mut expr).unwrap();
+        let r#type = expr.r#type.clone().unwrap().to_string();
+        let value = vm.evaluate(&expr).unwrap().unwrap().to_string();
+        format!("{}: {}", value, r#type)
+    };
+
+    assert_eq!(evaluate("x"), "4: Int");
+    assert_eq!(evaluate("string_len(word)"), "0: Int");
+    assert_eq!(evaluate("grid[1][0]"), "true: Bool");
+    assert_eq!(evaluate("\"abc\"[1]"), "'b': Char");
+
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{} {}", error.kind.code(), error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+    assert_eq!(errors("var a = []\n"), ["E0018 type annotations needed"]);
+    assert_eq!(
+        errors("var x\nprint(1)\n"),
+        ["E0018 type annotations needed"]
+    );
+    assert_eq!(errors("var a = []\na = 3\n"), ["E0008 mismatched types"]);
+    assert_eq!(
+        errors("pr f():\n\tprint(1)\nvar y = f()\n"),
+        ["E0017 'f' doesn't return a value"]
+    );
+    // the type of 'x' is found before the error is
+    assert_eq!(
+        errors("var x\nx = [1]\nprint(x[true])\n"),
+        ["E0008 mismatched types"]
+    );
+}
+
+#[test]
+fn test_nested_arrays() {
+    let source = "pr make() -> Int[][]:\n\treturn [[1, 2], [3, 4]]\n\
+                  var grid = make()\n\
+                  var copy = grid\n\
+                  copy[0][0] = 9\n\
+                  var i = 1\n\
+                  grid[i][i - 1] += 5\n\
+                  var flags = [true, false]\n\
+                  flags[1] = !flags[0]\n\
+                  var words = [\"ab\", \"cd\"]\n\
+                  words[1][0] = 'X'\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    let mut evaluate = |source: &str| {
+        let mut expr = parse_expression(lex(source.to_string()).unwrap()).unwrap();
+        analyzer.analyze_global_expression(&mut expr).unwrap();
+        vm.evaluate(&expr).unwrap().unwrap().to_string()
+    };
+
+    assert_eq!(evaluate("grid[1][0]"), "8");
+    // arrays are copied with the arrays inside them
+    assert_eq!(evaluate("grid[0][0]"), "1");
+    assert_eq!(evaluate("copy[0][0]"), "9");
+    assert_eq!(evaluate("make()[1][1]"), "4");
+    assert_eq!(evaluate("flags[1]"), "false");
+    assert_eq!(evaluate("words[1]"), "\"Xd\"");
+
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{} {}", error.kind.code(), error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+    assert_eq!(
+        errors("var g = [[1]]\ng[0][0] = true\n"),
+        ["E0008 mismatched types"]
+    );
+    assert_eq!(errors("var g = [[1]]\nprint(g[0][0][0])\n").len(), 1);
+}
+
+#[test]
+fn test_growable_arrays() {
+    let source = "var a = []\n\
+                  for i in 0..5:\n\tpush(a, i * i)\n\
+                  var last = pop(a)\n\
+                  pop(a)\n\
+                  insert(a, 0, 7)\n\
+                  insert(a, len(a), 8)\n\
+                  var removed = remove(a, 1)\n\
+                  var grid = [[1], [2]]\n\
+                  push(grid[1], 3)\n\
+                  var s = \"hello world\"\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    let mut evaluate = |source: &str| {
+        let mut expr = parse_expression(lex(source.to_string()).unwrap()).unwrap();
+        analyzer.analyze_global_expression(&mut expr).unwrap();
+        vm.evaluate(&expr).unwrap().unwrap().to_string()
+    };
+
+    assert_eq!(evaluate("a"), "[7, 1, 4, 8]");
+    assert_eq!(evaluate("last"), "16");
+    assert_eq!(evaluate("removed"), "0");
+    assert_eq!(evaluate("grid"), "[[1], [2, 3]]");
+    assert_eq!(evaluate("s[0..5] + \"!\""), "\"hello!\"");
+    assert_eq!(evaluate("len(s[6..len(s)])"), "5");
+    assert_eq!(evaluate("a[2..2]"), "[]");
+
+    let message = |source: &str| match run_source(source) {
+        Err(YoraError::Runtime(err)) => err.message,
+        _ => panic!("expected a runtime error"),
+    };
+    assert_eq!(
+        message("var a = [1]\npop(a)\npop(a)\n"),
+        "pop from an empty array"
+    );
+    assert_eq!(
+        message("var a = [1]\ninsert(a, 2, 0)\n"),
+        "insertion index out of bounds: the length is 1 but the index is 2"
+    );
+    assert_eq!(
+        message("var a = [1]\nprint(len(a[1..0]))\n"),
+        "range 1..0 out of bounds for length 1"
+    );
+
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{} {}", error.kind.code(), error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+    assert_eq!(errors("push([1], 2)\n"), ["E0004 invalid identifier"]);
+    assert_eq!(
+        errors("var a = [1]\npush(a, true)\n"),
+        ["E0008 mismatched types"]
+    );
+    assert_eq!(
+        errors("print(len(5))\n"),
+        ["E0007 undefined procedure 'len'"]
+    );
+    assert_eq!(
+        errors("print([1] + \"a\")\n"),
+        ["E0009 operation '+' not implemented between types 'Int[]' and 'Char[]'"]
+    );
+    assert_eq!(
+        errors("var a = [1]\nvar b = push(a, 2)\n"),
+        ["E0017 'push' doesn't return a value"]
+    );
+    // a slice is a new array
+    assert_eq!(
+        errors("var a = [1]\na[0..1] = [2]\n"),
+        ["E0004 invalid identifier"]
+    );
+}
+
+#[test]
+fn test_ref_parameters() {
+    let source = "pr swap(ref a: Int, ref b: Int):\n\tvar tmp = a\n\ta = b\n\tb = tmp\n\
+                  pr fill(ref a: Int[], n: Int):\n\tfor i in 0..n:\n\t\tpush(a, i)\n\
+                  pr fill_twice(ref a: Int[]):\n\tfill(a, 2)\n\tfill(a, 1)\n\
+                  var x = 1\n\
+                  var y = 2\n\
+                  swap(x, y)\n\
+                  var grid = [[5], [6]]\n\
+                  swap(grid[0][0], y)\n\
+                  fill_twice(grid[1])\n\
+                  var a = 0\n\
+                  var b = [a]\n\
+                  swap(a, b[0])\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    let mut evaluate = |source: &str| {
+        let mut expr = parse_expression(lex(source.to_string()).unwrap()).unwrap();
+        analyzer.analyze_global_expression(&mut expr).unwrap();
+        vm.evaluate(&expr).unwrap().unwrap().to_string()
+    };
+
+    assert_eq!(evaluate("x"), "2");
+    assert_eq!(evaluate("y"), "5");
+    assert_eq!(evaluate("grid"), "[[1], [6, 0, 1, 0]]");
+
+    // the argument is given back when the procedure fails
+    let source = "pr fail(ref a: Int[]):\n\tpush(a, 1)\n\tpop(a)\n\tpop(a)\n\tpop(a)\n\
+                  var a = [7]\n\
+                  fail(a)\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    assert!(vm.run(&ast).is_err());
+    let mut expr = parse_expression(lex("a".to_string()).unwrap()).unwrap();
+    analyzer.analyze_global_expression(&mut expr).unwrap();
+    assert_eq!(vm.evaluate(&expr).unwrap().unwrap().to_string(), "[]");
+
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{} {}", error.kind.code(), error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+    let swap = "pr swap(ref a: Int, ref b: Int):\n\ta = b\n";
+    assert_eq!(
+        errors(&format!("{swap}var x = 1\nswap(x, 2)\n")),
+        ["E0019 only variables, elements and fields can be passed by reference"]
+    );
+    assert_eq!(
+        errors(&format!("{swap}var x = [1, 2]\nswap(x[0], x[1])\n")),
+        ["E0020 'x' is passed by reference more than once"]
+    );
+    assert_eq!(
+        errors(&format!("{swap}var x = 1\nswap(x, x + 1)\n")),
+        ["E0019 only variables, elements and fields can be passed by reference"]
+    );
+}
+
+#[test]
+fn test_captured_variables() {
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{} {}", error.kind.code(), error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+
+    // globals are shared, the variables of blocks and procedures are copied
+    produce_ast(
+        "var total = 0
+pr add(n: Int):
+	total += n
+"
+        .to_string(),
+    )
+    .unwrap();
+    assert_eq!(
+        errors(
+            "pr f(n: Int):
+	pr g():
+		n += 1
+"
+        ),
+        ["E0021 can't change 'n', which the procedure captured by value"]
+    );
+    assert_eq!(
+        errors(
+            "if true:
+	var a = [1]
+	pr g():
+		push(a, 2)
+"
+        ),
+        ["E0021 can't change 'a', which the procedure captured by value"]
+    );
+    assert_eq!(
+        errors(
+            "pr set(ref a: Int):
+	a = 1
+var f = set
+"
+        ),
+        ["E0022 'set' has ref parameters, so it can only be called"]
+    );
+    assert_eq!(
+        errors(
+            "pr f():
+	print(1)
+var g
+g = f
+"
+        ),
+        ["E0023 'g' holds a procedure, so it needs a value"]
+    );
+    // a global can't be passed by reference to a procedure that uses it
+    assert_eq!(
+        errors(
+            "var a = [1, 2]
+pr f(ref x: Int[]) -> Int:
+	return len(a)
+print(f(a))
+"
+        ),
+        ["E0032 'a' is passed by reference to 'f', which uses it too"]
+    );
+    assert_eq!(
+        errors(
+            "var arr = [7, 8]
+pr g(ref x: Int):
+	print(arr[0])
+g(arr[0])
+"
+        ),
+        ["E0032 'arr' is passed by reference to 'g', which uses it too"]
+    );
+    assert_eq!(
+        errors(
+            "var a = [1]
+pr f(ref x: Int[]):
+	push(x, 5)
+	a = [7, 7, 7]
+f(a)
+"
+        ),
+        ["E0032 'a' is passed by reference to 'f', which uses it too"]
+    );
+    // also through the procedures it calls
+    assert_eq!(
+        errors(
+            "var a = [1]
+pr size() -> Int:
+	return len(a)
+pr f(ref x: Int[]):
+	push(x, size())
+f(a)
+"
+        ),
+        ["E0032 'a' is passed by reference to 'f', which uses it too"]
+    );
+    produce_ast(
+        "var a = [1]
+var b = [2]
+pr f(ref x: Int[]):
+	push(x, len(a))
+f(b)
+"
+        .to_string(),
+    )
+    .unwrap();
+    // a procedure only sees what is declared before it
+    assert_eq!(
+        errors(
+            "pr f() -> Int:
+	return later
+var later = 1
+"
+        ),
+        ["E0006 use of undeclared variable 'later'"]
+    );
+}
+
+#[test]
+fn test_procedure_types() {
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{} {}", error.kind.code(), error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+    let source = "pr double(n: Int) -> Int:\n\treturn n * 2\n\
+                  pr show(n: Int):\n\tprint(n)\n\
+                  var a = [1, 2]\n";
+
+    // the types of the arguments and of the return value are inferred
+    produce_ast(format!("{source}var b = [1]\nb = map([], double)\n")).unwrap();
+    produce_ast(format!(
+        "{source}pr max(x: Int, y: Int) -> Int:\n\treturn x\nvar b: Int = fold(map(a, double), 0, max)\n"
+    ))
+    .unwrap();
+    produce_ast(format!(
+        "{source}var f: Pr(Int) -> Int = double\nvar b = [f, double][1](3) + 1\n"
+    ))
+    .unwrap();
+    produce_ast(format!(
+        "{source}var fs = []\npush(fs, double)\nvar b = fs[0](1)\n"
+    ))
+    .unwrap();
+
+    assert_eq!(
+        errors(&format!("{source}var b = a[0](1)\n")),
+        ["E0024 values of type 'Int' can't be called"]
+    );
+    assert_eq!(
+        errors(&format!("{source}var f = double\nvar b = (f)(1, 2)\n")),
+        ["E0008 mismatched types"]
+    );
+    assert_eq!(
+        errors(&format!("{source}var f = show\nvar b = (f)(1)\n")),
+        ["E0017 'Pr(Int)' doesn't return a value"]
+    );
+    assert_eq!(
+        errors(&format!("{source}var f: Pr(Int) -> Bool = double\n")),
+        ["E0008 mismatched types"]
+    );
+    // `filter` needs a procedure that returns a Bool
+    assert_eq!(
+        errors(&format!("{source}var b = filter(a, double)\n")),
+        ["E0007 undefined procedure 'filter'"]
+    );
+    assert_eq!(
+        errors(&format!("{source}var b = map(a, show)\n")),
+        ["E0007 undefined procedure 'map'"]
+    );
+}
+
+#[test]
+fn test_discarded_return_value() {
+    let source = "pr f() -> Int:\n\treturn 1\n\
+                  pr g() -> Int:\n\tf()\n\treturn 2\n";
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    let mut expr = parse_expression(lex("g()".to_string()).unwrap()).unwrap();
+    analyzer.analyze_global_expression(&mut expr).unwrap();
+    assert_eq!(vm.evaluate(&expr).unwrap().unwrap().to_string(), "2");
+}
+
+#[test]
+fn test_match_errors() {
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{} {}", error.kind.code(), error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+
+    let source = "enum T:\n\tLeaf\n\tNode(Int, Bool)\nvar t = Node(1, true)\nmatch t:\n";
+    assert_eq!(
+        errors(&format!(
+            "{}\tNode(n, true):\n\t\tprint(n)\n\tLeaf:\n\t\tprint(0)\n",
+            source
+        )),
+        ["E0015 non-exhaustive patterns: 'Node(_, false)' not covered"]
+    );
+    assert_eq!(
+        errors(&format!("{}\tNode(_, _):\n\t\tprint(0)\n", source)),
+        ["E0015 non-exhaustive patterns: 'Leaf' not covered"]
+    );
+    assert_eq!(
+        errors(&format!(
+            "{}\tNod(n, b):\n\t\tprint(n)\n\t_:\n\t\tprint(0)\n",
+            source
+        )),
+        ["E0013 no variant 'Nod' in type 'T'"]
+    );
+    assert_eq!(
+        errors(&format!(
+            "{}\tNode(n):\n\t\tprint(n)\n\t_:\n\t\tprint(0)\n",
+            source
+        )),
+        ["E0014 'Node' has 2 fields, but the pattern has 1"]
+    );
+    assert_eq!(
+        errors(&format!(
+            "{}\tNode(1, 2):\n\t\tprint(0)\n\t_:\n\t\tprint(0)\n",
+            source
+        )),
+        ["E0008 mismatched types"]
+    );
+    assert_eq!(
+        errors("match 3:\n\t1:\n\t\tprint(1)\n"),
+        ["E0015 non-exhaustive patterns: '_' not covered"]
+    );
+    assert_eq!(
+        errors("enum U:\n\tA\n\tA\n"),
+        ["E0031 'A' is already defined"]
+    );
+    assert_eq!(
+        errors(&format!("{}\t_:\n\t\tprint(0)\nenum U:\n\tLeaf\n", source)),
+        ["E0031 'Leaf' is already defined"]
+    );
+    assert_eq!(
+        errors("struct P:\n\tx: Int\nenum U:\n\tP\n"),
+        ["E0031 'P' is already defined"]
+    );
+    assert_eq!(
+        errors(&format!(
+            "{}\t_:\n\t\tprint(0)\nstruct Leaf:\n\tx: Int\n",
+            source
+        )),
+        ["E0031 'Leaf' is already defined"]
+    );
+}
+
+#[test]
+fn test_control_flow_errors() {
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{}:{} {}", error.line, error.col, error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+
+    // every path returns
+    produce_ast(
+        "pr sign(n: Int) -> Int:\n\
+         \tif n < 0:\n\t\treturn -1\n\
+         \telse if n > 0:\n\t\treturn 1\n\
+         \telse:\n\t\treturn 0\n\
+         pr first(a: Int[]) -> Int:\n\
+         \tloop:\n\
+         \t\tif len(a) > 0:\n\t\t\treturn a[0]\n\
+         \t\tpush(a, 1)\n\
+         pr is_some(a: Int[]) -> Bool:\n\
+         \tmatch len(a):\n\t\t0:\n\t\t\treturn false\n\t\t_:\n\t\t\treturn true\n"
+            .to_string(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        errors("pr f(n: Int) -> Int:\n\tif n > 0:\n\t\treturn n\n"),
+        ["1:1 procedure 'f' may end without returning a value"]
+    );
+    // a broken loop ends
+    assert_eq!(
+        errors("pr f() -> Int:\n\tloop:\n\t\tbreak\n"),
+        ["1:1 procedure 'f' may end without returning a value"]
+    );
+    assert_eq!(
+        errors("pr f() -> Int:\n\treturn true\n"),
+        ["2:9 mismatched types"]
+    );
+    assert_eq!(
+        errors("pr f():\n\treturn 1\n"),
+        ["2:9 'f' doesn't return a value"]
+    );
+    assert_eq!(
+        errors("var a = 1\nreturn a\n"),
+        ["2:1 'return' outside of a procedure"]
+    );
+    assert_eq!(errors("break\n"), ["1:1 'break' outside of a loop"]);
+    // a procedure can't break the loop it is declared in
+    assert_eq!(
+        errors("loop:\n\tpr f():\n\t\tcontinue\n\tbreak\n"),
+        ["3:3 'continue' outside of a loop"]
+    );
+    assert_eq!(
+        errors("pr f() -> Int:\n\treturn 1\n\tprint(2)\n\tprint(3)\n"),
+        ["3:2 unreachable statement"]
+    );
+    assert_eq!(
+        errors("while true:\n\tif true:\n\t\tbreak\n\telse:\n\t\tcontinue\n\tprint(1)\n"),
+        ["6:2 unreachable statement"]
+    );
+    assert_eq!(
+        errors("loop:\n\tprint(1)\nprint(2)\n"),
+        ["3:1 unreachable statement"]
+    );
+}
+
+#[test]
+fn test_builtin_arguments() {
+    let errors = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| format!("{} {}", error.kind.code(), error.kind.message()))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+
+    produce_ast("print([1, 2])\nprint(\"a\")\nexit(1 + 1)\n".to_string()).unwrap();
+    for source in ["print()\n", "print(1, 2)\n", "exit(\"a\")\n", "exit()\n"] {
+        let name = &source[..source.find('(').unwrap()];
+        assert_eq!(
+            errors(source),
+            [format!("E0007 undefined procedure '{name}'")]
+        );
+    }
+    assert_eq!(
+        errors("var a = print(1)\n"),
+        ["E0017 'print' doesn't return a value"]
+    );
+}
+
+#[test]
+fn test_suggestions() {
+    let help = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors.iter().next().unwrap().help.clone(),
+        _ => panic!("expected a semantic error"),
+    };
+
+    assert_eq!(
+        help("print(string_ln(\"a\"))\n"),
+        Some("did you mean 'string_len'?".to_string())
+    );
+    // operators aren't called by name
+    assert_eq!(help("x()\n"), None);
+    assert_eq!(
+        help("pr _helper():\n\tprint(1)\n_helpr()\n"),
+        Some("did you mean '_helper'?".to_string())
+    );
+}
+
+#[test]
+fn test_record_errors() {
+    let kinds = |source: &str| match produce_ast(source.to_string()) {
+        Err(YoraError::Semantic(errors)) => errors
+            .iter()
+            .map(|error| error.kind.code())
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a semantic error"),
+    };
+
+    let source = "struct P:\n\tx: Int\nvar p = P(1)\n";
+    assert_eq!(kinds(&format!("{}print(p.y)\n", source)), ["E0012"]);
+    assert_eq!(kinds(&format!("{}p.x = true\n", source)), ["E0008"]);
+    assert_eq!(kinds(&format!("{}var q = P(true)\n", source)), ["E0007"]);
+    assert_eq!(kinds("pr f(a: Float):\n\tprint(1)\n"), ["E0005"]);
+    assert_eq!(kinds("pr f(a, b: Int):\n\tprint(a + b)\n"), ["E0018"]);
+    // a struct can't contain itself, and an enum needs a way out
+    assert_eq!(kinds("struct N:\n\tnext: N\n"), ["E0033"]);
+    assert_eq!(kinds("enum R:\n\tA(R)\n\tB(Int, R)\n"), ["E0033"]);
+    assert_eq!(
+        kinds(&format!("{}struct P:\n\ty: Int\n", source)),
+        ["E0031"]
+    );
+    assert_eq!(kinds("struct Q:\n\tx: Int\n\tx: Bool\n"), ["E0031"]);
+    assert_eq!(kinds("struct Int:\n\tx: Int\n"), ["E0031"]);
+    assert_eq!(
+        kinds(&format!("{}pr P():\n\tprint(1)\n", source)),
+        ["E0031"]
+    );
+    assert_eq!(
+        kinds("pr Q():\n\tprint(1)\nstruct Q:\n\tx: Int\n"),
+        ["E0031"]
+    );
+}
+
+#[test]
+fn test_bindings() {
+    let source = "var a = 1\n\
+                  if a == 1:\n\tvar b = a\n\
+                  var c = 2\n\
+                  pr f(n: Int) -> Int:\n\tvar a = n\n\treturn a\n";
+    let ast = produce_ast(source.to_string()).unwrap();
+
+    let binding = |statement: &Statement| match &statement.kind {
+        StatementKind::Declare { binding, .. } => binding.unwrap(),
+        _ => panic!("expected a declaration"),
+    };
+    let StatementKind::If { cond, block } = &ast[1].kind else {
+        panic!("expected an if");
+    };
+    let ExpressionKind::Call(_, args) = &cond.kind else {
+        panic!("expected a comparison");
+    };
+    assert_eq!(args[0].binding, Some(Binding { depth: 0, slot: 0 }));
+    assert_eq!(binding(&block[0]), Binding { depth: 0, slot: 1 });
+    // the slot of a block is reused once it ends
+    assert_eq!(binding(&ast[2]), Binding { depth: 0, slot: 1 });
+
+    // the procedure itself and its arguments come first
+    let StatementKind::Procedure {
+        block, binding: f, ..
+    } = &ast[3].kind
+    else {
+        panic!("expected a procedure");
+    };
+    assert_eq!(*f, Some(Binding { depth: 0, slot: 2 }));
+    assert_eq!(binding(&block[0]), Binding { depth: 1, slot: 2 });
+    let StatementKind::Return { value } = &block[1].kind else {
+        panic!("expected a return");
+    };
+    assert_eq!(value.binding, Some(Binding { depth: 1, slot: 2 }));
+}
@@ -1,22 +1,63 @@
-use yora_compiler::asm_generation::*;
-use yora_compiler::ir_generation::Ir;
+use yora::code_generation::asm_gen::*;
+use yora::code_generation::ir_gen::ir::*;
+use yora::core::PrimitiveType;
+
+fn id(id: &str) -> Value {
+    Value::Identifier { id: id.to_string() }
+}
+
+fn constant(value: &str) -> Value {
+    Value::Constant {
+        value: value.to_string(),
+    }
+}
 
 #[test]
 fn test_asm_generation() {
-    let input = vec![
-        Ir::Assign("t1".to_string(), "2".to_string()),
-        Ir::Assign("t2".to_string(), "3".to_string()),
-        Ir::Add("t1".to_string(), "t2".to_string()),
-        Ir::Exit("t1".to_string()),
-    ];
-    let output = "global _start\n\
+    let mut input = Ir::new();
+    input.add_instruction(IrInstruction::Ass {
+        dest: id("t1"),
+        src: constant("2"),
+        r#type: PrimitiveType::Int,
+    });
+    input.add_instruction(IrInstruction::Ass {
+        dest: id("t2"),
+        src: constant("3"),
+        r#type: PrimitiveType::Int,
+    });
+    input.add_instruction(IrInstruction::Op {
+        dest: id("t1"),
+        src1: id("t1"),
+        op: Op::Add,
+        src2: id("t2"),
+        r#type: PrimitiveType::Int,
+    });
+    input.add_instruction(IrInstruction::Param {
+        src: id("t1"),
+        r#type: PrimitiveType::Int,
+    });
+    input.add_instruction(IrInstruction::Call {
+        dest: None,
+        label: "exit".to_string(),
+    });
+
+    let output = "section .text\n\
+                    global _start\n\
                     _start:\n\
                         \tmov rbp, rsp\n\
-                        \tmov rbx, 2\n\
-                        \tmov r10, 3\n\
-                        \tadd rbx, r10\n\n\
-                        \tmov rdi, rbx\n\
-                        \tmov rax, 60\n\
-                        \tsyscall\n";
-    assert_eq!(generate_asm(input), output);
+                        \tsub rsp, 0\n\
+                        \tmov rax, 2\n\
+                        \tmov r10, rax\n\
+                        \tmov rax, 3\n\
+                        \tmov r11, rax\n\
+                        \tmov rax, r10\n\
+                        \tmov rcx, r11\n\
+                        \tadd rax, rcx\n\
+                        \tmov r10, rax\n\
+                        \tmov rdi, r10\n\
+                        \tcall exit\n";
+
+    let asm = generate_asm(input);
+    let text = &asm[asm.find("section .text").unwrap()..];
+    assert!(text.starts_with(output), "{}", text);
 }
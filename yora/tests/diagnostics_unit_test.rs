@@ -0,0 +1,214 @@
+use yora::syntax_analysis::errors::*;
+use yora::syntax_analysis::lexer::lex;
+use yora::syntax_analysis::parser::parse;
+use yora::syntax_analysis::produce_ast;
+
+#[test]
+fn test_render() {
+    let source = "var a = 1\nif true:\n\tprint(strin_len(a))\n";
+
+    let mut errors = Errors::new();
+    errors.push(
+        Error::new(
+            ErrorKind::UndefinedProcedure {
+                name: "strin_len".to_string(),
+            },
+            3,
+            8,
+        )
+        .with_len(9)
+        .with_note("a note".to_string())
+        .with_help("did you mean 'string_len'?".to_string()),
+    );
+
+    let output = "error[E0007]: undefined procedure 'strin_len'\n \
+                  --> main.yr:3:8\n  \
+                  |\n\
+                  3 |     print(strin_len(a))\n  \
+                  |           ^^^^^^^^^\n  \
+                  = note: a note\n  \
+                  = help: did you mean 'string_len'?\n\
+                  \n";
+
+    assert_eq!(errors.render("main.yr", source), output);
+}
+
+#[test]
+fn test_render_syntax_error() {
+    let source = "var x = (1 +\n";
+
+    let err = parse(lex(source.to_string()).unwrap()).unwrap_err();
+    let errors = err.diagnostics().unwrap();
+
+    let output = "error[E0002]: expected an expression, found end of line\n \
+                  --> main.yr:1:13\n  \
+                  |\n\
+                  1 | var x = (1 +\n  \
+                  |             ^ expected an expression\n\
+                  \n";
+
+    assert_eq!(errors.render("main.yr", source), output);
+}
+
+#[test]
+fn test_error_codes_are_unique() {
+    let kinds = [
+        ErrorKind::UnexpectedToken {
+            expected: String::new(),
+            found: String::new(),
+        },
+        ErrorKind::UnexpectedEnd {
+            expected: String::new(),
+        },
+        ErrorKind::InconsistentIndentation,
+        ErrorKind::InvalidIdentifier,
+        ErrorKind::UndefinedType {
+            type1: String::new(),
+        },
+        ErrorKind::UndeclaredVariable { var: String::new() },
+        ErrorKind::UndefinedProcedure {
+            name: String::new(),
+        },
+        ErrorKind::MismatchedTypes {
+            expected: String::new(),
+            found: String::new(),
+        },
+        ErrorKind::OperationNotImplemented {
+            op: String::new(),
+            type1: String::new(),
+            type2: String::new(),
+        },
+        ErrorKind::InvalidArray,
+        ErrorKind::RuntimeError {
+            message: String::new(),
+        },
+        ErrorKind::UndefinedField {
+            r#type: String::new(),
+            field: String::new(),
+        },
+        ErrorKind::UndefinedVariant {
+            r#type: String::new(),
+            variant: String::new(),
+        },
+        ErrorKind::WrongNumberOfFields {
+            name: String::new(),
+            expected: 0,
+            found: 0,
+        },
+        ErrorKind::NonExhaustiveMatch {
+            missing: String::new(),
+        },
+        ErrorKind::NotIterable {
+            r#type: String::new(),
+        },
+        ErrorKind::NoReturnValue {
+            name: String::new(),
+        },
+        ErrorKind::TypeAnnotationsNeeded,
+        ErrorKind::InvalidReference,
+        ErrorKind::AliasedReference { var: String::new() },
+        ErrorKind::ChangedCapture { var: String::new() },
+        ErrorKind::RefProcedureValue {
+            name: String::new(),
+        },
+        ErrorKind::NoDefaultValue { var: String::new() },
+        ErrorKind::NotCallable {
+            r#type: String::new(),
+        },
+        ErrorKind::MissingReturn {
+            name: String::new(),
+        },
+        ErrorKind::ReturnOutsideProcedure,
+        ErrorKind::OutsideLoop {
+            keyword: String::new(),
+        },
+        ErrorKind::UnreachableCode,
+        ErrorKind::InvalidEscape {
+            escape: String::new(),
+        },
+        ErrorKind::UnterminatedString,
+        ErrorKind::Redefinition {
+            name: String::new(),
+        },
+        ErrorKind::SharedReference {
+            var: String::new(),
+            name: String::new(),
+        },
+        ErrorKind::RecursiveType {
+            name: String::new(),
+        },
+    ];
+
+    let mut codes: Vec<&str> = kinds.iter().map(|kind| kind.code()).collect();
+    codes.sort();
+    codes.dedup();
+    assert_eq!(codes.len(), kinds.len());
+}
+
+#[test]
+fn test_find_similar() {
+    let names = ["string_len", "string_to_int", "print"];
+
+    assert_eq!(
+        find_similar("strin_len", names.into_iter()),
+        Some("string_len")
+    );
+    assert_eq!(find_similar("prnt", names.into_iter()), Some("print"));
+    assert_eq!(find_similar("input", names.into_iter()), None);
+}
+
+#[test]
+fn test_render_json() {
+    let mut errors = Errors::new();
+    errors.push(
+        Error::new(
+            ErrorKind::MismatchedTypes {
+                expected: "Int".to_string(),
+                found: "Bool".to_string(),
+            },
+            2,
+            5,
+        )
+        .with_len(4)
+        .with_note("a \"quoted\" note".to_string()),
+    );
+    errors.add(ErrorKind::InvalidArray, 1, 9);
+
+    assert_eq!(
+        errors.emit("main.yr", "", ErrorFormat::Json),
+        "{\"severity\":\"error\",\"code\":\"E0010\",\"message\":\"all array elements must have the same type\",\
+        \"file\":\"main.yr\",\"span\":{\"start\":{\"line\":1,\"col\":9},\"end\":{\"line\":1,\"col\":10}},\
+        \"label\":null,\"notes\":[],\"help\":null}\n\
+        {\"severity\":\"error\",\"code\":\"E0008\",\"message\":\"mismatched types\",\
+        \"file\":\"main.yr\",\"span\":{\"start\":{\"line\":2,\"col\":5},\"end\":{\"line\":2,\"col\":9}},\
+        \"label\":\"expected 'Int', found 'Bool'\",\"notes\":[\"a \\\"quoted\\\" note\"],\"help\":null}\n"
+    );
+}
+
+#[test]
+fn test_errors_at_the_same_position() {
+    let mut errors = Errors::new();
+    errors.add(ErrorKind::UnreachableCode, 3, 1);
+    errors.add(ErrorKind::InvalidArray, 3, 1);
+    errors.add(ErrorKind::InvalidArray, 1, 5);
+    // the same error is only kept once
+    errors.add(ErrorKind::InvalidArray, 3, 1);
+
+    let codes: Vec<_> = errors
+        .iter()
+        .map(|error| (error.line, error.col, error.kind.code()))
+        .collect();
+    assert_eq!(codes, [(1, 5, "E0010"), (3, 1, "E0010"), (3, 1, "E0028")]);
+
+    let source = "struct f:\n\tx: Int\npr f() -> Int:\n\tprint(1)\n";
+    let Err(err) = produce_ast(source.to_string()) else {
+        panic!("expected a semantic error");
+    };
+    let codes: Vec<_> = err
+        .diagnostics()
+        .unwrap()
+        .iter()
+        .map(|error| error.kind.code())
+        .collect();
+    assert_eq!(codes, ["E0025", "E0031"]);
+}
@@ -0,0 +1,149 @@
+use std::fs;
+use std::process::Command;
+use yora::formatter::format;
+
+fn assert_formats(source: &str, expected: &str) {
+    let formatted = format(source).unwrap();
+    assert_eq!(formatted, expected);
+    // formatting is idempotent
+    assert_eq!(format(&formatted).unwrap(), expected);
+}
+
+#[test]
+fn test_indentation_and_spacing() {
+    assert_formats(
+        "var a:Int[]=[1,2]\nwhile a[0]<3:\n\ta[0]+=1\n\tif a[0]==2 and !(a[1]>2):\n\t\tprint( a )\n",
+        "var a: Int[] = [1, 2]\nwhile a[0] < 3:\n    a[0] += 1\n    if a[0] == 2 and !(a[1] > 2):\n        print(a)\n",
+    );
+}
+
+#[test]
+fn test_parentheses() {
+    assert_formats(
+        "var a = ((1 + 2)) * (3 - (4 - 5)) - (6 * 7)\nvar b = -(-a) + (-a)\n",
+        "var a = (1 + 2) * (3 - (4 - 5)) - 6 * 7\nvar b = -(-a) + -a\n",
+    );
+}
+
+#[test]
+fn test_else_if() {
+    assert_formats(
+        "if true:\n  print(1)\nelse    if false:\n  print(2)\nelse:\n  if true:\n    print(3)\n",
+        "if true:\n    print(1)\nelse if false:\n    print(2)\nelse:\n    if true:\n        print(3)\n",
+    );
+}
+
+#[test]
+fn test_comments() {
+    assert_formats(
+        "# header\nloop: # forever\n\t# inside\n\tbreak\n\t# end of loop\n# after\nexit(0)   # done\n",
+        "# header\nloop: # forever\n    # inside\n    break\n    # end of loop\n# after\nexit(0) # done\n",
+    );
+}
+
+#[test]
+fn test_comments_before_else() {
+    assert_formats(
+        "if true:\n\tprint(1)\n# before else\nelse:\n\tprint(2)\n",
+        "if true:\n    print(1)\n# before else\nelse:\n    print(2)\n",
+    );
+    assert_formats(
+        "if true:\n\tprint(1)\n\t# in the if\n\n# before else if\nelse if false:\n\tprint(2)\n",
+        "if true:\n    print(1)\n    # in the if\n\n# before else if\nelse if false:\n    print(2)\n",
+    );
+}
+
+#[test]
+fn test_blank_lines() {
+    assert_formats(
+        "\n\nvar a = 1\n\n\n\nvar b = 2\npr f():\n\n\treturn 1\nprint(f())\n\n",
+        "var a = 1\n\nvar b = 2\n\npr f():\n    return 1\n\nprint(f())\n",
+    );
+}
+
+#[test]
+fn test_syntax_errors() {
+    assert!(format("var a = (1\n").is_err());
+}
+
+#[test]
+fn test_check() {
+    let path = std::env::temp_dir().join("yora_fmt_check.yr");
+    let check = || {
+        Command::new(env!("CARGO_BIN_EXE_yora"))
+            .args(["fmt", "--check"])
+            .arg(&path)
+            .status()
+            .unwrap()
+            .success()
+    };
+
+    fs::write(&path, "var a=1\n").unwrap();
+    assert!(!check());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "var a=1\n");
+
+    Command::new(env!("CARGO_BIN_EXE_yora"))
+        .arg("fmt")
+        .arg(&path)
+        .status()
+        .unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "var a = 1\n");
+    assert!(check());
+}
+
+#[test]
+fn test_examples_are_formatted() {
+    for example in ["caeser_cipher.yr", "fibonacci.yr", "prime.yr"] {
+        let path = format!("{}/../examples/{}", env!("CARGO_MANIFEST_DIR"), example);
+        let source = fs::read_to_string(path).unwrap();
+        assert_eq!(format(&source).unwrap(), source, "{}", example);
+    }
+}
+
+#[test]
+fn test_for() {
+    assert_formats(
+        "for i in 0 .. n+1:\n\tfor c in \"ab\": # chars\n\t\tprint(c)\n",
+        "for i in 0..n + 1:\n    for c in \"ab\": # chars\n        print(c)\n",
+    );
+}
+
+#[test]
+fn test_slices() {
+    assert_formats(
+        "print(s[1 .. len(s)-1]+(a+b)[0..1])\n",
+        "print(s[1..len(s) - 1] + (a + b)[0..1])\n",
+    );
+}
+
+#[test]
+fn test_ref_parameters() {
+    assert_formats(
+        "pr swap(ref a:Int,ref  b : Int):\n\ta = b\n",
+        "pr swap(ref a: Int, ref b: Int):\n    a = b\n",
+    );
+}
+
+#[test]
+fn test_procedure_values() {
+    assert_formats(
+        "pr apply(f:Pr( Int,Int )->Int, a:Int)->Int:\n\treturn (f)(a,a)\nvar g=(ops[0])(1)\n(-h)(2)\n",
+        "pr apply(f: Pr(Int, Int) -> Int, a: Int) -> Int:\n    return f(a, a)\n\nvar g = ops[0](1)\n(-h)(2)\n",
+    );
+}
+
+#[test]
+fn test_enums() {
+    assert_formats(
+        "enum E: # e\n\tA( Int,Bool )\n\n\tB\nmatch A(1,true):\n\n\tA(n,_): # a\n\t\tprint(n)\n\n\n\t# b\n\tB:\n\t\tprint(-1)\n\t# end\nprint(0)\n",
+        "enum E: # e\n    A(Int, Bool)\n\n    B\nmatch A(1, true):\n    A(n, _): # a\n        print(n)\n\n    # b\n    B:\n        print(-1)\n    # end\nprint(0)\n",
+    );
+}
+
+#[test]
+fn test_structs() {
+    assert_formats(
+        "struct Point:  # 2d\n\tx:Int\n\n\t# second\n\ty : Int\nvar p=Point(1,2)\np.x+=(-p).y\n",
+        "struct Point: # 2d\n    x: Int\n\n    # second\n    y: Int\nvar p = Point(1, 2)\np.x += (-p).y\n",
+    );
+}
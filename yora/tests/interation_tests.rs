@@ -1,4 +1,74 @@
-use yora_compiler;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run_example(example: &str, input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yora"))
+        .arg(format!(
+            "{}/../examples/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            example
+        ))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap()
+}
 
 #[test]
-fn integration_test01() {}
+fn integration_test01() {
+    assert_eq!(
+        run_example("fibonacci.yr", "10\n"),
+        "Nth fibonacci number to calculate: 55"
+    );
+}
+
+#[test]
+fn integration_test02() {
+    assert_eq!(
+        run_example("caeser_cipher.yr", "3\nHello, World\n"),
+        "Shift: Text: Khoor, Zruog\n"
+    );
+}
+
+#[test]
+fn integration_test03() {
+    assert_eq!(
+        run_example("prime.yr", "7\n4\n0\n"),
+        "Write the number to check if it is prime.\nWrite 0 to exit.\ntrue\nfalse\n"
+    );
+}
+
+#[test]
+fn test_failed_assembly() {
+    let dir = std::env::temp_dir().join(format!("yora_nasm_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("main.yr");
+    std::fs::write(&program, "print(1)\n").unwrap();
+    // an assembler that always fails
+    let nasm = dir.join("nasm");
+    std::fs::write(&nasm, "#!/bin/sh\nexit 1\n").unwrap();
+    Command::new("chmod").arg("+x").arg(&nasm).status().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yora"))
+        .arg("build")
+        .arg(&program)
+        .env("PATH", &dir)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("Failed to assemble '{}'\n", dir.join("main.asm").display())
+    );
+}
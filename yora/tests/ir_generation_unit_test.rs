@@ -1,132 +1,245 @@
-use yora_compiler::ir_generation::{generate_ir, Ir, JmpType};
-use yora_compiler::parser::Expression;
+use yora::code_generation::ir_gen::generate_ir;
+use yora::syntax_analysis::produce_ast;
 
 #[test]
 fn test_ir_generation_bool_literals() {
-    let input = vec![Expression::Sequence(vec![
-        Expression::Declare(
-            Box::new(Expression::Identifier("a".to_string())),
-            Box::new(Expression::IntLit("2".to_string())),
-        ),
-        Expression::If(
-            Box::new(Expression::BoolLit("true".to_string())),
-            Box::new(Expression::Sequence(vec![
-                Expression::Declare(
-                    Box::new(Expression::Identifier("b".to_string())),
-                    Box::new(Expression::IntLit("3".to_string())),
-                ),
-                Expression::If(
-                    Box::new(Expression::BoolLit("false".to_string())),
-                    Box::new(Expression::Sequence(vec![Expression::Exit(Box::new(
-                        Expression::Identifier("b".to_string()),
-                    ))])),
-                ),
-                Expression::Assign(
-                    Box::new(Expression::Identifier("a".to_string())),
-                    Box::new(Expression::Add(
-                        Box::new(Expression::Identifier("a".to_string())),
-                        Box::new(Expression::Identifier("b".to_string())),
-                    )),
-                ),
-            ])),
-        ),
-        Expression::Exit(Box::new(Expression::Identifier("a".to_string()))),
-    ])];
-
-    let output = vec![
-        Ir::Assign("t1".to_string(), "2".to_string()),
-        Ir::Assign("a".to_string(), "t1".to_string()),
-        Ir::Assign("t2".to_string(), "1".to_string()),
-        Ir::Jmp(
-            "t2".to_string(),
-            "0".to_string(),
-            "end_if1".to_string(),
-            JmpType::Je,
-        ),
-        Ir::Assign("t3".to_string(), "3".to_string()),
-        Ir::Assign("b".to_string(), "t3".to_string()),
-        Ir::Assign("t4".to_string(), "0".to_string()),
-        Ir::Jmp(
-            "t4".to_string(),
-            "0".to_string(),
-            "end_if2".to_string(),
-            JmpType::Je,
-        ),
-        Ir::Exit("b".to_string()),
-        Ir::Label("end_if2".to_string()),
-        Ir::Add("a".to_string(), "b".to_string()),
-        Ir::Assign("a".to_string(), "a".to_string()),
-        Ir::Label("end_if1".to_string()),
-        Ir::Exit("a".to_string()),
-    ];
-
-    assert_eq!(generate_ir(input), output);
+    let input = "var a = 2\n\
+                if true:\n\
+                \tvar b = 3\n\
+                \tif false:\n\
+                \t\texit(b)\n\
+                \ta = a + b\n\
+                exit(a)\n";
+
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \tInt:\ta = 2\n\
+                \x20   if 1 == 0 goto end_if_1\n\
+                \tInt:\tb = 3\n\
+                \x20   if 0 == 0 goto end_if_2\n\
+                \x20   param b\n\
+                \x20   call exit\n\
+                end_if_2:\n\
+                \tInt:\tt1 = a + b\n\
+                \tInt:\ta = t1\n\
+                end_if_1:\n\
+                \x20   param a\n\
+                \x20   call exit\n";
+
+    let ast = produce_ast(input.to_string()).unwrap();
+    assert_eq!(generate_ir(&ast).unwrap().to_string(), output);
 }
 
 #[test]
 fn test_ir_generation_greater() {
-    let input = vec![Expression::Sequence(vec![
-        Expression::Declare(
-            Box::new(Expression::Identifier("a".to_string())),
-            Box::new(Expression::IntLit("2".to_string())),
-        ),
-        Expression::If(
-            Box::new(Expression::Greater(
-                Box::new(Expression::Identifier("a".to_string())),
-                Box::new(Expression::IntLit("1".to_string())),
-            )),
-            Box::new(Expression::Sequence(vec![
-                Expression::Declare(
-                    Box::new(Expression::Identifier("b".to_string())),
-                    Box::new(Expression::IntLit("3".to_string())),
-                ),
-                Expression::If(
-                    Box::new(Expression::Greater(
-                        Box::new(Expression::Identifier("a".to_string())),
-                        Box::new(Expression::IntLit("b".to_string())),
-                    )),
-                    Box::new(Expression::Sequence(vec![Expression::Exit(Box::new(
-                        Expression::Identifier("b".to_string()),
-                    ))])),
-                ),
-                Expression::Assign(
-                    Box::new(Expression::Identifier("a".to_string())),
-                    Box::new(Expression::Add(
-                        Box::new(Expression::Identifier("a".to_string())),
-                        Box::new(Expression::Identifier("b".to_string())),
-                    )),
-                ),
-            ])),
-        ),
-        Expression::Exit(Box::new(Expression::Identifier("a".to_string()))),
-    ])];
-
-    let output = vec![
-        Ir::Assign("t1".to_string(), "2".to_string()),
-        Ir::Assign("a".to_string(), "t1".to_string()),
-        Ir::Assign("t2".to_string(), "1".to_string()),
-        Ir::Jmp(
-            "a".to_string(),
-            "t2".to_string(),
-            "end_if1".to_string(),
-            JmpType::Jle,
-        ),
-        Ir::Assign("t3".to_string(), "3".to_string()),
-        Ir::Assign("b".to_string(), "t3".to_string()),
-        Ir::Assign("t4".to_string(), "b".to_string()),
-        Ir::Jmp(
-            "a".to_string(),
-            "t4".to_string(),
-            "end_if2".to_string(),
-            JmpType::Jle,
-        ),
-        Ir::Exit("b".to_string()),
-        Ir::Label("end_if2".to_string()),
-        Ir::Add("a".to_string(), "b".to_string()),
-        Ir::Assign("a".to_string(), "a".to_string()),
-        Ir::Label("end_if1".to_string()),
-        Ir::Exit("a".to_string()),
-    ];
-
-    assert_eq!(generate_ir(input), output);
+    let input = "var a = 2\n\
+                if a > 1:\n\
+                \tvar b = 3\n\
+                \tif a > b:\n\
+                \t\texit(b)\n\
+                \ta = a + b\n\
+                exit(a)\n";
+
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \tInt:\ta = 2\n\
+                \x20   if a <= 1 goto end_if_1\n\
+                \tInt:\tb = 3\n\
+                \x20   if a <= b goto end_if_2\n\
+                \x20   param b\n\
+                \x20   call exit\n\
+                end_if_2:\n\
+                \tInt:\tt1 = a + b\n\
+                \tInt:\ta = t1\n\
+                end_if_1:\n\
+                \x20   param a\n\
+                \x20   call exit\n";
+
+    let ast = produce_ast(input.to_string()).unwrap();
+    assert_eq!(generate_ir(&ast).unwrap().to_string(), output);
+}
+
+#[test]
+fn test_ir_generation_recursive_procedure() {
+    let input = "pr fibonacci(n: Int) -> Int:\n\
+                \tif n > 2:\n\
+                \t\treturn fibonacci(n - 1) + fibonacci(n - 2)\n\
+                \telse:\n\
+                \t\treturn 1\n\
+                print(fibonacci(10))\n";
+
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \x20   param 10\n\
+                \x20   t6 = call fibonacci\n\
+                \x20   param t6\n\
+                \x20   call print_int\n\
+                pr fibonacci(n):\n\
+                \x20   if n <= 2 goto else_1\n\
+                \tInt:\tt2 = n - 1\n\
+                \x20   param t2\n\
+                \x20   t1 = call fibonacci\n\
+                \tInt:\tt4 = n - 2\n\
+                \x20   param t4\n\
+                \x20   t3 = call fibonacci\n\
+                \tInt:\tt5 = t1 + t3\n\
+                \x20   ret t5\n\
+                \x20   goto end_if_1\n\
+                else_1:\n\
+                \x20   ret 1\n\
+                end_if_1:\n\
+                end pr fibonacci\n";
+
+    let ast = produce_ast(input.to_string()).unwrap();
+    assert_eq!(generate_ir(&ast).unwrap().to_string(), output);
+}
+
+#[test]
+fn test_ir_generation_for() {
+    let input = "var s = 0\n\
+                for i in 1..4:\n\
+                \tif i == 2:\n\
+                \t\tcontinue\n\
+                \ts += i\n\
+                for c in \"ab\":\n\
+                \tprint(c)\n\
+                exit(s)\n";
+
+    let output = "section: data\n\
+                \x20   buf_1 97, 98\n\
+                \n\
+                section: code\n\
+                \tInt:\ts = 0\n\
+                \tInt:\tt1 = 1\n\
+                loop_1:\n\
+                \x20   if t1 >= 4 goto loop_end_1\n\
+                \tInt:\ti = t1\n\
+                \tInt:\tt1 = t1 + 1\n\
+                \x20   if i != 2 goto end_if_1\n\
+                \x20   goto loop_1\n\
+                end_if_1:\n\
+                \tInt:\tt2 = s + i\n\
+                \tInt:\ts = t2\n\
+                \x20   goto loop_1\n\
+                loop_end_1:\n\
+                \x20   param buf_1\n\
+                \x20   param 1\n\
+                \x20   t3 = call array_copy\n\
+                \x20   param t3\n\
+                \x20   t4 = call array_len\n\
+                \tInt:\tt5 = 0\n\
+                loop_2:\n\
+                \x20   if t5 >= t4 goto loop_end_2\n\
+                \tChar:\tc = [t3 + t5]\n\
+                \tInt:\tt5 = t5 + 1\n\
+                \x20   param c\n\
+                \x20   call print_char\n\
+                \x20   goto loop_2\n\
+                loop_end_2:\n\
+                \x20   param s\n\
+                \x20   call exit\n";
+
+    let ast = produce_ast(input.to_string()).unwrap();
+    assert_eq!(generate_ir(&ast).unwrap().to_string(), output);
+}
+
+#[test]
+fn test_ir_generation_nested_arrays() {
+    let input = "var g = [[1], [2, 3]]\n\
+                var h = g\n\
+                h[1][0] = g[1][1]\n";
+
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \x20   param 2\n\
+                \x20   param 8\n\
+                \x20   t1 = call array_new\n\
+                \x20   param 1\n\
+                \x20   param 8\n\
+                \x20   t2 = call array_new\n\
+                \tInt:\t[t2 + 0] = 1\n\
+                \tInt[]:\t[t1 + 0] = t2\n\
+                \x20   param 2\n\
+                \x20   param 8\n\
+                \x20   t3 = call array_new\n\
+                \tInt:\t[t3 + 0] = 2\n\
+                \tInt:\t[t3 + 1] = 3\n\
+                \tInt[]:\t[t1 + 1] = t3\n\
+                \tInt[][]:\tg = t1\n\
+                \x20   param g\n\
+                \x20   param 8\n\
+                \x20   param 1\n\
+                \x20   t4 = call array_copy_nested\n\
+                \tInt[][]:\th = t4\n\
+                \tInt[]:\tt5 = [g + 1]\n\
+                \tInt:\tt6 = [t5 + 1]\n\
+                \tInt[]:\tt7 = [h + 1]\n\
+                \tInt:\t[t7 + 0] = t6\n";
+
+    let ast = produce_ast(input.to_string()).unwrap();
+    assert_eq!(generate_ir(&ast).unwrap().to_string(), output);
+}
+
+#[test]
+fn test_ir_generation_growable_arrays() {
+    let input = "var a = [1]\n\
+                push(a, 2)\n\
+                var b = a[0..pop(a)] + a\n\
+                exit(len(b))\n";
+
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \x20   param 1\n\
+                \x20   param 8\n\
+                \x20   t1 = call array_new\n\
+                \tInt:\t[t1 + 0] = 1\n\
+                \tInt[]:\ta = t1\n\
+                \x20   param a\n\
+                \x20   param 2\n\
+                \x20   param 8\n\
+                \x20   t2 = call array_push\n\
+                \tInt[]:\ta = t2\n\
+                \x20   param a\n\
+                \x20   param 8\n\
+                \x20   t3 = call array_pop\n\
+                \x20   param a\n\
+                \x20   param 0\n\
+                \x20   param t3\n\
+                \x20   param 8\n\
+                \x20   t4 = call array_slice\n\
+                \x20   param t4\n\
+                \x20   param a\n\
+                \x20   param 8\n\
+                \x20   t5 = call array_concat\n\
+                \tInt[]:\tb = t5\n\
+                \x20   param b\n\
+                \x20   t6 = call array_len\n\
+                \x20   param t6\n\
+                \x20   call exit\n";
+
+    let ast = produce_ast(input.to_string()).unwrap();
+    assert_eq!(generate_ir(&ast).unwrap().to_string(), output);
+}
+
+#[test]
+fn test_ir_generation_unsupported() {
+    let error = |input: &str| generate_ir(&produce_ast(input.to_string()).unwrap()).unwrap_err();
+
+    assert_eq!(
+        error("pr f(ref x: Int):\n\tx += 1\n"),
+        "'ref' parameters are not supported by the compiler yet"
+    );
+    assert_eq!(
+        error("enum E:\n\tA\n\tB\nvar e = A\n"),
+        "Structs, enums and 'match' are not supported by the compiler yet"
+    );
+    assert_eq!(
+        error("pr add(a: Int, b: Int) -> Int:\n\treturn a + b\nprint(fold([1, 2], 0, add))\n"),
+        "Procedure values are not supported by the compiler yet"
+    );
 }
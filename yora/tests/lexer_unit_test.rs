@@ -1,18 +1,187 @@
-use yora_compiler::lexer::*;
+use yora::syntax_analysis::lexer::*;
+use yora::YoraError;
 
 #[test]
 fn test_input() {
-    let input = "exit(2 + 3);\n";
+    let input = "exit(2 + 3)\n";
 
+    let output: Vec<(&str, TokenKind)> = vec![
+        ("exit", TokenKind::Identifier),
+        ("(", TokenKind::Separator),
+        ("2", TokenKind::IntLit),
+        ("+", TokenKind::Operator),
+        ("3", TokenKind::IntLit),
+        (")", TokenKind::Separator),
+        ("", TokenKind::Newline),
+    ];
+
+    let tokens = lex(input.to_string()).unwrap();
+    let tokens: Vec<(&str, TokenKind)> = tokens
+        .iter()
+        .map(|token| (token.str.as_str(), token.kind.clone()))
+        .collect();
+
+    assert_eq!(tokens, output);
+}
+
+fn kinds(source: &str) -> Vec<TokenKind> {
+    lex(source.to_string())
+        .unwrap()
+        .into_iter()
+        .map(|token| token.kind)
+        .collect()
+}
+
+#[test]
+fn test_indentation() {
+    let input = "loop:\n\
+                \tif a:\n\
+                \n\
+                \t\t# comment\n\
+                \t\tbreak\n\
+                b";
+
+    let output = vec![
+        TokenKind::Keyword,
+        TokenKind::Separator,
+        TokenKind::Newline,
+        TokenKind::Indent,
+        TokenKind::Keyword,
+        TokenKind::Identifier,
+        TokenKind::Separator,
+        TokenKind::Newline,
+        TokenKind::Indent,
+        TokenKind::Keyword,
+        TokenKind::Newline,
+        TokenKind::Dedent,
+        TokenKind::Dedent,
+        TokenKind::Identifier,
+        TokenKind::Newline,
+    ];
+
+    assert_eq!(kinds(input), output);
+}
+
+#[test]
+fn test_blocks_are_closed_at_the_end() {
     let output = vec![
-        Token::Exit,
-        Token::OpenParen,
-        Token::IntLit("2".to_string()),
-        Token::Add,
-        Token::IntLit("3".to_string()),
-        Token::CloseParen,
-        Token::SemiColon,
+        TokenKind::Keyword,
+        TokenKind::Separator,
+        TokenKind::Newline,
+        TokenKind::Indent,
+        TokenKind::Keyword,
+        TokenKind::Newline,
+        TokenKind::Dedent,
     ];
 
-    assert_eq!(lex(input.to_string()), output);
+    assert_eq!(kinds("loop:\n    break\n"), output);
+}
+
+#[test]
+fn test_line_breaks_inside_brackets() {
+    let output = vec![
+        TokenKind::Identifier,
+        TokenKind::Separator,
+        TokenKind::Separator,
+        TokenKind::IntLit,
+        TokenKind::Separator,
+        TokenKind::IntLit,
+        TokenKind::Separator,
+        TokenKind::Separator,
+        TokenKind::Newline,
+    ];
+
+    assert_eq!(kinds("f([1,\n\t\t2])\n"), output);
+}
+
+#[test]
+fn test_inconsistent_indentation() {
+    // a tab and four spaces are different indentations
+    assert!(lex("loop:\n\tbreak\n    break\n".to_string()).is_err());
+    // dedenting to a level that was never opened
+    assert!(lex("loop:\n\t\tbreak\n\tbreak\n".to_string()).is_err());
+}
+
+#[test]
+fn test_separators_end_symbols() {
+    let tokens: Vec<String> = lex("f(-a[-1], !-b)\n".to_string())
+        .unwrap()
+        .into_iter()
+        .map(|token| token.str)
+        .collect();
+
+    assert_eq!(
+        tokens,
+        vec!["f", "(", "-", "a", "[", "-", "1", "]", ",", "!", "-", "b", ")", ""]
+    );
+}
+
+#[test]
+fn test_ranges() {
+    let tokens: Vec<String> = lex("for i in 0..n.len:\n".to_string())
+        .unwrap()
+        .into_iter()
+        .map(|token| token.str)
+        .collect();
+
+    assert_eq!(
+        tokens,
+        vec!["for", "i", "in", "0", "..", "n", ".", "len", ":", ""]
+    );
+}
+
+#[test]
+fn test_comments() {
+    let (tokens, comments) =
+        lex_with_comments("# first\nvar a = 1 # second  \n".to_string()).unwrap();
+
+    let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+    assert_eq!(kinds, self::kinds("var a = 1\n"));
+    assert_eq!(
+        comments,
+        vec![
+            Comment {
+                text: "# first".to_string(),
+                line: 1,
+                col: 1,
+            },
+            Comment {
+                text: "# second".to_string(),
+                line: 2,
+                col: 11,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_non_ascii_characters() {
+    assert_eq!(
+        kinds("var é = 'ñ'\n"),
+        [
+            TokenKind::Keyword,
+            TokenKind::Identifier,
+            TokenKind::Operator,
+            TokenKind::CharLit,
+            TokenKind::Newline,
+        ]
+    );
+    assert!(lex("print(\"añb\")\n".to_string()).is_ok());
+}
+
+#[test]
+fn test_invalid_strings() {
+    let errors = |source: &str| match lex(source.to_string()) {
+        Err(YoraError::Lex(errors)) => errors
+            .iter()
+            .map(|error| (error.kind.code(), error.line, error.col, error.len))
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a lexing error"),
+    };
+
+    assert_eq!(errors("print(\"a\\q\")\n"), [("E0029", 1, 9, 2)]);
+    // the quote after a backslash closes the string
+    assert_eq!(errors("print(\"é\\\")\n"), [("E0029", 1, 9, 1)]);
+    assert_eq!(errors("var s = \"a\nb\\q\"\n"), [("E0029", 2, 2, 2)]);
+    assert_eq!(errors("print(\"ab"), [("E0030", 1, 7, 1)]);
 }
@@ -0,0 +1,286 @@
+use std::io::{BufReader, Cursor, Write};
+use std::process::{Command, Stdio};
+
+use yora::lsp::json::Json;
+use yora::lsp::{read_message, serve, write_message};
+
+const URI: &str = "file:///main.yr";
+
+const SOURCE: &str = "pr square(n: Int) -> Int:\n\
+                      \treturn n * n\n\
+                      var total = 0\n\
+                      if total < 10:\n\
+                      \tvar total = true\n\
+                      \tprint(total)\n\
+                      print(square(total))\n";
+
+fn request(id: usize, method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id.into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+fn at(line: usize, character: usize) -> Json {
+    Json::object([
+        ("textDocument", Json::object([("uri", URI.into())])),
+        (
+            "position",
+            Json::object([("line", line.into()), ("character", character.into())]),
+        ),
+    ])
+}
+
+fn open(text: &str) -> Json {
+    notification(
+        "textDocument/didOpen",
+        Json::object([(
+            "textDocument",
+            Json::object([
+                ("uri", URI.into()),
+                ("languageId", "yora".into()),
+                ("version", 1.into()),
+                ("text", text.into()),
+            ]),
+        )]),
+    )
+}
+
+/// Sends the messages to the server, followed by a shutdown and an exit, and
+/// returns everything it sent back.
+fn run_session(messages: Vec<Json>) -> Vec<Json> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yora-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+    for message in messages.iter().chain([
+        &request(999, "shutdown", Json::Null),
+        &notification("exit", Json::Null),
+    ]) {
+        write_message(&mut stdin, message).unwrap();
+    }
+    stdin.flush().unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let mut reader = BufReader::new(output.stdout.as_slice());
+    let mut replies = Vec::new();
+    while let Some(message) = read_message(&mut reader).unwrap() {
+        replies.push(Json::parse(&message).unwrap());
+    }
+    replies
+}
+
+fn response(replies: &[Json], id: usize) -> &Json {
+    replies
+        .iter()
+        .find(|reply| *reply.get("id") == Json::from(id))
+        .unwrap()
+        .get("result")
+}
+
+fn range(line: usize, start: usize, end: usize) -> Json {
+    let position =
+        |character: usize| Json::object([("line", line.into()), ("character", character.into())]);
+    Json::object([("start", position(start)), ("end", position(end))])
+}
+
+#[test]
+fn test_initialize() {
+    let replies = run_session(vec![request(1, "initialize", Json::object([]))]);
+    let capabilities = response(&replies, 1).get("capabilities");
+
+    assert_eq!(*capabilities.get("hoverProvider"), Json::Bool(true));
+    assert_eq!(*capabilities.get("definitionProvider"), Json::Bool(true));
+}
+
+#[test]
+fn test_diagnostics() {
+    let replies = run_session(vec![open("var a = 1\nprint(b + 1)\n")]);
+
+    let publish = &replies[0];
+    assert_eq!(
+        publish.get("method").as_str(),
+        Some("textDocument/publishDiagnostics")
+    );
+    let diagnostics = publish.get("params").get("diagnostics").as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(*diagnostics[0].get("range"), range(1, 6, 7));
+    assert_eq!(diagnostics[0].get("code").as_str(), Some("E0006"));
+    assert_eq!(
+        diagnostics[0].get("message").as_str(),
+        Some("use of undeclared variable 'b'\nhelp: did you mean 'a'?")
+    );
+}
+
+#[test]
+fn test_hover() {
+    let replies = run_session(vec![
+        open(SOURCE),
+        request(1, "textDocument/hover", at(5, 7)),
+        request(2, "textDocument/hover", at(6, 8)),
+        request(3, "textDocument/hover", at(2, 0)),
+    ]);
+
+    let hover = response(&replies, 1);
+    assert_eq!(
+        hover.get("contents").get("value").as_str(),
+        Some("```yora\ntotal: Bool\n```")
+    );
+    assert_eq!(*hover.get("range"), range(5, 7, 12));
+    assert_eq!(
+        response(&replies, 2).get("contents").get("value").as_str(),
+        Some("```yora\nsquare(Int) -> Int\n```")
+    );
+    assert_eq!(*response(&replies, 3), Json::Null);
+}
+
+#[test]
+fn test_definition() {
+    let replies = run_session(vec![
+        open(SOURCE),
+        request(1, "textDocument/definition", at(1, 8)),
+        request(2, "textDocument/definition", at(5, 8)),
+        request(3, "textDocument/definition", at(6, 15)),
+        request(4, "textDocument/definition", at(6, 7)),
+    ]);
+
+    // the argument of the procedure
+    assert_eq!(*response(&replies, 1).get("range"), range(0, 10, 11));
+    // the inner declaration shadows the outer one
+    assert_eq!(*response(&replies, 2).get("range"), range(4, 5, 10));
+    assert_eq!(*response(&replies, 3).get("range"), range(2, 4, 9));
+    assert_eq!(*response(&replies, 4).get("range"), range(0, 3, 9));
+    assert_eq!(response(&replies, 4).get("uri").as_str(), Some(URI));
+}
+
+#[test]
+fn test_definition_in_nested_procedure() {
+    let source = "var base = 1\n\
+                  pr outer(x: Int) -> Int:\n\
+                  \x20   pr inner() -> Int:\n\
+                  \x20       return x + base\n\
+                  \x20   return inner()\n";
+    let replies = run_session(vec![
+        open(source),
+        request(1, "textDocument/definition", at(3, 15)),
+        request(2, "textDocument/definition", at(3, 19)),
+        request(3, "textDocument/definition", at(4, 11)),
+    ]);
+
+    // the variables of the enclosing procedure and of the program
+    assert_eq!(*response(&replies, 1).get("range"), range(1, 9, 10));
+    assert_eq!(*response(&replies, 2).get("range"), range(0, 4, 8));
+    assert_eq!(*response(&replies, 3).get("range"), range(2, 7, 12));
+}
+
+#[test]
+fn test_completion() {
+    let replies = run_session(vec![request(1, "textDocument/completion", at(0, 0))]);
+
+    let items = response(&replies, 1).as_array().unwrap();
+    let labels: Vec<&str> = items
+        .iter()
+        .filter_map(|item| item.get("label").as_str())
+        .collect();
+    assert_eq!(
+        labels,
+        [
+            "char_to_int",
+            "exit",
+            "filter",
+            "fold",
+            "input",
+            "insert",
+            "int_to_char",
+            "len",
+            "map",
+            "pop",
+            "print",
+            "push",
+            "remove",
+            "string_len",
+            "string_to_int"
+        ]
+    );
+    assert_eq!(
+        items[13].get("detail").as_str(),
+        Some("string_len(Char[]) -> Int")
+    );
+    assert_eq!(items[11].get("detail").as_str(), Some("push(_[], _)"));
+    // the types of a generic builtin can be anything
+    assert_eq!(
+        items[8].get("detail").as_str(),
+        Some("map(_[], Pr(_) -> _) -> _[]")
+    );
+}
+
+#[test]
+fn test_unknown_request() {
+    let replies = run_session(vec![request(1, "workspace/symbol", Json::object([]))]);
+
+    let error = replies[0].get("error");
+    assert_eq!(*error.get("code"), Json::Number(-32601.0));
+}
+
+#[test]
+fn test_malformed_message() {
+    let mut input = b"Content-Length: 3\r\n\r\n{x}".to_vec();
+    // without a length, and too long to be read
+    input.extend(b"Content-Type: application/json\r\n\r\n");
+    let len = 16 * 1024 * 1024 + 1;
+    input.extend(format!("Content-Length: {len}\r\n\r\n").as_bytes());
+    input.resize(input.len() + len, b' ');
+    for message in [
+        request(1, "initialize", Json::object([])),
+        request(2, "shutdown", Json::Null),
+        notification("exit", Json::Null),
+    ] {
+        write_message(&mut input, &message).unwrap();
+    }
+    let mut output = Vec::new();
+    assert!(serve(Cursor::new(input), &mut output).unwrap());
+
+    let mut reader = BufReader::new(output.as_slice());
+    for _ in 0..3 {
+        let error = Json::parse(&read_message(&mut reader).unwrap().unwrap()).unwrap();
+        assert_eq!(*error.get("id"), Json::Null);
+        assert_eq!(*error.get("error").get("code"), Json::Number(-32700.0));
+    }
+    // the server keeps going
+    let reply = Json::parse(&read_message(&mut reader).unwrap().unwrap()).unwrap();
+    assert_eq!(*reply.get("id"), Json::from(1));
+}
+
+#[test]
+fn test_diagnostics_while_typing() {
+    let replies = run_session(vec![
+        open("var é"),
+        open("print(\"\\q\")\n"),
+        open("print(\""),
+    ]);
+
+    let codes: Vec<_> = replies[..3]
+        .iter()
+        .map(|publish| {
+            let diagnostics = publish.get("params").get("diagnostics").as_array().unwrap();
+            diagnostics[0].get("code").as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(codes, ["E0018", "E0029", "E0030"]);
+}
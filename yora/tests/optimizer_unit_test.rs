@@ -0,0 +1,143 @@
+use yora::code_generation::ir_gen::generate_ir;
+use yora::code_generation::optimizer::*;
+use yora::syntax_analysis::produce_ast;
+
+const ARITHMETIC: &str = "var a = 2 * 3 + 4\n\
+                        if 1 < 2:\n\
+                        \ta = a + 1\n\
+                        print(a)\n";
+
+const PROCEDURE: &str = "pr sign(n: Int) -> Int:\n\
+                        \tif n < 0:\n\
+                        \t\treturn 0 - 1\n\
+                        \telse:\n\
+                        \t\treturn 1\n\
+                        var b = 5\n\
+                        var c = b\n\
+                        print(sign(c))\n";
+
+fn optimize_source(source: &str, passes: &Passes) -> String {
+    let ast = produce_ast(source.to_string()).unwrap();
+    optimize_with(generate_ir(&ast).unwrap(), passes).to_string()
+}
+
+#[test]
+fn test_constant_folding() {
+    let passes = Passes {
+        constant_folding: true,
+        ..Passes::none()
+    };
+
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \tInt:\tt1 = 6\n\
+                \tInt:\tt2 = t1 + 4\n\
+                \tInt:\ta = t2\n\
+                \tInt:\tt3 = a + 1\n\
+                \tInt:\ta = t3\n\
+                end_if_1:\n\
+                \x20   param a\n\
+                \x20   call print_int\n";
+
+    assert_eq!(optimize_source(ARITHMETIC, &passes), output);
+}
+
+#[test]
+fn test_copy_propagation() {
+    let passes = Passes {
+        copy_propagation: true,
+        ..Passes::none()
+    };
+
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \tInt:\tt1 = 2 * 3\n\
+                \tInt:\ta = t1 + 4\n\
+                \x20   if 1 >= 2 goto end_if_1\n\
+                \tInt:\ta = a + 1\n\
+                end_if_1:\n\
+                \x20   param a\n\
+                \x20   call print_int\n";
+
+    assert_eq!(optimize_source(ARITHMETIC, &passes), output);
+}
+
+#[test]
+fn test_dead_temporaries() {
+    let passes = Passes {
+        constant_folding: true,
+        copy_propagation: true,
+        dead_temporaries: true,
+        ..Passes::none()
+    };
+
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \tInt:\ta = 10\n\
+                \tInt:\ta = 11\n\
+                end_if_1:\n\
+                \x20   param a\n\
+                \x20   call print_int\n";
+
+    assert_eq!(optimize_source(ARITHMETIC, &passes), output);
+}
+
+#[test]
+fn test_unreachable_labels() {
+    let passes = Passes {
+        unreachable_labels: true,
+        ..Passes::none()
+    };
+
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \tInt:\tb = 5\n\
+                \tInt:\tc = b\n\
+                \x20   param c\n\
+                \x20   t2 = call sign\n\
+                \x20   param t2\n\
+                \x20   call print_int\n\
+                pr sign(n):\n\
+                \x20   if n >= 0 goto else_1\n\
+                \tInt:\tt1 = 0 - 1\n\
+                \x20   ret t1\n\
+                else_1:\n\
+                \x20   ret 1\n\
+                end pr sign\n";
+
+    assert_eq!(optimize_source(PROCEDURE, &passes), output);
+}
+
+#[test]
+fn test_all_passes() {
+    let output = "section: data\n\
+                \n\
+                section: code\n\
+                \tInt:\tb = 5\n\
+                \tInt:\tc = 5\n\
+                \x20   param 5\n\
+                \x20   t2 = call sign\n\
+                \x20   param t2\n\
+                \x20   call print_int\n\
+                pr sign(n):\n\
+                \x20   if n >= 0 goto else_1\n\
+                \x20   ret -1\n\
+                else_1:\n\
+                \x20   ret 1\n\
+                end pr sign\n";
+
+    assert_eq!(optimize_source(PROCEDURE, &Passes::all()), output);
+}
+
+#[test]
+fn test_no_passes() {
+    let ast = produce_ast(PROCEDURE.to_string()).unwrap();
+    assert_eq!(
+        optimize_with(generate_ir(&ast).unwrap(), &Passes::none()),
+        generate_ir(&ast).unwrap()
+    );
+}
@@ -1,22 +1,283 @@
-use yora_compiler::lexer::Token;
-use yora_compiler::parser::*;
+use yora::core::PrimitiveType;
+use yora::syntax_analysis::lexer::lex;
+use yora::syntax_analysis::parser::expression::*;
+use yora::syntax_analysis::parser::parse;
+use yora::syntax_analysis::parser::pattern::*;
+use yora::syntax_analysis::parser::statement::*;
 
 #[test]
 fn test_parser() {
-    let input = vec![
-        Token::Exit,
-        Token::OpenParen,
-        Token::IntLit("2".to_string()),
-        Token::Add,
-        Token::IntLit("3".to_string()),
-        Token::CloseParen,
-        Token::SemiColon,
+    let input = lex("exit(2 + 3)\n".to_string()).unwrap();
+
+    let output = vec![Statement {
+        kind: StatementKind::Call {
+            name: "exit".to_string(),
+            args: vec![Expression {
+                kind: ExpressionKind::Call(
+                    "+".to_string(),
+                    vec![
+                        Expression {
+                            kind: ExpressionKind::Lit("2".to_string()),
+                            line: 1,
+                            col: 6,
+                            r#type: Some(PrimitiveType::Int),
+                            binding: None,
+                        },
+                        Expression {
+                            kind: ExpressionKind::Lit("3".to_string()),
+                            line: 1,
+                            col: 10,
+                            r#type: Some(PrimitiveType::Int),
+                            binding: None,
+                        },
+                    ],
+                ),
+                line: 1,
+                col: 8,
+                r#type: None,
+                binding: None,
+            }],
+            binding: None,
+        },
+        line: 1,
+        col: 1,
+    }];
+
+    assert_eq!(parse(input).unwrap(), output);
+}
+
+fn to_sexpr(expr: &Expression) -> String {
+    match &expr.kind {
+        ExpressionKind::Call(name, args) => {
+            let args: Vec<String> = args.iter().map(to_sexpr).collect();
+            format!("({} {})", name, args.join(" "))
+        }
+        ExpressionKind::Field(record, field) => format!("(. {} {})", to_sexpr(record), field),
+        _ => expr.to_str().to_string(),
+    }
+}
+
+fn parse_source(source: &str) -> Vec<Statement> {
+    parse(lex(source.to_string()).unwrap()).unwrap()
+}
+
+#[test]
+fn test_operator_precedence() {
+    let ast =
+        parse_source("var a = 1 + 2 * 3 - 4 % 2\nvar b = !(a < 2) or a == -3 and b[1] >= 2\n");
+
+    let values: Vec<String> = ast
+        .iter()
+        .map(|statement| match &statement.kind {
+            StatementKind::Declare {
+                value: Some(value), ..
+            } => to_sexpr(value),
+            _ => panic!("Expected a declaration"),
+        })
+        .collect();
+
+    assert_eq!(
+        values,
+        vec![
+            "(- (+ 1 (* 2 3)) (% 4 2))",
+            "(and (or (! (< a 2)) (== a (- 3))) (>= ([] b 1) 2))",
+        ]
+    );
+}
+
+#[test]
+fn test_compound_assignment() {
+    let ast = parse_source("a[i] -= 1 + 2\n");
+
+    let StatementKind::Assign { dest, src } = &ast[0].kind else {
+        panic!("Expected an assignment");
+    };
+    assert_eq!(to_sexpr(dest), "([] a i)");
+    assert_eq!(to_sexpr(src), "(- ([] a i) (+ 1 2))");
+}
+
+#[test]
+fn test_blocks() {
+    let ast = parse_source(
+        "if a:\n\
+        \tif b:\n\
+        \t\tbreak\n\
+        \telse:\n\
+        \t\tcontinue\n\
+        else if c:\n\
+        \tloop:\n\
+        \t\tbreak\n\
+        print(a)\n",
+    );
+
+    assert_eq!(ast.len(), 2);
+    let StatementKind::IfElse {
+        true_block,
+        false_block,
+        ..
+    } = &ast[0].kind
+    else {
+        panic!("Expected an if else");
+    };
+    assert!(matches!(true_block[0].kind, StatementKind::IfElse { .. }));
+    assert!(matches!(false_block[0].kind, StatementKind::If { .. }));
+    assert!(matches!(ast[1].kind, StatementKind::Call { .. }));
+}
+
+#[test]
+fn test_fields() {
+    let ast = parse_source("a.b[1].c = f(x).y\nstruct P:\n\tx: Int\n\tys: P[]\n");
+
+    let StatementKind::Assign { dest, src } = &ast[0].kind else {
+        panic!("Expected an assignment");
+    };
+    assert_eq!(to_sexpr(dest), "(. ([] (. a b) 1) c)");
+    assert_eq!(to_sexpr(src), "(. (f x) y)");
+
+    let StatementKind::Struct { name, fields } = &ast[1].kind else {
+        panic!("Expected a struct");
+    };
+    assert_eq!(name, "P");
+    assert_eq!(
+        *fields,
+        vec![
+            ("x".to_string(), PrimitiveType::Int),
+            (
+                "ys".to_string(),
+                PrimitiveType::Arr(Box::new(PrimitiveType::Record("P".to_string())))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_for() {
+    let ast = parse_source("for i in a - 1..f(b):\n\tbreak\nfor x in [1, 2]:\n\tcontinue\n");
+
+    let StatementKind::For {
+        var,
+        iterable,
+        block,
+        ..
+    } = &ast[0].kind
+    else {
+        panic!("Expected a for");
+    };
+    assert_eq!(var, "i");
+    assert_eq!(to_sexpr(iterable), "(.. (- a 1) (f b))");
+    assert_eq!(block[0].kind, StatementKind::Break);
+
+    let StatementKind::For { var, iterable, .. } = &ast[1].kind else {
+        panic!("Expected a for");
+    };
+    assert_eq!(var, "x");
+    assert!(matches!(&iterable.kind, ExpressionKind::Array(values) if values.len() == 2));
+}
+
+#[test]
+fn test_slices() {
+    let ast = parse_source("var b = a[i + 1..len(a)][0]\n");
+
+    let StatementKind::Declare {
+        value: Some(value), ..
+    } = &ast[0].kind
+    else {
+        panic!("Expected a declaration");
+    };
+    assert_eq!(to_sexpr(value), "([] ([] a (.. (+ i 1) (len a))) 0)");
+    assert!(matches!(&value.kind, ExpressionKind::Call(_, args) if !args[1].is_range()));
+}
+
+#[test]
+fn test_procedure_types() {
+    let ast = parse_source("pr f(g: Pr(Int, Int) -> Bool, h: Pr()) -> Pr(Int) -> Int[]:\n\treturn g\nf(a, b)(1)[0](2)\n");
+
+    let StatementKind::Procedure { args, ret, .. } = &ast[0].kind else {
+        panic!("Expected a procedure");
+    };
+    assert_eq!(
+        args[0].r#type,
+        Some(PrimitiveType::Pr(
+            vec![PrimitiveType::Int, PrimitiveType::Int],
+            Some(Box::new(PrimitiveType::Bool))
+        ))
+    );
+    assert_eq!(args[1].r#type, Some(PrimitiveType::Pr(vec![], None)));
+    // the brackets belong to the return type
+    assert_eq!(ret.as_ref().unwrap().to_string(), "Pr(Int) -> Int[]");
+    let arrays = parse_source("var fs: (Pr(Int) -> Int)[]\n");
+    let StatementKind::Declare { type_hint, .. } = &arrays[0].kind else {
+        panic!("Expected a declaration");
+    };
+    assert_eq!(
+        type_hint.as_ref().unwrap().to_string(),
+        "(Pr(Int) -> Int)[]"
+    );
+
+    // the procedure called comes before the arguments
+    let StatementKind::Call { name, args, .. } = &ast[1].kind else {
+        panic!("Expected a call");
+    };
+    assert_eq!(name, "()");
+    assert_eq!(to_sexpr(&args[0]), "([] (() (f a b) 1) 0)");
+    assert_eq!(to_sexpr(&args[1]), "2");
+}
+
+#[test]
+fn test_enums() {
+    let ast = parse_source(
+        "enum E:\n\tA(Int, E)\n\tB\nmatch x:\n\tA(_, B(-1, \'c\')):\n\t\tprint(1)\n\tb:\n\t\tprint(2)\n",
+    );
+
+    let StatementKind::Enum { name, variants } = &ast[0].kind else {
+        panic!("Expected an enum");
+    };
+    assert_eq!(name, "E");
+    assert_eq!(
+        *variants,
+        vec![
+            (
+                "A".to_string(),
+                vec![PrimitiveType::Int, PrimitiveType::Record("E".to_string())]
+            ),
+            ("B".to_string(), vec![]),
+        ]
+    );
+
+    let StatementKind::Match { value, arms } = &ast[1].kind else {
+        panic!("Expected a match");
+    };
+    assert_eq!(to_sexpr(value), "x");
+    assert_eq!(arms.len(), 2);
+    assert_eq!(arms[0].0.to_string(), "A(_, B(-1, 'c'))");
+    assert_eq!((arms[0].0.line, arms[0].0.col), (5, 2));
+    assert_eq!(arms[1].0.kind, PatternKind::Binding("b".to_string()));
+    assert_eq!(arms[1].1.len(), 1);
+}
+
+#[test]
+fn test_syntax_errors() {
+    let sources = [
+        "var = 3\n",
+        "print((1 + 2)\n",
+        "print(1) 2\n",
+        "if true\n\tprint(1)\n",
+        "while true:\nprint(1)\n",
+        "pr f(a: float):\n\treturn a\n",
+        "struct P:\n\tx\n",
+        "enum E:\n\tA(1)\n",
+        "for i 0..3:\n\tprint(i)\n",
+        "for in a:\n\tprint(1)\n",
+        "match x:\n\tA(:\n\t\tprint(1)\n",
+        "match x:\n\t1 + 2:\n\t\tprint(1)\n",
+        "a + 1\n",
     ];
-    let output = vec![Expression::Sequence(vec![Expression::Exit(Box::new(
-        Expression::Add(
-            Box::new(Expression::IntLit("2".to_string())),
-            Box::new(Expression::IntLit("3".to_string())),
-        ),
-    ))])];
-    assert_eq!(parse(input), output);
+
+    for source in sources {
+        assert!(
+            lex(source.to_string()).map_or(true, |tokens| parse(tokens).is_err()),
+            "{}",
+            source
+        );
+    }
 }
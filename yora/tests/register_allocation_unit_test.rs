@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+
+use yora::code_generation::asm_gen::allocator::*;
+use yora::code_generation::ir_gen::ir::*;
+use yora::core::PrimitiveType;
+
+fn id(id: &str) -> Value {
+    Value::Identifier { id: id.to_string() }
+}
+
+fn constant(value: &str) -> Value {
+    Value::Constant {
+        value: value.to_string(),
+    }
+}
+
+fn assign(dest: &str, src: Value) -> IrInstruction {
+    IrInstruction::Ass {
+        dest: id(dest),
+        src,
+        r#type: PrimitiveType::Int,
+    }
+}
+
+fn add(dest: &str, src1: &str, src2: &str) -> IrInstruction {
+    IrInstruction::Op {
+        dest: id(dest),
+        src1: id(src1),
+        op: Op::Add,
+        src2: id(src2),
+        r#type: PrimitiveType::Int,
+    }
+}
+
+fn print(src: &str) -> Vec<IrInstruction> {
+    vec![
+        IrInstruction::Param {
+            src: id(src),
+            r#type: PrimitiveType::Int,
+        },
+        IrInstruction::Call {
+            dest: None,
+            label: "print_int".to_string(),
+        },
+    ]
+}
+
+#[test]
+fn test_live_intervals() {
+    // a is live around the loop, b only inside of it
+    let mut code = vec![
+        assign("a", constant("0")),
+        IrInstruction::Label("loop_1".to_string()),
+        IrInstruction::IfGoto {
+            src1: id("a"),
+            src2: constant("10"),
+            cond: Op::Geq,
+            label: "loop_end_1".to_string(),
+            r#type: PrimitiveType::Int,
+        },
+        assign("b", constant("1")),
+        add("a", "a", "b"),
+        IrInstruction::Goto {
+            label: "loop_1".to_string(),
+        },
+        IrInstruction::Label("loop_end_1".to_string()),
+    ];
+    code.extend(print("a"));
+
+    let intervals = get_live_intervals(&code, &[], &HashSet::new());
+
+    assert_eq!(
+        intervals,
+        vec![
+            Interval {
+                var: "a".to_string(),
+                start: 1,
+                end: 8,
+                crosses_call: false,
+            },
+            Interval {
+                var: "b".to_string(),
+                start: 4,
+                end: 5,
+                crosses_call: false,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_registers_are_reused() {
+    let mut code = vec![assign("a", constant("1"))];
+    code.extend(print("a"));
+    code.push(assign("b", constant("2")));
+    code.extend(print("b"));
+
+    let allocation = allocate_registers(&code, &[], &HashSet::new());
+
+    assert_eq!(allocation.locations["a"], "r10");
+    assert_eq!(allocation.locations["b"], "r10");
+    assert_eq!(allocation.stack_size, 0);
+}
+
+#[test]
+fn test_values_live_across_calls_use_callee_saved_registers() {
+    let mut code = vec![assign("a", constant("1")), assign("b", constant("2"))];
+    code.extend(print("a"));
+    code.extend(print("b"));
+
+    let allocation = allocate_registers(&code, &[], &HashSet::new());
+
+    assert_eq!(allocation.locations["a"], "r10");
+    assert_eq!(allocation.locations["b"], "rbx");
+}
+
+#[test]
+fn test_spilling() {
+    // eight values live at the same time, one more than there are registers
+    let vars = ["a", "b", "c", "d", "e", "f", "g", "h"];
+    let mut code: Vec<IrInstruction> = vars.iter().map(|var| assign(var, constant("1"))).collect();
+    for var in vars {
+        code.push(add("sum", "sum", var));
+    }
+    code.extend(print("sum"));
+
+    let allocation = allocate_registers(&code, &[], &HashSet::new());
+
+    let spilled: Vec<&String> = allocation
+        .locations
+        .values()
+        .filter(|location| location.starts_with("qword [rbp-"))
+        .collect();
+    assert_eq!(spilled.len(), 2);
+    assert_eq!(allocation.stack_size, 16);
+
+    let registers: HashSet<&String> = allocation
+        .locations
+        .values()
+        .filter(|location| !location.starts_with("qword"))
+        .collect();
+    assert_eq!(registers.len(), 7);
+}
+
+#[test]
+fn test_arguments_and_labels() {
+    let code = vec![
+        add("t1", "n", "buf_1"),
+        IrInstruction::Ret {
+            src: id("t1"),
+            r#type: PrimitiveType::Int,
+        },
+    ];
+
+    let allocation = allocate_registers(
+        &code,
+        &[id("n"), id("unused")],
+        &HashSet::from(["buf_1".to_string()]),
+    );
+
+    assert!(allocation.locations.contains_key("n"));
+    assert!(allocation.locations.contains_key("unused"));
+    assert!(!allocation.locations.contains_key("buf_1"));
+}
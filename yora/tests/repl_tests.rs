@@ -0,0 +1,99 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run_repl(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yora"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(input: &str) -> String {
+    String::from_utf8(run_repl(input).stdout).unwrap()
+}
+
+#[test]
+fn test_expressions_are_printed() {
+    assert_eq!(
+        stdout("var x = 20\nx + 1\nx > 1\n\"hi\"\nprint(x)\n"),
+        "21\ntrue\n\"hi\"\n20"
+    );
+}
+
+#[test]
+fn test_blocks() {
+    let input = "pr square(n: Int) -> Int:\n\
+                 \treturn n * n\n\
+                 \n\
+                 var i = 0\n\
+                 while i < 3:\n\
+                 \tprint(square(i))\n\
+                 \ti += 1\n\
+                 \n\
+                 square(12)\n";
+
+    assert_eq!(stdout(input), "014144\n");
+}
+
+#[test]
+fn test_commands() {
+    let input = ":type 1 + 2\n\
+                 :type \"abc\"\n\
+                 var x = 1\n\
+                 :reset\n\
+                 :type x\n\
+                 :ast\n\
+                 -1\n";
+
+    assert_eq!(
+        stdout(input),
+        "Int\nChar[]\nAST printing is on\n-\n└── 1\n-1\n"
+    );
+}
+
+#[test]
+fn test_errors_do_not_end_the_session() {
+    let output = run_repl("var x = 1\ny\nvar z = x + true\nx / 0\nz\nx\nexit(0)\nx\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    assert!(stderr.contains("error[E0006]: use of undeclared variable 'y'"));
+    assert!(stderr.contains("error[E0011]: division by zero"));
+    // the failed declaration of 'z' was discarded
+    assert!(stderr.contains("error[E0006]: use of undeclared variable 'z'"));
+}
+
+#[test]
+fn test_failed_declarations_are_discarded() {
+    let output = run_repl(
+        "pr mk(n: Int) -> Pr() -> Int:\n\
+         \tpr get() -> Int:\n\t\treturn n\n\
+         \treturn get\n\
+         \n\
+         struct P:\n\tx: Int\n\
+         \n\
+         var f = mk(1 / 0)\n\
+         f()\n\
+         var p = P(1 / 0)\n\
+         p.x\n\
+         var g = mk(2)\n\
+         g()\n",
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+    assert!(stderr.contains("error[E0007]: undefined procedure 'f'"));
+    assert!(stderr.contains("error[E0006]: use of undeclared variable 'p'"));
+}
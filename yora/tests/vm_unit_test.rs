@@ -0,0 +1,364 @@
+use yora::interpreter::Interpreter;
+use yora::syntax_analysis::analyzer::Analyzer;
+use yora::syntax_analysis::lexer::lex;
+use yora::syntax_analysis::parser::{parse, parse_expression};
+use yora::syntax_analysis::produce_ast;
+use yora::vm::compiler::Compiler;
+use yora::vm::Vm;
+use yora::YoraError;
+
+/// Runs the program with both the interpreter and the VM and checks that
+/// every expression evaluates to the same value afterwards.
+fn assert_same_values(source: &str, exprs: &[(&str, &str)]) {
+    let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+    let mut analyzer = Analyzer::new();
+    analyzer.analyze_program(&mut ast).unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.run(&ast).unwrap();
+    let mut vm = Vm::new();
+    vm.run(&ast).unwrap();
+
+    for (expr, expected) in exprs {
+        let mut expr = parse_expression(lex(expr.to_string()).unwrap()).unwrap();
+        analyzer.analyze_global_expression(&mut expr).unwrap();
+        let interpreted = interpreter.evaluate(&expr).unwrap().unwrap().to_string();
+        let executed = vm.evaluate(&expr).unwrap().unwrap().to_string();
+        assert_eq!(interpreted, *expected);
+        assert_eq!(executed, *expected);
+    }
+}
+
+/// Checks that both the interpreter and the VM fail with the same error,
+/// at the same position and with the same calls.
+fn assert_same_error(source: &str, message: &str) {
+    let ast = produce_ast(source.to_string()).unwrap();
+    let Err(YoraError::Runtime(interpreted)) = Interpreter::new().run(&ast) else {
+        panic!("expected a runtime error");
+    };
+    let Err(YoraError::Runtime(executed)) = Vm::new().run(&ast) else {
+        panic!("expected a runtime error");
+    };
+
+    assert_eq!(interpreted.message, message);
+    assert_eq!(executed, interpreted);
+}
+
+#[test]
+fn test_control_flow() {
+    let source = "var total = 0\n\
+                  var i = 0\n\
+                  while i < 10:\n\
+                  \ti += 1\n\
+                  \tif i % 2 == 0:\n\t\tcontinue\n\
+                  \tif i > 7:\n\t\tbreak\n\
+                  \ttotal += i\n\
+                  var n = 0\n\
+                  loop:\n\
+                  \tn += 1\n\
+                  \tif n == 4:\n\t\tbreak\n\
+                  \telse:\n\t\tvar n = 100\n\
+                  var sum = 0\n\
+                  for x in [1, 2, 3]:\n\
+                  \tfor y in 0..x:\n\t\tsum += y\n";
+
+    assert_same_values(source, &[("total", "16"), ("n", "4"), ("sum", "4")]);
+}
+
+#[test]
+fn test_procedures() {
+    let source = "pr fib(n: Int) -> Int:\n\
+                  \tif n < 2:\n\t\treturn n\n\
+                  \treturn fib(n - 1) + fib(n - 2)\n\
+                  pr first_even(a: Int[]) -> Int:\n\
+                  \tfor x in a:\n\
+                  \t\tif x % 2 == 0:\n\t\t\treturn x\n\
+                  \treturn 0 - 1\n\
+                  pr count(n: Int) -> Int:\n\
+                  \tvar i = 0\n\
+                  \twhile true:\n\
+                  \t\ti += 1\n\
+                  \t\tif i == n:\n\t\t\treturn i\n\
+                  \treturn 0\n\
+                  pr grow(ref a: Int[], n: Int):\n\
+                  \tfor i in 0..n:\n\t\tpush(a, i)\n\
+                  var a = [[1], []]\n\
+                  grow(a[1], 3)\n";
+
+    assert_same_values(
+        source,
+        &[
+            ("fib(15)", "610"),
+            ("first_even([3, 5, 8, 10])", "8"),
+            ("count(5)", "5"),
+            ("a", "[[1], [0, 1, 2]]"),
+        ],
+    );
+}
+
+#[test]
+fn test_closures() {
+    let source = "var total = 0\n\
+                  var base = 10\n\
+                  pr add(n: Int):\n\ttotal += n\n\
+                  pr make(x: Int) -> Int:\n\
+                  \tvar k = x * 2\n\
+                  \tpr scale(n: Int) -> Int:\n\t\treturn n * k + base\n\
+                  \tpr fact(n: Int) -> Int:\n\
+                  \t\tif n < 2:\n\t\t\treturn 1\n\
+                  \t\treturn n * fact(n - 1)\n\
+                  \treturn scale(3) + fact(k)\n\
+                  pr counter() -> Int:\n\
+                  \tvar c = 1\n\
+                  \tpr read() -> Int:\n\t\treturn c\n\
+                  \tc = 5\n\
+                  \treturn read() + c\n\
+                  add(5)\n\
+                  add(2)\n\
+                  var f = make\n\
+                  var g = f(2)\n\
+                  var hidden = 0\n\
+                  for i in 0..3:\n\
+                  \tpr get() -> Int:\n\t\treturn i\n\
+                  \thidden += get()\n";
+
+    assert_same_values(
+        source,
+        &[
+            ("total", "7"),
+            ("g", "46"),
+            ("f(1)", "18"),
+            ("counter()", "6"),
+            ("hidden", "3"),
+            ("f", "<pr make>"),
+        ],
+    );
+}
+
+#[test]
+fn test_procedure_values() {
+    let source = "pr double(n: Int) -> Int:\n\treturn n * 2\n\
+                  pr is_even(n: Int) -> Bool:\n\treturn n % 2 == 0\n\
+                  pr add(a: Int, b: Int) -> Int:\n\treturn a + b\n\
+                  pr apply(f: Pr(Int) -> Int, x: Int) -> Int:\n\treturn f(x)\n\
+                  pr adder(n: Int) -> Pr(Int) -> Int:\n\
+                  \tpr add_n(x: Int) -> Int:\n\t\treturn x + n\n\
+                  \treturn add_n\n\
+                  var calls = 0\n\
+                  pr count(n: Int):\n\tcalls += n\n\
+                  var nums = [1, 2, 3, 4]\n\
+                  var ops = [double, adder(10)]\n\
+                  var counter = count\n\
+                  counter(2)\n\
+                  [counter][0](3)\n";
+
+    assert_same_values(
+        source,
+        &[
+            ("map(nums, double)", "[2, 4, 6, 8]"),
+            ("filter(nums, is_even)", "[2, 4]"),
+            ("fold(nums, 0, add)", "10"),
+            ("map(filter([], is_even), adder(1))", "[]"),
+            ("apply(double, 21)", "42"),
+            ("adder(1)(2)", "3"),
+            ("ops[1](5)", "15"),
+            ("ops", "[<pr double>, <pr add_n>]"),
+            ("calls", "5"),
+        ],
+    );
+}
+
+#[test]
+fn test_arrays_and_records() {
+    let source = "struct Point:\n\tx: Int\n\ty: Int\n\
+                  enum Shape:\n\tCircle(Point, Int)\n\tEmpty\n\
+                  var grid = [[1, 2], [3, 4]]\n\
+                  grid[1][0] += 10\n\
+                  var row = grid[0]\n\
+                  row[0] = 9\n\
+                  var points: Point[]\n\
+                  push(points, Point(1, 2))\n\
+                  points[0].y = 7\n\
+                  var word = \"plan\" + \"et\"\n\
+                  var shapes = [Circle(Point(0, 0), 3), Empty]\n\
+                  var area = 0\n\
+                  for shape in shapes:\n\
+                  \tmatch shape:\n\
+                  \t\tCircle(_, r):\n\t\t\tarea += 3 * r * r\n\
+                  \t\tEmpty:\n\t\t\tarea += 0\n";
+
+    assert_same_values(
+        source,
+        &[
+            ("grid", "[[1, 2], [13, 4]]"),
+            ("row", "[9, 2]"),
+            ("points", "[Point(1, 7)]"),
+            ("word[0..4]", "\"plan\""),
+            ("len(word)", "6"),
+            ("area", "27"),
+            ("shapes[1]", "Empty"),
+        ],
+    );
+}
+
+#[test]
+fn test_runtime_errors() {
+    assert_same_error("var a = 0\nprint(5 % a)\n", "division by zero");
+    assert_same_error(
+        "var a = [[1], [2]]\na[1][3] = 0\n",
+        "index out of bounds: the length is 1 but the index is 3",
+    );
+    assert_same_error("var a = [1]\npop(a)\npop(a)\n", "pop from an empty array");
+    assert_same_error(
+        "pr divide(a: Int, b: Int) -> Int:\n\
+         \treturn a / b\n\
+         pr average(total: Int, n: Int) -> Int:\n\
+         \treturn divide(total, n)\n\
+         print(average(10, 0))\n",
+        "division by zero",
+    );
+    assert_same_error(
+        "pr inverse(n: Int) -> Int:\n\treturn 10 / n\n\
+         var a = map([1, 0], inverse)\n",
+        "division by zero",
+    );
+    assert_same_error(
+        "pr set(ref a: Int, b: Int):\n\ta = b\n\
+         var a = [1]\n\
+         set(a[2], 1 / 0)\n",
+        "index out of bounds: the length is 1 but the index is 2",
+    );
+
+    // the interpreter recurses on the native stack, so only the VM stops
+    let ast =
+        produce_ast("pr r(n: Int) -> Int:\n\treturn r(n + 1)\nprint(r(0))\n".to_string()).unwrap();
+    let Err(YoraError::Runtime(err)) = Vm::new().run(&ast) else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(err.message, "stack overflow");
+    assert_eq!((err.line, err.col), (2, 9));
+    assert_eq!(err.trace.len(), 10_000);
+    assert_eq!(
+        err.to_diagnostic().notes,
+        [
+            "in procedure 'r', called at 2:9 (9999 times)",
+            "in procedure 'r', called at 3:7"
+        ]
+    );
+}
+
+#[test]
+fn test_string_to_int() {
+    assert_same_values(
+        "var a = string_to_int(\"12\")\n\
+         var b = string_to_int(\"-7\\n\")\n",
+        &[("a", "12"), ("b", "-7")],
+    );
+    assert_same_error(
+        "print(string_to_int(\"abc\"))\n",
+        "'abc' is not a valid integer",
+    );
+    assert_same_error(
+        "print(string_to_int(\"1 2\\n\"))\n",
+        "'1 2' is not a valid integer",
+    );
+}
+
+#[test]
+fn test_globals_are_kept_between_runs() {
+    let mut analyzer = Analyzer::new();
+    let mut vm = Vm::new();
+    let mut run = |source: &str| {
+        let mut ast = parse(lex(source.to_string()).unwrap()).unwrap();
+        analyzer.analyze_program(&mut ast).unwrap();
+        vm.run(&ast)
+    };
+
+    run("var a = [1]\npr add(ref a: Int[], n: Int):\n\tpush(a, n)\n").unwrap();
+    run("add(a, 2)\nvar b = 1 / 0\n").unwrap_err();
+    run("add(a, len(a) + 1)\n").unwrap();
+
+    let mut expr = parse_expression(lex("a".to_string()).unwrap()).unwrap();
+    analyzer.analyze_global_expression(&mut expr).unwrap();
+    assert_eq!(
+        vm.evaluate(&expr).unwrap().unwrap().to_string(),
+        "[1, 2, 3]"
+    );
+}
+
+#[test]
+fn test_bytecode() {
+    let source = "pr square(n: Int) -> Int:\n\
+                  \treturn n * n\n\
+                  var a = [1, 2]\n\
+                  for x in a:\n\
+                  \ta[0] += square(x)\n";
+    let ast = produce_ast(source.to_string()).unwrap();
+    let mut compiler = Compiler::new();
+    let main = compiler.compile_program(&ast);
+
+    assert_eq!(
+        compiler.procedures[0].to_string(),
+        "square (2 locals):\n\
+         \x20  0 load local 1\n\
+         \x20  1 load local 1\n\
+         \x20  2 mul\n\
+         \x20  3 return_value\n\
+         \x20  4 return\n"
+    );
+    assert_eq!(
+        main.chunk.to_string(),
+        "\x20  0 closure 0\n\
+         \x20  1 store global 0\n\
+         \x20  2 push 1\n\
+         \x20  3 push 2\n\
+         \x20  4 array 2\n\
+         \x20  5 store global 1\n\
+         \x20  6 load global 1\n\
+         \x20  7 store local 0\n\
+         \x20  8 push 0\n\
+         \x20  9 store local 1\n\
+         \x20 10 next_in_array local 0 at local 1, global 2 else 20\n\
+         \x20 11 push 0\n\
+         \x20 12 load global 1[_]\n\
+         \x20 13 load global 0\n\
+         \x20 14 load global 2\n\
+         \x20 15 call 1\n\
+         \x20 16 add\n\
+         \x20 17 push 0\n\
+         \x20 18 store global 1[_]\n\
+         \x20 19 jump 10\n\
+         \x20 20 return\n"
+    );
+}
+
+#[test]
+fn test_shadowing() {
+    let mut source = "var a = 1\n\
+                      var b = 0\n\
+                      if a == 1:\n\
+                      \tvar a = a + 10\n\
+                      \tb = a\n\
+                      var c = a\n\
+                      for i in 0..3:\n\
+                      \tvar a = i * 2\n\
+                      \tc += a\n\
+                      pr double(n: Int) -> Int:\n\
+                      \tvar n = n * 2\n\
+                      \treturn n\n"
+        .to_string();
+    // more variables than a scope used to be able to count
+    for i in 0..200 {
+        source.push_str(&format!("var v{} = {}\n", i, i));
+    }
+
+    assert_same_values(
+        &source,
+        &[
+            ("a", "1"),
+            ("b", "11"),
+            ("c", "7"),
+            ("double(4)", "8"),
+            ("v199 - v0", "199"),
+        ],
+    );
+}